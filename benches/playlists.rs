@@ -0,0 +1,58 @@
+//! Packaging and serving costs that scale with the number of segments:
+//! checksumming a rendition playlist after packaging and re-verifying it.
+//! Run with `cargo bench --bench playlists`.
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::path::Path;
+use video::services::integrity;
+
+const SEGMENT_SIZE: usize = 256 * 1024;
+
+/// Writes a rendition directory like ffmpeg's HLS muxer would produce
+fn write_rendition(dir: &Path, segments: usize) {
+    let mut playlist = String::from(
+        "#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-TARGETDURATION:6\n#EXT-X-MEDIA-SEQUENCE:0\n",
+    );
+    for i in 0..segments {
+        let name = format!("segment_{:03}.ts", i);
+        std::fs::write(dir.join(&name), vec![i as u8; SEGMENT_SIZE]).unwrap();
+        playlist.push_str(&format!("#EXTINF:6.000000,\n{}\n", name));
+    }
+    playlist.push_str("#EXT-X-ENDLIST\n");
+    std::fs::write(dir.join("stream.m3u8"), playlist).unwrap();
+}
+
+fn playlists(c: &mut Criterion) {
+    let mut group = c.benchmark_group("playlists");
+    group.sample_size(20);
+
+    for segments in [10, 100] {
+        let hls_dir = tempfile::tempdir().unwrap();
+        let rendition = hls_dir.path().join("720p");
+        std::fs::create_dir(&rendition).unwrap();
+        write_rendition(&rendition, segments);
+        let playlist = rendition.join("stream.m3u8");
+
+        group.throughput(Throughput::Bytes((segments * SEGMENT_SIZE) as u64));
+        group.bench_with_input(
+            BenchmarkId::new("embed_checksums", segments),
+            &playlist,
+            |b, playlist| b.iter(|| integrity::with_checksums(playlist).unwrap()),
+        );
+        let checksummed = integrity::with_checksums(&playlist).unwrap();
+        std::fs::write(&playlist, checksummed).unwrap();
+        group.bench_with_input(
+            BenchmarkId::new("verify", segments),
+            hls_dir.path(),
+            |b, hls_dir| {
+                b.iter(|| {
+                    let report = integrity::verify(hls_dir).unwrap();
+                    assert!(report.mismatches.is_empty());
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, playlists);
+criterion_main!(benches);
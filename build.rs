@@ -0,0 +1,64 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    #[cfg(feature = "grpc")]
+    grpc_service();
+}
+
+/// Generates the server side of proto/vid_storage.proto. The messages are
+/// written by hand in src/grpc/proto.rs, so only the service is described here.
+#[cfg(feature = "grpc")]
+fn grpc_service() {
+    use tonic_build::manual::{Builder, Method, Service};
+
+    let method = |name: &str, route: &str, input: &str, output: &str| {
+        Method::builder()
+            .name(name)
+            .route_name(route)
+            .input_type(format!("crate::grpc::proto::{}", input))
+            .output_type(format!("crate::grpc::proto::{}", output))
+            .codec_path("tonic::codec::ProstCodec")
+    };
+
+    let service = Service::builder()
+        .name("VideoStorage")
+        .package("vid_storage")
+        .method(
+            method("upload_video", "UploadVideo", "UploadChunk", "Video")
+                .client_streaming()
+                .build(),
+        )
+        .method(method("import_video", "ImportVideo", "ImportVideoRequest", "Video").build())
+        .method(method("get_video", "GetVideo", "GetVideoRequest", "VideoDetails").build())
+        .method(
+            method(
+                "list_videos",
+                "ListVideos",
+                "ListVideosRequest",
+                "ListVideosResponse",
+            )
+            .build(),
+        )
+        .method(
+            method(
+                "watch_video",
+                "WatchVideo",
+                "WatchVideoRequest",
+                "ProgressEvent",
+            )
+            .server_streaming()
+            .build(),
+        )
+        .method(method("cancel_video", "CancelVideo", "CancelVideoRequest", "Video").build())
+        .method(
+            method(
+                "reprocess_video",
+                "ReprocessVideo",
+                "ReprocessVideoRequest",
+                "Video",
+            )
+            .build(),
+        )
+        .build();
+
+    Builder::new().build_client(false).compile(&[service]);
+}
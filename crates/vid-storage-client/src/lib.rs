@@ -0,0 +1,159 @@
+//! Client for the vid-storage HTTP API (v2).
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), vid_storage_client::Error> {
+//! let client = vid_storage_client::Client::new("http://localhost:8080");
+//! let video = client
+//!     .upload_file("clip.mp4".as_ref(), Some("My clip"))
+//!     .await?;
+//! let details = client.video(video.id).await?;
+//! # Ok(())
+//! # }
+//! ```
+use reqwest::{Method, RequestBuilder, StatusCode};
+use serde::de::DeserializeOwned;
+use std::path::Path;
+use uuid::Uuid;
+
+pub use vid_storage_models as models;
+use vid_storage_models::{
+    CreateVideoRequest, ErrorCode, ImportRequest, ListQueryParams, Page, PendingUpload,
+    ResponseType, VideoDetails, VideoListItem, VideoResource,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("failed to read upload: {0}")]
+    Io(#[from] std::io::Error),
+    /// The server answered with an error envelope
+    #[error("{code:?} ({status}): {message}")]
+    Api {
+        status: StatusCode,
+        code: ErrorCode,
+        cause: String,
+        message: String,
+    },
+    #[error("response had neither data nor error")]
+    EmptyResponse,
+}
+
+impl Error {
+    /// Error code of an API error, for branching on specific failures
+    pub fn code(&self) -> Option<ErrorCode> {
+        match self {
+            Error::Api { code, .. } => Some(*code),
+            _ => None,
+        }
+    }
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug, Clone)]
+pub struct Client {
+    base_url: String,
+    http: reqwest::Client,
+}
+
+impl Client {
+    /// `base_url` is the server root, e.g. `https://videos.example.com`
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self::with_http_client(base_url, reqwest::Client::new())
+    }
+
+    /// Uses a preconfigured `reqwest::Client`, e.g. with timeouts or a proxy
+    pub fn with_http_client(base_url: impl Into<String>, http: reqwest::Client) -> Self {
+        Client {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            http,
+        }
+    }
+
+    pub async fn list_videos(&self, query: &ListQueryParams) -> Result<Page<VideoListItem>> {
+        self.send(self.request(Method::GET, "/videos").query(query))
+            .await
+    }
+
+    pub async fn video(&self, id: Uuid) -> Result<VideoDetails> {
+        self.send(self.request(Method::GET, &format!("/videos/{}", id)))
+            .await
+    }
+
+    /// Creates a video without content; send the file with `upload_content`
+    pub async fn create_video(&self, request: &CreateVideoRequest) -> Result<PendingUpload> {
+        self.send(self.request(Method::POST, "/videos").json(request))
+            .await
+    }
+
+    /// Sends the content of a video created with `create_video`. The transfer
+    /// can be retried until it succeeds.
+    pub async fn upload_content(
+        &self,
+        pending: &PendingUpload,
+        body: impl Into<reqwest::Body>,
+    ) -> Result<VideoResource> {
+        self.send(
+            self.http
+                .put(&pending.upload_url)
+                .header("X-Upload-Token", &pending.upload_token)
+                .body(body),
+        )
+        .await
+    }
+
+    /// Creates a video and streams the file at `path` as its content
+    pub async fn upload_file(&self, path: &Path, title: Option<&str>) -> Result<VideoResource> {
+        let file = tokio::fs::File::open(path).await?;
+        let pending = self
+            .create_video(&CreateVideoRequest {
+                title: title.map(str::to_string),
+                description: None,
+                encoding_profile: None,
+                watermark_position: None,
+                priority: None,
+            })
+            .await?;
+        self.upload_content(&pending, file).await
+    }
+
+    /// Starts a server-side import of a remote URL
+    pub async fn import_video(&self, request: &ImportRequest) -> Result<VideoResource> {
+        self.send(self.request(Method::POST, "/videos/import").json(request))
+            .await
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        self.http
+            .request(method, format!("{}/api/v2{}", self.base_url, path))
+    }
+
+    async fn send<T: DeserializeOwned>(&self, request: RequestBuilder) -> Result<T> {
+        let response = request.send().await?;
+        let status = response.status();
+        let bytes = response.bytes().await?;
+
+        match serde_json::from_slice::<ResponseType<T>>(&bytes) {
+            Ok(ResponseType {
+                data: Some(data), ..
+            }) if status.is_success() => Ok(data),
+            Ok(ResponseType {
+                error: Some(error), ..
+            }) => Err(Error::Api {
+                status,
+                code: error.code,
+                cause: error.cause,
+                message: error.message,
+            }),
+            Ok(_) => Err(Error::EmptyResponse),
+            // e.g. a proxy's error page
+            Err(_) => Err(Error::Api {
+                status,
+                code: ErrorCode::from_status(status.as_u16()),
+                cause: "response".to_string(),
+                message: String::from_utf8_lossy(&bytes).into_owned(),
+            }),
+        }
+    }
+}
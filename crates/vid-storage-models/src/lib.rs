@@ -0,0 +1,870 @@
+//! Request and response types of the vid-storage HTTP API, shared by the
+//! server and the client so both sides agree on the JSON.
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// The OpenAPI document of the response envelope and the error code catalog
+pub const OPENAPI: &str = include_str!("../openapi.yaml");
+
+/// Machine-readable error codes. Clients should branch on these rather than
+/// on `message`, which is meant for people and may change. Each is described
+/// in `OPENAPI`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    /// The request was malformed
+    BadRequest,
+    /// An id in the request is not a valid UUID
+    InvalidId,
+    /// Missing credentials, e.g. no upload token
+    Unauthorized,
+    /// Credentials were given but don't grant access
+    Forbidden,
+    /// No such route or resource
+    NotFound,
+    /// The video doesn't exist or isn't available yet
+    VideoNotFound,
+    MethodNotAllowed,
+    /// The resource is in a state that doesn't allow the request
+    Conflict,
+    /// The upload exceeds the maximum file size
+    PayloadTooLarge,
+    UnsupportedMediaType,
+    /// A request field failed validation; `cause` names the field
+    ValidationFailed,
+    /// The upload is not a usable video or is outside the configured limits
+    InvalidMedia,
+    /// The upload doesn't match the SHA-256 the client supplied
+    ChecksumMismatch,
+    /// Too many requests; retry after the `Retry-After` delay
+    RateLimited,
+    /// The client used up its upload allowance; retry after the `Retry-After`
+    /// delay, when its window starts over
+    QuotaExceeded,
+    /// ffmpeg failed on the video, e.g. while extracting a frame
+    ProcessingFailed,
+    /// Something went wrong on the server
+    InternalError,
+
+    // Recorded in a video's `error_code` when it fails or is rejected,
+    // alongside CHECKSUM_MISMATCH, PAYLOAD_TOO_LARGE and PROCESSING_FAILED
+    /// The content scan rejected the upload
+    ScanRejected,
+    /// The upload isn't a media file ffprobe can read, or has no duration
+    UnreadableMedia,
+    /// The upload has no video stream
+    NoVideoStream,
+    /// The video is longer than the configured maximum
+    DurationExceeded,
+    /// The video is larger than the configured maximum resolution
+    ResolutionExceeded,
+    /// No content arrived for the upload for too long
+    UploadStalled,
+    /// A moderator removed the video
+    RemovedByModerator,
+    /// The content scan couldn't be run
+    ScanFailed,
+    /// The upload couldn't be stored
+    IngestFailed,
+    /// Processing failed while reading the upload
+    ProbeFailed,
+    /// Processing failed while transcoding: every rendition failed
+    TranscodeFailed,
+    /// Processing failed while generating thumbnails
+    ThumbnailFailed,
+    /// Processing failed while writing the master playlist
+    PackagingFailed,
+}
+
+impl ErrorCode {
+    /// Every code, in the order of the catalog
+    pub const ALL: [ErrorCode; 30] = [
+        ErrorCode::BadRequest,
+        ErrorCode::InvalidId,
+        ErrorCode::Unauthorized,
+        ErrorCode::Forbidden,
+        ErrorCode::NotFound,
+        ErrorCode::VideoNotFound,
+        ErrorCode::MethodNotAllowed,
+        ErrorCode::Conflict,
+        ErrorCode::PayloadTooLarge,
+        ErrorCode::UnsupportedMediaType,
+        ErrorCode::ValidationFailed,
+        ErrorCode::InvalidMedia,
+        ErrorCode::ChecksumMismatch,
+        ErrorCode::RateLimited,
+        ErrorCode::QuotaExceeded,
+        ErrorCode::ProcessingFailed,
+        ErrorCode::InternalError,
+        ErrorCode::ScanRejected,
+        ErrorCode::UnreadableMedia,
+        ErrorCode::NoVideoStream,
+        ErrorCode::DurationExceeded,
+        ErrorCode::ResolutionExceeded,
+        ErrorCode::UploadStalled,
+        ErrorCode::RemovedByModerator,
+        ErrorCode::ScanFailed,
+        ErrorCode::IngestFailed,
+        ErrorCode::ProbeFailed,
+        ErrorCode::TranscodeFailed,
+        ErrorCode::ThumbnailFailed,
+        ErrorCode::PackagingFailed,
+    ];
+
+    /// The code as serialized, e.g. `VIDEO_NOT_FOUND`, which is also how
+    /// it's stored in a video's `error_code`
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::BadRequest => "BAD_REQUEST",
+            ErrorCode::InvalidId => "INVALID_ID",
+            ErrorCode::Unauthorized => "UNAUTHORIZED",
+            ErrorCode::Forbidden => "FORBIDDEN",
+            ErrorCode::NotFound => "NOT_FOUND",
+            ErrorCode::VideoNotFound => "VIDEO_NOT_FOUND",
+            ErrorCode::MethodNotAllowed => "METHOD_NOT_ALLOWED",
+            ErrorCode::Conflict => "CONFLICT",
+            ErrorCode::PayloadTooLarge => "PAYLOAD_TOO_LARGE",
+            ErrorCode::UnsupportedMediaType => "UNSUPPORTED_MEDIA_TYPE",
+            ErrorCode::ValidationFailed => "VALIDATION_FAILED",
+            ErrorCode::InvalidMedia => "INVALID_MEDIA",
+            ErrorCode::ChecksumMismatch => "CHECKSUM_MISMATCH",
+            ErrorCode::RateLimited => "RATE_LIMITED",
+            ErrorCode::QuotaExceeded => "QUOTA_EXCEEDED",
+            ErrorCode::ProcessingFailed => "PROCESSING_FAILED",
+            ErrorCode::InternalError => "INTERNAL_ERROR",
+            ErrorCode::ScanRejected => "SCAN_REJECTED",
+            ErrorCode::UnreadableMedia => "UNREADABLE_MEDIA",
+            ErrorCode::NoVideoStream => "NO_VIDEO_STREAM",
+            ErrorCode::DurationExceeded => "DURATION_EXCEEDED",
+            ErrorCode::ResolutionExceeded => "RESOLUTION_EXCEEDED",
+            ErrorCode::UploadStalled => "UPLOAD_STALLED",
+            ErrorCode::RemovedByModerator => "REMOVED_BY_MODERATOR",
+            ErrorCode::ScanFailed => "SCAN_FAILED",
+            ErrorCode::IngestFailed => "INGEST_FAILED",
+            ErrorCode::ProbeFailed => "PROBE_FAILED",
+            ErrorCode::TranscodeFailed => "TRANSCODE_FAILED",
+            ErrorCode::ThumbnailFailed => "THUMBNAIL_FAILED",
+            ErrorCode::PackagingFailed => "PACKAGING_FAILED",
+        }
+    }
+
+    /// Generic code for errors that weren't given a specific one
+    pub fn from_status(status: u16) -> Self {
+        match status {
+            400 => ErrorCode::BadRequest,
+            401 => ErrorCode::Unauthorized,
+            403 => ErrorCode::Forbidden,
+            404 => ErrorCode::NotFound,
+            405 => ErrorCode::MethodNotAllowed,
+            409 => ErrorCode::Conflict,
+            413 => ErrorCode::PayloadTooLarge,
+            415 => ErrorCode::UnsupportedMediaType,
+            422 => ErrorCode::ValidationFailed,
+            429 => ErrorCode::RateLimited,
+            500..=599 => ErrorCode::InternalError,
+            _ => ErrorCode::BadRequest,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct APIError {
+    pub cause: String,
+    pub message: String,
+    pub code: ErrorCode,
+}
+
+/// Envelope around every JSON response: exactly one of `data` and `error` is
+/// set, except that reports detailing an error, like a rejected
+/// `MetadataImportReport`, come with both
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResponseType<T = String> {
+    pub data: Option<T>,
+    pub error: Option<APIError>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VideoResource {
+    pub id: Uuid,
+    pub title: String,
+    pub description: Option<String>,
+    pub duration: Option<f64>,
+    /// uploading, scanning, processing, processed, failed, rejected, removed
+    /// or canceled
+    pub status: String,
+    pub status_reason: Option<String>,
+    /// Set with `status_reason` when a video fails or is rejected, so clients
+    /// can tell what to fix: an `ErrorCode` such as `CHECKSUM_MISMATCH`,
+    /// `PAYLOAD_TOO_LARGE`, `DURATION_EXCEEDED` or, for server-side problems,
+    /// `TRANSCODE_FAILED`. Kept a string so clients built before a code was
+    /// added can still read the video.
+    pub error_code: Option<String>,
+    pub checksum: Option<String>,
+    /// Ladder override used the next time the video is processed
+    pub processing_profile: Option<ProcessingProfile>,
+    /// Encoding profile chosen at upload; null means the server's default
+    pub encoding_profile: Option<String>,
+    /// Playlists need a playback grant's token
+    pub restricted: bool,
+    /// Sites allowed to embed the video; empty allows any
+    pub embed_domains: Vec<String>,
+    /// Hidden from listings by a moderator
+    pub unlisted: bool,
+    /// Where the upload placed its watermark; null uses the server's position
+    pub watermark_position: Option<String>,
+    /// SPDX identifier of the content's license, e.g. `CC-BY-4.0`
+    pub license: Option<String>,
+    /// Credit the license asks for, e.g. the author's name
+    pub attribution: Option<String>,
+    /// Where the original work was published
+    pub source_url: Option<String>,
+    pub tags: Vec<String>,
+    /// The video is left out of listings until then
+    pub publish_at: Option<DateTime<Utc>>,
+    /// Name of the uploaded file
+    pub original_filename: Option<String>,
+    /// low, normal or high, the order workers process videos in
+    pub priority: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Per-video rendition ladder, replacing the one of the video's encoding
+/// profile
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProcessingProfile {
+    /// Highest quality first
+    pub renditions: Vec<RenditionSpec>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RenditionSpec {
+    /// Letters, digits, - and _, e.g. `2160p`
+    pub name: String,
+    /// `WIDTHxHEIGHT`
+    pub resolution: String,
+    /// e.g. `16000k`
+    pub video_bitrate: String,
+    pub audio_bitrate: String,
+    pub max_fps: f64,
+    /// `h264` (the default), `hevc` or `av1`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub codec: Option<String>,
+}
+
+/// Body of `PATCH /videos/{id}`. Missing fields are left as they are; a
+/// null `processing_profile` goes back to the encoding profile's ladder.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UpdateVideoRequest {
+    /// Admin only
+    #[serde(
+        default,
+        deserialize_with = "present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub processing_profile: Option<Option<ProcessingProfile>>,
+    /// Only serve the video's playlists to holders of a playback grant.
+    /// Admin only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub restricted: Option<bool>,
+    /// Host names, e.g. `example.com`, whose pages and their subdomains'
+    /// may embed the video; an empty list lifts the restriction. Admin only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub embed_domains: Option<Vec<String>>,
+    /// SPDX identifier such as `CC-BY-4.0`, `CC0-1.0` or
+    /// `LicenseRef-All-Rights-Reserved`; null clears it
+    #[serde(
+        default,
+        deserialize_with = "present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub license: Option<Option<String>>,
+    #[serde(
+        default,
+        deserialize_with = "present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub attribution: Option<Option<String>>,
+    /// An http(s) URL
+    #[serde(
+        default,
+        deserialize_with = "present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub source_url: Option<Option<String>>,
+}
+
+/// Tells a null field (`Some(None)`) apart from a missing one (`None`)
+fn present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// Body of `POST /videos/{id}/grants`. Exactly one of `expires_at` and
+/// `duration_secs` is required.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateGrantRequest {
+    /// Identifies the viewer, e.g. a session or user token of the
+    /// integrating system. Letters, digits and `-._~`.
+    pub token: String,
+    /// Defaults to now
+    pub starts_at: Option<DateTime<Utc>>,
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Window length from `starts_at`, e.g. 172800 for a 48-hour rental
+    pub duration_secs: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GrantResource {
+    pub id: Uuid,
+    pub video_id: Uuid,
+    /// Only in the response that creates the grant; listings leave it out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    pub starts_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    /// Whether the window covers the current time
+    pub active: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Body of `POST /videos/{id}/preview-links`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CreatePreviewLinkRequest {
+    /// How long the link works for; the server's default when unset
+    pub duration_secs: Option<u64>,
+}
+
+/// A link for reviewing a video before it's published, without an account
+/// and without the video showing up in listings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PreviewLinkResource {
+    pub id: Uuid,
+    pub video_id: Uuid,
+    /// Plays the video like a playback grant's token until the link expires.
+    /// Only in the response that creates the link, like `url`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    /// Player page to share
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    pub expires_at: DateTime<Utc>,
+    pub active: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A file attached to a video, downloaded from
+/// `GET /videos/{id}/attachments/{attachment_id}`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AttachmentResource {
+    pub id: Uuid,
+    pub video_id: Uuid,
+    pub filename: String,
+    pub content_type: String,
+    /// In bytes
+    pub size: i64,
+    /// SHA-256 of the content, hex encoded
+    pub checksum: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A step of a video's processing, in `GET /videos/{id}/jobs`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobNodeResource {
+    pub id: Uuid,
+    /// probe, rendition, ladder, thumbnails or finalize
+    pub stage: String,
+    /// The rendition a rendition step encodes
+    pub quality: Option<String>,
+    /// queued, running, done, failed or canceled
+    pub state: String,
+    pub attempts: i32,
+    pub error: Option<String>,
+    /// Steps that must be over before this one runs
+    pub depends_on: Vec<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Body of `POST /videos/{id}/report`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateReportRequest {
+    /// spam, abuse, sexual, violence, copyright or other
+    pub reason: String,
+    pub details: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReportResource {
+    pub id: Uuid,
+    pub video_id: Uuid,
+    pub reason: String,
+    pub details: Option<String>,
+    /// open, dismissed or actioned
+    pub status: String,
+    pub created_at: DateTime<Utc>,
+    pub resolved_at: Option<DateTime<Utc>>,
+}
+
+/// Body of `POST /moderation/reports/{id}/actions`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModerationActionRequest {
+    /// `dismiss` closes the report; `unlist` hides the video from listings
+    /// and `delete` removes its files, both closing every open report on it
+    pub action: String,
+    /// Kept in the audit log
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntryResource {
+    pub id: Uuid,
+    pub action: String,
+    pub video_id: Uuid,
+    pub report_id: Option<Uuid>,
+    pub note: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Body of `PUT /profiles/{name}`, creating or replacing the named profile
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncodingProfileRequest {
+    /// Highest quality first
+    pub renditions: Vec<RenditionSpec>,
+    /// x264 preset such as `veryfast` or `slow`; defaults to the server's
+    pub preset: Option<String>,
+    /// HLS segment length in seconds, a multiple of 2; defaults to the server's
+    pub segment_duration: Option<u32>,
+    /// Lower the ladder's bitrates for videos a test encode shows to be
+    /// simple; defaults to the server's
+    pub per_title: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncodingProfileResource {
+    pub name: String,
+    pub renditions: Vec<RenditionSpec>,
+    pub preset: Option<String>,
+    pub segment_duration: u32,
+    pub per_title: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QualityResource {
+    pub resolution: String,
+    /// For quality menus, e.g. `1080p60`; the resolution's name when there
+    /// is none
+    #[serde(default)]
+    pub label: String,
+    #[serde(default)]
+    pub frame_rate: Option<f64>,
+    /// Frame size of video renditions, which keeps the source's aspect ratio
+    #[serde(default)]
+    pub width: Option<u32>,
+    #[serde(default)]
+    pub height: Option<u32>,
+    pub bitrate: String,
+    /// pending, encoding, ready or failed
+    pub state: String,
+    /// Percent encoded, 0-100
+    pub progress: u8,
+    /// Transcode attempts so far; failed renditions are retried with backoff
+    pub attempts: u32,
+    pub playlist_url: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// An audio stream of the source. When there are several, each is an
+/// alternative rendition in the master playlist.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AudioTrackResource {
+    /// Index among the source's audio streams
+    pub position: u32,
+    /// ISO 639-2 code, e.g. `eng`, when the source tags one
+    pub language: Option<String>,
+    pub name: String,
+    pub channels: Option<u32>,
+    pub default: bool,
+}
+
+/// A WebVTT subtitle track, also listed in the master playlist
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubtitleResource {
+    pub id: Uuid,
+    /// ISO 639-2 code, e.g. `eng`, when known
+    pub language: Option<String>,
+    pub name: String,
+    /// embedded, for subtitles extracted from the source, upload, or
+    /// provider for those a captioning vendor returned
+    pub source: String,
+    pub default: bool,
+    pub forced: bool,
+    /// The WebVTT file, e.g. for a `<track>` element
+    pub url: String,
+    pub playlist_url: String,
+}
+
+/// Body of `POST /videos/{id}/caption-requests`, recording captions ordered
+/// from a vendor before it returns them
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateCaptionRequest {
+    /// One of the server's configured caption providers
+    pub provider: String,
+    /// BCP 47 tag of the captions, e.g. `en` or `pt-BR`
+    pub language: String,
+    /// Name of the subtitle track; the language when omitted
+    pub name: Option<String>,
+    /// The vendor's job reference, quoted in its callback; generated when
+    /// omitted
+    pub reference: Option<String>,
+}
+
+/// Captions ordered from a vendor
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptionRequestResource {
+    pub id: Uuid,
+    pub video_id: Uuid,
+    pub provider: String,
+    pub reference: String,
+    pub language: String,
+    pub name: Option<String>,
+    /// pending, completed or failed
+    pub state: String,
+    /// The subtitle the returned captions were attached as
+    pub subtitle_id: Option<Uuid>,
+    pub error: Option<String>,
+    /// Where the vendor returns the captions
+    pub callback_url: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Body a captioning vendor POSTs to `/captions/callback/{provider}`. The
+/// `X-Caption-Signature` header holds `sha256=` and the hex HMAC-SHA256 of
+/// the body, keyed with the provider's secret.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptionCallback {
+    /// Reference of the caption request
+    pub reference: String,
+    /// SRT or WebVTT; absent when the job failed
+    pub captions: Option<String>,
+    /// Why the job failed
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VideoDetails {
+    #[serde(flatten)]
+    pub video: VideoResource,
+    pub qualities: Vec<QualityResource>,
+    #[serde(default)]
+    pub audio_tracks: Vec<AudioTrackResource>,
+    #[serde(default)]
+    pub subtitles: Vec<SubtitleResource>,
+    /// Percent of all renditions encoded while the video is processing
+    pub progress: Option<u8>,
+    pub thumbnail_url: String,
+    pub stream_url: String,
+    /// Master playlist without the high-bitrate variants, for data saver
+    /// modes; null for videos packaged before it was added
+    #[serde(default)]
+    pub data_saver_url: Option<String>,
+    /// WebVTT mapping time ranges to tiles of sprite sheets, for hover
+    /// previews; null until processing has written it
+    #[serde(default)]
+    pub storyboard_url: Option<String>,
+    /// Looping animated WebP of short clips, for hover previews; null until
+    /// processing has written it
+    #[serde(default)]
+    pub preview_url: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VideoListItem {
+    #[serde(flatten)]
+    pub video: VideoResource,
+    pub thumbnail_url: String,
+}
+
+/// A video created without content, waiting for its file at `upload_url`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingUpload {
+    pub video: VideoResource,
+    pub upload_url: String,
+    pub upload_token: String,
+}
+
+/// A page of a listing. `meta` has the same keys for every listing.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub meta: PageMeta,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PageMeta {
+    pub total: i64,
+    pub page: i64,
+    pub per_page: i64,
+    pub total_pages: i64,
+    /// Seed of a `sort=random` listing, null otherwise
+    pub seed: Option<String>,
+    pub facets: serde_json::Value,
+}
+
+/// Body of `POST /videos` with a JSON content type
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CreateVideoRequest {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    /// Name of an encoding profile; the server's default when unset
+    pub encoding_profile: Option<String>,
+    /// top-left, top-right, bottom-left, bottom-right or center; the
+    /// server's watermark position when unset
+    pub watermark_position: Option<String>,
+    /// low, normal or high; workers process higher priorities first. normal
+    /// when unset
+    pub priority: Option<String>,
+}
+
+/// Result of `POST /admin/metadata-import`. When any row is invalid nothing
+/// is applied, and the response is a 422 with this report.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetadataImportReport {
+    /// Whether the updates were written; false for dry runs
+    pub applied: bool,
+    pub rows: Vec<MetadataImportRow>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetadataImportRow {
+    /// Line of the row in the CSV, the header being line 1
+    pub line: u64,
+    /// The video the row matched
+    pub video_id: Option<Uuid>,
+    /// Empty for valid rows
+    pub errors: Vec<String>,
+}
+
+/// Body of `POST /channels` and `PUT /channels/{id}`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChannelRequest {
+    pub name: String,
+    /// Played in this order, then from the start again. A video can appear
+    /// more than once.
+    pub video_ids: Vec<Uuid>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChannelResource {
+    pub id: Uuid,
+    pub name: String,
+    pub video_ids: Vec<Uuid>,
+    /// When the schedule started; changing the videos restarts it
+    pub starts_at: DateTime<Utc>,
+    /// Master playlist of the live stream
+    pub stream_url: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Body of `POST /videos/import`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportRequest {
+    pub url: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub encoding_profile: Option<String>,
+    /// low, normal or high; normal when unset
+    pub priority: Option<String>,
+}
+
+/// Body of `POST /videos/{id}/clips`, cutting a new video from a processed
+/// one
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClipRequest {
+    /// Start of the clip in the source, in seconds
+    pub start: f64,
+    /// End of the clip in the source, in seconds
+    pub end: f64,
+    /// Defaults to the source's title with " (clip)" appended
+    pub title: Option<String>,
+    pub description: Option<String>,
+}
+
+/// Body of `POST /convert` with a JSON content type, converting the original
+/// of an existing video. Codecs default to the container's usual ones.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConvertRequest {
+    pub video_id: Uuid,
+    /// mp4, mov, mkv or webm
+    pub container: String,
+    /// h264, hevc, vp9, av1 or copy, as the container allows
+    pub video_codec: Option<String>,
+    /// aac, opus, mp3 or copy, as the container allows
+    pub audio_codec: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConversionResource {
+    pub id: Uuid,
+    /// Source video, or null for a file uploaded with the request
+    pub video_id: Option<Uuid>,
+    pub container: String,
+    pub video_codec: String,
+    pub audio_codec: String,
+    /// queued, running, failed or done
+    pub state: String,
+    pub error: Option<String>,
+    /// Set once the conversion is done
+    pub download_url: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Body of `POST /videos/{id}/frames`. Exactly one of `interval_secs` and
+/// `timestamps` must be set.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FrameRequest {
+    /// Sample a frame every this many seconds, starting at 0
+    pub interval_secs: Option<f64>,
+    /// Sample frames at these times, in seconds
+    pub timestamps: Option<Vec<f64>>,
+    /// zip (the default) or ndjson
+    pub format: Option<String>,
+}
+
+/// JSON body of `PUT /videos/{id}/poster`; an image can be uploaded as
+/// multipart instead
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PosterRequest {
+    /// Time of the frame to use, in seconds
+    pub timestamp: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FrameExtractionResource {
+    pub id: Uuid,
+    pub video_id: Uuid,
+    pub format: String,
+    pub timestamps: Vec<f64>,
+    /// queued, running, failed or done
+    pub state: String,
+    pub error: Option<String>,
+    /// Set once the frames are extracted
+    pub download_url: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Body of `POST /webhooks`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateWebhookRequest {
+    pub url: String,
+    /// Key for signing payloads; generated when omitted
+    pub secret: Option<String>,
+    /// Events to send, e.g. `video.processed`; all when empty
+    #[serde(default)]
+    pub events: Vec<String>,
+    /// `full` (the default) sends a `WebhookEvent`, `compact` a
+    /// `CompactWebhookEvent`
+    pub format: Option<String>,
+    /// JSON to send instead, with `{{event}}` and `{{video.<field>}}`
+    /// placeholders in its strings taken from the full payload. A string
+    /// that is only a placeholder becomes the field's value, of any type.
+    pub template: Option<serde_json::Value>,
+}
+
+/// A registered webhook. The secret is only returned when it is created.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookResource {
+    pub id: Uuid,
+    pub url: String,
+    pub secret: Option<String>,
+    pub events: Vec<String>,
+    pub format: String,
+    pub template: Option<serde_json::Value>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Payload POSTed to webhooks when a video moves to processing, processed,
+/// failed, rejected or removed, unless they send a compact or templated one.
+/// The `X-Webhook-Signature` header holds `sha256=` and the hex HMAC-SHA256
+/// of `<timestamp>.<body>`, keyed with the webhook's secret, where the
+/// timestamp is the Unix time in the `X-Webhook-Timestamp` header.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookEvent {
+    /// `video.` followed by the new status, e.g. `video.processed`, or
+    /// `webhook.test` for test deliveries
+    pub event: String,
+    pub video: VideoResource,
+}
+
+/// Payload of webhooks with the compact format
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompactWebhookEvent {
+    pub event: String,
+    pub video_id: Uuid,
+    pub status: String,
+    pub error_code: Option<String>,
+}
+
+/// Body of `POST /webhooks/{id}/test`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TestWebhookRequest {
+    /// Video the test payload describes; the last one updated when omitted
+    pub video_id: Option<Uuid>,
+}
+
+/// How a test delivery went. It is tried once, without retries. Why it
+/// failed is only logged by the server, so the test can't be used to probe
+/// what answers at a URL.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookTestResult {
+    /// Whether the endpoint answered with a 2xx status
+    pub delivered: bool,
+}
+
+/// Body of `GET /videos/{id}/keyframes`: times in seconds, ascending, from
+/// the video's original
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Keyframes {
+    pub keyframes: Vec<f64>,
+    /// Detected cuts between shots
+    pub scene_changes: Vec<f64>,
+}
+
+/// Query parameters of `GET /videos`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ListQueryParams {
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+    /// `newest` (default) or `random`
+    pub sort: Option<String>,
+    /// Seed for `sort=random`; reuse the returned seed to page through the same shuffle
+    pub seed: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_error_code_is_documented() {
+        let documented = OPENAPI
+            .lines()
+            .filter(|line| line.trim_start().starts_with("- const: "))
+            .count();
+        assert_eq!(documented, ErrorCode::ALL.len());
+        for code in ErrorCode::ALL {
+            let name = serde_json::to_value(code).unwrap();
+            assert_eq!(name.as_str(), Some(code.as_str()));
+            let entry = format!("- const: {}\n", code.as_str());
+            assert!(OPENAPI.contains(&entry), "{} is not documented", name);
+        }
+    }
+}
@@ -0,0 +1 @@
+ALTER TABLE "videos" ADD COLUMN "status_reason" TEXT;
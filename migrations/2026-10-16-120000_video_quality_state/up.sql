@@ -0,0 +1,2 @@
+-- Existing qualities were only recorded once encoded, so they are ready
+ALTER TABLE "video_qualities" ADD COLUMN "state" VARCHAR NOT NULL DEFAULT 'ready';
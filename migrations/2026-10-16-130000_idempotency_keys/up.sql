@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS "idempotency_keys"(
+	"key" VARCHAR NOT NULL PRIMARY KEY,
+	"video_id" UUID NOT NULL,
+	"created_at" TIMESTAMP NOT NULL
+);
@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS "processing_jobs"(
+	"id" UUID NOT NULL PRIMARY KEY,
+	"video_id" UUID NOT NULL,
+	"state" VARCHAR NOT NULL,
+	"attempts" INT4 NOT NULL DEFAULT 0,
+	"error" TEXT,
+	"created_at" TIMESTAMP NOT NULL,
+	"updated_at" TIMESTAMP NOT NULL,
+	FOREIGN KEY ("video_id") REFERENCES "videos"("id")
+);
+
+-- Workers claim the oldest queued job
+CREATE INDEX IF NOT EXISTS "processing_jobs_queued_idx" ON "processing_jobs"("created_at") WHERE "state" = 'queued';
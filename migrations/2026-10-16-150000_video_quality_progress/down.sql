@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE "video_qualities" DROP COLUMN IF EXISTS "progress";
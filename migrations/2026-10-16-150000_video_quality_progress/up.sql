@@ -0,0 +1,2 @@
+-- Percent of the rendition encoded so far, updated while it is encoding
+ALTER TABLE "video_qualities" ADD COLUMN "progress" INT4 NOT NULL DEFAULT 0;
@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS "webhooks"(
+	"id" UUID NOT NULL PRIMARY KEY,
+	"url" VARCHAR NOT NULL,
+	"secret" VARCHAR NOT NULL,
+	"created_at" TIMESTAMP NOT NULL
+);
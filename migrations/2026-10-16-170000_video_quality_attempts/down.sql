@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE "video_qualities" DROP COLUMN IF EXISTS "error";
+ALTER TABLE "video_qualities" DROP COLUMN IF EXISTS "attempts";
@@ -0,0 +1,2 @@
+ALTER TABLE "video_qualities" ADD COLUMN "attempts" INT4 NOT NULL DEFAULT 0;
+ALTER TABLE "video_qualities" ADD COLUMN "error" TEXT;
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+DELETE FROM "processing_jobs" WHERE "conversion_id" IS NOT NULL;
+ALTER TABLE "processing_jobs" DROP CONSTRAINT IF EXISTS "processing_jobs_target_check";
+ALTER TABLE "processing_jobs" DROP COLUMN IF EXISTS "conversion_id";
+ALTER TABLE "processing_jobs" ALTER COLUMN "video_id" SET NOT NULL;
+DROP TABLE IF EXISTS "conversions";
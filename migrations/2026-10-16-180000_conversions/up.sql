@@ -0,0 +1,16 @@
+CREATE TABLE IF NOT EXISTS "conversions"(
+	"id" UUID NOT NULL PRIMARY KEY,
+	-- Null when the source was uploaded with the request
+	"video_id" UUID,
+	"container" VARCHAR NOT NULL,
+	"video_codec" VARCHAR NOT NULL,
+	"audio_codec" VARCHAR NOT NULL,
+	"created_at" TIMESTAMP NOT NULL,
+	FOREIGN KEY ("video_id") REFERENCES "videos"("id")
+);
+
+-- A job either processes a video or runs a conversion
+ALTER TABLE "processing_jobs" ALTER COLUMN "video_id" DROP NOT NULL;
+ALTER TABLE "processing_jobs" ADD COLUMN "conversion_id" UUID REFERENCES "conversions"("id");
+ALTER TABLE "processing_jobs" ADD CONSTRAINT "processing_jobs_target_check"
+	CHECK (("video_id" IS NULL) <> ("conversion_id" IS NULL));
@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+DELETE FROM "processing_jobs" WHERE "frame_extraction_id" IS NOT NULL;
+ALTER TABLE "processing_jobs" DROP CONSTRAINT IF EXISTS "processing_jobs_target_check";
+ALTER TABLE "processing_jobs" DROP COLUMN IF EXISTS "frame_extraction_id";
+ALTER TABLE "processing_jobs" ADD CONSTRAINT "processing_jobs_target_check"
+	CHECK (("video_id" IS NULL) <> ("conversion_id" IS NULL));
+DROP TABLE IF EXISTS "frame_extractions";
@@ -0,0 +1,16 @@
+CREATE TABLE IF NOT EXISTS "frame_extractions"(
+	"id" UUID NOT NULL PRIMARY KEY,
+	"video_id" UUID NOT NULL,
+	-- Set when frames were requested at an interval rather than at timestamps
+	"interval_secs" FLOAT8,
+	-- Resolved timestamps, in output order
+	"timestamps" FLOAT8[] NOT NULL,
+	"format" VARCHAR NOT NULL,
+	"created_at" TIMESTAMP NOT NULL,
+	FOREIGN KEY ("video_id") REFERENCES "videos"("id")
+);
+
+ALTER TABLE "processing_jobs" ADD COLUMN "frame_extraction_id" UUID REFERENCES "frame_extractions"("id");
+ALTER TABLE "processing_jobs" DROP CONSTRAINT "processing_jobs_target_check";
+ALTER TABLE "processing_jobs" ADD CONSTRAINT "processing_jobs_target_check"
+	CHECK (num_nonnulls("video_id", "conversion_id", "frame_extraction_id") = 1);
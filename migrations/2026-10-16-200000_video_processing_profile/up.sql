@@ -0,0 +1,2 @@
+-- Rendition ladder overriding `transcoding.renditions` for this video
+ALTER TABLE "videos" ADD COLUMN "processing_profile" JSONB;
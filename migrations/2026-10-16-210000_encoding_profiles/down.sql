@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE "videos" DROP COLUMN IF EXISTS "encoding_profile";
+DROP TABLE IF EXISTS "encoding_profiles";
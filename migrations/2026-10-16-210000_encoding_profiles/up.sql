@@ -0,0 +1,12 @@
+-- Named presets selectable per upload; videos without one use `transcoding`
+CREATE TABLE IF NOT EXISTS "encoding_profiles"(
+	"name" VARCHAR NOT NULL PRIMARY KEY,
+	"renditions" JSONB NOT NULL,
+	"preset" VARCHAR,
+	"segment_duration" INT4 NOT NULL,
+	"created_at" TIMESTAMP NOT NULL,
+	"updated_at" TIMESTAMP NOT NULL
+);
+
+-- Profiles in use can't be deleted
+ALTER TABLE "videos" ADD COLUMN "encoding_profile" VARCHAR REFERENCES "encoding_profiles"("name");
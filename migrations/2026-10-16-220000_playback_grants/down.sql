@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS "playback_grants";
+ALTER TABLE "videos" DROP COLUMN IF EXISTS "restricted";
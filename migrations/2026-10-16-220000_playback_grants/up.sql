@@ -0,0 +1,14 @@
+-- Restricted videos only play for tokens with an active grant
+ALTER TABLE "videos" ADD COLUMN "restricted" BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE IF NOT EXISTS "playback_grants"(
+	"id" UUID NOT NULL PRIMARY KEY,
+	"video_id" UUID NOT NULL,
+	"token" VARCHAR NOT NULL,
+	"starts_at" TIMESTAMP NOT NULL,
+	"expires_at" TIMESTAMP NOT NULL,
+	"created_at" TIMESTAMP NOT NULL,
+	FOREIGN KEY ("video_id") REFERENCES "videos"("id")
+);
+
+CREATE INDEX "playback_grants_video_id_token_idx" ON "playback_grants"("video_id", "token");
@@ -0,0 +1,2 @@
+-- Sites allowed to embed the video; empty allows any
+ALTER TABLE "videos" ADD COLUMN "embed_domains" TEXT[] NOT NULL DEFAULT '{}';
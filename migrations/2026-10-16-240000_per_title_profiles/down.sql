@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE "encoding_profiles" DROP COLUMN IF EXISTS "per_title";
@@ -0,0 +1,2 @@
+-- Profiles can size their ladder's bitrates to each video's complexity
+ALTER TABLE "encoding_profiles" ADD COLUMN "per_title" BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS "audit_log";
+DROP TABLE IF EXISTS "video_reports";
+ALTER TABLE "videos" DROP COLUMN IF EXISTS "unlisted";
@@ -0,0 +1,26 @@
+-- Unlisted videos stay playable by link but are left out of listings
+ALTER TABLE "videos" ADD COLUMN "unlisted" BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE IF NOT EXISTS "video_reports"(
+	"id" UUID NOT NULL PRIMARY KEY,
+	"video_id" UUID NOT NULL,
+	"reason" VARCHAR NOT NULL,
+	"details" TEXT,
+	"status" VARCHAR NOT NULL DEFAULT 'open',
+	"created_at" TIMESTAMP NOT NULL,
+	"resolved_at" TIMESTAMP,
+	FOREIGN KEY ("video_id") REFERENCES "videos"("id")
+);
+
+CREATE INDEX "video_reports_status_created_at_idx" ON "video_reports"("status", "created_at");
+
+CREATE TABLE IF NOT EXISTS "audit_log"(
+	"id" UUID NOT NULL PRIMARY KEY,
+	"action" VARCHAR NOT NULL,
+	"video_id" UUID NOT NULL,
+	"report_id" UUID,
+	"note" TEXT,
+	"created_at" TIMESTAMP NOT NULL,
+	FOREIGN KEY ("video_id") REFERENCES "videos"("id"),
+	FOREIGN KEY ("report_id") REFERENCES "video_reports"("id")
+);
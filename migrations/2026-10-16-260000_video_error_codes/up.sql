@@ -0,0 +1,2 @@
+-- Machine-readable counterpart of "status_reason" for failed and rejected videos
+ALTER TABLE "videos" ADD COLUMN "error_code" VARCHAR;
@@ -0,0 +1,2 @@
+-- Where an upload asked for its watermark; NULL uses the server's position
+ALTER TABLE "videos" ADD COLUMN "watermark_position" VARCHAR;
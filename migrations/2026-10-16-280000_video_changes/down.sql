@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP TRIGGER IF EXISTS "videos_record_update" ON "videos";
+DROP TRIGGER IF EXISTS "videos_record_change" ON "videos";
+DROP FUNCTION IF EXISTS "record_video_change"();
+DROP TABLE IF EXISTS "video_changes";
@@ -0,0 +1,40 @@
+-- Outbox of changes to video records for incremental sync. A trigger fills
+-- it, so no write path can forget to; "id" is the cursor clients resume from.
+CREATE TABLE IF NOT EXISTS "video_changes"(
+	"id" BIGSERIAL NOT NULL PRIMARY KEY,
+	"video_id" UUID NOT NULL,
+	"change" VARCHAR NOT NULL,
+	"created_at" TIMESTAMP NOT NULL DEFAULT (NOW() AT TIME ZONE 'utc')
+);
+
+-- Videos removed by moderation count as deleted, like rows that are
+-- actually deleted
+CREATE OR REPLACE FUNCTION "record_video_change"() RETURNS TRIGGER AS $$
+BEGIN
+	IF TG_OP = 'INSERT' THEN
+		INSERT INTO "video_changes"("video_id", "change") VALUES (NEW."id", 'created');
+	ELSIF TG_OP = 'DELETE' THEN
+		INSERT INTO "video_changes"("video_id", "change") VALUES (OLD."id", 'deleted');
+	ELSIF NEW."status" = 'removed' AND OLD."status" <> 'removed' THEN
+		INSERT INTO "video_changes"("video_id", "change") VALUES (NEW."id", 'deleted');
+	ELSIF NEW."status" <> 'removed' THEN
+		INSERT INTO "video_changes"("video_id", "change") VALUES (NEW."id", 'updated');
+	END IF;
+	RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER "videos_record_change"
+AFTER INSERT OR DELETE ON "videos"
+FOR EACH ROW EXECUTE FUNCTION "record_video_change"();
+
+CREATE TRIGGER "videos_record_update"
+AFTER UPDATE ON "videos"
+FOR EACH ROW WHEN (OLD.* IS DISTINCT FROM NEW.*)
+EXECUTE FUNCTION "record_video_change"();
+
+-- Existing videos, so syncing from the start sees every one
+INSERT INTO "video_changes"("video_id", "change", "created_at")
+SELECT "id", 'created', "created_at" FROM "videos"
+WHERE "status" <> 'removed'
+ORDER BY "created_at";
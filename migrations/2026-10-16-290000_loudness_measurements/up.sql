@@ -0,0 +1,12 @@
+-- EBU R128 loudness of each video's source audio, as measured before it was
+-- normalized
+CREATE TABLE IF NOT EXISTS "loudness_measurements"(
+	"video_id" UUID NOT NULL PRIMARY KEY,
+	"integrated_lufs" FLOAT8 NOT NULL,
+	"true_peak_dbtp" FLOAT8 NOT NULL,
+	"loudness_range_lu" FLOAT8 NOT NULL,
+	"threshold_lufs" FLOAT8 NOT NULL,
+	"target_offset_lu" FLOAT8 NOT NULL,
+	"created_at" TIMESTAMP NOT NULL,
+	FOREIGN KEY ("video_id") REFERENCES "videos"("id")
+);
@@ -0,0 +1,14 @@
+-- Auxiliary files of a video, such as slides or project files, stored in its
+-- directory under "attachments/<id>"
+CREATE TABLE IF NOT EXISTS "video_attachments"(
+	"id" UUID NOT NULL PRIMARY KEY,
+	"video_id" UUID NOT NULL,
+	"filename" VARCHAR NOT NULL,
+	"content_type" VARCHAR NOT NULL,
+	"size" BIGINT NOT NULL,
+	"checksum" VARCHAR NOT NULL,
+	"created_at" TIMESTAMP NOT NULL,
+	FOREIGN KEY ("video_id") REFERENCES "videos"("id")
+);
+
+CREATE INDEX "video_attachments_video_id_idx" ON "video_attachments"("video_id");
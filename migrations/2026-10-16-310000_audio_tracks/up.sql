@@ -0,0 +1,14 @@
+-- Audio streams of each video's source, packaged as separate HLS renditions
+-- when there is more than one
+CREATE TABLE IF NOT EXISTS "audio_tracks"(
+	"id" UUID NOT NULL PRIMARY KEY,
+	"video_id" UUID NOT NULL,
+	"position" INTEGER NOT NULL,
+	"language" VARCHAR,
+	"name" VARCHAR NOT NULL,
+	"channels" INTEGER,
+	"is_default" BOOLEAN NOT NULL,
+	"created_at" TIMESTAMP NOT NULL,
+	FOREIGN KEY ("video_id") REFERENCES "videos"("id"),
+	UNIQUE ("video_id", "position")
+);
@@ -0,0 +1,16 @@
+-- WebVTT subtitles of each video, packaged as HLS subtitle renditions under
+-- "hls/subtitles"
+CREATE TABLE IF NOT EXISTS "subtitles"(
+	"id" UUID NOT NULL PRIMARY KEY,
+	"video_id" UUID NOT NULL,
+	"language" VARCHAR,
+	"name" VARCHAR NOT NULL,
+	"file_path" VARCHAR NOT NULL,
+	"source" VARCHAR NOT NULL,
+	"is_default" BOOLEAN NOT NULL,
+	"forced" BOOLEAN NOT NULL,
+	"created_at" TIMESTAMP NOT NULL,
+	FOREIGN KEY ("video_id") REFERENCES "videos"("id")
+);
+
+CREATE INDEX "subtitles_video_id_idx" ON "subtitles"("video_id");
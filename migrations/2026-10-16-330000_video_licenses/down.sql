@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE "videos" DROP COLUMN IF EXISTS "source_url";
+ALTER TABLE "videos" DROP COLUMN IF EXISTS "attribution";
+ALTER TABLE "videos" DROP COLUMN IF EXISTS "license";
@@ -0,0 +1,4 @@
+-- Licensing of the video's content, e.g. for archives of CC-licensed material
+ALTER TABLE "videos" ADD COLUMN "license" VARCHAR;
+ALTER TABLE "videos" ADD COLUMN "attribution" TEXT;
+ALTER TABLE "videos" ADD COLUMN "source_url" VARCHAR;
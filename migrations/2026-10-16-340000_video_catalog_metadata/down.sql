@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS "videos_original_filename_idx";
+ALTER TABLE "videos" DROP COLUMN IF EXISTS "original_filename";
+ALTER TABLE "videos" DROP COLUMN IF EXISTS "publish_at";
+ALTER TABLE "videos" DROP COLUMN IF EXISTS "tags";
@@ -0,0 +1,7 @@
+-- Catalog metadata, e.g. as imported in bulk when migrating a library. The
+-- original file name lets import rows refer to videos by the uploaded file.
+ALTER TABLE "videos" ADD COLUMN "tags" TEXT[] NOT NULL DEFAULT '{}';
+ALTER TABLE "videos" ADD COLUMN "publish_at" TIMESTAMP;
+ALTER TABLE "videos" ADD COLUMN "original_filename" VARCHAR;
+
+CREATE INDEX "videos_original_filename_idx" ON "videos"("original_filename");
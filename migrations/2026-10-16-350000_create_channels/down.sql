@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS "channel_videos";
+DROP TABLE IF EXISTS "channels";
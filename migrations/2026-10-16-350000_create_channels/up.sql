@@ -0,0 +1,18 @@
+-- Linear channels looping a list of videos as one live stream
+CREATE TABLE IF NOT EXISTS "channels"(
+	"id" UUID NOT NULL PRIMARY KEY,
+	"name" VARCHAR NOT NULL,
+	-- When the first video started playing; the schedule repeats from here
+	"starts_at" TIMESTAMP NOT NULL,
+	"created_at" TIMESTAMP NOT NULL,
+	"updated_at" TIMESTAMP NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS "channel_videos"(
+	"channel_id" UUID NOT NULL,
+	"position" INTEGER NOT NULL,
+	"video_id" UUID NOT NULL,
+	PRIMARY KEY ("channel_id", "position"),
+	FOREIGN KEY ("channel_id") REFERENCES "channels"("id"),
+	FOREIGN KEY ("video_id") REFERENCES "videos"("id")
+);
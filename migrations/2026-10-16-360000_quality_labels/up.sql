@@ -0,0 +1,4 @@
+-- What players show in their quality menus, e.g. 1080p60. Audio renditions
+-- have neither.
+ALTER TABLE "video_qualities" ADD COLUMN "label" VARCHAR;
+ALTER TABLE "video_qualities" ADD COLUMN "frame_rate" DOUBLE PRECISION;
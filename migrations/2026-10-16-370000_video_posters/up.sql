@@ -0,0 +1,3 @@
+-- Chosen poster image, relative to the video's directory. Videos without
+-- one show their first thumbnail.
+ALTER TABLE "videos" ADD COLUMN "poster" VARCHAR;
@@ -0,0 +1,4 @@
+-- Frame size each video rendition is encoded at, which follows the source's
+-- aspect ratio. Audio renditions have none.
+ALTER TABLE "video_qualities" ADD COLUMN "width" INTEGER;
+ALTER TABLE "video_qualities" ADD COLUMN "height" INTEGER;
@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+DELETE FROM "processing_jobs" WHERE "chunk_encode_id" IS NOT NULL;
+ALTER TABLE "processing_jobs" DROP CONSTRAINT IF EXISTS "processing_jobs_target_check";
+ALTER TABLE "processing_jobs" DROP COLUMN IF EXISTS "chunk_encode_id";
+ALTER TABLE "processing_jobs" ADD CONSTRAINT "processing_jobs_target_check"
+	CHECK (num_nonnulls("video_id", "conversion_id", "frame_extraction_id") = 1);
+DROP TABLE IF EXISTS "chunk_encodes";
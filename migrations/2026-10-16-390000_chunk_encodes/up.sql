@@ -0,0 +1,21 @@
+-- A chunk of a long rendition, queued as a job any worker can take when
+-- `ffmpeg.distribute_chunks` is set
+CREATE TABLE IF NOT EXISTS "chunk_encodes"(
+	"id" UUID NOT NULL PRIMARY KEY,
+	"video_id" UUID NOT NULL,
+	"quality" VARCHAR NOT NULL,
+	"chunk" INT4 NOT NULL,
+	-- Seconds into the source; no length for the last chunk, which runs to the end
+	"start" FLOAT8 NOT NULL,
+	"length" FLOAT8,
+	-- ffmpeg arguments, run in the chunk's directory
+	"args" TEXT[] NOT NULL,
+	"created_at" TIMESTAMP NOT NULL,
+	FOREIGN KEY ("video_id") REFERENCES "videos"("id"),
+	UNIQUE ("video_id", "quality", "chunk")
+);
+
+ALTER TABLE "processing_jobs" ADD COLUMN "chunk_encode_id" UUID REFERENCES "chunk_encodes"("id") ON DELETE CASCADE;
+ALTER TABLE "processing_jobs" DROP CONSTRAINT "processing_jobs_target_check";
+ALTER TABLE "processing_jobs" ADD CONSTRAINT "processing_jobs_target_check"
+	CHECK (num_nonnulls("video_id", "conversion_id", "frame_extraction_id", "chunk_encode_id") = 1);
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS "job_dependencies";
+DELETE FROM "processing_jobs" WHERE "stage" IS NOT NULL AND "stage" <> 'probe';
+ALTER TABLE "processing_jobs" DROP COLUMN IF EXISTS "quality";
+ALTER TABLE "processing_jobs" DROP COLUMN IF EXISTS "stage";
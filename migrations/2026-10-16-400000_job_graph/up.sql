@@ -0,0 +1,14 @@
+-- Processing a video runs as a graph of jobs: the probe plans the
+-- renditions, which are encoded alongside the thumbnails, and finalize
+-- writes the master playlist once they are all over
+ALTER TABLE "processing_jobs" ADD COLUMN "stage" VARCHAR;
+-- The rendition a rendition job encodes
+ALTER TABLE "processing_jobs" ADD COLUMN "quality" VARCHAR;
+UPDATE "processing_jobs" SET "stage" = 'probe' WHERE "video_id" IS NOT NULL;
+
+-- A job only runs once every job it depends on is done or failed
+CREATE TABLE IF NOT EXISTS "job_dependencies"(
+	"job_id" UUID NOT NULL REFERENCES "processing_jobs"("id") ON DELETE CASCADE,
+	"depends_on" UUID NOT NULL REFERENCES "processing_jobs"("id") ON DELETE CASCADE,
+	PRIMARY KEY ("job_id", "depends_on")
+);
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP TRIGGER IF EXISTS "processing_jobs_notify" ON "processing_jobs";
+DROP FUNCTION IF EXISTS notify_processing_jobs();
+ALTER TABLE "processing_jobs" DROP COLUMN IF EXISTS "heartbeat_at";
+ALTER TABLE "processing_jobs" DROP COLUMN IF EXISTS "worker_id";
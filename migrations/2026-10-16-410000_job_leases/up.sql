@@ -0,0 +1,19 @@
+-- Running jobs are leased by the worker process that claimed them, which
+-- renews the heartbeat while it runs them. Once it stops, any worker may take
+-- them over.
+ALTER TABLE "processing_jobs" ADD COLUMN "worker_id" UUID;
+ALTER TABLE "processing_jobs" ADD COLUMN "heartbeat_at" TIMESTAMP;
+
+-- Wakes idle workers whenever a job may have become ready to claim: it was
+-- queued, or a job others depend on is over
+CREATE FUNCTION notify_processing_jobs() RETURNS trigger AS $$
+BEGIN
+	PERFORM pg_notify('processing_jobs', '');
+	RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER "processing_jobs_notify"
+	AFTER INSERT OR UPDATE OF "state" ON "processing_jobs"
+	FOR EACH ROW WHEN (NEW."state" <> 'running')
+	EXECUTE FUNCTION notify_processing_jobs();
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE "webhooks" DROP COLUMN IF EXISTS "template";
+ALTER TABLE "webhooks" DROP COLUMN IF EXISTS "format";
+ALTER TABLE "webhooks" DROP COLUMN IF EXISTS "events";
@@ -0,0 +1,6 @@
+-- Events the webhook is sent, e.g. `video.processed`; empty sends them all
+ALTER TABLE "webhooks" ADD COLUMN "events" TEXT[] NOT NULL DEFAULT '{}';
+-- full sends the video resource, compact only its id, status and error code
+ALTER TABLE "webhooks" ADD COLUMN "format" VARCHAR NOT NULL DEFAULT 'full';
+-- JSON the payload is rendered from instead of either format
+ALTER TABLE "webhooks" ADD COLUMN "template" JSONB;
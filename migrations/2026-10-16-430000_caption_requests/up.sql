@@ -0,0 +1,21 @@
+-- Captions ordered from a third-party vendor, who returns them to
+-- "/captions/callback/{provider}" quoting the reference
+CREATE TABLE IF NOT EXISTS "caption_requests"(
+	"id" UUID NOT NULL PRIMARY KEY,
+	"video_id" UUID NOT NULL,
+	"provider" VARCHAR NOT NULL,
+	"reference" VARCHAR NOT NULL,
+	"language" VARCHAR NOT NULL,
+	"name" VARCHAR,
+	-- pending, completed or failed
+	"state" VARCHAR NOT NULL,
+	"subtitle_id" UUID,
+	"error" TEXT,
+	"created_at" TIMESTAMP NOT NULL,
+	"updated_at" TIMESTAMP NOT NULL,
+	FOREIGN KEY ("video_id") REFERENCES "videos"("id"),
+	FOREIGN KEY ("subtitle_id") REFERENCES "subtitles"("id") ON DELETE SET NULL,
+	UNIQUE ("provider", "reference")
+);
+
+CREATE INDEX "caption_requests_video_id_idx" ON "caption_requests"("video_id");
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS "processing_jobs_queued_idx";
+CREATE INDEX IF NOT EXISTS "processing_jobs_queued_idx" ON "processing_jobs"("created_at") WHERE "state" = 'queued';
+ALTER TABLE "processing_jobs" DROP COLUMN IF EXISTS "priority";
+ALTER TABLE "videos" DROP COLUMN IF EXISTS "priority";
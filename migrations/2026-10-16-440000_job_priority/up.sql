@@ -0,0 +1,6 @@
+-- low, normal or high; the jobs processing the video are queued with it
+ALTER TABLE "videos" ADD COLUMN "priority" VARCHAR NOT NULL DEFAULT 'normal';
+-- Workers take higher priorities first, then the oldest job
+ALTER TABLE "processing_jobs" ADD COLUMN "priority" INTEGER NOT NULL DEFAULT 0;
+DROP INDEX IF EXISTS "processing_jobs_queued_idx";
+CREATE INDEX IF NOT EXISTS "processing_jobs_queued_idx" ON "processing_jobs"("priority" DESC, "created_at") WHERE "state" = 'queued';
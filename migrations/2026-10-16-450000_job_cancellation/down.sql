@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TRIGGER IF EXISTS "processing_jobs_notify_canceled" ON "processing_jobs";
+DROP FUNCTION IF EXISTS notify_canceled_jobs();
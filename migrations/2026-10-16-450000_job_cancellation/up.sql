@@ -0,0 +1,13 @@
+-- Tells the worker running a job that it was canceled, so it kills the job's
+-- processes. The payload is the job's id.
+CREATE FUNCTION notify_canceled_jobs() RETURNS trigger AS $$
+BEGIN
+	PERFORM pg_notify('processing_cancels', NEW."id"::text);
+	RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER "processing_jobs_notify_canceled"
+	AFTER UPDATE OF "state" ON "processing_jobs"
+	FOR EACH ROW WHEN (OLD."state" = 'running' AND NEW."state" = 'canceled')
+	EXECUTE FUNCTION notify_canceled_jobs();
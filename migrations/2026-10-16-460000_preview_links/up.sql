@@ -0,0 +1,12 @@
+-- Expiring links for reviewing a video before it's published, whose token
+-- plays it like a playback grant
+CREATE TABLE IF NOT EXISTS "preview_links"(
+	"id" UUID NOT NULL PRIMARY KEY,
+	"video_id" UUID NOT NULL,
+	"token" VARCHAR NOT NULL UNIQUE,
+	"expires_at" TIMESTAMP NOT NULL,
+	"created_at" TIMESTAMP NOT NULL,
+	FOREIGN KEY ("video_id") REFERENCES "videos"("id")
+);
+
+CREATE INDEX "preview_links_video_id_idx" ON "preview_links"("video_id");
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS "video_changes_xid_id_idx";
+ALTER TABLE "video_changes" DROP COLUMN IF EXISTS "xid";
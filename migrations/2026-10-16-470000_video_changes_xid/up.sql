@@ -0,0 +1,8 @@
+-- Transaction that recorded each change. Ids are taken when a change is
+-- written, not when it commits, so a change can become visible after ones
+-- with higher ids; the feed orders by transaction instead, and only reads
+-- transactions older than every one still running.
+ALTER TABLE "video_changes"
+ADD COLUMN "xid" BIGINT NOT NULL DEFAULT (pg_current_xact_id()::TEXT::BIGINT);
+
+CREATE INDEX "video_changes_xid_id_idx" ON "video_changes"("xid", "id");
@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+UPDATE "videos"
+SET "error_code" = CASE "error_code"
+    WHEN 'PAYLOAD_TOO_LARGE' THEN 'file_too_large'
+    ELSE LOWER("error_code")
+END
+WHERE "error_code" IS NOT NULL;
@@ -0,0 +1,7 @@
+-- Error codes are now the names of ErrorCode, as in error responses
+UPDATE "videos"
+SET "error_code" = CASE "error_code"
+    WHEN 'file_too_large' THEN 'PAYLOAD_TOO_LARGE'
+    ELSE UPPER("error_code")
+END
+WHERE "error_code" IS NOT NULL;
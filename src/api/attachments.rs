@@ -0,0 +1,294 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::api::grants;
+use crate::api::moderation::require_admin;
+use crate::api::shared::ResponseType;
+use crate::config::AppConfig;
+use crate::db::models::VideoAttachment;
+use crate::db::DbPool;
+use crate::services::entitlement::Entitlements;
+use crate::services::video_processor;
+use actix_files::NamedFile;
+use actix_multipart::Multipart;
+use actix_web::http::header::{
+    self, Charset, ContentDisposition, DispositionParam, DispositionType, ExtendedValue,
+};
+use actix_web::mime::{self, Mime};
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use chrono::Utc;
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl};
+use diesel_async::RunQueryDsl;
+use futures::TryStreamExt;
+use serde_json::json;
+use uuid::Uuid;
+use vid_storage_models::AttachmentResource;
+
+const MAX_ATTACHMENTS: i64 = 50;
+
+fn attachment_resource(attachment: VideoAttachment) -> AttachmentResource {
+    AttachmentResource {
+        id: attachment.id,
+        video_id: attachment.video_id,
+        filename: attachment.filename,
+        content_type: attachment.content_type,
+        size: attachment.size,
+        checksum: attachment.checksum,
+        created_at: attachment.created_at.and_utc(),
+    }
+}
+
+/// Where a video's attachments are kept. Outside `uploads`, so they can only
+/// be downloaded through `download_attachment` and its playback check.
+pub fn attachments_dir(video_id: Uuid) -> PathBuf {
+    PathBuf::from("attachments").join(video_id.to_string())
+}
+
+fn attachment_path(video_id: Uuid, attachment_id: Uuid) -> PathBuf {
+    attachments_dir(video_id).join(attachment_id.to_string())
+}
+
+/// Moves attachments stored by earlier versions, inside the video
+/// directories, to `attachments_dir`
+pub async fn move_legacy_attachments() -> std::io::Result<()> {
+    let mut videos = match tokio::fs::read_dir("uploads").await {
+        Ok(videos) => videos,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    while let Some(entry) = videos.next_entry().await? {
+        let Some(video_id) = entry.file_name().to_str().and_then(|n| n.parse().ok()) else {
+            continue;
+        };
+        let legacy = entry.path().join("attachments");
+        if !tokio::fs::try_exists(&legacy).await? {
+            continue;
+        }
+        tokio::fs::create_dir_all("attachments").await?;
+        tokio::fs::rename(&legacy, attachments_dir(video_id)).await?;
+        log::info!("Moved the attachments of video {}", video_id);
+    }
+    Ok(())
+}
+
+/// The uploader's file name, or a placeholder when it has none
+fn clean_filename(filename: Option<&str>) -> String {
+    let name = video_processor::client_file_name(filename.unwrap_or_default());
+    match name.as_str() {
+        "" | "." | ".." => "attachment".to_string(),
+        _ => name,
+    }
+}
+
+/// Stores the multipart `file` part as an attachment of the video
+pub async fn create_attachment(
+    req: HttpRequest,
+    video_id: web::Path<Uuid>,
+    mut payload: Multipart,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+) -> Result<HttpResponse, Error> {
+    use crate::db::schema::{video_attachments, videos};
+
+    require_admin(&req, &config)?;
+    let video_id = *video_id;
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    videos::table
+        .find(video_id)
+        .filter(videos::status.ne("removed"))
+        .select(videos::id)
+        .first::<Uuid>(conn)
+        .await
+        .optional()
+        .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Video not found"))?;
+    let count = video_attachments::table
+        .filter(video_attachments::video_id.eq(video_id))
+        .count()
+        .get_result::<i64>(conn)
+        .await
+        .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?;
+    if count >= MAX_ATTACHMENTS {
+        return Err(actix_web::error::ErrorConflict(format!(
+            "A video can have at most {} attachments",
+            MAX_ATTACHMENTS
+        )));
+    }
+
+    let id = Uuid::new_v4();
+    let path = attachment_path(video_id, id);
+    tokio::fs::create_dir_all(path.parent().unwrap())
+        .await
+        .map_err(|e| {
+            log::error!("Failed to create attachment directory: {}", e);
+            actix_web::error::ErrorInternalServerError("Storage error")
+        })?;
+
+    let mut stored = None;
+    while let Some(mut field) = payload.try_next().await? {
+        if field.name() != Some("file") || stored.is_some() {
+            while (field.try_next().await?).is_some() {}
+            continue;
+        }
+        let filename = clean_filename(field.content_disposition().and_then(|cd| cd.get_filename()));
+        let content_type = field
+            .content_type()
+            .map_or(mime::APPLICATION_OCTET_STREAM.to_string(), |mime| {
+                mime.essence_str().to_string()
+            });
+        let written = video_processor::write_body(
+            &mut field,
+            &path,
+            config.storage.max_attachment_size,
+            |_, _| {},
+        )
+        .await;
+        match written {
+            Ok((checksum, size)) => stored = Some((filename, content_type, checksum, size)),
+            Err(e) => {
+                let _ = tokio::fs::remove_file(&path).await;
+                return Err(e);
+            }
+        }
+    }
+    let (filename, content_type, checksum, size) =
+        stored.ok_or_else(|| actix_web::error::ErrorBadRequest("No file provided"))?;
+
+    let attachment = VideoAttachment {
+        id,
+        video_id,
+        filename,
+        content_type,
+        size: size as i64,
+        checksum,
+        created_at: Utc::now().naive_utc(),
+    };
+    if let Err(e) = diesel::insert_into(video_attachments::table)
+        .values(&attachment)
+        .execute(conn)
+        .await
+    {
+        log::error!("Error inserting attachment: {}", e);
+        let _ = tokio::fs::remove_file(&path).await;
+        return Err(actix_web::error::ErrorInternalServerError("Database error"));
+    }
+
+    Ok(
+        HttpResponse::Created().json(json!(ResponseType::<AttachmentResource> {
+            data: Some(attachment_resource(attachment)),
+            error: None
+        })),
+    )
+}
+
+/// Attachments of the video, oldest first. Restricted videos need the same
+/// token as for playback.
+pub async fn list_attachments(
+    req: HttpRequest,
+    video_id: web::Path<Uuid>,
+    pool: web::Data<DbPool>,
+    entitlements: web::Data<Entitlements>,
+) -> Result<HttpResponse, Error> {
+    use crate::db::schema::video_attachments;
+
+    grants::authorize_playback(&req, *video_id, &pool, &entitlements).await?;
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    let attachments = video_attachments::table
+        .filter(video_attachments::video_id.eq(*video_id))
+        .order(video_attachments::created_at.asc())
+        .load::<VideoAttachment>(conn)
+        .await
+        .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+    Ok(
+        HttpResponse::Ok().json(json!(ResponseType::<Vec<AttachmentResource>> {
+            data: Some(attachments.into_iter().map(attachment_resource).collect()),
+            error: None
+        })),
+    )
+}
+
+/// Downloads an attachment under its original name. Restricted videos need
+/// the same token as for playback.
+pub async fn download_attachment(
+    req: HttpRequest,
+    params: web::Path<(Uuid, Uuid)>,
+    pool: web::Data<DbPool>,
+    entitlements: web::Data<Entitlements>,
+) -> Result<HttpResponse, Error> {
+    use crate::db::schema::video_attachments;
+
+    let (video_id, attachment_id) = params.into_inner();
+    grants::authorize_playback(&req, video_id, &pool, &entitlements).await?;
+    let attachment = {
+        let conn = &mut pool.get().await.expect("Failed to get DB connection");
+        video_attachments::table
+            .filter(video_attachments::id.eq(attachment_id))
+            .filter(video_attachments::video_id.eq(video_id))
+            .first::<VideoAttachment>(conn)
+            .await
+            .optional()
+            .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?
+            .ok_or_else(|| actix_web::error::ErrorNotFound("Attachment not found"))?
+    };
+
+    let filename = if attachment.filename.is_ascii() {
+        DispositionParam::Filename(attachment.filename)
+    } else {
+        DispositionParam::FilenameExt(ExtendedValue {
+            charset: Charset::Ext("UTF-8".to_string()),
+            language_tag: None,
+            value: attachment.filename.into_bytes(),
+        })
+    };
+    let mut response = NamedFile::open(attachment_path(video_id, attachment_id))
+        .map_err(|_| actix_web::error::ErrorNotFound("Attachment not found"))?
+        .set_content_type(
+            attachment
+                .content_type
+                .parse::<Mime>()
+                .unwrap_or(mime::APPLICATION_OCTET_STREAM),
+        )
+        .set_content_disposition(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![filename],
+        })
+        .into_response(&req);
+    // Uploaded HTML or SVG must never render in the server's origin
+    response.headers_mut().insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        header::HeaderValue::from_static("nosniff"),
+    );
+    Ok(response)
+}
+
+pub async fn delete_attachment(
+    req: HttpRequest,
+    params: web::Path<(Uuid, Uuid)>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+) -> Result<HttpResponse, Error> {
+    use crate::db::schema::video_attachments;
+
+    require_admin(&req, &config)?;
+    let (video_id, attachment_id) = params.into_inner();
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    let deleted = diesel::delete(
+        video_attachments::table
+            .filter(video_attachments::id.eq(attachment_id))
+            .filter(video_attachments::video_id.eq(video_id)),
+    )
+    .execute(conn)
+    .await
+    .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?;
+    if deleted == 0 {
+        return Err(actix_web::error::ErrorNotFound("Attachment not found"));
+    }
+
+    match tokio::fs::remove_file(attachment_path(video_id, attachment_id)).await {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => log::error!("Failed to remove attachment {}: {}", attachment_id, e),
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
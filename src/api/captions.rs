@@ -0,0 +1,615 @@
+use std::sync::Arc;
+
+use crate::api::dto::subtitle_resource;
+use crate::api::moderation::require_admin;
+use crate::api::shared::{validation_error, ErrorCode, ResponseType};
+use crate::config::app_config::FfmpegConfig;
+use crate::config::AppConfig;
+use crate::db::models::{CaptionRequest, Subtitle};
+use crate::db::DbPool;
+use crate::services::{dedup, subtitles, video_processor, webhooks};
+use actix_multipart::Multipart;
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use chrono::Utc;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use futures::TryStreamExt;
+use serde_json::json;
+use tokio::fs;
+use uuid::Uuid;
+use vid_storage_models::{
+    CaptionCallback, CaptionRequestResource, CreateCaptionRequest, SubtitleResource,
+};
+
+const MAX_SUBTITLES: usize = 50;
+const MAX_SUBTITLE_SIZE: usize = 5 * 1024 * 1024;
+const MAX_LANGUAGE_LEN: usize = 35;
+const MAX_NAME_LEN: usize = 100;
+const MAX_REFERENCE_LEN: usize = 200;
+/// Header of caption callbacks holding `sha256=` and the hex HMAC-SHA256 of
+/// the body, keyed with the provider's secret
+const SIGNATURE_HEADER: &str = "X-Caption-Signature";
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/captions").service(
+            web::resource("/callback/{provider}")
+                // Captions arrive inline, escaped in JSON
+                .app_data(web::PayloadConfig::new(2 * MAX_SUBTITLE_SIZE))
+                .route(web::post().to(caption_callback)),
+        ),
+    );
+}
+
+/// A BCP 47 language tag in its conventional case, e.g. `pt-BR` or `zh-Hant`
+fn normalize_language(tag: &str) -> Option<String> {
+    let tag = tag.trim();
+    if tag.is_empty() || tag.len() > MAX_LANGUAGE_LEN {
+        return None;
+    }
+    let mut subtags = Vec::new();
+    for (i, subtag) in tag.split(['-', '_']).enumerate() {
+        let alphabetic = subtag.chars().all(|c| c.is_ascii_alphabetic());
+        let valid = if i == 0 {
+            alphabetic && (2..=8).contains(&subtag.len())
+        } else {
+            subtag.chars().all(|c| c.is_ascii_alphanumeric()) && (1..=8).contains(&subtag.len())
+        };
+        if !valid {
+            return None;
+        }
+        subtags.push(match subtag.len() {
+            2 if i > 0 && alphabetic => subtag.to_ascii_uppercase(),
+            4 if i > 0 && alphabetic => {
+                subtag[..1].to_ascii_uppercase() + &subtag[1..].to_ascii_lowercase()
+            }
+            _ => subtag.to_ascii_lowercase(),
+        });
+    }
+    Some(subtags.join("-"))
+}
+
+/// Adds the multipart `file` part, in SRT or WebVTT, as subtitles in the
+/// `language` part's language, named after the `name` part if given. They
+/// are converted to WebVTT and listed in the master playlist right away, or
+/// once the video is processed. Only admins may add subtitles.
+pub async fn upload_subtitle(
+    req: HttpRequest,
+    video_id: web::Path<Uuid>,
+    mut payload: Multipart,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+) -> Result<HttpResponse, Error> {
+    require_admin(&req, &config)?;
+    let video_id = *video_id;
+
+    let mut file = None;
+    let mut language = None;
+    let mut name = None;
+    while let Some(mut field) = payload.try_next().await? {
+        let mut data = web::BytesMut::new();
+        while let Some(chunk) = field.try_next().await? {
+            if data.len() + chunk.len() > MAX_SUBTITLE_SIZE {
+                return Err(actix_web::error::ErrorPayloadTooLarge(format!(
+                    "Subtitle files can be at most {} MB",
+                    MAX_SUBTITLE_SIZE / 1024 / 1024
+                )));
+            }
+            data.extend_from_slice(&chunk);
+        }
+        match field.name() {
+            Some("file") => file = Some(data),
+            Some("language") => language = Some(String::from_utf8_lossy(&data).into_owned()),
+            Some("name") => name = Some(String::from_utf8_lossy(&data).trim().to_string()),
+            _ => {}
+        }
+    }
+    let file = file.ok_or_else(|| actix_web::error::ErrorBadRequest("No file provided"))?;
+    let language = valid_language(language.as_deref())?;
+
+    // Not taken before the body is read, so slow uploads don't hold it
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    let (duration, existing) = subtitle_target(conn, video_id).await?;
+    let name = track_name(name, &language, &existing)?;
+
+    let id = Uuid::new_v4();
+    let subtitle = Subtitle {
+        id,
+        video_id,
+        language: Some(language),
+        name,
+        file_path: subtitles::upload_file_path(id),
+        source: "upload".to_string(),
+        is_default: false,
+        forced: false,
+        created_at: Utc::now().naive_utc(),
+    };
+    let subtitle = attach_subtitle(conn, subtitle, &file, duration, existing, &config.ffmpeg)
+        .await?
+        .ok_or_else(|| {
+            validation_error(
+                "file".to_string(),
+                "File must be in SRT or WebVTT format".to_string(),
+                ErrorCode::ValidationFailed,
+            )
+        })?;
+
+    Ok(
+        HttpResponse::Created().json(json!(ResponseType::<SubtitleResource> {
+            data: Some(subtitle_resource(subtitle, &base_url(&req))),
+            error: None
+        })),
+    )
+}
+
+/// Records captions ordered from one of the configured vendors, so those it
+/// returns to `/captions/callback/{provider}` are attached to the video.
+/// Admins only: whoever records a reference first decides which video the
+/// vendor's captions for it go to.
+pub async fn create_caption_request(
+    req: HttpRequest,
+    video_id: web::Path<Uuid>,
+    body: web::Json<CreateCaptionRequest>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+) -> Result<HttpResponse, Error> {
+    use crate::db::schema::{caption_requests, videos};
+
+    require_admin(&req, &config)?;
+    let body = body.into_inner();
+    if config.captions.provider(&body.provider).is_none() {
+        return Err(validation_error(
+            "provider".to_string(),
+            format!("Unknown caption provider {}", body.provider),
+            ErrorCode::ValidationFailed,
+        ));
+    }
+    let language = valid_language(Some(&body.language))?;
+    let name = body
+        .name
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty());
+    if name
+        .as_ref()
+        .is_some_and(|name| name.chars().count() > MAX_NAME_LEN)
+    {
+        return Err(validation_error(
+            "name".to_string(),
+            format!("Name must be at most {} characters", MAX_NAME_LEN),
+            ErrorCode::ValidationFailed,
+        ));
+    }
+    let reference = match body.reference {
+        Some(reference) => reference.trim().to_string(),
+        None => Uuid::new_v4().simple().to_string(),
+    };
+    if reference.is_empty() || reference.len() > MAX_REFERENCE_LEN {
+        return Err(validation_error(
+            "reference".to_string(),
+            format!("Reference must be 1 to {} bytes", MAX_REFERENCE_LEN),
+            ErrorCode::ValidationFailed,
+        ));
+    }
+
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    let video_id = videos::table
+        .find(*video_id)
+        .filter(videos::status.ne("removed"))
+        .select(videos::id)
+        .first::<Uuid>(conn)
+        .await
+        .optional()
+        .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Video not found"))?;
+
+    let now = Utc::now().naive_utc();
+    let request = CaptionRequest {
+        id: Uuid::new_v4(),
+        video_id,
+        provider: body.provider,
+        reference,
+        language,
+        name,
+        state: "pending".to_string(),
+        subtitle_id: None,
+        error: None,
+        created_at: now,
+        updated_at: now,
+    };
+    match diesel::insert_into(caption_requests::table)
+        .values(&request)
+        .execute(conn)
+        .await
+    {
+        Ok(_) => {}
+        Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+            return Err(actix_web::error::ErrorConflict(
+                "The provider already has a caption request with this reference",
+            ));
+        }
+        Err(e) => {
+            log::error!("Error inserting caption request: {}", e);
+            return Err(actix_web::error::ErrorInternalServerError("Database error"));
+        }
+    }
+
+    Ok(
+        HttpResponse::Created().json(json!(ResponseType::<CaptionRequestResource> {
+            data: Some(caption_request_resource(request, &base_url(&req))),
+            error: None
+        })),
+    )
+}
+
+/// The video's caption requests, oldest first. Admins only, as they show
+/// the vendors' references.
+pub async fn list_caption_requests(
+    req: HttpRequest,
+    video_id: web::Path<Uuid>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+) -> Result<HttpResponse, Error> {
+    use crate::db::schema::caption_requests;
+
+    require_admin(&req, &config)?;
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    let requests = caption_requests::table
+        .filter(caption_requests::video_id.eq(*video_id))
+        .order(caption_requests::created_at.asc())
+        .load::<CaptionRequest>(conn)
+        .await
+        .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+    let base_url = base_url(&req);
+    Ok(
+        HttpResponse::Ok().json(json!(ResponseType::<Vec<CaptionRequestResource>> {
+            data: Some(
+                requests
+                    .into_iter()
+                    .map(|request| caption_request_resource(request, &base_url))
+                    .collect()
+            ),
+            error: None
+        })),
+    )
+}
+
+/// Attaches the captions a vendor returns for one of its caption requests,
+/// found by its reference, or records why there are none. Callbacks for
+/// requests already completed change nothing, so vendors may repeat them.
+async fn caption_callback(
+    req: HttpRequest,
+    provider: web::Path<String>,
+    body: web::Bytes,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+) -> Result<HttpResponse, Error> {
+    use crate::db::schema::caption_requests;
+
+    let secret = &config
+        .captions
+        .provider(&provider)
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Unknown caption provider"))?
+        .secret;
+    let signature = req
+        .headers()
+        .get(SIGNATURE_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    if !webhooks::verify(secret, &body, signature) {
+        return Err(actix_web::error::ErrorUnauthorized("Invalid signature"));
+    }
+    let callback = serde_json::from_slice::<CaptionCallback>(&body)
+        .map_err(|e| actix_web::error::ErrorBadRequest(format!("Invalid callback: {}", e)))?;
+
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    let request = caption_requests::table
+        .filter(caption_requests::provider.eq(provider.as_str()))
+        .filter(caption_requests::reference.eq(&callback.reference))
+        .first::<CaptionRequest>(conn)
+        .await
+        .optional()
+        .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Caption request not found"))?;
+
+    let request = if request.state == "completed" {
+        request
+    } else {
+        let attached = match callback.captions {
+            Some(captions) => attach_returned(conn, &request, captions.as_bytes(), &config).await?,
+            None => Err(callback
+                .error
+                .unwrap_or_else(|| "The provider returned no captions".to_string())),
+        };
+        if let Err(e) = &attached {
+            log::info!(
+                "Caption request {} of video {} failed: {}",
+                request.id,
+                request.video_id,
+                e
+            );
+        }
+        let (state, subtitle_id, error) = match attached {
+            Ok(subtitle) => ("completed", Some(subtitle.id), None),
+            Err(e) => ("failed", None, Some(e)),
+        };
+        diesel::update(caption_requests::table.find(request.id))
+            .set((
+                caption_requests::state.eq(state),
+                caption_requests::subtitle_id.eq(subtitle_id),
+                caption_requests::error.eq(error),
+                caption_requests::updated_at.eq(Utc::now().naive_utc()),
+            ))
+            .get_result::<CaptionRequest>(conn)
+            .await
+            .map_err(|e| {
+                log::error!("Error updating caption request {}: {}", request.id, e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?
+    };
+
+    Ok(
+        HttpResponse::Ok().json(json!(ResponseType::<CaptionRequestResource> {
+            data: Some(caption_request_resource(request, &base_url(&req))),
+            error: None
+        })),
+    )
+}
+
+/// Attaches captions returned for `request` as a subtitle of its video. The
+/// inner error, recorded on the request, is why they couldn't be; server
+/// problems are left for the vendor to retry.
+async fn attach_returned(
+    conn: &mut AsyncPgConnection,
+    request: &CaptionRequest,
+    captions: &[u8],
+    config: &AppConfig,
+) -> Result<Result<Subtitle, String>, Error> {
+    if captions.len() > MAX_SUBTITLE_SIZE {
+        return Ok(Err(format!(
+            "Captions can be at most {} MB",
+            MAX_SUBTITLE_SIZE / 1024 / 1024
+        )));
+    }
+    let rejected = |e: Error| {
+        let status = e.as_response_error().status_code();
+        if status.is_server_error() {
+            Err(e)
+        } else {
+            Ok(Err(e.to_string()))
+        }
+    };
+    let (duration, existing) = match subtitle_target(conn, request.video_id).await {
+        Ok(target) => target,
+        Err(e) => return rejected(e),
+    };
+    let name = match track_name(request.name.clone(), &request.language, &existing) {
+        Ok(name) => name,
+        Err(e) => return rejected(e),
+    };
+
+    let id = Uuid::new_v4();
+    let subtitle = Subtitle {
+        id,
+        video_id: request.video_id,
+        language: Some(request.language.clone()),
+        name,
+        file_path: subtitles::upload_file_path(id),
+        source: "provider".to_string(),
+        is_default: false,
+        forced: false,
+        created_at: Utc::now().naive_utc(),
+    };
+    Ok(
+        attach_subtitle(conn, subtitle, captions, duration, existing, &config.ffmpeg)
+            .await?
+            .ok_or_else(|| "Captions must be in SRT or WebVTT format".to_string()),
+    )
+}
+
+/// The duration and subtitles of a video that has room for another
+async fn subtitle_target(
+    conn: &mut AsyncPgConnection,
+    video_id: Uuid,
+) -> Result<(Option<f64>, Vec<Subtitle>), Error> {
+    use crate::db::schema::videos;
+
+    let duration = videos::table
+        .find(video_id)
+        .filter(videos::status.ne("removed"))
+        .select(videos::duration)
+        .first::<Option<f64>>(conn)
+        .await
+        .optional()
+        .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Video not found"))?;
+    let existing = subtitles::load(conn, video_id)
+        .await
+        .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?;
+    if existing.len() >= MAX_SUBTITLES {
+        return Err(actix_web::error::ErrorConflict(format!(
+            "A video can have at most {} subtitles",
+            MAX_SUBTITLES
+        )));
+    }
+    Ok((duration, existing))
+}
+
+fn valid_language(language: Option<&str>) -> Result<String, Error> {
+    language.and_then(normalize_language).ok_or_else(|| {
+        validation_error(
+            "language".to_string(),
+            "Language must be a BCP 47 tag such as en or pt-BR".to_string(),
+            ErrorCode::ValidationFailed,
+        )
+    })
+}
+
+/// Name of a new subtitle track: `name`, or its language, made unique among
+/// the `existing` ones as players tell renditions apart by name
+fn track_name(
+    name: Option<String>,
+    language: &str,
+    existing: &[Subtitle],
+) -> Result<String, Error> {
+    let name = name
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| language.to_string());
+    if name.chars().count() > MAX_NAME_LEN {
+        return Err(validation_error(
+            "name".to_string(),
+            format!("Name must be at most {} characters", MAX_NAME_LEN),
+            ErrorCode::ValidationFailed,
+        ));
+    }
+    if existing.iter().any(|subtitle| subtitle.name == name) {
+        return Ok(format!("{} ({})", name, existing.len() + 1));
+    }
+    Ok(name)
+}
+
+/// Converts `file` to WebVTT and records it as `subtitle`, one more than
+/// the `existing` subtitles of its video. It is listed in the master
+/// playlist right away, or by processing once the video is packaged. None
+/// if `file` isn't SRT or WebVTT.
+async fn attach_subtitle(
+    conn: &mut AsyncPgConnection,
+    subtitle: Subtitle,
+    file: &[u8],
+    duration: Option<f64>,
+    mut existing: Vec<Subtitle>,
+    ffmpeg: &FfmpegConfig,
+) -> Result<Option<Subtitle>, Error> {
+    use crate::db::schema::subtitles as subtitles_table;
+
+    let (id, video_id) = (subtitle.id, subtitle.video_id);
+    // ffmpeg runs in the output directory, so paths must be absolute
+    let stored = async {
+        let video_dir = video_processor::get_video_dir(video_id);
+        fs::create_dir_all(video_dir.join(subtitles::UPLOADS_DIR)).await?;
+        let video_dir = fs::canonicalize(video_dir).await?;
+        let upload = video_dir
+            .join(subtitles::UPLOADS_DIR)
+            .join(format!("{}.upload", id));
+        fs::write(&upload, file).await?;
+        Ok::<_, std::io::Error>((video_dir, upload))
+    };
+    let (video_dir, upload) = stored.await.map_err(|e| {
+        log::error!("Failed to store subtitle upload: {}", e);
+        actix_web::error::ErrorInternalServerError("Storage error")
+    })?;
+    let converted = subtitles::convert_upload(&upload, &video_dir, id, ffmpeg).await;
+    let _ = fs::remove_file(&upload).await;
+    if let Err(e) = converted {
+        log::info!("Rejected subtitles for video {}: {}", video_id, e);
+        return Ok(None);
+    }
+
+    if let Err(e) = diesel::insert_into(subtitles_table::table)
+        .values(&subtitle)
+        .execute(conn)
+        .await
+    {
+        log::error!("Error inserting subtitle: {}", e);
+        let vtt = video_dir
+            .join(subtitles::UPLOADS_DIR)
+            .join(format!("{}.vtt", id));
+        let _ = fs::remove_file(vtt).await;
+        return Err(actix_web::error::ErrorInternalServerError("Database error"));
+    }
+
+    // Processing publishes them with the package otherwise
+    let hls_dir = video_dir.join("hls");
+    if fs::try_exists(hls_dir.join("master.m3u8"))
+        .await
+        .unwrap_or(false)
+    {
+        existing.push(subtitle.clone());
+        let published = async {
+            dedup::unshare_package(&video_dir).await?;
+            subtitles::publish_uploaded(
+                &video_dir,
+                std::slice::from_ref(&subtitle),
+                duration.unwrap_or_default(),
+            )
+            .await?;
+            subtitles::rebuild_master(&hls_dir, &existing).await
+        };
+        if let Err(e) = published.await {
+            log::error!("Failed to publish subtitles of video {}: {}", video_id, e);
+            let _ = diesel::delete(subtitles_table::table.find(id))
+                .execute(conn)
+                .await;
+            return Err(actix_web::error::ErrorInternalServerError("Storage error"));
+        }
+    }
+    Ok(Some(subtitle))
+}
+
+fn caption_request_resource(request: CaptionRequest, base_url: &str) -> CaptionRequestResource {
+    CaptionRequestResource {
+        callback_url: format!("{}/api/v1/captions/callback/{}", base_url, request.provider),
+        id: request.id,
+        video_id: request.video_id,
+        provider: request.provider,
+        reference: request.reference,
+        language: request.language,
+        name: request.name,
+        state: request.state,
+        subtitle_id: request.subtitle_id,
+        error: request.error,
+        created_at: request.created_at.and_utc(),
+        updated_at: request.updated_at.and_utc(),
+    }
+}
+
+fn base_url(req: &HttpRequest) -> String {
+    format!(
+        "{}://{}",
+        req.connection_info().scheme(),
+        req.connection_info().host()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn language_tags_get_their_conventional_case() {
+        for (tag, normalized) in [
+            ("EN", "en"),
+            ("pt-br", "pt-BR"),
+            ("ZH_hant_tw", "zh-Hant-TW"),
+            (" sr-LATN-rs ", "sr-Latn-RS"),
+            ("es-419", "es-419"),
+            ("de-CH-1996", "de-CH-1996"),
+            ("en-x-Custom", "en-x-custom"),
+        ] {
+            assert_eq!(
+                normalize_language(tag).as_deref(),
+                Some(normalized),
+                "{}",
+                tag
+            );
+        }
+    }
+
+    #[test]
+    fn malformed_language_tags_are_rejected() {
+        let long = format!("en-{}", "a".repeat(MAX_LANGUAGE_LEN));
+        for tag in [
+            "",
+            "e",
+            "1en",
+            "en US",
+            "en--US",
+            "en-",
+            "toolonglang",
+            &long,
+        ] {
+            assert_eq!(normalize_language(tag), None, "{}", tag);
+        }
+    }
+}
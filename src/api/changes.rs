@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+
+use crate::api::dto::{ChangeResponse, ChangesPage, VideoResponse};
+use crate::api::shared::{validation_error, ErrorCode, ResponseType};
+use crate::db::models::{Video, VideoChange};
+use crate::db::DbPool;
+use actix_web::{web, Error, HttpResponse};
+use diesel::dsl::sql;
+use diesel::sql_types::BigInt;
+use diesel::{BoolExpressionMethods, ExpressionMethods, QueryDsl};
+use diesel_async::RunQueryDsl;
+use serde::Deserialize;
+use serde_json::json;
+use uuid::Uuid;
+
+const DEFAULT_LIMIT: i64 = 100;
+const MAX_LIMIT: i64 = 1000;
+
+#[derive(Deserialize)]
+pub struct ChangesQuery {
+    /// `next_cursor` of the previous call; unset starts from the beginning
+    since: Option<String>,
+    /// Most outbox entries read, 1 to 1000
+    limit: Option<i64>,
+}
+
+/// Position of a change in the feed: its transaction, then its id in it
+fn cursor(change: &VideoChange) -> String {
+    format!("{}-{}", change.xid, change.id)
+}
+
+fn parse_cursor(cursor: &str) -> Option<(i64, i64)> {
+    let (xid, id) = cursor.split_once('-')?;
+    Some((xid.parse().ok()?, id.parse().ok()?))
+}
+
+/// Video records created, updated or deleted after the `since` cursor, one
+/// entry per video in the order of their latest change. Clients call again
+/// with `next_cursor` until `has_more` is false, and keep the cursor for the
+/// next sync.
+///
+/// Changes come in the order of the transactions that made them, and only
+/// from transactions older than any still running. A change committed late
+/// therefore can't land behind a cursor already handed out; it only shows
+/// up once every transaction that started before it has finished.
+pub async fn list_changes(
+    query: web::Query<ChangesQuery>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, Error> {
+    use crate::db::schema::{video_changes, videos};
+
+    let since = match query.since.as_deref() {
+        None => (0, 0),
+        Some(cursor) => parse_cursor(cursor).ok_or_else(|| {
+            validation_error(
+                "since".to_string(),
+                "Not a cursor from this endpoint".to_string(),
+                ErrorCode::ValidationFailed,
+            )
+        })?,
+    };
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+    if !(1..=MAX_LIMIT).contains(&limit) {
+        return Err(validation_error(
+            "limit".to_string(),
+            format!("Limit must be from 1 to {}", MAX_LIMIT),
+            ErrorCode::ValidationFailed,
+        ));
+    }
+
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    // Transactions below the snapshot's xmin have all committed or aborted
+    let xmin = sql::<BigInt>("pg_snapshot_xmin(pg_current_snapshot())::TEXT::BIGINT");
+    let mut changes = video_changes::table
+        .filter(video_changes::xid.lt(xmin))
+        .filter(
+            video_changes::xid.gt(since.0).or(video_changes::xid
+                .eq(since.0)
+                .and(video_changes::id.gt(since.1))),
+        )
+        .order((video_changes::xid.asc(), video_changes::id.asc()))
+        .limit(limit + 1)
+        .load::<VideoChange>(conn)
+        .await
+        .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?;
+    let has_more = changes.len() as i64 > limit;
+    changes.truncate(limit as usize);
+    let next_cursor = changes.last().map(cursor).or(query.since.clone());
+
+    // Only the latest change of each video matters, except that one created
+    // within the page is still new to the client
+    let mut latest: Vec<VideoChange> = Vec::new();
+    for mut change in changes {
+        if let Some(i) = latest.iter().position(|c| c.video_id == change.video_id) {
+            let earlier = latest.remove(i);
+            if earlier.change == "created" && change.change == "updated" {
+                change.change = earlier.change;
+            }
+        }
+        latest.push(change);
+    }
+
+    let ids: Vec<Uuid> = latest
+        .iter()
+        .filter(|change| change.change != "deleted")
+        .map(|change| change.video_id)
+        .collect();
+    let mut current: HashMap<Uuid, Video> = videos::table
+        .filter(videos::id.eq_any(ids))
+        .load::<Video>(conn)
+        .await
+        .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?
+        .into_iter()
+        .map(|video| (video.id, video))
+        .collect();
+
+    let changes: Vec<ChangeResponse> = latest
+        .into_iter()
+        .map(|change| {
+            let video = current.remove(&change.video_id);
+            ChangeResponse {
+                cursor: cursor(&change),
+                // Rows deleted by hand still have a deleted entry coming
+                change: if video.is_some() {
+                    change.change
+                } else {
+                    "deleted".to_string()
+                },
+                video_id: change.video_id,
+                video: video.map(VideoResponse::from),
+            }
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(json!(ResponseType::<ChangesPage> {
+        data: Some(ChangesPage {
+            changes,
+            next_cursor,
+            has_more,
+        }),
+        error: None
+    })))
+}
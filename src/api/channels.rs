@@ -0,0 +1,427 @@
+use std::collections::HashMap;
+
+use crate::api::shared::{validation_error, ErrorCode, ResponseType};
+use crate::db::models::{Channel, ChannelVideo};
+use crate::db::DbPool;
+use crate::services::{channels, playlist, video_processor};
+use actix_web::http::header;
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use chrono::Utc;
+use diesel::{BoolExpressionMethods, ExpressionMethods, OptionalExtension, QueryDsl};
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+use serde_json::json;
+use uuid::Uuid;
+use vid_storage_models::{ChannelRequest, ChannelResource};
+
+const MAX_NAME_LEN: usize = 255;
+const MAX_VIDEOS: usize = 500;
+const PLAYLIST_CONTENT_TYPE: &str = "application/vnd.apple.mpegurl";
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/channels")
+            .route("", web::post().to(create_channel))
+            .route("", web::get().to(list_channels))
+            .route("/{id}", web::get().to(get_channel))
+            .route("/{id}", web::put().to(update_channel))
+            .route("/{id}", web::delete().to(delete_channel))
+            .route("/{id}/master.m3u8", web::get().to(serve_master))
+            .route("/{id}/{quality}.m3u8", web::get().to(serve_quality)),
+    );
+}
+
+fn base_url(req: &HttpRequest) -> String {
+    format!(
+        "{}://{}",
+        req.connection_info().scheme(),
+        req.connection_info().host()
+    )
+}
+
+fn channel_resource(channel: Channel, video_ids: Vec<Uuid>, base_url: &str) -> ChannelResource {
+    ChannelResource {
+        stream_url: format!("{}/api/v1/channels/{}/master.m3u8", base_url, channel.id),
+        id: channel.id,
+        name: channel.name,
+        video_ids,
+        starts_at: channel.starts_at.and_utc(),
+        created_at: channel.created_at.and_utc(),
+        updated_at: channel.updated_at.and_utc(),
+    }
+}
+
+fn db_error(e: diesel::result::Error) -> Error {
+    log::error!("Channel query failed: {}", e);
+    actix_web::error::ErrorInternalServerError("Database error")
+}
+
+/// Checks the name and that every video can be stitched into a channel:
+/// processed, public, and with at most one audio track, as separate audio
+/// renditions aren't carried over
+async fn validate(conn: &mut AsyncPgConnection, body: &ChannelRequest) -> Result<(), Error> {
+    use crate::db::schema::{audio_tracks, videos};
+
+    let name = body.name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LEN {
+        return Err(validation_error(
+            "name".to_string(),
+            format!("Name must be 1 to {} characters", MAX_NAME_LEN),
+            ErrorCode::ValidationFailed,
+        ));
+    }
+    if body.video_ids.is_empty() || body.video_ids.len() > MAX_VIDEOS {
+        return Err(validation_error(
+            "video_ids".to_string(),
+            format!("A channel has 1 to {} videos", MAX_VIDEOS),
+            ErrorCode::ValidationFailed,
+        ));
+    }
+
+    let playable = videos::table
+        .filter(videos::id.eq_any(&body.video_ids))
+        .filter(videos::status.eq("processed"))
+        .filter(videos::restricted.eq(false))
+        .select(videos::id)
+        .load::<Uuid>(conn)
+        .await
+        .map_err(db_error)?;
+    if let Some(id) = body.video_ids.iter().find(|id| !playable.contains(id)) {
+        return Err(validation_error(
+            "video_ids".to_string(),
+            format!("Video {} is not a processed, unrestricted video", id),
+            ErrorCode::ValidationFailed,
+        ));
+    }
+    let multi_audio = audio_tracks::table
+        .filter(audio_tracks::video_id.eq_any(&body.video_ids))
+        .filter(audio_tracks::position.gt(0))
+        .select(audio_tracks::video_id)
+        .first::<Uuid>(conn)
+        .await
+        .optional()
+        .map_err(db_error)?;
+    if let Some(id) = multi_audio {
+        return Err(validation_error(
+            "video_ids".to_string(),
+            format!("Video {} has several audio tracks", id),
+            ErrorCode::ValidationFailed,
+        ));
+    }
+    Ok(())
+}
+
+/// Replaces the channel's videos and restarts its schedule
+async fn save(
+    conn: &mut AsyncPgConnection,
+    channel: &Channel,
+    video_ids: &[Uuid],
+) -> Result<(), diesel::result::Error> {
+    use crate::db::schema::{channel_videos, channels};
+
+    let items = video_ids
+        .iter()
+        .enumerate()
+        .map(|(position, &video_id)| ChannelVideo {
+            channel_id: channel.id,
+            position: position as i32,
+            video_id,
+        })
+        .collect::<Vec<_>>();
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        async move {
+            diesel::insert_into(channels::table)
+                .values(channel)
+                .on_conflict(channels::id)
+                .do_update()
+                .set((
+                    channels::name.eq(&channel.name),
+                    channels::starts_at.eq(channel.starts_at),
+                    channels::updated_at.eq(channel.updated_at),
+                ))
+                .execute(conn)
+                .await?;
+            diesel::delete(channel_videos::table.filter(channel_videos::channel_id.eq(channel.id)))
+                .execute(conn)
+                .await?;
+            diesel::insert_into(channel_videos::table)
+                .values(&items)
+                .execute(conn)
+                .await?;
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+async fn load_video_ids(
+    conn: &mut AsyncPgConnection,
+    channel_id: Uuid,
+) -> Result<Vec<Uuid>, diesel::result::Error> {
+    use crate::db::schema::channel_videos;
+
+    channel_videos::table
+        .filter(channel_videos::channel_id.eq(channel_id))
+        .order(channel_videos::position.asc())
+        .select(channel_videos::video_id)
+        .load::<Uuid>(conn)
+        .await
+}
+
+async fn load_channel(conn: &mut AsyncPgConnection, id: Uuid) -> Result<Channel, Error> {
+    use crate::db::schema::channels;
+
+    channels::table
+        .find(id)
+        .first::<Channel>(conn)
+        .await
+        .optional()
+        .map_err(db_error)?
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Channel not found"))
+}
+
+async fn create_channel(
+    req: HttpRequest,
+    body: web::Json<ChannelRequest>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, Error> {
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    validate(conn, &body).await?;
+
+    let now = Utc::now().naive_utc();
+    let channel = Channel {
+        id: Uuid::new_v4(),
+        name: body.name.trim().to_string(),
+        starts_at: now,
+        created_at: now,
+        updated_at: now,
+    };
+    save(conn, &channel, &body.video_ids)
+        .await
+        .map_err(db_error)?;
+
+    Ok(
+        HttpResponse::Created().json(json!(ResponseType::<ChannelResource> {
+            data: Some(channel_resource(
+                channel,
+                body.into_inner().video_ids,
+                &base_url(&req)
+            )),
+            error: None
+        })),
+    )
+}
+
+async fn list_channels(req: HttpRequest, pool: web::Data<DbPool>) -> Result<HttpResponse, Error> {
+    use crate::db::schema::{channel_videos, channels};
+
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    let stored = channels::table
+        .order(channels::created_at.asc())
+        .load::<Channel>(conn)
+        .await
+        .map_err(db_error)?;
+    let mut video_ids: HashMap<Uuid, Vec<Uuid>> = HashMap::new();
+    for item in channel_videos::table
+        .order(channel_videos::position.asc())
+        .load::<ChannelVideo>(conn)
+        .await
+        .map_err(db_error)?
+    {
+        video_ids
+            .entry(item.channel_id)
+            .or_default()
+            .push(item.video_id);
+    }
+
+    let base_url = base_url(&req);
+    Ok(
+        HttpResponse::Ok().json(json!(ResponseType::<Vec<ChannelResource>> {
+            data: Some(
+                stored
+                    .into_iter()
+                    .map(|channel| {
+                        let ids = video_ids.remove(&channel.id).unwrap_or_default();
+                        channel_resource(channel, ids, &base_url)
+                    })
+                    .collect()
+            ),
+            error: None
+        })),
+    )
+}
+
+async fn get_channel(
+    req: HttpRequest,
+    id: web::Path<Uuid>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, Error> {
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    let channel = load_channel(conn, *id).await?;
+    let video_ids = load_video_ids(conn, channel.id).await.map_err(db_error)?;
+
+    Ok(
+        HttpResponse::Ok().json(json!(ResponseType::<ChannelResource> {
+            data: Some(channel_resource(channel, video_ids, &base_url(&req))),
+            error: None
+        })),
+    )
+}
+
+/// Renames the channel and replaces its videos. The schedule restarts from
+/// the first video, so players need to reload the stream.
+async fn update_channel(
+    req: HttpRequest,
+    id: web::Path<Uuid>,
+    body: web::Json<ChannelRequest>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, Error> {
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    let mut channel = load_channel(conn, *id).await?;
+    validate(conn, &body).await?;
+
+    let now = Utc::now().naive_utc();
+    channel.name = body.name.trim().to_string();
+    channel.starts_at = now;
+    channel.updated_at = now;
+    save(conn, &channel, &body.video_ids)
+        .await
+        .map_err(db_error)?;
+
+    Ok(
+        HttpResponse::Ok().json(json!(ResponseType::<ChannelResource> {
+            data: Some(channel_resource(
+                channel,
+                body.into_inner().video_ids,
+                &base_url(&req)
+            )),
+            error: None
+        })),
+    )
+}
+
+async fn delete_channel(
+    id: web::Path<Uuid>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, Error> {
+    use crate::db::schema::{channel_videos, channels};
+
+    let id = *id;
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    let deleted = conn
+        .transaction::<_, diesel::result::Error, _>(|conn| {
+            async move {
+                diesel::delete(channel_videos::table.filter(channel_videos::channel_id.eq(id)))
+                    .execute(conn)
+                    .await?;
+                diesel::delete(channels::table.find(id)).execute(conn).await
+            }
+            .scope_boxed()
+        })
+        .await
+        .map_err(db_error)?;
+    if deleted == 0 {
+        return Err(actix_web::error::ErrorNotFound("Channel not found"));
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// The channel and the videos of its schedule that can still be played.
+/// Videos removed, restricted or scheduled for later since drop out.
+async fn load_schedule(pool: &DbPool, id: Uuid) -> Result<(Channel, Vec<Uuid>), Error> {
+    use crate::db::schema::{channel_videos, videos};
+
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    let channel = load_channel(conn, id).await?;
+    let video_ids = channel_videos::table
+        .inner_join(videos::table)
+        .filter(channel_videos::channel_id.eq(id))
+        .filter(videos::status.eq("processed"))
+        .filter(videos::restricted.eq(false))
+        .filter(
+            videos::publish_at
+                .is_null()
+                .or(videos::publish_at.le(Utc::now().naive_utc())),
+        )
+        .order(channel_videos::position.asc())
+        .select(channel_videos::video_id)
+        .load::<Uuid>(conn)
+        .await
+        .map_err(db_error)?;
+    if video_ids.is_empty() {
+        return Err(actix_web::error::ErrorNotFound(
+            "The channel has no playable videos",
+        ));
+    }
+    Ok((channel, video_ids))
+}
+
+/// Variants offered in every video of the channel
+async fn serve_master(id: web::Path<Uuid>, pool: web::Data<DbPool>) -> Result<HttpResponse, Error> {
+    let (_, video_ids) = load_schedule(&pool, *id).await?;
+
+    let mut variants = Vec::with_capacity(video_ids.len());
+    for video_id in video_ids {
+        let path = video_processor::get_video_dir(video_id)
+            .join("hls")
+            .join("master.m3u8");
+        let master = tokio::fs::read_to_string(path).await.map_err(|e| {
+            log::error!("Failed to read master playlist of {}: {}", video_id, e);
+            actix_web::error::ErrorInternalServerError("Storage error")
+        })?;
+        variants.push(playlist::parse_variants(&master));
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(PLAYLIST_CONTENT_TYPE)
+        .body(channels::master(&variants)))
+}
+
+/// The live window of one quality, rebuilt on every request from the time
+/// since the schedule started
+async fn serve_quality(
+    params: web::Path<(Uuid, String)>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, Error> {
+    let (id, quality) = params.into_inner();
+    if quality.is_empty() || quality.contains(['/', '\\', '.']) {
+        return Err(actix_web::error::ErrorNotFound("Quality not found"));
+    }
+    let (channel, video_ids) = load_schedule(&pool, id).await?;
+
+    let mut videos = Vec::with_capacity(video_ids.len());
+    for video_id in video_ids {
+        let path = video_processor::get_video_dir(video_id)
+            .join("hls")
+            .join(&quality)
+            .join("stream.m3u8");
+        let media = tokio::fs::read_to_string(path)
+            .await
+            .map_err(|_| actix_web::error::ErrorNotFound("Quality not found"))?;
+        let media = playlist::parse_media(&media).map_err(|e| {
+            log::error!("Invalid {} playlist of video {}: {}", quality, video_id, e);
+            actix_web::error::ErrorInternalServerError("Invalid playlist")
+        })?;
+        // Relative to the video's rendition, not the channel
+        let absolute = |uri: &str| format!("/uploads/{}/hls/{}/{}", video_id, quality, uri);
+        videos.push(
+            media
+                .segments
+                .into_iter()
+                .map(|(segment, _)| playlist::Segment {
+                    uri: absolute(&segment.uri),
+                    map: segment.map.as_deref().map(absolute),
+                    ..segment
+                })
+                .collect::<Vec<_>>(),
+        );
+    }
+
+    let window = channels::live_window(&videos, channel.starts_at.and_utc(), Utc::now())
+        .ok_or_else(|| actix_web::error::ErrorNotFound("The channel has no playable videos"))?;
+    Ok(HttpResponse::Ok()
+        .content_type(PLAYLIST_CONTENT_TYPE)
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .body(playlist::write_media(&window)))
+}
@@ -0,0 +1,127 @@
+use std::sync::Arc;
+
+use crate::api::dto::VideoResponse;
+use crate::api::grants;
+use crate::api::moderation::is_admin;
+use crate::api::shared::{validation_error, ErrorCode};
+use crate::config::AppConfig;
+use crate::db::{models::Video, DbPool};
+use crate::services::blocking::BlockingPool;
+use crate::services::clips::{self, Clip};
+use crate::services::entitlement::Entitlements;
+use crate::services::events::ProgressEvents;
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl};
+use diesel_async::RunQueryDsl;
+use uuid::Uuid;
+use vid_storage_models::ClipRequest;
+
+/// Shortest clip that is worth packaging
+const MIN_CLIP_SECS: f64 = 1.0;
+
+/// Creates a video from part of a processed one. Cutting and processing
+/// continue in the background, as for imports. Callers other than admins
+/// need the same token as for playing the source.
+#[allow(clippy::too_many_arguments)]
+pub async fn create_clip(
+    req: HttpRequest,
+    video_id: web::Path<Uuid>,
+    body: web::Json<ClipRequest>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+    events: web::Data<ProgressEvents>,
+    blocking: web::Data<BlockingPool>,
+    entitlements: web::Data<Entitlements>,
+) -> Result<HttpResponse, Error> {
+    use crate::db::schema::videos;
+
+    if !is_admin(&req, &config) {
+        grants::authorize_playback(&req, *video_id, &pool, &entitlements).await?;
+    }
+    let body = body.into_inner();
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    let source = videos::table
+        .find(*video_id)
+        .filter(videos::status.ne("removed"))
+        .first::<Video>(conn)
+        .await
+        .optional()
+        .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Video not found"))?;
+    if source.status != "processed" {
+        return Err(actix_web::error::ErrorConflict(
+            "Clips can only be cut once the video is processed",
+        ));
+    }
+
+    let duration = source.duration.unwrap_or(0.0);
+    if !(0.0..duration).contains(&body.start) {
+        return Err(validation_error(
+            "start".to_string(),
+            format!("{} is outside the video (0 to {})", body.start, duration),
+            ErrorCode::ValidationFailed,
+        ));
+    }
+    if !(body.end <= duration && body.end - body.start >= MIN_CLIP_SECS) {
+        return Err(validation_error(
+            "end".to_string(),
+            format!(
+                "Must be at least {}s after start and at most {}",
+                MIN_CLIP_SECS, duration
+            ),
+            ErrorCode::ValidationFailed,
+        ));
+    }
+
+    // Clips keep the source's access rules, schedule and rights information
+    let video = Video {
+        id: Uuid::new_v4(),
+        title: body
+            .title
+            .unwrap_or_else(|| format!("{} (clip)", source.title)),
+        description: body.description,
+        duration: None,
+        status: "uploading".to_string(),
+        created_at: chrono::Utc::now().naive_utc(),
+        updated_at: chrono::Utc::now().naive_utc(),
+        status_reason: None,
+        upload_token: None,
+        checksum: None,
+        processing_profile: source.processing_profile,
+        encoding_profile: source.encoding_profile,
+        restricted: source.restricted,
+        embed_domains: source.embed_domains,
+        unlisted: source.unlisted,
+        error_code: None,
+        watermark_position: source.watermark_position,
+        license: source.license,
+        attribution: source.attribution,
+        source_url: None,
+        tags: source.tags,
+        publish_at: source.publish_at,
+        original_filename: None,
+        poster: None,
+        priority: source.priority,
+    };
+
+    diesel::insert_into(videos::table)
+        .values(&video)
+        .execute(conn)
+        .await
+        .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+    clips::spawn_clip(
+        video.id,
+        Clip {
+            source_id: source.id,
+            start: body.start,
+            end: body.end,
+        },
+        pool.clone(),
+        config.get_ref().clone(),
+        events,
+        blocking,
+    );
+
+    Ok(HttpResponse::Accepted().json(VideoResponse::from(video)))
+}
@@ -0,0 +1,324 @@
+use std::sync::Arc;
+
+use crate::api::shared::{validation_error, ErrorCode, ResponseType};
+use crate::api::videos::is_json;
+use crate::config::AppConfig;
+use crate::db::models::{Conversion, Video};
+use crate::db::DbPool;
+use crate::services::convert::{self, Target};
+use crate::services::{jobs, probe, video_processor};
+use actix_files::NamedFile;
+use actix_multipart::Multipart;
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::{guard, web, Error, HttpRequest, HttpResponse};
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use futures::TryStreamExt;
+use serde_json::json;
+use uuid::Uuid;
+use vid_storage_models::{ConversionResource, ConvertRequest};
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/convert")
+            .route(
+                "",
+                web::post()
+                    .guard(guard::fn_guard(is_json))
+                    .to(convert_video),
+            )
+            .route("", web::post().to(convert_upload))
+            .route("/{id}", web::get().to(conversion_status))
+            .route("/{id}/download", web::get().to(download_conversion)),
+    );
+}
+
+fn target_error((field, message): (&'static str, String)) -> Error {
+    validation_error(field.to_string(), message, ErrorCode::ValidationFailed)
+}
+
+/// Converts the original of an existing video
+async fn convert_video(
+    req: HttpRequest,
+    body: web::Json<ConvertRequest>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, Error> {
+    use crate::db::schema::videos;
+
+    let body = body.into_inner();
+    let target = Target::parse(
+        &body.container,
+        body.video_codec.as_deref(),
+        body.audio_codec.as_deref(),
+    )
+    .map_err(target_error)?;
+
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    // Only validated uploads are known to be media
+    videos::table
+        .find(body.video_id)
+        .filter(videos::status.eq_any(["processing", "processed"]))
+        .first::<Video>(conn)
+        .await
+        .optional()
+        .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Video not found"))?;
+
+    let conversion = start(Uuid::new_v4(), Some(body.video_id), target, conn).await?;
+    Ok(accepted(&req, conversion))
+}
+
+/// Converts a file sent as the multipart `video` field, with the target in
+/// the `container`, `video_codec` and `audio_codec` fields
+async fn convert_upload(
+    req: HttpRequest,
+    mut payload: Multipart,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+) -> Result<HttpResponse, Error> {
+    let c_id = Uuid::new_v4();
+    let dir = convert::conversion_dir(c_id);
+    tokio::fs::create_dir_all(&dir).await.map_err(|e| {
+        log::error!("Failed to create conversion directory: {}", e);
+        actix_web::error::ErrorInternalServerError("Storage error")
+    })?;
+
+    let result = match receive_upload(&mut payload, &dir, &config).await {
+        Ok(target) => validate_source(&dir, &config).await.map(|_| target),
+        Err(e) => Err(e),
+    };
+    let target = match result {
+        Ok(target) => target,
+        Err(e) => {
+            if let Err(e) = tokio::fs::remove_dir_all(&dir).await {
+                log::error!("Failed to remove conversion upload {}: {}", c_id, e);
+            }
+            return Err(e);
+        }
+    };
+
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    let conversion = start(c_id, None, target, conn).await?;
+    Ok(accepted(&req, conversion))
+}
+
+async fn receive_upload(
+    payload: &mut Multipart,
+    dir: &std::path::Path,
+    config: &AppConfig,
+) -> Result<Target, Error> {
+    let mut received = false;
+    let mut container = None;
+    let mut video_codec = None;
+    let mut audio_codec = None;
+
+    while let Some(mut field) = payload.try_next().await? {
+        let content_disposition = field
+            .content_disposition()
+            .ok_or_else(|| actix_web::error::ErrorBadRequest("No content disposition"))?;
+        let field_name = content_disposition
+            .get_name()
+            .ok_or_else(|| actix_web::error::ErrorBadRequest("No field name"))?
+            .to_owned();
+
+        if field_name == "video" {
+            let filename = video_processor::original_file_name(content_disposition.get_filename());
+            video_processor::write_body(
+                &mut field,
+                &dir.join(filename),
+                config.storage.max_file_size,
+                |_, _| {},
+            )
+            .await?;
+            received = true;
+            continue;
+        }
+
+        let mut value = String::new();
+        while let Some(chunk) = field.try_next().await? {
+            value.push_str(std::str::from_utf8(&chunk)?);
+        }
+        match field_name.as_str() {
+            "container" => container = Some(value),
+            "video_codec" => video_codec = Some(value),
+            "audio_codec" => audio_codec = Some(value),
+            _ => {}
+        }
+    }
+
+    if !received {
+        return Err(actix_web::error::ErrorBadRequest("No video file provided"));
+    }
+    let container = container.ok_or_else(|| {
+        validation_error(
+            "container".to_string(),
+            "A target container is required".to_string(),
+            ErrorCode::ValidationFailed,
+        )
+    })?;
+    Target::parse(&container, video_codec.as_deref(), audio_codec.as_deref()).map_err(target_error)
+}
+
+/// Probes the uploaded source, so files that aren't usable videos or are
+/// beyond the upload limits are turned down before any job runs on them
+async fn validate_source(dir: &std::path::Path, config: &AppConfig) -> Result<(), Error> {
+    let dir = tokio::fs::canonicalize(dir).await;
+    let path = match dir {
+        Ok(dir) => video_processor::find_original(&dir).await,
+        Err(e) => Err(e),
+    };
+    let path = path.map_err(|e| {
+        log::error!("Failed to find conversion upload: {}", e);
+        actix_web::error::ErrorInternalServerError("Storage error")
+    })?;
+    let info = probe::probe(&path, &config.ffmpeg).await.map_err(|e| {
+        log::warn!("Failed to probe conversion upload: {}", e);
+        validation_error(
+            "video".to_string(),
+            "File is not a readable media file".to_string(),
+            ErrorCode::InvalidMedia,
+        )
+    })?;
+    video_processor::validate_upload(&info, &config.validation).map_err(|(_, reason)| {
+        validation_error("video".to_string(), reason, ErrorCode::InvalidMedia)
+    })
+}
+
+async fn start(
+    c_id: Uuid,
+    video_id: Option<Uuid>,
+    target: Target,
+    conn: &mut AsyncPgConnection,
+) -> Result<Conversion, Error> {
+    use crate::db::schema::conversions;
+
+    tokio::fs::create_dir_all(convert::conversion_dir(c_id))
+        .await
+        .map_err(|e| {
+            log::error!("Failed to create conversion directory: {}", e);
+            actix_web::error::ErrorInternalServerError("Storage error")
+        })?;
+
+    let conversion = Conversion {
+        id: c_id,
+        video_id,
+        container: target.container,
+        video_codec: target.video_codec,
+        audio_codec: target.audio_codec,
+        created_at: chrono::Utc::now().naive_utc(),
+    };
+    diesel::insert_into(conversions::table)
+        .values(&conversion)
+        .execute(conn)
+        .await
+        .map_err(|e| {
+            log::error!("Error inserting conversion {}: {}", c_id, e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+    jobs::enqueue_conversion(c_id, conn).await.map_err(|e| {
+        log::error!("Error queueing conversion {}: {}", c_id, e);
+        actix_web::error::ErrorInternalServerError("Database error")
+    })?;
+
+    Ok(conversion)
+}
+
+fn accepted(req: &HttpRequest, conversion: Conversion) -> HttpResponse {
+    HttpResponse::Accepted().json(json!(ResponseType::<ConversionResource> {
+        data: Some(conversion_resource(
+            req,
+            conversion,
+            "queued".to_string(),
+            None
+        )),
+        error: None
+    }))
+}
+
+fn conversion_resource(
+    req: &HttpRequest,
+    conversion: Conversion,
+    state: String,
+    error: Option<String>,
+) -> ConversionResource {
+    let download_url = (state == "done").then(|| {
+        format!(
+            "{}://{}/api/v1/convert/{}/download",
+            req.connection_info().scheme(),
+            req.connection_info().host(),
+            conversion.id
+        )
+    });
+    ConversionResource {
+        id: conversion.id,
+        video_id: conversion.video_id,
+        container: conversion.container,
+        video_codec: conversion.video_codec,
+        audio_codec: conversion.audio_codec,
+        state,
+        error,
+        download_url,
+        created_at: conversion.created_at.and_utc(),
+    }
+}
+
+/// A conversion and the state and error of its job
+async fn load_conversion(
+    c_id: Uuid,
+    pool: &DbPool,
+) -> Result<(Conversion, String, Option<String>), Error> {
+    use crate::db::schema::{conversions, processing_jobs};
+
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    let conversion = conversions::table
+        .find(c_id)
+        .first::<Conversion>(conn)
+        .await
+        .optional()
+        .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Conversion not found"))?;
+    let (state, error) = processing_jobs::table
+        .filter(processing_jobs::conversion_id.eq(c_id))
+        .order(processing_jobs::created_at.desc())
+        .select((processing_jobs::state, processing_jobs::error))
+        .first::<(String, Option<String>)>(conn)
+        .await
+        .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+    Ok((conversion, state, error))
+}
+
+async fn conversion_status(
+    req: HttpRequest,
+    c_id: web::Path<Uuid>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, Error> {
+    let (conversion, state, error) = load_conversion(*c_id, &pool).await?;
+    Ok(
+        HttpResponse::Ok().json(json!(ResponseType::<ConversionResource> {
+            data: Some(conversion_resource(&req, conversion, state, error)),
+            error: None
+        })),
+    )
+}
+
+async fn download_conversion(
+    req: HttpRequest,
+    c_id: web::Path<Uuid>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, Error> {
+    let (conversion, state, _) = load_conversion(*c_id, &pool).await?;
+    if state != "done" {
+        return Err(actix_web::error::ErrorConflict("Conversion is not done"));
+    }
+
+    let filename = format!("{}.{}", conversion.id, conversion.container);
+    let file = NamedFile::open_async(convert::output_path(&conversion))
+        .await
+        .map_err(|_| actix_web::error::ErrorNotFound("Conversion output not found"))?
+        .set_content_disposition(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(filename)],
+        });
+    Ok(file.into_response(&req))
+}
@@ -0,0 +1,312 @@
+// src/api/dto.rs
+//! JSON shapes of the v1 API and conversions from the database models. The
+//! Diesel models are not serializable, so a schema change only reaches clients
+//! through a change here. v2 types live in `vid_storage_models`.
+use crate::db::models::{AudioTrack, Subtitle, Video, VideoQuality};
+use crate::services::{preview, storyboard, video_processor};
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use uuid::Uuid;
+use vid_storage_models::{
+    AudioTrackResource, ProcessingProfile, QualityResource, SubtitleResource, VideoResource,
+};
+
+/// v1 timestamps are serialized as naive UTC, e.g. `2026-10-16T12:00:00.123456`
+#[derive(Debug, Serialize)]
+pub struct VideoResponse {
+    pub id: Uuid,
+    pub title: String,
+    pub description: Option<String>,
+    pub duration: Option<f64>,
+    pub status: String,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub status_reason: Option<String>,
+    pub checksum: Option<String>,
+    pub processing_profile: Option<ProcessingProfile>,
+    pub encoding_profile: Option<String>,
+    pub restricted: bool,
+    pub embed_domains: Vec<String>,
+    pub unlisted: bool,
+    pub error_code: Option<String>,
+    pub watermark_position: Option<String>,
+    pub license: Option<String>,
+    pub attribution: Option<String>,
+    pub source_url: Option<String>,
+    pub tags: Vec<String>,
+    pub publish_at: Option<NaiveDateTime>,
+    pub original_filename: Option<String>,
+    pub priority: String,
+}
+
+/// Profiles are validated before they are stored, so this only drops ones
+/// written by hand
+fn processing_profile(value: Option<serde_json::Value>) -> Option<ProcessingProfile> {
+    value.and_then(|value| serde_json::from_value(value).ok())
+}
+
+impl From<Video> for VideoResponse {
+    fn from(video: Video) -> Self {
+        VideoResponse {
+            id: video.id,
+            title: video.title,
+            description: video.description,
+            duration: video.duration,
+            status: video.status,
+            created_at: video.created_at,
+            updated_at: video.updated_at,
+            status_reason: video.status_reason,
+            checksum: video.checksum,
+            processing_profile: processing_profile(video.processing_profile),
+            encoding_profile: video.encoding_profile,
+            restricted: video.restricted,
+            embed_domains: video.embed_domains,
+            unlisted: video.unlisted,
+            error_code: video.error_code,
+            watermark_position: video.watermark_position,
+            license: video.license,
+            attribution: video.attribution,
+            source_url: video.source_url,
+            tags: video.tags,
+            publish_at: video.publish_at,
+            original_filename: video.original_filename,
+            priority: video.priority,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct QualityResponse {
+    pub id: Uuid,
+    pub video_id: Uuid,
+    pub resolution: String,
+    pub label: String,
+    pub frame_rate: Option<f64>,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub bitrate: String,
+    pub file_path: String,
+    pub created_at: NaiveDateTime,
+    pub state: String,
+    pub progress: i32,
+    pub attempts: i32,
+}
+
+impl From<VideoQuality> for QualityResponse {
+    fn from(quality: VideoQuality) -> Self {
+        QualityResponse {
+            id: quality.id,
+            video_id: quality.video_id,
+            label: quality.label.unwrap_or_else(|| quality.resolution.clone()),
+            frame_rate: quality.frame_rate,
+            width: quality.width,
+            height: quality.height,
+            resolution: quality.resolution,
+            bitrate: quality.bitrate,
+            file_path: quality.file_path,
+            created_at: quality.created_at,
+            state: quality.state,
+            progress: quality.progress,
+            attempts: quality.attempts,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct VideoWithMeta {
+    #[serde(flatten)]
+    pub video: VideoResponse,
+    pub qualities: Vec<QualityResponse>,
+    pub audio_tracks: Vec<AudioTrackResource>,
+    pub subtitles: Vec<SubtitleResource>,
+    /// Percent of all renditions encoded while the video is processing
+    pub progress: Option<u8>,
+    pub thumbnail_url: String,
+    pub stream_url: String,
+    pub data_saver_url: Option<String>,
+    pub storyboard_url: Option<String>,
+    pub preview_url: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VideoWithThumbnail {
+    #[serde(flatten)]
+    pub video: VideoResponse,
+    pub thumbnail_url: String,
+}
+
+impl From<Video> for VideoResource {
+    fn from(video: Video) -> Self {
+        VideoResource {
+            id: video.id,
+            title: video.title,
+            description: video.description,
+            duration: video.duration,
+            status: video.status,
+            status_reason: video.status_reason,
+            checksum: video.checksum,
+            processing_profile: processing_profile(video.processing_profile),
+            encoding_profile: video.encoding_profile,
+            restricted: video.restricted,
+            embed_domains: video.embed_domains,
+            unlisted: video.unlisted,
+            error_code: video.error_code,
+            watermark_position: video.watermark_position,
+            license: video.license,
+            attribution: video.attribution,
+            source_url: video.source_url,
+            tags: video.tags,
+            publish_at: video.publish_at.map(|at| at.and_utc()),
+            original_filename: video.original_filename,
+            priority: video.priority,
+            created_at: video.created_at.and_utc(),
+            updated_at: video.updated_at.and_utc(),
+        }
+    }
+}
+
+impl From<AudioTrack> for AudioTrackResource {
+    fn from(track: AudioTrack) -> Self {
+        AudioTrackResource {
+            position: track.position.max(0) as u32,
+            language: track.language,
+            name: track.name,
+            channels: track.channels.map(|channels| channels.max(0) as u32),
+            default: track.is_default,
+        }
+    }
+}
+
+/// The playlist URL is absolute, so unlike the other conversions this needs
+/// the server's base URL
+pub fn quality_resource(quality: VideoQuality, base_url: &str) -> QualityResource {
+    QualityResource {
+        playlist_url: format!(
+            "{}/uploads/{}/{}",
+            base_url, quality.video_id, quality.file_path
+        ),
+        label: quality.label.unwrap_or_else(|| quality.resolution.clone()),
+        frame_rate: quality.frame_rate,
+        width: quality.width.map(|width| width.max(0) as u32),
+        height: quality.height.map(|height| height.max(0) as u32),
+        resolution: quality.resolution,
+        bitrate: quality.bitrate,
+        state: quality.state,
+        progress: quality.progress.clamp(0, 100) as u8,
+        attempts: quality.attempts.max(0) as u32,
+        created_at: quality.created_at.and_utc(),
+    }
+}
+
+/// Like `quality_resource`, with absolute URLs
+pub fn subtitle_resource(subtitle: Subtitle, base_url: &str) -> SubtitleResource {
+    let url = |path: &str| format!("{}/uploads/{}/{}", base_url, subtitle.video_id, path);
+    let vtt_path = format!(
+        "{}.vtt",
+        subtitle
+            .file_path
+            .strip_suffix(".m3u8")
+            .unwrap_or(&subtitle.file_path)
+    );
+    SubtitleResource {
+        id: subtitle.id,
+        url: url(&vtt_path),
+        playlist_url: url(&subtitle.file_path),
+        language: subtitle.language,
+        name: subtitle.name,
+        source: subtitle.source,
+        default: subtitle.is_default,
+        forced: subtitle.forced,
+    }
+}
+
+/// URL of the video's chosen poster, or of its first thumbnail
+pub fn thumbnail_url(video: &Video, base_url: &str) -> String {
+    format!(
+        "{}/uploads/{}/{}",
+        base_url,
+        video.id,
+        video.poster.as_deref().unwrap_or("thumbnails/thumb_0.jpg")
+    )
+}
+
+/// URL of the video's WebVTT storyboard, once processing has written one
+pub async fn storyboard_url(video_id: Uuid, base_url: &str) -> Option<String> {
+    let path = storyboard::vtt_path(&video_processor::get_video_dir(video_id));
+    tokio::fs::try_exists(path).await.ok()?.then(|| {
+        format!(
+            "{}/uploads/{}/{}/{}",
+            base_url,
+            video_id,
+            storyboard::DIR,
+            storyboard::VTT_FILE
+        )
+    })
+}
+
+/// URL of the video's master playlist without high-bitrate variants, for
+/// packages written since it was added
+pub async fn data_saver_url(video_id: Uuid, base_url: &str) -> Option<String> {
+    let path = video_processor::get_video_dir(video_id)
+        .join("hls")
+        .join(video_processor::LOW_MASTER);
+    tokio::fs::try_exists(path).await.ok()?.then(|| {
+        format!(
+            "{}/uploads/{}/hls/{}",
+            base_url,
+            video_id,
+            video_processor::LOW_MASTER
+        )
+    })
+}
+
+/// URL of the video's animated preview, once processing has written one
+pub async fn preview_url(video_id: Uuid, base_url: &str) -> Option<String> {
+    let path = preview::preview_path(&video_processor::get_video_dir(video_id));
+    tokio::fs::try_exists(path).await.ok()?.then(|| {
+        format!(
+            "{}/uploads/{}/thumbnails/{}",
+            base_url,
+            video_id,
+            preview::FILE
+        )
+    })
+}
+
+/// Overall percent complete of a processing video, counting finished
+/// renditions (including failed ones) as complete; None once processed
+pub fn processing_progress(video: &Video, qualities: &[VideoQuality]) -> Option<u8> {
+    if video.status != "processing" {
+        return None;
+    }
+    if qualities.is_empty() {
+        return Some(0);
+    }
+    let total: i32 = qualities
+        .iter()
+        .map(|quality| match quality.state.as_str() {
+            "ready" | "failed" => 100,
+            _ => quality.progress.clamp(0, 100),
+        })
+        .sum();
+    Some((total / qualities.len() as i32) as u8)
+}
+
+/// An entry of `GET /videos/changes`: the video as it is now, or only its id
+/// once deleted
+#[derive(Debug, Serialize)]
+pub struct ChangeResponse {
+    pub cursor: String,
+    pub change: String,
+    pub video_id: Uuid,
+    pub video: Option<VideoResponse>,
+}
+
+/// Body of `GET /videos/changes`
+#[derive(Debug, Serialize)]
+pub struct ChangesPage {
+    pub changes: Vec<ChangeResponse>,
+    /// Where the next call resumes; unset until there has been a change
+    pub next_cursor: Option<String>,
+    pub has_more: bool,
+}
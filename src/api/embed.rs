@@ -0,0 +1,237 @@
+use crate::api::shared::{validation_error, ErrorCode};
+use crate::db::DbPool;
+use actix_web::http::header;
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use diesel::{OptionalExtension, QueryDsl};
+use diesel_async::RunQueryDsl;
+use uuid::Uuid;
+
+const MAX_EMBED_DOMAINS: usize = 100;
+
+/// Host name of the page a request comes from: the Origin header, which
+/// players send on cross-origin playlist requests, or else the Referer
+fn embedder_host(req: &HttpRequest) -> Option<String> {
+    [header::ORIGIN, header::REFERER]
+        .iter()
+        .filter_map(|name| req.headers().get(name)?.to_str().ok())
+        .find_map(|value| {
+            // Sandboxed frames send `Origin: null`, which has no host
+            let (_, rest) = value.split_once("://")?;
+            let authority = rest.split(['/', '?', '#']).next()?;
+            let host = authority.rsplit('@').next()?.split(':').next()?;
+            (!host.is_empty()).then(|| host.to_ascii_lowercase())
+        })
+}
+
+fn domain_allowed(host: &str, domains: &[String]) -> bool {
+    domains.iter().any(|domain| {
+        host == domain
+            || host
+                .strip_suffix(domain.as_str())
+                .is_some_and(|sub| sub.ends_with('.'))
+    })
+}
+
+/// Lower-cased, de-duplicated host names for `embed_domains`
+pub(crate) fn normalize_domains(domains: Vec<String>) -> Result<Vec<String>, Error> {
+    let invalid = |message: String| {
+        validation_error(
+            "embed_domains".to_string(),
+            message,
+            ErrorCode::ValidationFailed,
+        )
+    };
+    if domains.len() > MAX_EMBED_DOMAINS {
+        return Err(invalid(format!(
+            "At most {} embed domains are allowed",
+            MAX_EMBED_DOMAINS
+        )));
+    }
+
+    let mut normalized: Vec<String> = Vec::with_capacity(domains.len());
+    for domain in domains {
+        let domain = domain.trim().trim_end_matches('.').to_ascii_lowercase();
+        let valid = domain.len() <= 253
+            && domain.split('.').all(|label| {
+                !label.is_empty()
+                    && label.len() <= 63
+                    && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            });
+        if !valid {
+            return Err(invalid(format!(
+                "{:?} is not a host name like example.com",
+                domain
+            )));
+        }
+        if !normalized.contains(&domain) {
+            normalized.push(domain);
+        }
+    }
+    Ok(normalized)
+}
+
+/// Rejects requests from pages outside the video's `embed_domains`. Pages
+/// served by this server, like the embed page, are always allowed; requests
+/// without an Origin or Referer are refused once the list is set.
+pub(crate) async fn check_embedder(
+    req: &HttpRequest,
+    video_id: Uuid,
+    pool: &DbPool,
+) -> Result<Vec<String>, Error> {
+    use crate::db::schema::videos;
+
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    let domains = videos::table
+        .find(video_id)
+        .select(videos::embed_domains)
+        .first::<Vec<String>>(conn)
+        .await
+        .optional()
+        .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?
+        .unwrap_or_default();
+    if domains.is_empty() {
+        return Ok(domains);
+    }
+
+    let own_host = req.connection_info().host().to_ascii_lowercase();
+    let own_host = own_host.split(':').next().unwrap_or_default().to_string();
+    match embedder_host(req) {
+        Some(host) if host == own_host || domain_allowed(&host, &domains) => Ok(domains),
+        Some(host) => Err(actix_web::error::ErrorForbidden(format!(
+            "This video can't be embedded on {}",
+            host
+        ))),
+        None => Err(actix_web::error::ErrorForbidden(
+            "This video can only be played from an allowed site",
+        )),
+    }
+}
+
+fn html_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Minimal player page for `<iframe>` embedding. Its query string, such as a
+/// playback `?token=`, is passed on to the master playlist.
+pub async fn embed_page(
+    req: HttpRequest,
+    video_id: web::Path<Uuid>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, Error> {
+    use crate::db::schema::videos;
+
+    let title = {
+        let conn = &mut pool.get().await.expect("Failed to get DB connection");
+        videos::table
+            .find(*video_id)
+            .select(videos::title)
+            .first::<String>(conn)
+            .await
+            .optional()
+            .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?
+            .ok_or_else(|| actix_web::error::ErrorNotFound("Video not found"))?
+    };
+    let domains = check_embedder(&req, *video_id, &pool).await?;
+
+    let page = format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{title}</title>
+<style>html, body {{ margin: 0; height: 100%; background: #000; }} video {{ width: 100%; height: 100%; }}</style>
+</head>
+<body>
+<video controls playsinline></video>
+<script src="https://cdn.jsdelivr.net/npm/hls.js@1"></script>
+<script>
+const video = document.querySelector("video");
+const src = "/uploads/{id}/hls/master.m3u8" + location.search;
+if (video.canPlayType("application/vnd.apple.mpegurl")) {{
+  video.src = src;
+}} else if (window.Hls && Hls.isSupported()) {{
+  const hls = new Hls();
+  hls.loadSource(src);
+  hls.attachMedia(video);
+}}
+</script>
+</body>
+</html>
+"#,
+        title = html_escape(&title),
+        id = video_id,
+    );
+
+    let mut response = HttpResponse::Ok();
+    response.content_type("text/html; charset=utf-8");
+    if !domains.is_empty() {
+        // Browsers also refuse to frame the page elsewhere, whatever the
+        // Referer says
+        let ancestors = domains
+            .iter()
+            .map(|domain| format!("{} *.{}", domain, domain))
+            .collect::<Vec<_>>()
+            .join(" ");
+        response.insert_header((
+            header::CONTENT_SECURITY_POLICY,
+            format!("frame-ancestors 'self' {}", ancestors),
+        ));
+    }
+    Ok(response.body(page))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn domains_cover_their_subdomains() {
+        let domains = vec!["example.com".to_string()];
+        let cases = [
+            ("example.com", true),
+            ("www.example.com", true),
+            ("a.b.example.com", true),
+            ("badexample.com", false),
+            ("example.com.evil.net", false),
+            ("com", false),
+        ];
+        for (host, allowed) in cases {
+            assert_eq!(domain_allowed(host, &domains), allowed, "{}", host);
+        }
+        assert!(!domain_allowed("example.com", &[]));
+    }
+
+    #[test]
+    fn domains_are_normalized_and_deduplicated() {
+        let domains = vec![
+            " Example.COM. ".to_string(),
+            "example.com".to_string(),
+            "player.example.org".to_string(),
+        ];
+        assert_eq!(
+            normalize_domains(domains).unwrap(),
+            ["example.com", "player.example.org"]
+        );
+    }
+
+    #[test]
+    fn wildcards_and_urls_are_rejected() {
+        // A domain already covers its subdomains, so `*.` isn't needed
+        for domain in ["*.example.com", "https://example.com", "example..com", ""] {
+            assert!(
+                normalize_domains(vec![domain.to_string()]).is_err(),
+                "{}",
+                domain
+            );
+        }
+        let too_many = (0..=MAX_EMBED_DOMAINS)
+            .map(|i| format!("site{}.com", i))
+            .collect();
+        assert!(normalize_domains(too_many).is_err());
+    }
+}
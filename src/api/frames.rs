@@ -0,0 +1,474 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::api::rate_limit::{too_many_requests, RequestLimiter};
+use crate::api::shared::{api_error, validation_error, ErrorCode, ResponseType};
+use crate::api::{embed, grants};
+use crate::config::AppConfig;
+use crate::db::models::{FrameExtraction, Video};
+use crate::db::DbPool;
+use crate::services::entitlement::Entitlements;
+use crate::services::{frames, jobs, video_processor};
+use actix_files::NamedFile;
+use actix_web::http::header::{self, ContentDisposition, DispositionParam, DispositionType};
+use actix_web::http::StatusCode;
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl};
+use diesel_async::RunQueryDsl;
+use serde::Deserialize;
+use serde_json::json;
+use uuid::Uuid;
+use vid_storage_models::{FrameExtractionResource, FrameRequest};
+
+/// How long a request for a short video waits for its frames before
+/// falling back to a job to poll
+const SYNC_WAIT: Duration = Duration::from_secs(60);
+const SYNC_POLL_INTERVAL: Duration = Duration::from_millis(250);
+const MIN_THUMBNAIL_WIDTH: u32 = 16;
+const MAX_THUMBNAIL_WIDTH: u32 = 3840;
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/frames")
+            .route("/{id}", web::get().to(extraction_status))
+            .route("/{id}/download", web::get().to(download_frames))
+            .route("/{id}/{file}", web::get().to(serve_frame)),
+    );
+}
+
+/// Frames show the video, so they need the same access as its playlists.
+/// Returns the playback token the frame URLs have to carry.
+async fn authorize(
+    req: &HttpRequest,
+    video_id: Uuid,
+    pool: &DbPool,
+    entitlements: &Entitlements,
+) -> Result<Option<String>, Error> {
+    embed::check_embedder(req, video_id, pool).await?;
+    grants::authorize_playback(req, video_id, pool, entitlements).await
+}
+
+/// Samples frames from a processed video's original. Short videos are
+/// answered with the frames themselves; longer ones get 202 and a job whose
+/// status is at `/frames/{id}`.
+#[allow(clippy::too_many_arguments)]
+pub async fn extract_frames(
+    req: HttpRequest,
+    video_id: web::Path<Uuid>,
+    body: web::Json<FrameRequest>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+    limiter: web::Data<RequestLimiter>,
+    entitlements: web::Data<Entitlements>,
+) -> Result<HttpResponse, Error> {
+    use crate::db::schema::{frame_extractions, videos};
+
+    if let Some(ip) = req.peer_addr().map(|addr| addr.ip()) {
+        if let Err(retry_after) = limiter.check(ip) {
+            log::warn!("Frame request from {} rejected by rate limit", ip);
+            return Err(too_many_requests(
+                retry_after,
+                ErrorCode::RateLimited,
+                "frames",
+                "Too many frame requests, try again later",
+            ));
+        }
+    }
+
+    let token = authorize(&req, *video_id, &pool, &entitlements).await?;
+    let body = body.into_inner();
+    let format = body.format.as_deref().unwrap_or("zip").to_ascii_lowercase();
+    if !frames::FORMATS.contains(&format.as_str()) {
+        return Err(validation_error(
+            "format".to_string(),
+            format!("Format must be one of {}", frames::FORMATS.join(", ")),
+            ErrorCode::ValidationFailed,
+        ));
+    }
+
+    let mut conn = pool.get().await.expect("Failed to get DB connection");
+    let video = videos::table
+        .find(*video_id)
+        .filter(videos::status.eq("processed"))
+        .first::<Video>(&mut conn)
+        .await
+        .optional()
+        .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Video not found"))?;
+    let duration = video.duration.unwrap_or(0.0);
+
+    let timestamps = frames::resolve_timestamps(
+        body.interval_secs,
+        body.timestamps.as_deref(),
+        duration,
+        config.frames.max_frames,
+    )
+    .map_err(|(field, message)| {
+        validation_error(field.to_string(), message, ErrorCode::ValidationFailed)
+    })?;
+
+    let extraction = FrameExtraction {
+        id: Uuid::new_v4(),
+        video_id: video.id,
+        interval_secs: body.interval_secs,
+        timestamps,
+        format,
+        created_at: chrono::Utc::now().naive_utc(),
+    };
+    tokio::fs::create_dir_all(frames::frames_dir(extraction.id))
+        .await
+        .map_err(|e| {
+            log::error!("Failed to create frames directory: {}", e);
+            actix_web::error::ErrorInternalServerError("Storage error")
+        })?;
+    diesel::insert_into(frame_extractions::table)
+        .values(&extraction)
+        .execute(&mut conn)
+        .await
+        .map_err(|e| {
+            log::error!("Error inserting frame extraction: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+    jobs::enqueue_frames(extraction.id, &mut conn)
+        .await
+        .map_err(|e| {
+            log::error!("Error queueing frame extraction {}: {}", extraction.id, e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+
+    // Not held while waiting, the job needs connections too
+    drop(conn);
+
+    if duration <= config.frames.sync_max_duration_secs {
+        let started = Instant::now();
+        while started.elapsed() < SYNC_WAIT {
+            tokio::time::sleep(SYNC_POLL_INTERVAL).await;
+            let (state, error) = job_state(extraction.id, &pool).await?;
+            match state.as_str() {
+                "done" => return frames_response(&req, &extraction, token.as_deref()).await,
+                "failed" => {
+                    log::error!(
+                        "Frame extraction {} failed: {}",
+                        extraction.id,
+                        error.as_deref().unwrap_or("unknown error")
+                    );
+                    return Err(frame_extraction_failed());
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(
+        HttpResponse::Accepted().json(json!(ResponseType::<FrameExtractionResource> {
+            data: Some(extraction_resource(
+                &req,
+                extraction,
+                "queued".to_string(),
+                None,
+                token.as_deref()
+            )),
+            error: None
+        })),
+    )
+}
+
+#[derive(Deserialize)]
+pub struct ThumbnailQuery {
+    /// Seconds into the video
+    t: f64,
+    /// Width in pixels; the video's own when unset
+    w: Option<u32>,
+}
+
+/// The frame `t` seconds into the video as a JPEG, for previews at times the
+/// fixed thumbnails don't cover. Times are rounded down to a tenth of a
+/// second, and frames are kept once extracted, so only extracting one counts
+/// against the frame rate limit.
+#[allow(clippy::too_many_arguments)]
+pub async fn thumbnail(
+    req: HttpRequest,
+    video_id: web::Path<Uuid>,
+    query: web::Query<ThumbnailQuery>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+    limiter: web::Data<RequestLimiter>,
+    entitlements: web::Data<Entitlements>,
+) -> Result<HttpResponse, Error> {
+    use crate::db::schema::videos;
+
+    if let Some(w) = query
+        .w
+        .filter(|w| !(MIN_THUMBNAIL_WIDTH..=MAX_THUMBNAIL_WIDTH).contains(w))
+    {
+        return Err(validation_error(
+            "w".to_string(),
+            format!(
+                "Width must be {} to {} pixels, not {}",
+                MIN_THUMBNAIL_WIDTH, MAX_THUMBNAIL_WIDTH, w
+            ),
+            ErrorCode::ValidationFailed,
+        ));
+    }
+    let token = authorize(&req, *video_id, &pool, &entitlements).await?;
+    let duration = {
+        let conn = &mut pool.get().await.expect("Failed to get DB connection");
+        videos::table
+            .find(*video_id)
+            .filter(videos::status.eq("processed"))
+            .select(videos::duration)
+            .first::<Option<f64>>(conn)
+            .await
+            .optional()
+            .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?
+            .ok_or_else(|| actix_web::error::ErrorNotFound("Video not found"))?
+            .unwrap_or(0.0)
+    };
+    // A frame exactly at the end doesn't exist
+    if !(0.0..duration).contains(&query.t) {
+        return Err(validation_error(
+            "t".to_string(),
+            format!("{} is outside the video (0 to {})", query.t, duration),
+            ErrorCode::ValidationFailed,
+        ));
+    }
+    let tenths = (query.t * 10.0).floor() as u64;
+
+    let storage_error = |e: std::io::Error| {
+        log::error!("Failed to store thumbnail of {}: {}", video_id, e);
+        actix_web::error::ErrorInternalServerError("Storage error")
+    };
+    let video_dir = tokio::fs::canonicalize(video_processor::get_video_dir(*video_id))
+        .await
+        .map_err(storage_error)?;
+    let path = frames::thumbnail_path(&video_dir, tenths, query.w);
+    if !tokio::fs::try_exists(&path).await.map_err(storage_error)? {
+        if let Some(ip) = req.peer_addr().map(|addr| addr.ip()) {
+            if let Err(retry_after) = limiter.check(ip) {
+                log::warn!("Thumbnail request from {} rejected by rate limit", ip);
+                return Err(too_many_requests(
+                    retry_after,
+                    ErrorCode::RateLimited,
+                    "frames",
+                    "Too many frame requests, try again later",
+                ));
+            }
+        }
+
+        let dir = path.parent().unwrap();
+        tokio::fs::create_dir_all(dir)
+            .await
+            .map_err(storage_error)?;
+        // Concurrent requests for the same frame each write their own
+        let partial = dir.join(format!("{}.partial.jpg", Uuid::new_v4()));
+        let input = video_processor::find_original(&video_dir)
+            .await
+            .map_err(storage_error)?;
+        let timestamp = tenths as f64 / 10.0;
+        if let Err(e) =
+            frames::extract_frame(&input, &partial, timestamp, query.w, &config.ffmpeg).await
+        {
+            log::error!("Failed to extract thumbnail of {}: {}", video_id, e);
+            let _ = tokio::fs::remove_file(&partial).await;
+            return Err(frame_extraction_failed());
+        }
+        tokio::fs::rename(&partial, &path)
+            .await
+            .map_err(storage_error)?;
+    }
+
+    let mut response = NamedFile::open_async(&path)
+        .await
+        .map_err(|_| actix_web::error::ErrorNotFound("Thumbnail not found"))?
+        .use_last_modified(true)
+        .into_response(&req);
+    // The frame at a time never changes, but shared caches mustn't hand out
+    // frames of a restricted video
+    let cache_control = if token.is_some() {
+        "private, max-age=86400"
+    } else {
+        "public, max-age=86400"
+    };
+    response.headers_mut().insert(
+        header::CACHE_CONTROL,
+        header::HeaderValue::from_static(cache_control),
+    );
+    Ok(response)
+}
+
+fn base_url(req: &HttpRequest) -> String {
+    format!(
+        "{}://{}",
+        req.connection_info().scheme(),
+        req.connection_info().host()
+    )
+}
+
+/// `?token=` for the URLs of frames of a restricted video
+fn token_query(token: Option<&str>) -> String {
+    token.map_or_else(String::new, |token| format!("?token={}", token))
+}
+
+fn extraction_resource(
+    req: &HttpRequest,
+    extraction: FrameExtraction,
+    state: String,
+    error: Option<String>,
+    token: Option<&str>,
+) -> FrameExtractionResource {
+    let download_url = (state == "done").then(|| {
+        format!(
+            "{}/api/v1/frames/{}/download{}",
+            base_url(req),
+            extraction.id,
+            token_query(token)
+        )
+    });
+    FrameExtractionResource {
+        id: extraction.id,
+        video_id: extraction.video_id,
+        format: extraction.format,
+        timestamps: extraction.timestamps,
+        state,
+        error,
+        download_url,
+        created_at: extraction.created_at.and_utc(),
+    }
+}
+
+/// State and error of the extraction's job
+async fn job_state(f_id: Uuid, pool: &DbPool) -> Result<(String, Option<String>), Error> {
+    use crate::db::schema::processing_jobs;
+
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    processing_jobs::table
+        .filter(processing_jobs::frame_extraction_id.eq(f_id))
+        .order(processing_jobs::created_at.desc())
+        .select((processing_jobs::state, processing_jobs::error))
+        .first::<(String, Option<String>)>(conn)
+        .await
+        .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))
+}
+
+async fn load_extraction(f_id: Uuid, pool: &DbPool) -> Result<FrameExtraction, Error> {
+    use crate::db::schema::frame_extractions;
+
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    frame_extractions::table
+        .find(f_id)
+        .first::<FrameExtraction>(conn)
+        .await
+        .optional()
+        .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Frame extraction not found"))
+}
+
+async fn extraction_status(
+    req: HttpRequest,
+    f_id: web::Path<Uuid>,
+    pool: web::Data<DbPool>,
+    entitlements: web::Data<Entitlements>,
+) -> Result<HttpResponse, Error> {
+    let extraction = load_extraction(*f_id, &pool).await?;
+    let token = authorize(&req, extraction.video_id, &pool, &entitlements).await?;
+    let (state, error) = job_state(*f_id, &pool).await?;
+    Ok(
+        HttpResponse::Ok().json(json!(ResponseType::<FrameExtractionResource> {
+            data: Some(extraction_resource(
+                &req,
+                extraction,
+                state,
+                error,
+                token.as_deref()
+            )),
+            error: None
+        })),
+    )
+}
+
+async fn download_frames(
+    req: HttpRequest,
+    f_id: web::Path<Uuid>,
+    pool: web::Data<DbPool>,
+    entitlements: web::Data<Entitlements>,
+) -> Result<HttpResponse, Error> {
+    let extraction = load_extraction(*f_id, &pool).await?;
+    let token = authorize(&req, extraction.video_id, &pool, &entitlements).await?;
+    let (state, _) = job_state(*f_id, &pool).await?;
+    if state != "done" {
+        return Err(actix_web::error::ErrorConflict(
+            "Frames are not extracted yet",
+        ));
+    }
+    frames_response(&req, &extraction, token.as_deref()).await
+}
+
+/// The zip archive, or one JSON line per frame with its timestamp and URL
+async fn frames_response(
+    req: &HttpRequest,
+    extraction: &FrameExtraction,
+    token: Option<&str>,
+) -> Result<HttpResponse, Error> {
+    if extraction.format == "ndjson" {
+        let base_url = base_url(req);
+        let query = token_query(token);
+        let body = extraction
+            .timestamps
+            .iter()
+            .enumerate()
+            .map(|(index, timestamp)| {
+                let url = format!(
+                    "{}/api/v1/frames/{}/{}{}",
+                    base_url,
+                    extraction.id,
+                    frames::frame_name(index),
+                    query
+                );
+                format!("{}\n", json!({ "timestamp": timestamp, "url": url }))
+            })
+            .collect::<String>();
+        return Ok(HttpResponse::Ok()
+            .content_type("application/x-ndjson")
+            .body(body));
+    }
+
+    let file = NamedFile::open_async(frames::archive_path(extraction))
+        .await
+        .map_err(|_| actix_web::error::ErrorNotFound("Frame archive not found"))?
+        .set_content_disposition(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format!(
+                "{}-frames.zip",
+                extraction.video_id
+            ))],
+        });
+    Ok(file.into_response(req))
+}
+
+async fn serve_frame(
+    req: HttpRequest,
+    params: web::Path<(Uuid, String)>,
+    pool: web::Data<DbPool>,
+    entitlements: web::Data<Entitlements>,
+) -> Result<NamedFile, Error> {
+    let (f_id, file) = params.into_inner();
+    // Only frame files, not the archive or anything outside the directory
+    if !(file.starts_with("frame_") && file.ends_with(".jpg")) || file.contains("..") {
+        return Err(actix_web::error::ErrorNotFound("Frame not found"));
+    }
+    let extraction = load_extraction(f_id, &pool).await?;
+    authorize(&req, extraction.video_id, &pool, &entitlements).await?;
+    NamedFile::open_async(frames::frames_dir(f_id).join(file))
+        .await
+        .map_err(|_| actix_web::error::ErrorNotFound("Frame not found"))
+}
+
+/// The error of a frame ffmpeg couldn't extract
+pub(crate) fn frame_extraction_failed() -> Error {
+    api_error(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        ErrorCode::ProcessingFailed,
+        "frames".to_string(),
+        "Frame extraction failed".to_string(),
+    )
+}
@@ -0,0 +1,249 @@
+use crate::api::moderation::{is_admin, require_admin};
+use crate::api::shared::{validation_error, ErrorCode, ResponseType};
+use crate::config::AppConfig;
+use crate::db::models::PlaybackGrant;
+use crate::db::DbPool;
+use crate::services::entitlement::Entitlements;
+use crate::services::grants::{self, Access};
+use actix_web::http::header;
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use chrono::{TimeDelta, Utc};
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl};
+use diesel_async::RunQueryDsl;
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+use uuid::Uuid;
+use vid_storage_models::{CreateGrantRequest, GrantResource};
+
+#[derive(Deserialize)]
+struct PlaybackQuery {
+    token: Option<String>,
+}
+
+/// The viewer's token, from `?token=` (which players keep on playlist
+/// requests) or an `Authorization: Bearer` header
+pub(crate) fn playback_token(req: &HttpRequest) -> Option<String> {
+    web::Query::<PlaybackQuery>::from_query(req.query_string())
+        .ok()
+        .and_then(|query| query.into_inner().token)
+        .or_else(|| {
+            req.headers()
+                .get(header::AUTHORIZATION)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.strip_prefix("Bearer "))
+                .map(str::to_string)
+        })
+        .filter(|token| !token.is_empty())
+}
+
+/// Rejects playlist and segment requests for a restricted video unless the
+/// token has an active grant or the entitlement endpoint allows it, and for a
+/// video scheduled for later unless the token is a preview link's or an
+/// admin's. Returns the token when the video is restricted or scheduled, so
+/// playlists can pass it on to the files they list.
+pub(crate) async fn authorize_playback(
+    req: &HttpRequest,
+    video_id: Uuid,
+    pool: &DbPool,
+    entitlements: &Entitlements,
+) -> Result<Option<String>, Error> {
+    let token = playback_token(req);
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    let access = grants::check(conn, video_id, token.as_deref())
+        .await
+        .map_err(|e| {
+            log::error!("Error checking playback grants of {}: {}", video_id, e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+    match (access, token) {
+        (Access::Public, _) => Ok(None),
+        (Access::Granted, token) => Ok(token),
+        (Access::Denied, Some(token)) if entitlements.enabled() => {
+            match entitlements.allows(video_id, &token).await {
+                Ok(true) => Ok(Some(token)),
+                Ok(false) => Err(actix_web::error::ErrorForbidden(
+                    "This token is not entitled to watch the video",
+                )),
+                Err(e) => {
+                    log::error!("Error checking entitlement to {}: {:#}", video_id, e);
+                    Err(actix_web::error::ErrorServiceUnavailable(
+                        "Entitlement check failed",
+                    ))
+                }
+            }
+        }
+        (Access::Denied, _) => Err(actix_web::error::ErrorForbidden(
+            "A playback grant is required for this video",
+        )),
+        (Access::Unpublished, token) => {
+            let admin = req
+                .app_data::<web::Data<Arc<AppConfig>>>()
+                .is_some_and(|config| is_admin(req, config));
+            if admin {
+                Ok(token)
+            } else {
+                // Like an unknown video, as listings don't show it either
+                Err(actix_web::error::ErrorNotFound("Video not found"))
+            }
+        }
+    }
+}
+
+/// Listings leave out `token`: whoever can read them shouldn't be able to
+/// play the video as every viewer with a grant
+fn grant_resource(grant: PlaybackGrant, with_token: bool) -> GrantResource {
+    let now = Utc::now().naive_utc();
+    GrantResource {
+        id: grant.id,
+        video_id: grant.video_id,
+        token: with_token.then_some(grant.token),
+        active: grant.starts_at <= now && now < grant.expires_at,
+        starts_at: grant.starts_at.and_utc(),
+        expires_at: grant.expires_at.and_utc(),
+        created_at: grant.created_at.and_utc(),
+    }
+}
+
+/// Lets a token play the video for a time window. Grants only matter once
+/// the video is restricted.
+pub async fn create_grant(
+    req: HttpRequest,
+    video_id: web::Path<Uuid>,
+    body: web::Json<CreateGrantRequest>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+) -> Result<HttpResponse, Error> {
+    use crate::db::schema::{playback_grants, videos};
+
+    require_admin(&req, &config)?;
+    let body = body.into_inner();
+    let invalid = |field: &str, message: &str| {
+        validation_error(
+            field.to_string(),
+            message.to_string(),
+            ErrorCode::ValidationFailed,
+        )
+    };
+    let safe_token = body
+        .token
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "-._~".contains(c));
+    if body.token.is_empty() || body.token.len() > 255 || !safe_token {
+        return Err(invalid(
+            "token",
+            "Tokens are 1 to 255 letters, digits and -._~",
+        ));
+    }
+
+    let starts_at = body.starts_at.unwrap_or_else(Utc::now);
+    let expires_at = match (body.expires_at, body.duration_secs) {
+        (Some(expires_at), None) => expires_at,
+        (None, Some(secs)) => i64::try_from(secs)
+            .ok()
+            .and_then(TimeDelta::try_seconds)
+            .and_then(|duration| starts_at.checked_add_signed(duration))
+            .ok_or_else(|| invalid("duration_secs", "Duration is too long"))?,
+        _ => {
+            return Err(invalid(
+                "expires_at",
+                "Exactly one of expires_at and duration_secs must be set",
+            ))
+        }
+    };
+    if expires_at <= starts_at {
+        return Err(invalid("expires_at", "The window must end after it starts"));
+    }
+
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    videos::table
+        .find(*video_id)
+        .select(videos::id)
+        .first::<Uuid>(conn)
+        .await
+        .optional()
+        .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Video not found"))?;
+
+    let grant = PlaybackGrant {
+        id: Uuid::new_v4(),
+        video_id: *video_id,
+        token: body.token,
+        starts_at: starts_at.naive_utc(),
+        expires_at: expires_at.naive_utc(),
+        created_at: Utc::now().naive_utc(),
+    };
+    diesel::insert_into(playback_grants::table)
+        .values(&grant)
+        .execute(conn)
+        .await
+        .map_err(|e| {
+            log::error!("Error inserting playback grant: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+
+    Ok(
+        HttpResponse::Created().json(json!(ResponseType::<GrantResource> {
+            data: Some(grant_resource(grant, true)),
+            error: None
+        })),
+    )
+}
+
+pub async fn list_grants(
+    req: HttpRequest,
+    video_id: web::Path<Uuid>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+) -> Result<HttpResponse, Error> {
+    use crate::db::schema::playback_grants;
+
+    require_admin(&req, &config)?;
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    let grants = playback_grants::table
+        .filter(playback_grants::video_id.eq(*video_id))
+        .order(playback_grants::created_at.asc())
+        .load::<PlaybackGrant>(conn)
+        .await
+        .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+    Ok(
+        HttpResponse::Ok().json(json!(ResponseType::<Vec<GrantResource>> {
+            data: Some(
+                grants
+                    .into_iter()
+                    .map(|grant| grant_resource(grant, false))
+                    .collect()
+            ),
+            error: None
+        })),
+    )
+}
+
+/// Revokes a grant; playlists already fetched keep working until they are
+/// requested again
+pub async fn delete_grant(
+    req: HttpRequest,
+    params: web::Path<(Uuid, Uuid)>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+) -> Result<HttpResponse, Error> {
+    use crate::db::schema::playback_grants;
+
+    require_admin(&req, &config)?;
+    let (video_id, grant_id) = params.into_inner();
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    let deleted = diesel::delete(
+        playback_grants::table
+            .filter(playback_grants::id.eq(grant_id))
+            .filter(playback_grants::video_id.eq(video_id)),
+    )
+    .execute(conn)
+    .await
+    .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?;
+    if deleted == 0 {
+        return Err(actix_web::error::ErrorNotFound("Grant not found"));
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
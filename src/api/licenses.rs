@@ -0,0 +1,76 @@
+use crate::api::shared::{validation_error, ErrorCode};
+use actix_web::Error;
+use reqwest::Url;
+
+/// SPDX identifiers accepted for `license`, plus references for works that
+/// are in the public domain or not licensed at all
+const LICENSES: &[&str] = &[
+    "CC0-1.0",
+    "CC-BY-4.0",
+    "CC-BY-SA-4.0",
+    "CC-BY-NC-4.0",
+    "CC-BY-NC-SA-4.0",
+    "CC-BY-ND-4.0",
+    "CC-BY-NC-ND-4.0",
+    "CC-BY-3.0",
+    "CC-BY-SA-3.0",
+    "CC-BY-NC-3.0",
+    "CC-BY-NC-SA-3.0",
+    "CC-BY-ND-3.0",
+    "CC-BY-NC-ND-3.0",
+    "CC-BY-2.0",
+    "CC-BY-SA-2.0",
+    "LicenseRef-Public-Domain",
+    "LicenseRef-All-Rights-Reserved",
+];
+const MAX_ATTRIBUTION_LEN: usize = 1000;
+const MAX_SOURCE_URL_LEN: usize = 2048;
+
+/// The canonical spelling of a known license, matched case-insensitively
+pub(crate) fn normalize_license(license: String) -> Result<String, Error> {
+    let license = license.trim();
+    LICENSES
+        .iter()
+        .find(|known| known.eq_ignore_ascii_case(license))
+        .map(|known| known.to_string())
+        .ok_or_else(|| {
+            validation_error(
+                "license".to_string(),
+                format!("License must be one of {}", LICENSES.join(", ")),
+                ErrorCode::ValidationFailed,
+            )
+        })
+}
+
+pub(crate) fn normalize_attribution(attribution: String) -> Result<String, Error> {
+    let attribution = attribution.trim();
+    if attribution.is_empty() || attribution.chars().count() > MAX_ATTRIBUTION_LEN {
+        return Err(validation_error(
+            "attribution".to_string(),
+            format!(
+                "Attribution must be 1 to {} characters; use null to clear it",
+                MAX_ATTRIBUTION_LEN
+            ),
+            ErrorCode::ValidationFailed,
+        ));
+    }
+    Ok(attribution.to_string())
+}
+
+pub(crate) fn normalize_source_url(source_url: String) -> Result<String, Error> {
+    let source_url = source_url.trim();
+    let valid = source_url.len() <= MAX_SOURCE_URL_LEN
+        && Url::parse(source_url)
+            .is_ok_and(|url| matches!(url.scheme(), "http" | "https") && url.host_str().is_some());
+    if !valid {
+        return Err(validation_error(
+            "source_url".to_string(),
+            format!(
+                "Source URL must be an http or https URL of at most {} characters",
+                MAX_SOURCE_URL_LEN
+            ),
+            ErrorCode::ValidationFailed,
+        ));
+    }
+    Ok(source_url.to_string())
+}
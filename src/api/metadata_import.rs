@@ -0,0 +1,449 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use crate::api::moderation::require_admin;
+use crate::api::shared::{validation_error, ErrorCode, ResponseType};
+use crate::config::AppConfig;
+use crate::db::DbPool;
+use crate::services::video_processor;
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use diesel::{AsChangeset, ExpressionMethods, QueryDsl};
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use serde::Deserialize;
+use serde_json::json;
+use uuid::Uuid;
+use vid_storage_models::{APIError, MetadataImportReport, MetadataImportRow};
+
+const COLUMNS: &[&str] = &[
+    "id",
+    "filename",
+    "title",
+    "description",
+    "tags",
+    "publish_at",
+];
+const MAX_CSV_SIZE: usize = 10 * 1024 * 1024;
+const MAX_ROWS: usize = 10_000;
+const MAX_TITLE_LEN: usize = 255;
+const MAX_DESCRIPTION_LEN: usize = 5000;
+const MAX_TAGS: usize = 50;
+const MAX_TAG_LEN: usize = 50;
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/admin").service(
+            web::resource("/metadata-import")
+                .app_data(web::PayloadConfig::new(MAX_CSV_SIZE))
+                .route(web::post().to(import_metadata)),
+        ),
+    );
+}
+
+#[derive(Deserialize)]
+struct ImportQuery {
+    /// Validate and match the rows without applying them
+    #[serde(default)]
+    dry_run: bool,
+}
+
+/// How a row refers to its video
+enum Target {
+    Id(Uuid),
+    Filename(String),
+}
+
+#[derive(AsChangeset)]
+#[diesel(table_name = crate::db::schema::videos)]
+struct MetadataChanges {
+    title: Option<String>,
+    description: Option<String>,
+    tags: Option<Vec<String>>,
+    publish_at: Option<NaiveDateTime>,
+    updated_at: NaiveDateTime,
+}
+
+struct Row {
+    line: u64,
+    target: Option<Target>,
+    changes: MetadataChanges,
+    errors: Vec<String>,
+}
+
+/// Comma- or semicolon-separated, without duplicates
+fn parse_tags(value: &str, errors: &mut Vec<String>) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+    for tag in value.split([',', ';']).map(str::trim) {
+        if tag.is_empty() || tags.iter().any(|t| t == tag) {
+            continue;
+        }
+        if tag.chars().count() > MAX_TAG_LEN {
+            errors.push(format!("Tag {:?} is over {} characters", tag, MAX_TAG_LEN));
+        }
+        tags.push(tag.to_string());
+    }
+    if tags.len() > MAX_TAGS {
+        errors.push(format!("At most {} tags are allowed", MAX_TAGS));
+    }
+    tags
+}
+
+/// An RFC 3339 time, or a date for midnight UTC
+fn parse_publish_at(value: &str) -> Option<NaiveDateTime> {
+    if let Ok(at) = DateTime::parse_from_rfc3339(value) {
+        return Some(at.with_timezone(&Utc).naive_utc());
+    }
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .ok()?
+        .and_hms_opt(0, 0, 0)
+}
+
+fn parse_row(line: u64, record: &csv::StringRecord, columns: &[&str]) -> Row {
+    let mut errors = Vec::new();
+    let value = |column: &str| {
+        columns
+            .iter()
+            .position(|&c| c == column)
+            .and_then(|i| record.get(i))
+            .filter(|value| !value.is_empty())
+    };
+
+    let target = match (value("id"), value("filename")) {
+        (Some(id), _) => match Uuid::parse_str(id) {
+            Ok(id) => Some(Target::Id(id)),
+            Err(_) => {
+                errors.push(format!("{:?} is not a valid video id", id));
+                None
+            }
+        },
+        (None, Some(filename)) => Some(Target::Filename(video_processor::client_file_name(
+            filename,
+        ))),
+        (None, None) => {
+            errors.push("Either id or filename is required".to_string());
+            None
+        }
+    };
+
+    let title = value("title").map(str::to_string);
+    if title
+        .as_ref()
+        .is_some_and(|title| title.chars().count() > MAX_TITLE_LEN)
+    {
+        errors.push(format!("Title is over {} characters", MAX_TITLE_LEN));
+    }
+    let description = value("description").map(str::to_string);
+    if description
+        .as_ref()
+        .is_some_and(|description| description.chars().count() > MAX_DESCRIPTION_LEN)
+    {
+        errors.push(format!(
+            "Description is over {} characters",
+            MAX_DESCRIPTION_LEN
+        ));
+    }
+    let tags = value("tags").map(|tags| parse_tags(tags, &mut errors));
+    let publish_at = value("publish_at").and_then(|at| {
+        let parsed = parse_publish_at(at);
+        if parsed.is_none() {
+            errors.push(format!(
+                "publish_at {:?} is not an RFC 3339 time or a YYYY-MM-DD date",
+                at
+            ));
+        }
+        parsed
+    });
+
+    Row {
+        line,
+        target,
+        changes: MetadataChanges {
+            title,
+            description,
+            tags,
+            publish_at,
+            updated_at: Utc::now().naive_utc(),
+        },
+        errors,
+    }
+}
+
+/// Updates titles, descriptions, tags and publish times from a CSV, e.g.
+/// when migrating a library. Rows name their video by `id` or by the
+/// `filename` it was uploaded as; empty cells leave a field unchanged. Every
+/// row is applied in one transaction, and none if any is invalid.
+async fn import_metadata(
+    req: HttpRequest,
+    query: web::Query<ImportQuery>,
+    body: web::Bytes,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+) -> Result<HttpResponse, Error> {
+    use crate::db::schema::videos;
+
+    require_admin(&req, &config)?;
+    let invalid =
+        |message: String| validation_error("csv".to_string(), message, ErrorCode::ValidationFailed);
+
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(&body[..]);
+    let headers = reader
+        .headers()
+        .map_err(|e| invalid(format!("Invalid header row: {}", e)))?
+        .iter()
+        .map(str::to_ascii_lowercase)
+        .collect::<Vec<_>>();
+    if let Some(unknown) = headers.iter().find(|h| !COLUMNS.contains(&h.as_str())) {
+        return Err(invalid(format!(
+            "Unknown column {:?}; columns are {}",
+            unknown,
+            COLUMNS.join(", ")
+        )));
+    }
+    if !headers.iter().any(|h| h == "id" || h == "filename") {
+        return Err(invalid("An id or filename column is required".to_string()));
+    }
+    let columns = headers.iter().map(String::as_str).collect::<Vec<_>>();
+
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        if rows.len() == MAX_ROWS {
+            return Err(invalid(format!("At most {} rows are allowed", MAX_ROWS)));
+        }
+        rows.push(match record {
+            Ok(record) => {
+                let line = record.position().map_or(0, |p| p.line());
+                parse_row(line, &record, &columns)
+            }
+            Err(e) => Row {
+                line: e.position().map_or(0, |p| p.line()),
+                target: None,
+                changes: MetadataChanges {
+                    title: None,
+                    description: None,
+                    tags: None,
+                    publish_at: None,
+                    updated_at: Utc::now().naive_utc(),
+                },
+                errors: vec![format!("Unreadable row: {}", e)],
+            },
+        });
+    }
+    if rows.is_empty() {
+        return Err(invalid("The CSV has no rows".to_string()));
+    }
+
+    let db_error = |e: diesel::result::Error| {
+        log::error!("Error importing metadata: {}", e);
+        actix_web::error::ErrorInternalServerError("Database error")
+    };
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    let ids = rows
+        .iter()
+        .filter_map(|row| match &row.target {
+            Some(Target::Id(id)) => Some(*id),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    let existing = videos::table
+        .filter(videos::id.eq_any(&ids))
+        .filter(videos::status.ne("removed"))
+        .select(videos::id)
+        .load::<Uuid>(conn)
+        .await
+        .map_err(db_error)?
+        .into_iter()
+        .collect::<HashSet<_>>();
+    let filenames = rows
+        .iter()
+        .filter_map(|row| match &row.target {
+            Some(Target::Filename(filename)) => Some(filename.as_str()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    let mut uploaded_as: HashMap<String, Vec<Uuid>> = HashMap::new();
+    for (id, filename) in videos::table
+        .filter(videos::original_filename.eq_any(&filenames))
+        .filter(videos::status.ne("removed"))
+        .select((videos::id, videos::original_filename))
+        .load::<(Uuid, Option<String>)>(conn)
+        .await
+        .map_err(db_error)?
+    {
+        if let Some(filename) = filename {
+            uploaded_as.entry(filename).or_default().push(id);
+        }
+    }
+
+    // Line each video was first matched on
+    let mut matched: HashMap<Uuid, u64> = HashMap::new();
+    let mut report = Vec::with_capacity(rows.len());
+    let mut updates = Vec::with_capacity(rows.len());
+    for mut row in rows {
+        let video_id = match &row.target {
+            Some(Target::Id(id)) if existing.contains(id) => Some(*id),
+            Some(Target::Id(id)) => {
+                row.errors.push(format!("No video with id {}", id));
+                None
+            }
+            Some(Target::Filename(filename)) => {
+                match uploaded_as.get(filename).map(Vec::as_slice) {
+                    Some([id]) => Some(*id),
+                    Some(ids) => {
+                        row.errors.push(format!(
+                            "{} videos were uploaded as {:?}; use the id column",
+                            ids.len(),
+                            filename
+                        ));
+                        None
+                    }
+                    None => {
+                        row.errors
+                            .push(format!("No video was uploaded as {:?}", filename));
+                        None
+                    }
+                }
+            }
+            None => None,
+        };
+        if let Some(video_id) = video_id {
+            if let Some(line) = matched.insert(video_id, row.line) {
+                matched.insert(video_id, line);
+                row.errors
+                    .push(format!("The video is already updated on line {}", line));
+            }
+        }
+
+        if let (Some(video_id), true) = (video_id, row.errors.is_empty()) {
+            updates.push((video_id, row.changes));
+        }
+        report.push(MetadataImportRow {
+            line: row.line,
+            video_id,
+            errors: row.errors,
+        });
+    }
+
+    let failed = report.iter().filter(|row| !row.errors.is_empty()).count();
+    if failed > 0 {
+        let message = format!(
+            "{} of {} rows are invalid; nothing was applied",
+            failed,
+            report.len()
+        );
+        return Ok(
+            HttpResponse::UnprocessableEntity().json(json!(ResponseType::<MetadataImportReport> {
+                data: Some(MetadataImportReport {
+                    applied: false,
+                    rows: report,
+                }),
+                error: Some(APIError {
+                    cause: "csv".to_string(),
+                    message,
+                    code: ErrorCode::ValidationFailed,
+                }),
+            })),
+        );
+    }
+
+    if !query.dry_run {
+        conn.transaction::<_, diesel::result::Error, _>(|conn| {
+            async move {
+                for (video_id, changes) in &updates {
+                    diesel::update(videos::table.find(video_id))
+                        .set(changes)
+                        .execute(conn)
+                        .await?;
+                }
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await
+        .map_err(db_error)?;
+    }
+
+    Ok(
+        HttpResponse::Ok().json(json!(ResponseType::<MetadataImportReport> {
+            data: Some(MetadataImportReport {
+                applied: !query.dry_run,
+                rows: report,
+            }),
+            error: None
+        })),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(cells: [&str; 6]) -> Row {
+        parse_row(2, &csv::StringRecord::from(cells.to_vec()), COLUMNS)
+    }
+
+    #[test]
+    fn tags_are_split_trimmed_and_deduplicated() {
+        let mut errors = Vec::new();
+        let tags = parse_tags(" news, live;news ,, sports ", &mut errors);
+        assert_eq!(tags, ["news", "live", "sports"]);
+        assert!(errors.is_empty());
+
+        let too_many = (0..=MAX_TAGS).map(|i| i.to_string()).collect::<Vec<_>>();
+        parse_tags(&too_many.join(","), &mut errors);
+        parse_tags(&"x".repeat(MAX_TAG_LEN + 1), &mut errors);
+        assert_eq!(errors.len(), 2, "{:?}", errors);
+    }
+
+    #[test]
+    fn publish_at_takes_times_and_dates() {
+        let at = |s: &str| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").unwrap();
+        assert_eq!(
+            parse_publish_at("2024-05-01T10:30:00+02:00"),
+            Some(at("2024-05-01 08:30:00"))
+        );
+        assert_eq!(
+            parse_publish_at("2024-05-01"),
+            Some(at("2024-05-01 00:00:00"))
+        );
+        assert_eq!(parse_publish_at("05/01/2024"), None);
+        assert_eq!(parse_publish_at("2024-05-01 10:30"), None);
+    }
+
+    #[test]
+    fn rows_name_their_video_by_id_or_filename() {
+        let id = "6f1c0e9a-2b3d-4c5e-8f70-1a2b3c4d5e6f";
+        let by_id = row([id, "clip.mp4", "", "", "", ""]);
+        assert!(matches!(by_id.target, Some(Target::Id(v)) if v.to_string() == id));
+        assert!(by_id.errors.is_empty());
+
+        let by_name = row(["", "C:\\footage\\clip.mp4", "", "", "", ""]);
+        assert!(matches!(by_name.target, Some(Target::Filename(ref f)) if f == "clip.mp4"));
+
+        assert_eq!(row(["", "", "Title", "", "", ""]).errors.len(), 1);
+        assert_eq!(row(["not-a-uuid", "", "", "", "", ""]).errors.len(), 1);
+    }
+
+    #[test]
+    fn empty_cells_leave_fields_unchanged() {
+        let parsed = row(["", "clip.mp4", "Launch", "", "a;b", ""]);
+        assert_eq!(parsed.changes.title.as_deref(), Some("Launch"));
+        assert_eq!(parsed.changes.description, None);
+        assert_eq!(
+            parsed.changes.tags,
+            Some(vec!["a".to_string(), "b".to_string()])
+        );
+        assert_eq!(parsed.changes.publish_at, None);
+    }
+
+    #[test]
+    fn invalid_cells_are_reported() {
+        let title = "t".repeat(MAX_TITLE_LEN + 1);
+        let description = "d".repeat(MAX_DESCRIPTION_LEN + 1);
+        let parsed = row(["", "clip.mp4", &title, &description, "", "tomorrow"]);
+        assert_eq!(parsed.errors.len(), 3, "{:?}", parsed.errors);
+        assert_eq!(parsed.changes.publish_at, None);
+    }
+}
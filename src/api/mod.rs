@@ -1,14 +1,50 @@
 // src/api/mod.rs
+pub mod attachments;
+pub mod captions;
+pub mod changes;
+pub mod channels;
+pub mod clips;
+pub mod convert;
+pub mod dto;
+pub mod embed;
+pub mod frames;
+pub mod grants;
 pub mod health;
+pub mod licenses;
+pub mod metadata_import;
+pub mod moderation;
+pub mod posters;
+pub mod preview_links;
+pub mod profiles;
+pub mod rate_limit;
 pub mod shared;
+pub mod v2;
 pub mod videos;
+pub mod webhooks;
 
+use actix_web::middleware::{from_fn, ErrorHandlers};
 use actix_web::web;
 
 pub fn configure(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope("/api/v1")
+            .wrap(from_fn(rate_limit::limit_uploads))
             .configure(videos::configure)
+            .configure(webhooks::configure)
+            .configure(captions::configure)
+            .configure(convert::configure)
+            .configure(frames::configure)
+            .configure(profiles::configure)
+            .configure(channels::configure)
+            .configure(moderation::configure)
+            .configure(metadata_import::configure)
             .configure(health::configure),
+    )
+    .service(
+        web::scope("/api/v2")
+            // Registered first so rate limit errors also get the v2 envelope
+            .wrap(from_fn(rate_limit::limit_uploads))
+            .wrap(ErrorHandlers::new().default_handler(v2::error_envelope))
+            .configure(v2::configure),
     );
 }
@@ -0,0 +1,355 @@
+use std::sync::Arc;
+
+use crate::api::attachments;
+use crate::api::shared::{has_bearer_token, validation_error, ErrorCode, ResponseType};
+use crate::config::AppConfig;
+use crate::db::models::{AuditEntry, VideoReport};
+use crate::db::DbPool;
+use crate::services::{chunks, jobs, video_processor, webhooks};
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use chrono::Utc;
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl};
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use serde::Deserialize;
+use serde_json::json;
+use uuid::Uuid;
+use vid_storage_models::{
+    AuditEntryResource, CreateReportRequest, ModerationActionRequest, ReportResource,
+};
+
+const REPORT_REASONS: &[&str] = &["spam", "abuse", "sexual", "violence", "copyright", "other"];
+const MODERATION_ACTIONS: &[&str] = &["dismiss", "unlist", "delete"];
+const MAX_DETAILS_LEN: usize = 2000;
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/moderation")
+            .route("/reports", web::get().to(list_reports))
+            .route("/reports/{id}/actions", web::post().to(take_action))
+            .route("/audit", web::get().to(list_audit)),
+    );
+}
+
+#[derive(Deserialize)]
+struct ReportQuery {
+    /// open (the default), dismissed or actioned
+    status: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AuditQuery {
+    video_id: Option<Uuid>,
+}
+
+/// Whether the request carries one of the `moderation.admin_tokens`
+pub(crate) fn is_admin(req: &HttpRequest, config: &AppConfig) -> bool {
+    has_bearer_token(req, &config.moderation.admin_tokens)
+}
+
+/// Rejects requests without one of the `moderation.admin_tokens`
+pub(crate) fn require_admin(req: &HttpRequest, config: &AppConfig) -> Result<(), Error> {
+    if !is_admin(req, config) {
+        return Err(actix_web::error::ErrorForbidden(
+            "This endpoint is restricted to admins",
+        ));
+    }
+    Ok(())
+}
+
+fn report_resource(report: VideoReport) -> ReportResource {
+    ReportResource {
+        id: report.id,
+        video_id: report.video_id,
+        reason: report.reason,
+        details: report.details,
+        status: report.status,
+        created_at: report.created_at.and_utc(),
+        resolved_at: report.resolved_at.map(|at| at.and_utc()),
+    }
+}
+
+fn audit_resource(entry: AuditEntry) -> AuditEntryResource {
+    AuditEntryResource {
+        id: entry.id,
+        action: entry.action,
+        video_id: entry.video_id,
+        report_id: entry.report_id,
+        note: entry.note,
+        created_at: entry.created_at.and_utc(),
+    }
+}
+
+/// Lets any viewer flag a video for the moderation queue
+pub async fn report_video(
+    video_id: web::Path<Uuid>,
+    body: web::Json<CreateReportRequest>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, Error> {
+    use crate::db::schema::{video_reports, videos};
+
+    let body = body.into_inner();
+    if !REPORT_REASONS.contains(&body.reason.as_str()) {
+        return Err(validation_error(
+            "reason".to_string(),
+            format!("Reason must be one of {}", REPORT_REASONS.join(", ")),
+            ErrorCode::ValidationFailed,
+        ));
+    }
+    if body
+        .details
+        .as_ref()
+        .is_some_and(|details| details.chars().count() > MAX_DETAILS_LEN)
+    {
+        return Err(validation_error(
+            "details".to_string(),
+            format!("Details are limited to {} characters", MAX_DETAILS_LEN),
+            ErrorCode::ValidationFailed,
+        ));
+    }
+
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    videos::table
+        .find(*video_id)
+        .filter(videos::status.ne("removed"))
+        .select(videos::id)
+        .first::<Uuid>(conn)
+        .await
+        .optional()
+        .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Video not found"))?;
+
+    let report = VideoReport {
+        id: Uuid::new_v4(),
+        video_id: *video_id,
+        reason: body.reason,
+        details: body.details.filter(|details| !details.trim().is_empty()),
+        status: "open".to_string(),
+        created_at: Utc::now().naive_utc(),
+        resolved_at: None,
+    };
+    diesel::insert_into(video_reports::table)
+        .values(&report)
+        .execute(conn)
+        .await
+        .map_err(|e| {
+            log::error!("Error inserting report: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+
+    Ok(
+        HttpResponse::Created().json(json!(ResponseType::<ReportResource> {
+            data: Some(report_resource(report)),
+            error: None
+        })),
+    )
+}
+
+/// The moderation queue, oldest report first
+async fn list_reports(
+    req: HttpRequest,
+    query: web::Query<ReportQuery>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+) -> Result<HttpResponse, Error> {
+    use crate::db::schema::video_reports;
+
+    require_admin(&req, &config)?;
+    let status = query.status.as_deref().unwrap_or("open");
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    let reports = video_reports::table
+        .filter(video_reports::status.eq(status))
+        .order(video_reports::created_at.asc())
+        .load::<VideoReport>(conn)
+        .await
+        .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+    Ok(
+        HttpResponse::Ok().json(json!(ResponseType::<Vec<ReportResource>> {
+            data: Some(reports.into_iter().map(report_resource).collect()),
+            error: None
+        })),
+    )
+}
+
+/// Resolves an open report and records the action in the audit log
+async fn take_action(
+    req: HttpRequest,
+    report_id: web::Path<Uuid>,
+    body: web::Json<ModerationActionRequest>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+) -> Result<HttpResponse, Error> {
+    use crate::db::schema::{audit_log, video_reports, videos};
+
+    require_admin(&req, &config)?;
+    let body = body.into_inner();
+    if !MODERATION_ACTIONS.contains(&body.action.as_str()) {
+        return Err(validation_error(
+            "action".to_string(),
+            format!("Action must be one of {}", MODERATION_ACTIONS.join(", ")),
+            ErrorCode::ValidationFailed,
+        ));
+    }
+
+    let db_error = |e: diesel::result::Error| {
+        log::error!("Error applying moderation action: {}", e);
+        actix_web::error::ErrorInternalServerError("Database error")
+    };
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    let report_id = *report_id;
+    let action = body.action.clone();
+    // The report is locked, so two moderators can't act on it at once
+    let acted = conn
+        .transaction::<_, diesel::result::Error, _>(|conn| {
+            async move {
+                let Some(report) = video_reports::table
+                    .find(report_id)
+                    .filter(video_reports::status.eq("open"))
+                    .for_update()
+                    .first::<VideoReport>(conn)
+                    .await
+                    .optional()?
+                else {
+                    return Ok(None);
+                };
+
+                let now = Utc::now().naive_utc();
+                let video_id = report.video_id;
+                match action.as_str() {
+                    "dismiss" => {
+                        diesel::update(video_reports::table.find(report.id))
+                            .set((
+                                video_reports::status.eq("dismissed"),
+                                video_reports::resolved_at.eq(now),
+                            ))
+                            .execute(conn)
+                            .await?;
+                    }
+                    action => {
+                        if action == "unlist" {
+                            diesel::update(videos::table.find(video_id))
+                                .set((videos::unlisted.eq(true), videos::updated_at.eq(now)))
+                                .execute(conn)
+                                .await?;
+                        } else {
+                            // Workers see their jobs canceled before the
+                            // files go, rather than failing on missing ones
+                            jobs::cancel(conn, video_id).await?;
+                            diesel::update(videos::table.find(video_id))
+                                .set((
+                                    videos::status.eq("removed"),
+                                    videos::status_reason.eq("Removed by a moderator"),
+                                    videos::error_code.eq(ErrorCode::RemovedByModerator.as_str()),
+                                    videos::updated_at.eq(now),
+                                ))
+                                .execute(conn)
+                                .await?;
+                        }
+                        // Other reports about the video are settled by the same action
+                        diesel::update(
+                            video_reports::table
+                                .filter(video_reports::video_id.eq(video_id))
+                                .filter(video_reports::status.eq("open")),
+                        )
+                        .set((
+                            video_reports::status.eq("actioned"),
+                            video_reports::resolved_at.eq(now),
+                        ))
+                        .execute(conn)
+                        .await?;
+                    }
+                }
+
+                diesel::insert_into(audit_log::table)
+                    .values(&AuditEntry {
+                        id: Uuid::new_v4(),
+                        action,
+                        video_id,
+                        report_id: Some(report.id),
+                        note: body.note,
+                        created_at: now,
+                    })
+                    .execute(conn)
+                    .await?;
+                Ok(Some(report))
+            }
+            .scope_boxed()
+        })
+        .await
+        .map_err(db_error)?;
+    let Some(report) = acted else {
+        let exists = video_reports::table
+            .find(report_id)
+            .select(video_reports::id)
+            .first::<Uuid>(conn)
+            .await
+            .optional()
+            .map_err(db_error)?;
+        return Err(match exists {
+            Some(_) => actix_web::error::ErrorConflict("Report is already resolved"),
+            None => actix_web::error::ErrorNotFound("Report not found"),
+        });
+    };
+
+    // Files only go once the removal is committed
+    if body.action == "delete" {
+        let video_id = report.video_id;
+        let video_dir = video_processor::get_video_dir(video_id);
+        match tokio::fs::remove_dir_all(&video_dir).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => log::error!("Failed to remove files of video {}: {}", video_id, e),
+        }
+        match tokio::fs::remove_dir_all(attachments::attachments_dir(video_id)).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => log::error!("Failed to remove attachments of {}: {}", video_id, e),
+        }
+        if let Err(e) = chunks::remove(conn, video_id).await {
+            log::error!("Failed to remove chunks of video {}: {}", video_id, e);
+        }
+        webhooks::notify(conn, video_id).await;
+    }
+
+    let report = video_reports::table
+        .find(report.id)
+        .first::<VideoReport>(conn)
+        .await
+        .map_err(db_error)?;
+    Ok(
+        HttpResponse::Ok().json(json!(ResponseType::<ReportResource> {
+            data: Some(report_resource(report)),
+            error: None
+        })),
+    )
+}
+
+/// Moderation actions, newest first
+async fn list_audit(
+    req: HttpRequest,
+    query: web::Query<AuditQuery>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+) -> Result<HttpResponse, Error> {
+    use crate::db::schema::audit_log;
+
+    require_admin(&req, &config)?;
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    let mut entries = audit_log::table.into_boxed();
+    if let Some(video_id) = query.video_id {
+        entries = entries.filter(audit_log::video_id.eq(video_id));
+    }
+    let entries = entries
+        .order(audit_log::created_at.desc())
+        .load::<AuditEntry>(conn)
+        .await
+        .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+    Ok(
+        HttpResponse::Ok().json(json!(ResponseType::<Vec<AuditEntryResource>> {
+            data: Some(entries.into_iter().map(audit_resource).collect()),
+            error: None
+        })),
+    )
+}
@@ -0,0 +1,202 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::api::dto::{thumbnail_url, VideoWithThumbnail};
+use crate::api::frames::frame_extraction_failed;
+use crate::api::moderation::require_admin;
+use crate::api::shared::{validation_error, ErrorCode, ResponseType};
+use crate::config::AppConfig;
+use crate::db::models::Video;
+use crate::db::DbPool;
+use crate::services::{frames, video_processor};
+use actix_multipart::Multipart;
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl};
+use diesel_async::RunQueryDsl;
+use futures::TryStreamExt;
+use serde_json::json;
+use tokio::fs;
+use uuid::Uuid;
+use vid_storage_models::PosterRequest;
+
+const MAX_POSTER_SIZE: usize = 10 * 1024 * 1024;
+/// Uploaded posters wider than this are scaled down
+const MAX_POSTER_WIDTH: u32 = 3840;
+
+async fn load_video(video_id: Uuid, pool: &DbPool) -> Result<Video, Error> {
+    use crate::db::schema::videos;
+
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    videos::table
+        .find(video_id)
+        .filter(videos::status.ne("removed"))
+        .first::<Video>(conn)
+        .await
+        .optional()
+        .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Video not found"))
+}
+
+/// Uses the frame `timestamp` seconds into a processed video as its poster.
+/// Only admins may change posters.
+pub async fn set_poster_frame(
+    req: HttpRequest,
+    video_id: web::Path<Uuid>,
+    body: web::Json<PosterRequest>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+) -> Result<HttpResponse, Error> {
+    require_admin(&req, &config)?;
+    let video = load_video(*video_id, &pool).await?;
+    if video.status != "processed" {
+        return Err(actix_web::error::ErrorConflict(
+            "Frames can only be chosen once the video is processed",
+        ));
+    }
+    let duration = video.duration.unwrap_or(0.0);
+    // A frame exactly at the end doesn't exist
+    if !(0.0..duration).contains(&body.timestamp) {
+        return Err(validation_error(
+            "timestamp".to_string(),
+            format!(
+                "{} is outside the video (0 to {})",
+                body.timestamp, duration
+            ),
+            ErrorCode::ValidationFailed,
+        ));
+    }
+
+    let poster_id = Uuid::new_v4();
+    let (video_dir, output) = poster_dir(video.id, poster_id).await?;
+    let input = video_processor::find_original(&video_dir)
+        .await
+        .map_err(|e| storage_error(video.id, e))?;
+    if let Err(e) =
+        frames::extract_frame(&input, &output, body.timestamp, None, &config.ffmpeg).await
+    {
+        log::error!("Failed to extract poster of {}: {}", video.id, e);
+        let _ = fs::remove_file(&output).await;
+        return Err(frame_extraction_failed());
+    }
+    save_poster(&req, video, &video_dir, poster_id, &pool).await
+}
+
+/// Uses the multipart `image` part, in any format ffmpeg reads, as the
+/// video's poster. It is stored as a JPEG. Admins only, as for frames.
+pub async fn upload_poster(
+    req: HttpRequest,
+    video_id: web::Path<Uuid>,
+    mut payload: Multipart,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+) -> Result<HttpResponse, Error> {
+    require_admin(&req, &config)?;
+    let video = load_video(*video_id, &pool).await?;
+
+    let mut image = None;
+    while let Some(mut field) = payload.try_next().await? {
+        if field.name() != Some("image") {
+            continue;
+        }
+        let mut data = web::BytesMut::new();
+        while let Some(chunk) = field.try_next().await? {
+            if data.len() + chunk.len() > MAX_POSTER_SIZE {
+                return Err(actix_web::error::ErrorPayloadTooLarge(format!(
+                    "Posters can be at most {} MB",
+                    MAX_POSTER_SIZE / 1024 / 1024
+                )));
+            }
+            data.extend_from_slice(&chunk);
+        }
+        image = Some(data);
+    }
+    let image = image.ok_or_else(|| actix_web::error::ErrorBadRequest("No image provided"))?;
+
+    let poster_id = Uuid::new_v4();
+    let (video_dir, output) = poster_dir(video.id, poster_id).await?;
+    let upload = output.with_extension("upload");
+    fs::write(&upload, &image)
+        .await
+        .map_err(|e| storage_error(video.id, e))?;
+    let converted = frames::convert_image(&upload, &output, MAX_POSTER_WIDTH, &config.ffmpeg).await;
+    let _ = fs::remove_file(&upload).await;
+    if let Err(e) = converted {
+        log::info!("Rejected poster for video {}: {}", video.id, e);
+        let _ = fs::remove_file(&output).await;
+        return Err(validation_error(
+            "image".to_string(),
+            "File must be an image such as JPEG, PNG or WebP".to_string(),
+            ErrorCode::ValidationFailed,
+        ));
+    }
+    save_poster(&req, video, &video_dir, poster_id, &pool).await
+}
+
+fn storage_error(video_id: Uuid, e: impl std::fmt::Display) -> Error {
+    log::error!("Failed to store poster of {}: {}", video_id, e);
+    actix_web::error::ErrorInternalServerError("Storage error")
+}
+
+/// The video directory, and where the new poster goes in it. ffmpeg runs in
+/// the output directory, so both are absolute.
+async fn poster_dir(video_id: Uuid, poster_id: Uuid) -> Result<(PathBuf, PathBuf), Error> {
+    let video_dir = video_processor::get_video_dir(video_id);
+    let output = frames::poster_path(poster_id);
+    let created = async {
+        fs::create_dir_all(video_dir.join(output.parent().unwrap())).await?;
+        fs::canonicalize(&video_dir).await
+    };
+    let video_dir = created.await.map_err(|e| storage_error(video_id, e))?;
+    let output = video_dir.join(output);
+    Ok((video_dir, output))
+}
+
+/// Points the video at its new poster and removes the one it replaces
+async fn save_poster(
+    req: &HttpRequest,
+    video: Video,
+    video_dir: &Path,
+    poster_id: Uuid,
+    pool: &DbPool,
+) -> Result<HttpResponse, Error> {
+    use crate::db::schema::videos;
+
+    let poster = frames::poster_path(poster_id)
+        .to_string_lossy()
+        .into_owned();
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    let updated = diesel::update(videos::table.find(video.id))
+        .set((
+            videos::poster.eq(Some(&poster)),
+            videos::updated_at.eq(chrono::Utc::now().naive_utc()),
+        ))
+        .get_result::<Video>(conn)
+        .await
+        .map_err(|e| {
+            log::error!("Error saving poster of {}: {}", video.id, e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        });
+    let removed = match updated {
+        Ok(_) => video.poster.as_deref(),
+        Err(_) => Some(poster.as_str()),
+    };
+    if let Some(removed) = removed {
+        let _ = fs::remove_file(video_dir.join(removed)).await;
+    }
+    let video = updated?;
+
+    let base_url = format!(
+        "{}://{}",
+        req.connection_info().scheme(),
+        req.connection_info().host()
+    );
+    Ok(
+        HttpResponse::Ok().json(json!(ResponseType::<VideoWithThumbnail> {
+            data: Some(VideoWithThumbnail {
+                thumbnail_url: thumbnail_url(&video, &base_url),
+                video: video.into(),
+            }),
+            error: None
+        })),
+    )
+}
@@ -0,0 +1,174 @@
+use crate::api::moderation::require_admin;
+use crate::api::shared::{validation_error, ErrorCode, ResponseType};
+use crate::config::AppConfig;
+use crate::db::models::{PreviewLink, Video};
+use crate::db::DbPool;
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use chrono::{NaiveDateTime, TimeDelta, Utc};
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl};
+use diesel_async::RunQueryDsl;
+use serde_json::json;
+use std::sync::Arc;
+use uuid::Uuid;
+use vid_storage_models::{CreatePreviewLinkRequest, PreviewLinkResource};
+
+/// Statuses of videos still on their way to being published
+const UNPUBLISHED_STATUSES: &[&str] = &["uploading", "scanning", "processing"];
+
+/// Without `base_url`, as in listings, the token and the link carrying it
+/// are left out
+fn preview_link_resource(link: PreviewLink, base_url: Option<&str>) -> PreviewLinkResource {
+    PreviewLinkResource {
+        id: link.id,
+        video_id: link.video_id,
+        url: base_url
+            .map(|base_url| format!("{}/embed/{}?token={}", base_url, link.video_id, link.token)),
+        token: base_url.map(|_| link.token),
+        active: Utc::now().naive_utc() < link.expires_at,
+        expires_at: link.expires_at.and_utc(),
+        created_at: link.created_at.and_utc(),
+    }
+}
+
+/// Whether the video is a draft: not processed yet, or scheduled for later
+fn unpublished(video: &Video, now: NaiveDateTime) -> bool {
+    UNPUBLISHED_STATUSES.contains(&video.status.as_str())
+        || (video.status == "processed" && video.publish_at.is_some_and(|at| at > now))
+}
+
+/// Creates an expiring link to the player page of a video that isn't
+/// published yet. Its token plays the video even when it's restricted, so
+/// reviewers need no account; published videos get playback grants instead.
+pub async fn create_preview_link(
+    req: HttpRequest,
+    video_id: web::Path<Uuid>,
+    body: Option<web::Json<CreatePreviewLinkRequest>>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+) -> Result<HttpResponse, Error> {
+    use crate::db::schema::{preview_links, videos};
+
+    require_admin(&req, &config)?;
+
+    let body = body.map(web::Json::into_inner).unwrap_or_default();
+    let previews = &config.previews;
+    let duration_secs = body.duration_secs.unwrap_or(previews.default_duration_secs);
+    if duration_secs == 0 || duration_secs > previews.max_duration_secs {
+        return Err(validation_error(
+            "duration_secs".to_string(),
+            format!(
+                "Duration must be from 1 to {} seconds",
+                previews.max_duration_secs
+            ),
+            ErrorCode::ValidationFailed,
+        ));
+    }
+
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    let video = videos::table
+        .find(*video_id)
+        .first::<Video>(conn)
+        .await
+        .optional()
+        .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Video not found"))?;
+    let now = Utc::now().naive_utc();
+    if !unpublished(&video, now) {
+        return Err(actix_web::error::ErrorConflict(
+            "Only videos that aren't published yet get preview links",
+        ));
+    }
+
+    let expires_at = i64::try_from(duration_secs)
+        .ok()
+        .and_then(TimeDelta::try_seconds)
+        .and_then(|duration| now.checked_add_signed(duration))
+        .ok_or_else(|| actix_web::error::ErrorBadRequest("Duration is too long"))?;
+    let link = PreviewLink {
+        id: Uuid::new_v4(),
+        video_id: video.id,
+        token: Uuid::new_v4().simple().to_string(),
+        expires_at,
+        created_at: now,
+    };
+    diesel::insert_into(preview_links::table)
+        .values(&link)
+        .execute(conn)
+        .await
+        .map_err(|e| {
+            log::error!("Error inserting preview link: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+
+    Ok(
+        HttpResponse::Created().json(json!(ResponseType::<PreviewLinkResource> {
+            data: Some(preview_link_resource(link, Some(&base_url(&req)))),
+            error: None
+        })),
+    )
+}
+
+pub async fn list_preview_links(
+    req: HttpRequest,
+    video_id: web::Path<Uuid>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+) -> Result<HttpResponse, Error> {
+    use crate::db::schema::preview_links;
+
+    require_admin(&req, &config)?;
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    let links = preview_links::table
+        .filter(preview_links::video_id.eq(*video_id))
+        .order(preview_links::created_at.asc())
+        .load::<PreviewLink>(conn)
+        .await
+        .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+    Ok(
+        HttpResponse::Ok().json(json!(ResponseType::<Vec<PreviewLinkResource>> {
+            data: Some(
+                links
+                    .into_iter()
+                    .map(|link| preview_link_resource(link, None))
+                    .collect()
+            ),
+            error: None
+        })),
+    )
+}
+
+/// Revokes a preview link before it expires
+pub async fn delete_preview_link(
+    req: HttpRequest,
+    params: web::Path<(Uuid, Uuid)>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+) -> Result<HttpResponse, Error> {
+    use crate::db::schema::preview_links;
+
+    require_admin(&req, &config)?;
+    let (video_id, link_id) = params.into_inner();
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    let deleted = diesel::delete(
+        preview_links::table
+            .filter(preview_links::id.eq(link_id))
+            .filter(preview_links::video_id.eq(video_id)),
+    )
+    .execute(conn)
+    .await
+    .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?;
+    if deleted == 0 {
+        return Err(actix_web::error::ErrorNotFound("Preview link not found"));
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+fn base_url(req: &HttpRequest) -> String {
+    format!(
+        "{}://{}",
+        req.connection_info().scheme(),
+        req.connection_info().host()
+    )
+}
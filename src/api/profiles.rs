@@ -0,0 +1,207 @@
+use std::sync::Arc;
+
+use crate::api::moderation::require_admin;
+use crate::api::shared::{validation_error, ErrorCode, ResponseType};
+use crate::config::app_config::TranscodingConfig;
+use crate::config::AppConfig;
+use crate::db::models::EncodingProfile;
+use crate::db::DbPool;
+use crate::services::profiles;
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use diesel::upsert::excluded;
+use diesel::{ExpressionMethods, QueryDsl};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde_json::json;
+use vid_storage_models::{EncodingProfileRequest, EncodingProfileResource, RenditionSpec};
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/profiles")
+            .route("", web::get().to(list_profiles))
+            .route("/{name}", web::get().to(get_profile))
+            .route("/{name}", web::put().to(put_profile))
+            .route("/{name}", web::delete().to(delete_profile)),
+    );
+}
+
+/// Fails with a validation error unless `name` is unset or a stored profile
+pub(crate) async fn check_exists(
+    name: Option<&str>,
+    conn: &mut AsyncPgConnection,
+) -> Result<(), Error> {
+    let Some(name) = name else {
+        return Ok(());
+    };
+    match profiles::load(conn, name).await {
+        Ok(Some(_)) => Ok(()),
+        Ok(None) => Err(validation_error(
+            "encoding_profile".to_string(),
+            format!("Encoding profile {} does not exist", name),
+            ErrorCode::ValidationFailed,
+        )),
+        Err(e) => {
+            log::error!("Error loading encoding profile {}: {}", name, e);
+            Err(actix_web::error::ErrorInternalServerError("Database error"))
+        }
+    }
+}
+
+fn profile_resource(profile: EncodingProfile) -> EncodingProfileResource {
+    EncodingProfileResource {
+        // Validated before they are stored
+        renditions: serde_json::from_value::<Vec<RenditionSpec>>(profile.renditions)
+            .unwrap_or_default(),
+        name: profile.name,
+        preset: profile.preset,
+        segment_duration: profile.segment_duration as u32,
+        per_title: profile.per_title,
+        created_at: profile.created_at.and_utc(),
+        updated_at: profile.updated_at.and_utc(),
+    }
+}
+
+async fn list_profiles(pool: web::Data<DbPool>) -> Result<HttpResponse, Error> {
+    use crate::db::schema::encoding_profiles;
+
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    let stored = encoding_profiles::table
+        .order(encoding_profiles::name.asc())
+        .load::<EncodingProfile>(conn)
+        .await
+        .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+    Ok(
+        HttpResponse::Ok().json(json!(ResponseType::<Vec<EncodingProfileResource>> {
+            data: Some(stored.into_iter().map(profile_resource).collect()),
+            error: None
+        })),
+    )
+}
+
+async fn get_profile(
+    name: web::Path<String>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, Error> {
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    let profile = profiles::load(conn, &name)
+        .await
+        .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Encoding profile not found"))?;
+
+    Ok(
+        HttpResponse::Ok().json(json!(ResponseType::<EncodingProfileResource> {
+            data: Some(profile_resource(profile)),
+            error: None
+        })),
+    )
+}
+
+/// Creates or replaces a profile. Videos already processed with it keep
+/// their output; the new settings apply the next time one is processed.
+/// Only admins may change profiles.
+async fn put_profile(
+    req: HttpRequest,
+    name: web::Path<String>,
+    body: web::Json<EncodingProfileRequest>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+) -> Result<HttpResponse, Error> {
+    use crate::db::schema::encoding_profiles;
+
+    require_admin(&req, &config)?;
+    let name = name.into_inner();
+    let safe_name = name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if name.is_empty() || name.len() > 64 || !safe_name {
+        return Err(validation_error(
+            "name".to_string(),
+            "Profile names are 1 to 64 letters, digits, - and _".to_string(),
+            ErrorCode::ValidationFailed,
+        ));
+    }
+
+    let body = body.into_inner();
+    let settings = TranscodingConfig {
+        renditions: profiles::renditions(&body.renditions),
+        preset: body.preset,
+        segment_duration: body
+            .segment_duration
+            .unwrap_or(config.transcoding.segment_duration),
+        per_title: body.per_title.unwrap_or(config.transcoding.per_title),
+    };
+    settings.validate().map_err(|e| {
+        validation_error(
+            "profile".to_string(),
+            e.to_string(),
+            ErrorCode::ValidationFailed,
+        )
+    })?;
+
+    let now = chrono::Utc::now().naive_utc();
+    let profile = EncodingProfile {
+        name,
+        renditions: serde_json::to_value(&body.renditions)
+            .map_err(|_e| actix_web::error::ErrorInternalServerError("Serialization error"))?,
+        preset: settings.preset,
+        segment_duration: settings.segment_duration as i32,
+        created_at: now,
+        updated_at: now,
+        per_title: settings.per_title,
+    };
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    let profile = diesel::insert_into(encoding_profiles::table)
+        .values(&profile)
+        .on_conflict(encoding_profiles::name)
+        .do_update()
+        .set((
+            encoding_profiles::renditions.eq(excluded(encoding_profiles::renditions)),
+            encoding_profiles::preset.eq(excluded(encoding_profiles::preset)),
+            encoding_profiles::segment_duration.eq(excluded(encoding_profiles::segment_duration)),
+            encoding_profiles::per_title.eq(excluded(encoding_profiles::per_title)),
+            encoding_profiles::updated_at.eq(excluded(encoding_profiles::updated_at)),
+        ))
+        .get_result::<EncodingProfile>(conn)
+        .await
+        .map_err(|e| {
+            log::error!("Error saving encoding profile: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+
+    Ok(
+        HttpResponse::Ok().json(json!(ResponseType::<EncodingProfileResource> {
+            data: Some(profile_resource(profile)),
+            error: None
+        })),
+    )
+}
+
+/// Removes a profile no video was uploaded with. Admins only.
+async fn delete_profile(
+    req: HttpRequest,
+    name: web::Path<String>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+) -> Result<HttpResponse, Error> {
+    use crate::db::schema::encoding_profiles;
+
+    require_admin(&req, &config)?;
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    let deleted = diesel::delete(encoding_profiles::table.find(name.as_str()))
+        .execute(conn)
+        .await
+        .map_err(|e| match e {
+            DieselError::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _) => {
+                actix_web::error::ErrorConflict("Encoding profile is used by videos")
+            }
+            _ => actix_web::error::ErrorInternalServerError("Database error"),
+        })?;
+    if deleted == 0 {
+        return Err(actix_web::error::ErrorNotFound(
+            "Encoding profile not found",
+        ));
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,446 @@
+// src/api/rate_limit.rs
+use crate::api::shared::{APIError, ErrorCode, ResponseType};
+use crate::config::app_config::UploadLimitConfig;
+use actix_web::body::MessageBody;
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::error::PayloadError;
+use actix_web::http::{header, Method};
+use actix_web::middleware::Next;
+use actix_web::web::Bytes;
+use actix_web::{web, Error, HttpMessage, HttpResponse};
+use futures::{Stream, TryStreamExt};
+use serde_json::json;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Suggested wait when a client is at its concurrent upload limit
+const CONCURRENCY_RETRY_AFTER: Duration = Duration::from_secs(10);
+
+struct Usage {
+    active: usize,
+    window_start: Instant,
+    bytes: u64,
+}
+
+/// Tracks running uploads and bytes uploaded per client IP
+pub struct UploadLimiter {
+    config: UploadLimitConfig,
+    usage: Mutex<HashMap<IpAddr, Usage>>,
+}
+
+/// Held for the duration of an upload request
+pub(crate) struct UploadPermit {
+    limiter: web::Data<UploadLimiter>,
+    ip: IpAddr,
+    /// Bytes charged when the upload started
+    charged: u64,
+    /// Bytes counted as they arrive; those beyond `charged` are charged when
+    /// the upload ends
+    received: Arc<AtomicU64>,
+}
+
+impl UploadLimiter {
+    pub fn new(config: UploadLimitConfig) -> Self {
+        UploadLimiter {
+            config,
+            usage: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn window(&self) -> Duration {
+        Duration::from_secs(self.config.window_secs)
+    }
+
+    /// The address uploads from `peer` are counted against: its own, or for
+    /// one of `trusted_proxies`, the last address in `forwarded_for` (an
+    /// `X-Forwarded-For` value) that isn't a trusted proxy. Clients can put
+    /// anything in the header, so it's only read after a proxy appended to it.
+    pub(crate) fn client_ip(&self, peer: IpAddr, forwarded_for: Option<&str>) -> IpAddr {
+        let trusted = &self.config.trusted_proxies;
+        if !trusted.contains(&peer) {
+            return peer;
+        }
+        forwarded_for
+            .into_iter()
+            .flat_map(|value| value.rsplit(','))
+            .map_while(|addr| addr.trim().parse::<IpAddr>().ok())
+            .find(|ip| !trusted.contains(ip))
+            .unwrap_or(peer)
+    }
+
+    /// Starts an upload of `expected_bytes` (if known) for `ip`, or returns
+    /// whether the client is at its concurrency limit or out of bytes, and how
+    /// long it should wait before trying again
+    pub(crate) fn acquire(
+        limiter: &web::Data<UploadLimiter>,
+        ip: IpAddr,
+        expected_bytes: Option<u64>,
+    ) -> Result<UploadPermit, (ErrorCode, Duration)> {
+        let config = &limiter.config;
+        let window = limiter.window();
+        let now = Instant::now();
+
+        let mut usage = limiter.usage.lock().unwrap();
+        usage.retain(|_, u| u.active > 0 || now.duration_since(u.window_start) < window);
+        let entry = usage.entry(ip).or_insert(Usage {
+            active: 0,
+            window_start: now,
+            bytes: 0,
+        });
+        if now.duration_since(entry.window_start) >= window {
+            entry.window_start = now;
+            entry.bytes = 0;
+        }
+
+        if config.max_concurrent_uploads > 0 && entry.active >= config.max_concurrent_uploads {
+            return Err((ErrorCode::RateLimited, CONCURRENCY_RETRY_AFTER));
+        }
+        if config.max_upload_bytes > 0
+            && entry.bytes + expected_bytes.unwrap_or(0) > config.max_upload_bytes
+        {
+            let retry_after = window.saturating_sub(now.duration_since(entry.window_start));
+            return Err((ErrorCode::QuotaExceeded, retry_after));
+        }
+
+        entry.active += 1;
+        entry.bytes += expected_bytes.unwrap_or(0);
+        Ok(UploadPermit {
+            limiter: limiter.clone(),
+            ip,
+            charged: expected_bytes.unwrap_or(0),
+            received: Arc::new(AtomicU64::new(0)),
+        })
+    }
+}
+
+impl UploadPermit {
+    /// Counter for the body to add the bytes it receives to, for uploads
+    /// whose size isn't known or enforced up front
+    pub(crate) fn received(&self) -> Arc<AtomicU64> {
+        self.received.clone()
+    }
+}
+
+/// Counts requests per client IP in fixed windows, for endpoints that are
+/// expensive to serve rather than large to upload
+pub struct RequestLimiter {
+    max_requests: u32,
+    window: Duration,
+    usage: Mutex<HashMap<IpAddr, (Instant, u32)>>,
+}
+
+impl RequestLimiter {
+    /// Allows `max_requests` per `window_secs`; 0 disables the limit
+    pub fn new(max_requests: u32, window_secs: u64) -> Self {
+        RequestLimiter {
+            max_requests,
+            window: Duration::from_secs(window_secs),
+            usage: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Counts a request from `ip`, or returns how long the client should wait
+    /// if it is over the limit
+    pub fn check(&self, ip: IpAddr) -> Result<(), Duration> {
+        if self.max_requests == 0 {
+            return Ok(());
+        }
+        let now = Instant::now();
+        let mut usage = self.usage.lock().unwrap();
+        usage.retain(|_, (start, _)| now.duration_since(*start) < self.window);
+        let (start, count) = usage.entry(ip).or_insert((now, 0));
+        if *count >= self.max_requests {
+            return Err(self.window.saturating_sub(now.duration_since(*start)));
+        }
+        *count += 1;
+        Ok(())
+    }
+}
+
+impl Drop for UploadPermit {
+    fn drop(&mut self) {
+        let mut usage = self.limiter.usage.lock().unwrap();
+        if let Some(entry) = usage.get_mut(&self.ip) {
+            entry.active -= 1;
+            let received = self.received.load(Ordering::Relaxed);
+            entry.bytes += received.saturating_sub(self.charged);
+        }
+    }
+}
+
+/// Rejects upload requests from clients over their concurrency or byte limits
+/// with 429 and `Retry-After`. Uploads with a Content-Length are charged up
+/// front; chunked ones are counted as they arrive and charged when done.
+/// Imports leave their permit in the request's extensions, for the download
+/// to hold and count its bytes against.
+pub async fn limit_uploads(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let limiter = req.app_data::<web::Data<UploadLimiter>>().cloned();
+    let (Some(limiter), Some(peer)) = (limiter, req.peer_addr().map(|addr| addr.ip())) else {
+        return next.call(req).await;
+    };
+    let is_json = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"));
+    let Some(kind) = upload_kind(req.method(), req.path(), is_json) else {
+        return next.call(req).await;
+    };
+    let forwarded_for = req
+        .headers()
+        .get_all("X-Forwarded-For")
+        .filter_map(|v| v.to_str().ok())
+        .collect::<Vec<_>>()
+        .join(",");
+    let ip = limiter.client_ip(peer, Some(&forwarded_for));
+
+    // An import's size is only known once its download starts
+    let content_length = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok())
+        .filter(|_| kind == UploadKind::Body);
+
+    let permit = match UploadLimiter::acquire(&limiter, ip, content_length) {
+        Ok(permit) => permit,
+        Err((code, retry_after)) => {
+            log::warn!("Upload from {} rejected by rate limit", ip);
+            let message = rejection_message(code);
+            return Err(too_many_requests(retry_after, code, "upload", message));
+        }
+    };
+
+    if kind == UploadKind::Import {
+        req.extensions_mut().insert(permit);
+        return next.call(req).await;
+    }
+    if content_length.is_none() {
+        let received = permit.received();
+        let payload = req.take_payload().inspect_ok(move |chunk| {
+            received.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+        });
+        let payload: Pin<Box<dyn Stream<Item = Result<Bytes, PayloadError>>>> = Box::pin(payload);
+        req.set_payload(Payload::from(payload));
+    }
+
+    let res = next.call(req).await;
+    drop(permit);
+    res
+}
+
+/// Why `UploadLimiter::acquire` turned an upload down
+pub(crate) fn rejection_message(code: ErrorCode) -> &'static str {
+    match code {
+        ErrorCode::QuotaExceeded => "Upload quota exceeded, try again later",
+        _ => "Too many uploads, try again later",
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum UploadKind {
+    /// The file is in the request body
+    Body,
+    /// The server downloads the file after answering
+    Import,
+}
+
+/// Requests that bring in files: multipart and batch uploads, the second
+/// half of two-phase uploads, imports, conversions, posters, subtitles,
+/// attachments and metadata CSVs
+fn upload_kind(method: &Method, path: &str, is_json: bool) -> Option<UploadKind> {
+    let path = path.trim_end_matches('/');
+    let body = match *method {
+        Method::POST if path.ends_with("/videos/import") => return Some(UploadKind::Import),
+        Method::POST => {
+            path.ends_with("/videos/batch")
+                || path.ends_with("/subtitles")
+                || path.ends_with("/attachments")
+                || path.ends_with("/admin/metadata-import")
+                || ((path.ends_with("/videos") || path.ends_with("/convert")) && !is_json)
+        }
+        Method::PUT => path.ends_with("/content") || (path.ends_with("/poster") && !is_json),
+        _ => false,
+    };
+    body.then_some(UploadKind::Body)
+}
+
+pub(crate) fn too_many_requests(
+    retry_after: Duration,
+    code: ErrorCode,
+    cause: &str,
+    message: &str,
+) -> Error {
+    let body = json!(ResponseType::<String> {
+        data: None,
+        error: Some(APIError {
+            cause: cause.to_string(),
+            message: message.to_string(),
+            code,
+        })
+    });
+    let response = HttpResponse::TooManyRequests()
+        .insert_header((header::RETRY_AFTER, retry_after.as_secs().max(1)))
+        .json(&body);
+    actix_web::error::InternalError::from_response(body, response).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLIENT: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 0, 2, 1));
+    const OTHER: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 0, 2, 2));
+
+    fn limiter(max_concurrent_uploads: usize, max_upload_bytes: u64) -> web::Data<UploadLimiter> {
+        web::Data::new(UploadLimiter::new(UploadLimitConfig {
+            max_concurrent_uploads,
+            max_upload_bytes,
+            window_secs: 60,
+            trusted_proxies: Vec::new(),
+        }))
+    }
+
+    #[test]
+    fn concurrent_uploads_are_limited_per_client() {
+        let limiter = limiter(2, 0);
+        let first = UploadLimiter::acquire(&limiter, CLIENT, None).ok();
+        let _second = UploadLimiter::acquire(&limiter, CLIENT, None).ok();
+        assert_eq!(
+            UploadLimiter::acquire(&limiter, CLIENT, None).err(),
+            Some((ErrorCode::RateLimited, CONCURRENCY_RETRY_AFTER))
+        );
+        assert!(UploadLimiter::acquire(&limiter, OTHER, None).is_ok());
+
+        // Finishing an upload frees its slot
+        drop(first);
+        assert!(UploadLimiter::acquire(&limiter, CLIENT, None).is_ok());
+    }
+
+    #[test]
+    fn upload_bytes_are_limited_per_window() {
+        let limiter = limiter(0, 100);
+        drop(UploadLimiter::acquire(&limiter, CLIENT, Some(60)));
+        let result = UploadLimiter::acquire(&limiter, CLIENT, Some(50));
+        let (code, retry_after) = result.err().unwrap();
+        assert_eq!(code, ErrorCode::QuotaExceeded);
+        assert!(retry_after <= Duration::from_secs(60));
+
+        assert!(UploadLimiter::acquire(&limiter, CLIENT, Some(40)).is_ok());
+        assert!(UploadLimiter::acquire(&limiter, OTHER, Some(100)).is_ok());
+    }
+
+    #[test]
+    fn uploads_of_unknown_size_are_charged_when_done() {
+        let limiter = limiter(0, 100);
+        let permit = UploadLimiter::acquire(&limiter, CLIENT, None).ok().unwrap();
+        permit.received().fetch_add(80, Ordering::Relaxed);
+        assert!(UploadLimiter::acquire(&limiter, CLIENT, Some(30)).is_ok());
+
+        drop(permit);
+        assert_eq!(
+            UploadLimiter::acquire(&limiter, CLIENT, Some(30))
+                .err()
+                .map(|(code, _)| code),
+            Some(ErrorCode::QuotaExceeded)
+        );
+    }
+
+    #[test]
+    fn zero_disables_upload_limits() {
+        let limiter = limiter(0, 0);
+        let permits: Vec<_> = (0..10)
+            .map(|_| UploadLimiter::acquire(&limiter, CLIENT, Some(u32::MAX as u64)).ok())
+            .collect();
+        assert!(permits.iter().all(Option::is_some));
+    }
+
+    #[test]
+    fn requests_are_counted_per_client() {
+        let limiter = RequestLimiter::new(2, 60);
+        assert_eq!(limiter.check(CLIENT), Ok(()));
+        assert_eq!(limiter.check(CLIENT), Ok(()));
+        let retry_after = limiter.check(CLIENT).unwrap_err();
+        assert!(retry_after > Duration::ZERO && retry_after <= Duration::from_secs(60));
+        assert_eq!(limiter.check(OTHER), Ok(()));
+
+        let unlimited = RequestLimiter::new(0, 60);
+        assert!((0..10).all(|_| unlimited.check(CLIENT).is_ok()));
+    }
+
+    #[test]
+    fn forwarded_addresses_are_only_read_from_trusted_proxies() {
+        let proxy = IpAddr::V4(std::net::Ipv4Addr::new(10, 0, 0, 1));
+        let limiter = UploadLimiter::new(UploadLimitConfig {
+            max_concurrent_uploads: 0,
+            max_upload_bytes: 0,
+            window_secs: 60,
+            trusted_proxies: vec![proxy],
+        });
+
+        assert_eq!(limiter.client_ip(proxy, Some("192.0.2.1")), CLIENT);
+        // Only the addresses the proxies appended can be trusted
+        assert_eq!(
+            limiter.client_ip(proxy, Some("203.0.113.9, 192.0.2.1")),
+            CLIENT
+        );
+        assert_eq!(
+            limiter.client_ip(proxy, Some("192.0.2.1, 10.0.0.1")),
+            CLIENT
+        );
+        assert_eq!(limiter.client_ip(proxy, Some("")), proxy);
+        assert_eq!(limiter.client_ip(proxy, None), proxy);
+        // Anyone else is counted by their own address, whatever they send
+        assert_eq!(limiter.client_ip(OTHER, Some("192.0.2.1")), OTHER);
+    }
+
+    #[test]
+    fn every_upload_route_is_limited() {
+        let uploads = [
+            (Method::POST, "/api/v1/videos", false),
+            (Method::POST, "/api/v2/videos/batch", false),
+            (Method::PUT, "/api/v1/videos/id/content", false),
+            (Method::POST, "/api/v1/convert", false),
+            (Method::PUT, "/api/v1/videos/id/poster", false),
+            (Method::POST, "/api/v1/videos/id/subtitles", false),
+            (Method::POST, "/api/v1/videos/id/attachments", false),
+            (Method::POST, "/api/v1/admin/metadata-import", false),
+        ];
+        for (method, path, is_json) in uploads {
+            assert_eq!(
+                upload_kind(&method, path, is_json),
+                Some(UploadKind::Body),
+                "{} {}",
+                method,
+                path
+            );
+        }
+        assert_eq!(
+            upload_kind(&Method::POST, "/api/v2/videos/import", true),
+            Some(UploadKind::Import)
+        );
+
+        let others = [
+            (Method::POST, "/api/v1/videos", true),
+            (Method::PUT, "/api/v1/videos/id/poster", true),
+            (Method::GET, "/api/v1/videos/id/attachments", false),
+            (Method::POST, "/api/v1/videos/id/report", true),
+        ];
+        for (method, path, is_json) in others {
+            assert_eq!(
+                upload_kind(&method, path, is_json),
+                None,
+                "{} {}",
+                method,
+                path
+            );
+        }
+    }
+}
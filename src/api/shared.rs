@@ -1,22 +1,55 @@
-use actix_web::Error;
-use serde::Serialize;
+use actix_web::http::{header, StatusCode};
+use actix_web::{Error, HttpRequest};
 use serde_json::json;
+use subtle::ConstantTimeEq;
 
-#[derive(Debug, Serialize)]
-pub struct APIError {
-    pub cause: String,
-    pub message: String,
+pub use vid_storage_models::{APIError, ErrorCode, ResponseType};
+
+/// An error whose body is the response envelope with the given code
+pub fn api_error(status: StatusCode, code: ErrorCode, cause: String, message: String) -> Error {
+    actix_web::error::InternalError::new(
+        json!(ResponseType::<String> {
+            data: None,
+            error: Some(APIError {
+                cause,
+                message,
+                code
+            })
+        }),
+        status,
+    )
+    .into()
+}
+
+pub fn parse_error(cause: String, message: String, code: ErrorCode) -> Error {
+    api_error(StatusCode::INTERNAL_SERVER_ERROR, code, cause, message)
+}
+
+pub fn validation_error(cause: String, message: String, code: ErrorCode) -> Error {
+    api_error(StatusCode::UNPROCESSABLE_ENTITY, code, cause, message)
+}
+
+/// Whether the request's `Authorization: Bearer` token is one of `tokens`
+pub fn has_bearer_token(req: &HttpRequest, tokens: &[String]) -> bool {
+    req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| is_bearer_token(v, tokens))
 }
 
-#[derive(Debug, Serialize)]
-pub struct ResponseType<T = String> {
-    pub data: Option<T>,
-    pub error: Option<APIError>,
+/// Whether an `Authorization` value is `Bearer` and one of `tokens`. Every
+/// token is compared, in constant time, so response timing doesn't reveal
+/// how much of a guess matched or which token it was.
+pub fn is_bearer_token(authorization: &str, tokens: &[String]) -> bool {
+    let Some(token) = authorization.strip_prefix("Bearer ") else {
+        return false;
+    };
+    tokens
+        .iter()
+        .fold(false, |found, allowed| found | tokens_match(allowed, token))
 }
 
-pub fn parse_error(cause: String, message: String) -> Error {
-    actix_web::error::ErrorInternalServerError(json!(ResponseType::<String> {
-        data: None,
-        error: Some(APIError { cause, message })
-    }))
+/// Whether `given` is the secret `expected`, compared in constant time
+pub fn tokens_match(expected: &str, given: &str) -> bool {
+    bool::from(expected.as_bytes().ct_eq(given.as_bytes()))
 }
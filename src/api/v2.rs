@@ -0,0 +1,322 @@
+// src/api/v2.rs
+//! Version 2 of the HTTP API. Every JSON response, including errors, uses the
+//! `ResponseType` envelope; errors carry a machine-readable `code` and
+//! timestamps are RFC3339 UTC. Handlers share their logic with v1.
+use std::sync::Arc;
+
+use crate::api::dto::{
+    data_saver_url, preview_url, processing_progress, quality_resource, storyboard_url,
+    subtitle_resource, thumbnail_url,
+};
+use crate::api::rate_limit::UploadPermit;
+use crate::api::shared::{api_error, APIError, ErrorCode, ResponseType};
+use crate::api::{grants, moderation, videos};
+use crate::config::AppConfig;
+use crate::db::DbPool;
+use crate::services::events::ProgressEvents;
+use crate::services::import::ImportTracker;
+use actix_multipart::Multipart;
+use actix_web::dev::ServiceResponse;
+use actix_web::guard;
+use actix_web::http::{header, StatusCode};
+use actix_web::middleware::ErrorHandlerResponse;
+use actix_web::{web, Error, HttpMessage, HttpRequest, HttpResponse};
+use chrono::Utc;
+use serde::Serialize;
+use uuid::Uuid;
+use vid_storage_models::{
+    CreateVideoRequest, ImportRequest, ListQueryParams, Page, PageMeta, PendingUpload,
+    UpdateVideoRequest, VideoDetails, VideoListItem, VideoResource,
+};
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.route("/health", web::get().to(health_check))
+        .route("/openapi.yaml", web::get().to(openapi))
+        .service(
+            web::scope("/videos")
+                .app_data(web::PathConfig::default().error_handler(|e, _| {
+                    api_error(
+                        StatusCode::BAD_REQUEST,
+                        ErrorCode::InvalidId,
+                        "id".to_string(),
+                        e.to_string(),
+                    )
+                }))
+                .route(
+                    "",
+                    web::post()
+                        .guard(guard::fn_guard(videos::is_json))
+                        .to(create_video),
+                )
+                .route("", web::post().to(upload_video))
+                .route("/batch", web::post().to(batch_upload))
+                .route("/{id}/content", web::put().to(upload_content))
+                .route("/import", web::post().to(import_video))
+                .route("/{id}", web::get().to(video_details))
+                .route("/{id}", web::patch().to(update_video))
+                .route(
+                    "/{id}/master.m3u8",
+                    web::get().to(videos::serve_master_playlist),
+                )
+                .route(
+                    "/{id}/master_low.m3u8",
+                    web::get().to(videos::serve_low_master_playlist),
+                )
+                .route("/{id}/integrity", web::get().to(videos::check_integrity))
+                .route("/{id}/import", web::get().to(videos::import_progress))
+                .route("/{id}/events", web::get().to(videos::video_events))
+                .route(
+                    "/{id}/{quality}/playlist.m3u8",
+                    web::get().to(videos::serve_quality_playlist),
+                )
+                .route(
+                    "/{video_id}/{quality}/{segment}",
+                    web::get().to(videos::serve_segment),
+                )
+                .route("", web::get().to(list_videos)),
+        );
+}
+
+fn success<T: Serialize>(data: T) -> ResponseType<T> {
+    ResponseType {
+        data: Some(data),
+        error: None,
+    }
+}
+
+fn base_url(req: &HttpRequest) -> String {
+    format!(
+        "{}://{}",
+        req.connection_info().scheme(),
+        req.connection_info().host()
+    )
+}
+
+async fn health_check() -> HttpResponse {
+    HttpResponse::Ok().json(success(serde_json::json!({
+        "status": "ok",
+        "timestamp": Utc::now().to_rfc3339(),
+    })))
+}
+
+/// The OpenAPI document of the envelope and the error codes
+async fn openapi() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("application/yaml")
+        .body(vid_storage_models::OPENAPI)
+}
+
+async fn create_video(
+    req: HttpRequest,
+    body: web::Json<CreateVideoRequest>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, Error> {
+    let (video, upload_token) = videos::create_video_record(body.into_inner(), pool).await?;
+    let upload_url = format!("{}/api/v2/videos/{}/content", base_url(&req), video.id);
+
+    Ok(HttpResponse::Created().json(success(PendingUpload {
+        video: VideoResource::from(video),
+        upload_url,
+        upload_token,
+    })))
+}
+
+async fn upload_video(
+    req: HttpRequest,
+    payload: Multipart,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+    events: web::Data<ProgressEvents>,
+) -> Result<HttpResponse, Error> {
+    let video = videos::receive_upload(req, payload, pool, config, events).await?;
+    Ok(HttpResponse::Created().json(success(VideoResource::from(video))))
+}
+
+async fn batch_upload(
+    payload: Multipart,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+    events: web::Data<ProgressEvents>,
+) -> Result<HttpResponse, Error> {
+    let ids = videos::receive_batch(payload, pool, config, events).await?;
+    Ok(HttpResponse::Created().json(success(ids)))
+}
+
+async fn update_video(
+    req: HttpRequest,
+    video_id: web::Path<Uuid>,
+    body: web::Json<UpdateVideoRequest>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+) -> Result<HttpResponse, Error> {
+    let admin = moderation::is_admin(&req, &config);
+    let video = videos::update_video(*video_id, body.into_inner(), admin, pool).await?;
+    Ok(HttpResponse::Ok().json(success(VideoResource::from(video))))
+}
+
+async fn upload_content(
+    req: HttpRequest,
+    video_id: web::Path<Uuid>,
+    payload: web::Payload,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+    events: web::Data<ProgressEvents>,
+) -> Result<HttpResponse, Error> {
+    let video =
+        videos::receive_content(req, video_id.into_inner(), payload, pool, config, events).await?;
+    Ok(HttpResponse::Ok().json(success(VideoResource::from(video))))
+}
+
+async fn import_video(
+    req: HttpRequest,
+    body: web::Json<ImportRequest>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+    tracker: web::Data<ImportTracker>,
+    events: web::Data<ProgressEvents>,
+) -> Result<HttpResponse, Error> {
+    let permit = req.extensions_mut().remove::<UploadPermit>();
+    let body = body.into_inner();
+    let video = videos::start_import(body, permit, pool, config, tracker, events).await?;
+    Ok(HttpResponse::Accepted().json(success(VideoResource::from(video))))
+}
+
+async fn video_details(
+    req: HttpRequest,
+    video_id: web::Path<Uuid>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+) -> Result<HttpResponse, Error> {
+    let video_id = video_id.into_inner();
+    let base_url = base_url(&req);
+    let reviewer = videos::is_reviewer(&req, &config);
+    let admin = moderation::is_admin(&req, &config);
+    let token = grants::playback_token(&req);
+    let (video, qualities) =
+        videos::load_video_details(video_id, pool.clone(), reviewer, admin, token.as_deref())
+            .await?
+            .ok_or_else(|| {
+                api_error(
+                    StatusCode::NOT_FOUND,
+                    ErrorCode::VideoNotFound,
+                    "video_id".to_string(),
+                    "Video not found".to_string(),
+                )
+            })?;
+    let audio_tracks = videos::load_audio_tracks(video_id, &pool).await?;
+    let subtitles = videos::load_subtitles(video_id, &pool).await?;
+    let thumbnail_url = thumbnail_url(&video, &base_url);
+
+    Ok(HttpResponse::Ok().json(success(VideoDetails {
+        progress: processing_progress(&video, &qualities),
+        video: VideoResource::from(video),
+        qualities: qualities
+            .into_iter()
+            .map(|quality| quality_resource(quality, &base_url))
+            .collect(),
+        audio_tracks: audio_tracks.into_iter().map(Into::into).collect(),
+        subtitles: subtitles
+            .into_iter()
+            .map(|subtitle| subtitle_resource(subtitle, &base_url))
+            .collect(),
+        thumbnail_url,
+        stream_url: format!("{}/uploads/{}/hls/master.m3u8", base_url, video_id),
+        data_saver_url: data_saver_url(video_id, &base_url).await,
+        storyboard_url: storyboard_url(video_id, &base_url).await,
+        preview_url: preview_url(video_id, &base_url).await,
+    })))
+}
+
+async fn list_videos(
+    req: HttpRequest,
+    query: web::Query<ListQueryParams>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, Error> {
+    let base_url = base_url(&req);
+    let page = videos::load_video_page(&query, pool).await?;
+    let total_pages = page.total_pages();
+
+    let items = page
+        .videos
+        .into_iter()
+        .map(|video| VideoListItem {
+            thumbnail_url: thumbnail_url(&video, &base_url),
+            video: VideoResource::from(video),
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(success(Page {
+        items,
+        meta: PageMeta {
+            total: page.total,
+            page: page.page,
+            per_page: page.per_page,
+            total_pages,
+            seed: page.seed,
+            facets: page.facets,
+        },
+    })))
+}
+
+/// Rewrites every error response under /api/v2 into the envelope with a code,
+/// whether it came from a handler, an extractor or routing. Headers such as
+/// `Retry-After` are kept.
+pub fn error_envelope<B>(res: ServiceResponse<B>) -> actix_web::Result<ErrorHandlerResponse<B>> {
+    let status = res.status();
+    let (cause, message, code) = match res.response().error() {
+        Some(e) => error_parts(e),
+        None => (
+            "request".to_string(),
+            status.canonical_reason().unwrap_or("Error").to_string(),
+            None,
+        ),
+    };
+
+    let (req, original) = res.into_parts();
+    let mut response = HttpResponse::build(status).json(ResponseType::<()> {
+        data: None,
+        error: Some(APIError {
+            cause,
+            message,
+            code: code.unwrap_or_else(|| ErrorCode::from_status(status.as_u16())),
+        }),
+    });
+    for (name, value) in original.headers() {
+        if name != header::CONTENT_TYPE && name != header::CONTENT_LENGTH {
+            response.headers_mut().insert(name.clone(), value.clone());
+        }
+    }
+
+    Ok(ErrorHandlerResponse::Response(
+        ServiceResponse::new(req, response).map_into_right_body(),
+    ))
+}
+
+/// Errors built with `api_error` already hold an envelope as their message;
+/// plain errors become a generic `request` cause with a code from the status
+fn error_parts(error: &Error) -> (String, String, Option<ErrorCode>) {
+    let message = error.to_string();
+    if let Ok(ResponseBody {
+        error: Some(ErrorBody {
+            cause,
+            message,
+            code,
+        }),
+    }) = serde_json::from_str(&message)
+    {
+        return (cause, message, Some(code));
+    }
+    ("request".to_string(), message, None)
+}
+
+#[derive(serde::Deserialize)]
+struct ResponseBody {
+    error: Option<ErrorBody>,
+}
+
+#[derive(serde::Deserialize)]
+struct ErrorBody {
+    cause: String,
+    message: String,
+    code: ErrorCode,
+}
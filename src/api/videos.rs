@@ -1,26 +1,150 @@
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::Arc;
 
-use crate::api::shared::{parse_error, ResponseType};
-use crate::db::models::{VideoQuality, VideoWithMeta};
+use crate::api::dto::{
+    data_saver_url, preview_url, processing_progress, storyboard_url, subtitle_resource,
+    thumbnail_url, VideoResponse, VideoWithMeta, VideoWithThumbnail,
+};
+use crate::api::rate_limit::UploadPermit;
+use crate::api::shared::{
+    api_error, has_bearer_token, parse_error, tokens_match, validation_error, ErrorCode,
+    ResponseType,
+};
+use crate::api::{
+    attachments, captions, changes, clips, embed, frames, grants, licenses, moderation, posters,
+    preview_links, profiles,
+};
+use crate::config::app_config::TranscodingConfig;
+use crate::config::AppConfig;
+use crate::db::models::{
+    AudioTrack, IdempotencyKey, JobDependency, ProcessingJob, Subtitle, VideoQuality,
+};
 use crate::db::{models::Video, DbPool};
-use crate::services::video_processor;
+use crate::services::blocking::BlockingPool;
+use crate::services::entitlement::Entitlements;
+use crate::services::events::{ProgressEvent, ProgressEvents};
+use crate::services::import::{self, ImportTracker};
+use crate::services::segment_cache::SegmentCache;
+use crate::services::stalled::Heartbeat;
+use crate::services::video_processor::SavedUpload;
+use crate::services::{
+    chunks, integrity, jobs, keyframes, outbound, playlist, recovery, validator, video_processor,
+    watermark, webhooks,
+};
 use actix_files::NamedFile;
 use actix_multipart::Multipart;
-use actix_web::{web, Error, HttpRequest, HttpResponse};
-use diesel::{BoolExpressionMethods, ExpressionMethods, QueryDsl};
-use diesel_async::RunQueryDsl;
+use actix_web::body::SizedStream;
+use actix_web::guard::{self, GuardContext};
+use actix_web::http::{header, StatusCode};
+use actix_web::mime::Mime;
+use actix_web::{web, Error, HttpMessage, HttpRequest, HttpResponse};
+use diesel::dsl::sql;
+use diesel::sql_types::{BigInt, Text};
+use diesel::{
+    AsChangeset, BoolExpressionMethods, ExpressionMethods, OptionalExtension, QueryDsl,
+    QueryableByName,
+};
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
 use futures::TryStreamExt;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use uuid::Uuid;
+use vid_storage_models::{
+    CreateVideoRequest, ImportRequest, JobNodeResource, Keyframes, ListQueryParams,
+    UpdateVideoRequest,
+};
+
+/// Tallest variant served to clients asking to save data
+const SAVE_DATA_MAX_HEIGHT: u32 = 480;
+/// Media segment files, as opposed to playlists, in a rendition directory
+const SEGMENT_EXTENSIONS: &[&str] = &["ts", "m4s", "mp4"];
+/// Directories of a video served under `/uploads`. The original upload,
+/// attachments, chunks and review proxy are never served from there.
+const PUBLIC_DIRS: &[&str] = &["hls", "thumbnails", "storyboard", "posters"];
 
 pub fn configure(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope("/videos")
+            .route(
+                "",
+                web::post().guard(guard::fn_guard(is_json)).to(create_video),
+            )
             .route("", web::post().to(upload_video))
+            .route("/batch", web::post().to(batch_upload))
+            .route("/{id}/content", web::put().to(upload_content))
+            .route("/import", web::post().to(import_video))
+            .route("/changes", web::get().to(changes::list_changes))
             .route("/{id}", web::get().to(video_details))
+            .route("/{id}", web::patch().to(patch_video))
             .route("/{id}/master.m3u8", web::get().to(serve_master_playlist))
+            .route(
+                "/{id}/master_low.m3u8",
+                web::get().to(serve_low_master_playlist),
+            )
+            .route("/{id}/integrity", web::get().to(check_integrity))
+            .route("/{id}/validate", web::get().to(validate_package))
+            .route("/{id}/jobs", web::get().to(processing_jobs))
+            .route("/{id}/cancel", web::post().to(cancel_video))
+            .route("/{id}/reprocess", web::post().to(reprocess_video))
+            .route("/{id}/keyframes", web::get().to(video_keyframes))
+            .route("/{id}/import", web::get().to(import_progress))
+            .route("/{id}/events", web::get().to(video_events))
+            .route("/{id}/frames", web::post().to(frames::extract_frames))
+            .route("/{id}/thumbnail", web::get().to(frames::thumbnail))
+            .route(
+                "/{id}/poster",
+                web::put()
+                    .guard(guard::fn_guard(is_json))
+                    .to(posters::set_poster_frame),
+            )
+            .route("/{id}/poster", web::put().to(posters::upload_poster))
+            .route("/{id}/clips", web::post().to(clips::create_clip))
+            .route("/{id}/report", web::post().to(moderation::report_video))
+            .route("/{id}/grants", web::post().to(grants::create_grant))
+            .route("/{id}/grants", web::get().to(grants::list_grants))
+            .route(
+                "/{id}/grants/{grant_id}",
+                web::delete().to(grants::delete_grant),
+            )
+            .route(
+                "/{id}/preview-links",
+                web::post().to(preview_links::create_preview_link),
+            )
+            .route(
+                "/{id}/preview-links",
+                web::get().to(preview_links::list_preview_links),
+            )
+            .route(
+                "/{id}/preview-links/{link_id}",
+                web::delete().to(preview_links::delete_preview_link),
+            )
+            .route("/{id}/subtitles", web::post().to(captions::upload_subtitle))
+            .route(
+                "/{id}/caption-requests",
+                web::post().to(captions::create_caption_request),
+            )
+            .route(
+                "/{id}/caption-requests",
+                web::get().to(captions::list_caption_requests),
+            )
+            .route(
+                "/{id}/attachments",
+                web::post().to(attachments::create_attachment),
+            )
+            .route(
+                "/{id}/attachments",
+                web::get().to(attachments::list_attachments),
+            )
+            .route(
+                "/{id}/attachments/{attachment_id}",
+                web::get().to(attachments::download_attachment),
+            )
+            .route(
+                "/{id}/attachments/{attachment_id}",
+                web::delete().to(attachments::delete_attachment),
+            )
             .route(
                 "/{id}/{quality}/playlist.m3u8",
                 web::get().to(serve_quality_playlist),
@@ -37,19 +161,54 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
 pub struct VideoMetadata {
     title: String,
     description: Option<String>,
+    /// Expected SHA-256 of the video, from the `sha256` part or `X-Content-SHA256`
+    sha256: Option<String>,
+    encoding_profile: Option<String>,
+    watermark_position: Option<String>,
+    /// One of `jobs::PRIORITIES`
+    priority: Option<String>,
 }
 
 pub async fn upload_video(
+    req: HttpRequest,
     payload: Multipart,
     pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+    events: web::Data<ProgressEvents>,
 ) -> Result<HttpResponse, Error> {
+    let video = receive_upload(req, payload, pool, config, events).await?;
+    Ok(HttpResponse::Ok().json(VideoResponse::from(video)))
+}
+
+/// Stores a multipart upload and queues it for processing
+pub(crate) async fn receive_upload(
+    req: HttpRequest,
+    payload: Multipart,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+    events: web::Data<ProgressEvents>,
+) -> Result<Video, Error> {
+    use crate::db::schema::idempotency_keys;
+
     let video_id = Uuid::new_v4();
-    let conn = &mut pool.get().await.expect("Failed to get DB connection");
 
-    let mut video_file: Option<(String, Vec<u8>)> = None;
+    // A retry of an upload that already went through gets the original video
+    let idempotency_key = idempotency_key(&req)?;
+    if let Some(key) = &idempotency_key {
+        let conn = &mut pool.get().await.expect("Failed to get DB connection");
+        if let Some(video) = find_idempotent_upload(key, conn).await? {
+            return Ok(video);
+        }
+    }
+
+    let mut video_file: Option<(String, SavedUpload)> = None;
     let mut metadata = VideoMetadata {
         title: "Untitled".to_string(),
         description: None,
+        sha256: content_sha256(&req),
+        encoding_profile: None,
+        watermark_position: None,
+        priority: None,
     };
 
     let mut payload = payload;
@@ -68,11 +227,17 @@ pub async fn upload_video(
                     .ok_or_else(|| actix_web::error::ErrorBadRequest("No filename"))?
                     .to_owned();
 
-                let mut video_data = Vec::new();
-                while let Some(chunk) = field.try_next().await? {
-                    video_data.extend_from_slice(&chunk);
-                }
-                video_file = Some((filename, video_data));
+                let saved = video_processor::save_upload(
+                    &mut field,
+                    video_id,
+                    config.storage.max_file_size,
+                    None,
+                    Some(&filename),
+                    &config,
+                    &events,
+                )
+                .await?;
+                video_file = Some((filename, saved));
             }
             "title" => {
                 let mut title = String::new();
@@ -88,6 +253,40 @@ pub async fn upload_video(
                 }
                 metadata.description = Some(description);
             }
+            "sha256" => {
+                let mut sha256 = String::new();
+                while let Some(chunk) = field.try_next().await? {
+                    sha256.push_str(std::str::from_utf8(&chunk)?);
+                }
+                metadata.sha256 = Some(sha256);
+            }
+            "encoding_profile" => {
+                let mut name = String::new();
+                while let Some(chunk) = field.try_next().await? {
+                    name.push_str(std::str::from_utf8(&chunk)?);
+                }
+                metadata.encoding_profile = Some(name);
+            }
+            "watermark" => {
+                if let Err(e) = watermark::save_image(&mut field, video_id).await {
+                    discard_upload(video_id).await;
+                    return Err(e);
+                }
+            }
+            "watermark_position" => {
+                let mut position = String::new();
+                while let Some(chunk) = field.try_next().await? {
+                    position.push_str(std::str::from_utf8(&chunk)?);
+                }
+                metadata.watermark_position = Some(position);
+            }
+            "priority" => {
+                let mut priority = String::new();
+                while let Some(chunk) = field.try_next().await? {
+                    priority.push_str(std::str::from_utf8(&chunk)?);
+                }
+                metadata.priority = Some(priority);
+            }
             _ => {
                 // Skip unknown fields
                 while (field.try_next().await?).is_some() {}
@@ -95,8 +294,59 @@ pub async fn upload_video(
         }
     }
 
-    let (_filename, video_data) =
-        video_file.ok_or_else(|| actix_web::error::ErrorBadRequest("No video file provided"))?;
+    let Some((filename, SavedUpload { checksum, info })) = video_file else {
+        // A watermark may have been stored already
+        discard_upload(video_id).await;
+        return Err(actix_web::error::ErrorBadRequest("No video file provided"));
+    };
+
+    let checked = match metadata.watermark_position.as_deref() {
+        Some(position) => watermark::validate_position(position),
+        None => Ok(()),
+    }
+    .and_then(|()| match metadata.priority.as_deref() {
+        Some(priority) => jobs::validate_priority(priority),
+        None => Ok(()),
+    });
+    if let Err(e) = checked {
+        discard_upload(video_id).await;
+        return Err(e);
+    }
+
+    // Only taken now that the body is stored, so slow uploads don't hold
+    // connections other requests need
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    if let Err(e) = profiles::check_exists(metadata.encoding_profile.as_deref(), conn).await {
+        discard_upload(video_id).await;
+        return Err(e);
+    }
+
+    if let Some(key) = &idempotency_key {
+        let claimed = diesel::insert_into(idempotency_keys::table)
+            .values(&IdempotencyKey {
+                key: key.clone(),
+                video_id,
+                created_at: chrono::Utc::now().naive_utc(),
+            })
+            .on_conflict_do_nothing()
+            .execute(conn)
+            .await
+            .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+        // A concurrent request with the same key got there first
+        if claimed == 0 {
+            if let Err(e) =
+                tokio::fs::remove_dir_all(video_processor::get_video_dir(video_id)).await
+            {
+                log::error!("Failed to remove duplicate upload {}: {}", video_id, e);
+            }
+            return find_idempotent_upload(key, conn).await?.ok_or_else(|| {
+                actix_web::error::ErrorConflict(
+                    "A request with this Idempotency-Key is in progress",
+                )
+            });
+        }
+    }
 
     let video = Video {
         id: video_id,
@@ -106,201 +356,1864 @@ pub async fn upload_video(
         status: "uploading".to_string(),
         created_at: chrono::Utc::now().naive_utc(),
         updated_at: chrono::Utc::now().naive_utc(),
+        status_reason: None,
+        upload_token: None,
+        checksum: Some(checksum.clone()),
+        processing_profile: None,
+        encoding_profile: metadata.encoding_profile,
+        restricted: false,
+        embed_domains: Vec::new(),
+        unlisted: false,
+        error_code: None,
+        watermark_position: metadata.watermark_position,
+        license: None,
+        attribution: None,
+        source_url: None,
+        tags: Vec::new(),
+        publish_at: None,
+        original_filename: Some(video_processor::client_file_name(&filename)),
+        poster: None,
+        priority: metadata.priority.unwrap_or_else(|| "normal".to_string()),
     };
 
-    diesel::insert_into(crate::db::schema::videos::table)
+    if let Err(e) = diesel::insert_into(crate::db::schema::videos::table)
         .values(&video)
         .execute(conn)
         .await
-        .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?;
-
-    match video_processor::handle_upload(video_data, video_id, pool).await {
-        Ok(_) => {
-            diesel::update(crate::db::schema::videos::table)
-                .filter(crate::db::schema::videos::id.eq(video_id))
-                .set(crate::db::schema::videos::status.eq("processing"))
-                .execute(conn)
-                .await
-                .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?;
-        }
-        Err(e) => {
-            log::error!("Failed to handle upload: {}", e);
-            diesel::update(crate::db::schema::videos::table)
-                .filter(crate::db::schema::videos::id.eq(video_id))
-                .set(crate::db::schema::videos::status.eq("failed"))
+    {
+        log::error!("Error inserting video {}: {}", video_id, e);
+        // Release the key so the client can retry
+        if let Some(key) = &idempotency_key {
+            let _ = diesel::delete(idempotency_keys::table.find(key))
                 .execute(conn)
-                .await
-                .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?;
-            return Err(e);
+                .await;
         }
+        return Err(actix_web::error::ErrorInternalServerError("Database error"));
     }
 
-    Ok(HttpResponse::Ok().json(video))
+    video_processor::verify_checksum(video_id, &checksum, metadata.sha256.as_deref(), conn).await?;
+    video_processor::ingest(video_id, info, conn, pool, config.get_ref().clone(), events).await?;
+
+    Ok(video)
 }
 
-#[derive(Debug, Serialize)]
-struct VideoWithThumbnail {
-    #[serde(flatten)]
-    pub video: Video,
-    pub thumbnail_url: String,
+/// Removes the files of an upload that was refused before its video was created
+async fn discard_upload(video_id: Uuid) {
+    match tokio::fs::remove_dir_all(video_processor::get_video_dir(video_id)).await {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => log::error!("Failed to remove upload {}: {}", video_id, e),
+    }
 }
 
-#[derive(Debug, Deserialize)]
-pub struct ListQueryParams {
-    pub page: Option<i64>,
-    pub per_page: Option<i64>,
+/// Per-file metadata for batch uploads, given as a JSON array in the `metadata`
+/// part and matched to the `video` parts by position
+#[derive(Deserialize, Debug, Default)]
+pub struct BatchItemMetadata {
+    title: Option<String>,
+    description: Option<String>,
+    sha256: Option<String>,
+    encoding_profile: Option<String>,
+    priority: Option<String>,
 }
 
-pub async fn list_videos(
-    req: HttpRequest,
-    query: web::Query<ListQueryParams>,
+/// Uploads several videos in one multipart request, returning their ids in the
+/// order the files were sent. Each file is validated and processed on its own,
+/// so a rejected file shows up in its video's status rather than failing the
+/// batch.
+pub async fn batch_upload(
+    payload: Multipart,
     pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+    events: web::Data<ProgressEvents>,
 ) -> Result<HttpResponse, Error> {
-    use crate::db::schema::videos::dsl::*;
+    let ids = receive_batch(payload, pool, config, events).await?;
+    Ok(HttpResponse::Ok().json(ids))
+}
+
+pub(crate) async fn receive_batch(
+    mut payload: Multipart,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+    events: web::Data<ProgressEvents>,
+) -> Result<Vec<Uuid>, Error> {
+    // (video id, filename, checksum) of each stored file
+    let mut files: Vec<(Uuid, String, SavedUpload)> = Vec::new();
+    let mut metadata: Vec<BatchItemMetadata> = Vec::new();
+
+    let received = async {
+        while let Some(mut field) = payload.try_next().await? {
+            let content_disposition = field
+                .content_disposition()
+                .expect("Failed to get content disposition");
+            let field_name = content_disposition
+                .get_name()
+                .ok_or_else(|| actix_web::error::ErrorBadRequest("No field name"))?;
+
+            match field_name {
+                "video" => {
+                    let filename = content_disposition
+                        .get_filename()
+                        .ok_or_else(|| actix_web::error::ErrorBadRequest("No filename"))?
+                        .to_owned();
+                    let video_id = Uuid::new_v4();
+                    let saved = video_processor::save_upload(
+                        &mut field,
+                        video_id,
+                        config.storage.max_file_size,
+                        None,
+                        Some(&filename),
+                        &config,
+                        &events,
+                    )
+                    .await?;
+                    files.push((video_id, filename, saved));
+                }
+                "metadata" => {
+                    let mut json = String::new();
+                    while let Some(chunk) = field.try_next().await? {
+                        json.push_str(std::str::from_utf8(&chunk)?);
+                    }
+                    metadata = serde_json::from_str(&json).map_err(|e| {
+                        validation_error(
+                            "metadata".to_string(),
+                            format!("Invalid metadata: {}", e),
+                            ErrorCode::ValidationFailed,
+                        )
+                    })?;
+                }
+                _ => {
+                    // Skip unknown fields
+                    while (field.try_next().await?).is_some() {}
+                }
+            }
+        }
+
+        if files.is_empty() {
+            return Err(actix_web::error::ErrorBadRequest("No video files provided"));
+        }
+        if metadata.len() > files.len() {
+            return Err(validation_error(
+                "metadata".to_string(),
+                "More metadata entries than video files".to_string(),
+                ErrorCode::ValidationFailed,
+            ));
+        }
+        let conn = &mut pool.get().await.expect("Failed to get DB connection");
+        for item in &metadata {
+            profiles::check_exists(item.encoding_profile.as_deref(), conn).await?;
+            if let Some(priority) = item.priority.as_deref() {
+                jobs::validate_priority(priority)?;
+            }
+        }
+        Ok(())
+    }
+    .await;
+
+    if let Err(e) = received {
+        for (video_id, _, _) in &files {
+            if let Err(e) =
+                tokio::fs::remove_dir_all(video_processor::get_video_dir(*video_id)).await
+            {
+                log::error!("Failed to remove batch upload {}: {}", video_id, e);
+            }
+        }
+        return Err(e);
+    }
+
+    let mut metadata = metadata.into_iter();
+    let mut uploads = Vec::with_capacity(files.len());
+    let mut videos = Vec::with_capacity(files.len());
+    for (video_id, filename, SavedUpload { checksum, info }) in files {
+        let item = metadata.next().unwrap_or_default();
+        let original_filename = Some(video_processor::client_file_name(&filename));
+        videos.push(Video {
+            id: video_id,
+            // Bulk uploads without metadata are still told apart by file name
+            title: item.title.unwrap_or(filename),
+            description: item.description,
+            duration: None,
+            status: "uploading".to_string(),
+            created_at: chrono::Utc::now().naive_utc(),
+            updated_at: chrono::Utc::now().naive_utc(),
+            status_reason: None,
+            upload_token: None,
+            checksum: Some(checksum.clone()),
+            processing_profile: None,
+            encoding_profile: item.encoding_profile,
+            restricted: false,
+            embed_domains: Vec::new(),
+            unlisted: false,
+            error_code: None,
+            watermark_position: None,
+            license: None,
+            attribution: None,
+            source_url: None,
+            tags: Vec::new(),
+            publish_at: None,
+            original_filename,
+            poster: None,
+            priority: item.priority.unwrap_or_else(|| "normal".to_string()),
+        });
+        uploads.push((video_id, checksum, info, item.sha256));
+    }
+
+    // All rows or none, so a failed insert leaves no video stuck "uploading"
     let conn = &mut pool.get().await.expect("Failed to get DB connection");
-    let base_url = format!(
-        "{}://{}",
-        req.connection_info().scheme(),
-        req.connection_info().host()
-    );
+    if let Err(e) = diesel::insert_into(crate::db::schema::videos::table)
+        .values(&videos)
+        .execute(conn)
+        .await
+    {
+        log::error!("Error inserting batch upload: {}", e);
+        for (video_id, _, _, _) in &uploads {
+            discard_upload(*video_id).await;
+        }
+        return Err(actix_web::error::ErrorInternalServerError("Database error"));
+    }
 
-    let page = query.page.unwrap_or(1);
-    let per_page = query.per_page.unwrap_or(10).min(100); // Maximum 100 items per page
-    let offset = (page - 1) * per_page;
+    let mut ids = Vec::with_capacity(uploads.len());
+    for (video_id, checksum, info, sha256) in uploads {
+        ids.push(video_id);
 
-    let video_list = videos
-        .filter(status.eq("processed"))
-        .order_by(created_at.desc())
-        .offset(offset)
-        .limit(per_page)
-        .load::<Video>(conn)
+        // Failures are recorded on the video itself
+        let ingested =
+            match video_processor::verify_checksum(video_id, &checksum, sha256.as_deref(), conn)
+                .await
+            {
+                Ok(()) => {
+                    video_processor::ingest(
+                        video_id,
+                        info,
+                        conn,
+                        pool.clone(),
+                        config.get_ref().clone(),
+                        events.clone(),
+                    )
+                    .await
+                }
+                Err(e) => Err(e),
+            };
+        if let Err(e) = ingested {
+            log::warn!("Batch upload {} was not queued: {}", video_id, e);
+        }
+    }
+
+    Ok(ids)
+}
+
+fn idempotency_key(req: &HttpRequest) -> Result<Option<String>, Error> {
+    let Some(value) = req.headers().get("Idempotency-Key") else {
+        return Ok(None);
+    };
+    match value.to_str() {
+        Ok(key) if !key.is_empty() && key.len() <= 255 => Ok(Some(key.to_string())),
+        _ => Err(validation_error(
+            "Idempotency-Key".to_string(),
+            "Idempotency-Key must be 1 to 255 visible ASCII characters".to_string(),
+            ErrorCode::ValidationFailed,
+        )),
+    }
+}
+
+/// The video created by an earlier request with this idempotency key, if any.
+/// Fails with a conflict while that request is still receiving its upload.
+async fn find_idempotent_upload(
+    key: &str,
+    conn: &mut AsyncPgConnection,
+) -> Result<Option<Video>, Error> {
+    use crate::db::schema::{idempotency_keys, videos};
+
+    let Some(video_id) = idempotency_keys::table
+        .find(key)
+        .select(idempotency_keys::video_id)
+        .first::<Uuid>(conn)
         .await
-        .map_err(|e| {
-            eprintln!("Error loading videos: {}", e);
-            actix_web::error::ErrorInternalServerError("Database error")
-        })?;
+        .optional()
+        .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?
+    else {
+        return Ok(None);
+    };
 
-    let videos_with_thumbnail: Vec<VideoWithThumbnail> = video_list
-        .into_iter()
-        .map(|video| {
-            let video_id = video.id;
-            VideoWithThumbnail {
-                video,
-                thumbnail_url: format!("{}/uploads/{}/thumbnails/thumb_0.jpg", base_url, video_id),
-            }
+    videos::table
+        .find(video_id)
+        .first::<Video>(conn)
+        .await
+        .optional()
+        .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?
+        .map(Some)
+        .ok_or_else(|| {
+            actix_web::error::ErrorConflict("A request with this Idempotency-Key is in progress")
         })
-        .collect();
+}
 
-    let total_count: i64 = videos.count().get_result(conn).await.map_err(|e| {
-        eprintln!("Error getting total count: {}", e);
-        actix_web::error::ErrorInternalServerError("Database error")
-    })?;
+fn content_sha256(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get("X-Content-SHA256")
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+}
 
-    Ok(HttpResponse::Ok().json(json!({
-        "videos": videos_with_thumbnail,
-        "meta": {
-            "total": total_count,
-            "page": page,
-            "per_page": per_page,
-            "total_pages": (total_count as f64 / per_page as f64).ceil() as i64,
-            "base": base_url,
-        }
-    })))
+pub(crate) fn is_json(ctx: &GuardContext) -> bool {
+    ctx.head()
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"))
 }
 
-pub async fn video_details(
+/// First half of the two-phase upload: creates the video record and returns the
+/// URL and token for sending the file with `PUT /videos/{id}/content`
+pub async fn create_video(
     req: HttpRequest,
-    path: web::Path<String>,
+    body: web::Json<CreateVideoRequest>,
     pool: web::Data<DbPool>,
 ) -> Result<HttpResponse, Error> {
-    use crate::db::schema::{video_qualities, videos};
-    let conn = &mut pool.get().await.expect("Failed to get DB connection");
-    let video_id = match Uuid::from_str(&path.into_inner()) {
-        Ok(v) => v,
-        Err(_) => {
-            return Err(parse_error(
-                "video_id".to_string(),
-                "Failed to parse video id".to_string(),
-            ))
-        }
-    };
+    let (video, upload_token) = create_video_record(body.into_inner(), pool).await?;
     let base_url = format!(
         "{}://{}",
         req.connection_info().scheme(),
         req.connection_info().host()
     );
 
-    let video = match videos::table
-        .filter(videos::id.eq(video_id).and(videos::status.eq("processed")))
-        .first::<Video>(conn)
+    Ok(HttpResponse::Created().json(json!({
+        "upload_url": format!("{}/api/v1/videos/{}/content", base_url, video.id),
+        "video": VideoResponse::from(video),
+        "upload_token": upload_token,
+    })))
+}
+
+/// Inserts an "uploading" video without content, returning it with its upload token
+pub(crate) async fn create_video_record(
+    body: CreateVideoRequest,
+    pool: web::Data<DbPool>,
+) -> Result<(Video, String), Error> {
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    profiles::check_exists(body.encoding_profile.as_deref(), conn).await?;
+    if let Some(position) = body.watermark_position.as_deref() {
+        watermark::validate_position(position)?;
+    }
+    if let Some(priority) = body.priority.as_deref() {
+        jobs::validate_priority(priority)?;
+    }
+    let upload_token = Uuid::new_v4().simple().to_string();
+
+    let video = Video {
+        id: Uuid::new_v4(),
+        title: body.title.unwrap_or_else(|| "Untitled".to_string()),
+        description: body.description,
+        duration: None,
+        status: "uploading".to_string(),
+        created_at: chrono::Utc::now().naive_utc(),
+        updated_at: chrono::Utc::now().naive_utc(),
+        status_reason: None,
+        upload_token: Some(upload_token.clone()),
+        checksum: None,
+        processing_profile: None,
+        encoding_profile: body.encoding_profile,
+        restricted: false,
+        embed_domains: Vec::new(),
+        unlisted: false,
+        error_code: None,
+        watermark_position: body.watermark_position,
+        license: None,
+        attribution: None,
+        source_url: None,
+        tags: Vec::new(),
+        publish_at: None,
+        original_filename: None,
+        poster: None,
+        priority: body.priority.unwrap_or_else(|| "normal".to_string()),
+    };
+
+    diesel::insert_into(crate::db::schema::videos::table)
+        .values(&video)
+        .execute(conn)
         .await
-    {
-        Ok(v) => v,
-        Err(_) => {
-            return Err(parse_error(
-                "db_video_data".to_string(),
-                "Failed to load video data".to_string(),
-            ))
+        .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+    Ok((video, upload_token))
+}
+
+/// Second half of the two-phase upload. The raw request body is the video file;
+/// the transfer can be retried until it succeeds.
+pub async fn upload_content(
+    req: HttpRequest,
+    video_id: web::Path<Uuid>,
+    payload: web::Payload,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+    events: web::Data<ProgressEvents>,
+) -> Result<HttpResponse, Error> {
+    let video = receive_content(req, video_id.into_inner(), payload, pool, config, events).await?;
+    Ok(HttpResponse::Ok().json(VideoResponse::from(video)))
+}
+
+pub(crate) async fn receive_content(
+    req: HttpRequest,
+    video_id: Uuid,
+    mut payload: web::Payload,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+    events: web::Data<ProgressEvents>,
+) -> Result<Video, Error> {
+    use crate::db::schema::videos;
+
+    let token = req
+        .headers()
+        .get("X-Upload-Token")
+        .and_then(|v| v.to_str().ok())
+        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Missing upload token"))?;
+
+    let mut video = {
+        let conn = &mut pool.get().await.expect("Failed to get DB connection");
+        let video = videos::table
+            .filter(videos::id.eq(video_id))
+            .first::<Video>(conn)
+            .await
+            .map_err(|_| actix_web::error::ErrorNotFound("Video not found"))?;
+
+        let valid = video
+            .upload_token
+            .as_deref()
+            .is_some_and(|expected| tokens_match(expected, token));
+        // The token is cleared once the content is in, or while it streams
+        if !valid && video.upload_token.is_some() {
+            return Err(actix_web::error::ErrorForbidden("Invalid upload token"));
+        }
+        // Taking the token claims the upload, so a concurrent request for the
+        // same video can't write the file too
+        let claimed = valid
+            && diesel::update(videos::table.find(video_id))
+                .filter(videos::status.eq("uploading"))
+                .filter(videos::upload_token.eq(token))
+                .set(videos::upload_token.eq(None::<String>))
+                .execute(conn)
+                .await
+                .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?
+                > 0;
+        if !claimed {
+            return Err(actix_web::error::ErrorConflict(
+                "Video content has already been uploaded",
+            ));
         }
+        video
     };
 
-    let video_qualities = video_qualities::table
-        .filter(video_qualities::video_id.eq(video_id))
-        .load::<VideoQuality>(conn)
+    let content_length = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok());
+    let heartbeat = Heartbeat::start(video_id, "uploading", pool.clone());
+    let saved = video_processor::save_upload(
+        &mut payload,
+        video_id,
+        config.storage.max_file_size,
+        content_length,
+        None,
+        &config,
+        &events,
+    )
+    .await;
+    drop(heartbeat);
+    // Only taken now that the body is stored, like in receive_upload
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    let SavedUpload { checksum, info } = match saved {
+        Ok(saved) => saved,
+        Err(e) => {
+            // No retry can succeed once the content is over the limit; other
+            // failures hand the token back so the client can try again
+            if e.as_response_error().status_code() == StatusCode::PAYLOAD_TOO_LARGE {
+                let reason = format!(
+                    "The file exceeds the maximum upload size of {} bytes",
+                    config.storage.max_file_size
+                );
+                match diesel::update(videos::table)
+                    .filter(videos::id.eq(video_id))
+                    .set((
+                        videos::status.eq("rejected"),
+                        videos::status_reason.eq(reason),
+                        videos::error_code.eq(ErrorCode::PayloadTooLarge.as_str()),
+                    ))
+                    .execute(conn)
+                    .await
+                {
+                    Ok(_) => webhooks::notify(conn, video_id).await,
+                    Err(e) => log::error!("Error recording rejection for {}: {}", video_id, e),
+                }
+            } else if let Err(e) = diesel::update(videos::table.find(video_id))
+                .filter(videos::status.eq("uploading"))
+                .set(videos::upload_token.eq(token))
+                .execute(conn)
+                .await
+            {
+                log::error!("Error releasing upload {}: {}", video_id, e);
+            }
+            return Err(e);
+        }
+    };
+    diesel::update(videos::table)
+        .filter(videos::id.eq(video_id))
+        .set(videos::checksum.eq(&checksum))
+        .execute(conn)
         .await
-        .map_err(|e| {
-            eprintln!("Error loading video qualities: {}", e);
-            actix_web::error::ErrorInternalServerError("Database error")
-        })?;
+        .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?;
 
-    Ok(
-        HttpResponse::Ok().json(json!(ResponseType::<VideoWithMeta> {
-            data: Some(VideoWithMeta {
-                video,
-                qualities: video_qualities,
-                thumbnail_url: format!("{}/uploads/{}/thumbnails/thumb_0.jpg", base_url, video_id),
-                stream_url: format!("{}/uploads/{}/hls/master.m3u8", base_url, video_id),
-            }),
-            error: None
-        })),
+    video_processor::verify_checksum(video_id, &checksum, content_sha256(&req).as_deref(), conn)
+        .await?;
+    video_processor::ingest(
+        video_id,
+        info,
+        conn,
+        pool.clone(),
+        config.get_ref().clone(),
+        events,
     )
-}
-
-pub async fn serve_master_playlist(video_id: web::Path<Uuid>) -> Result<NamedFile, Error> {
-    let path = PathBuf::from("uploads")
-        .join(video_id.to_string())
-        .join("hls")
-        .join("master.m3u8");
+    .await?;
 
-    Ok(NamedFile::open(path)
-        .map_err(|_| actix_web::error::ErrorNotFound("Playlist not found"))?
-        // .set_content_type("application/vnd.apple.mpegurl")
-        .use_last_modified(true))
+    video.upload_token = None;
+    video.status = if config.scan.enabled() {
+        "scanning"
+    } else {
+        "processing"
+    }
+    .to_string();
+    video.checksum = Some(checksum);
+    Ok(video)
 }
 
-pub async fn serve_quality_playlist(params: web::Path<(Uuid, String)>) -> Result<NamedFile, Error> {
-    let (video_id, quality) = params.into_inner();
-    let path = PathBuf::from("uploads")
-        .join(video_id.to_string())
-        .join("hls")
-        .join(quality)
-        .join("playlist.m3u8");
-
-    Ok(NamedFile::open(path)
-        .map_err(|_| actix_web::error::ErrorNotFound("Playlist not found"))?
-        // .set_content_type("application/vnd.apple.mpegurl")
-        .use_last_modified(true))
+/// Creates a video from a remote URL; the download and processing continue in
+/// the background and can be followed via `GET /videos/{id}/import`
+pub async fn import_video(
+    req: HttpRequest,
+    body: web::Json<ImportRequest>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+    tracker: web::Data<ImportTracker>,
+    events: web::Data<ProgressEvents>,
+) -> Result<HttpResponse, Error> {
+    let permit = req.extensions_mut().remove::<UploadPermit>();
+    let video = start_import(body.into_inner(), permit, pool, config, tracker, events).await?;
+    Ok(HttpResponse::Accepted().json(VideoResponse::from(video)))
 }
 
-pub async fn serve_segment(params: web::Path<(Uuid, String, String)>) -> Result<NamedFile, Error> {
-    let (video_id, quality, segment) = params.into_inner();
+/// Creates the video of an import and starts downloading it, holding the
+/// rate limit `permit` until the download ends
+pub(crate) async fn start_import(
+    body: ImportRequest,
+    permit: Option<UploadPermit>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+    tracker: web::Data<ImportTracker>,
+    events: web::Data<ProgressEvents>,
+) -> Result<Video, Error> {
+    if let Err(e) = body
+        .url
+        .parse()
+        .map_err(anyhow::Error::from)
+        .and_then(|url| outbound::check_url(&url))
+    {
+        return Err(validation_error(
+            "url".to_string(),
+            format!("The URL can't be imported: {}", e),
+            ErrorCode::ValidationFailed,
+        ));
+    }
+
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    profiles::check_exists(body.encoding_profile.as_deref(), conn).await?;
+    if let Some(priority) = body.priority.as_deref() {
+        jobs::validate_priority(priority)?;
+    }
+    let video = Video {
+        id: Uuid::new_v4(),
+        title: body.title.unwrap_or_else(|| "Untitled".to_string()),
+        description: body.description,
+        duration: None,
+        status: "uploading".to_string(),
+        created_at: chrono::Utc::now().naive_utc(),
+        updated_at: chrono::Utc::now().naive_utc(),
+        status_reason: None,
+        upload_token: None,
+        checksum: None,
+        processing_profile: None,
+        encoding_profile: body.encoding_profile,
+        restricted: false,
+        embed_domains: Vec::new(),
+        unlisted: false,
+        error_code: None,
+        watermark_position: None,
+        license: None,
+        attribution: None,
+        source_url: None,
+        tags: Vec::new(),
+        publish_at: None,
+        original_filename: None,
+        poster: None,
+        priority: body.priority.unwrap_or_else(|| "normal".to_string()),
+    };
+
+    diesel::insert_into(crate::db::schema::videos::table)
+        .values(&video)
+        .execute(conn)
+        .await
+        .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+    import::spawn_import(
+        video.id,
+        body.url,
+        permit,
+        pool.clone(),
+        config.get_ref().clone(),
+        tracker,
+        events,
+    );
+
+    Ok(video)
+}
+
+pub async fn import_progress(
+    video_id: web::Path<Uuid>,
+    tracker: web::Data<ImportTracker>,
+) -> Result<HttpResponse, Error> {
+    let progress = tracker
+        .get(*video_id)
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Import not found"))?;
+
+    Ok(
+        HttpResponse::Ok().json(json!(ResponseType::<import::ImportProgress> {
+            data: Some(progress),
+            error: None
+        })),
+    )
+}
+
+/// Streams upload and processing progress as Server-Sent Events until the video
+/// is done or has failed. Videos that already finished get a single final event.
+pub async fn video_events(
+    video_id: web::Path<Uuid>,
+    pool: web::Data<DbPool>,
+    events: web::Data<ProgressEvents>,
+) -> Result<HttpResponse, Error> {
+    use crate::db::schema::videos;
+
+    let video_id = video_id.into_inner();
+    // Subscribe before reading the status so nothing is missed in between
+    let receiver = events.subscribe(video_id);
+
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    let (status, status_reason) = videos::table
+        .filter(videos::id.eq(video_id))
+        .select((videos::status, videos::status_reason))
+        .first::<(String, Option<String>)>(conn)
+        .await
+        .map_err(|_| actix_web::error::ErrorNotFound("Video not found"))?;
+
+    let finished = match status.as_str() {
+        "processed" => Some(ProgressEvent::Done),
+        "failed" | "rejected" | "canceled" => Some(ProgressEvent::Failed {
+            reason: status_reason.unwrap_or_else(|| "Processing failed".to_string()),
+        }),
+        _ => None,
+    };
+
+    let stream = futures::stream::unfold(
+        (receiver, finished, false),
+        |(mut receiver, finished, closed)| async move {
+            if closed {
+                return None;
+            }
+            let event = match finished {
+                Some(event) => event,
+                None => loop {
+                    match receiver.recv().await {
+                        Ok(event) => break event,
+                        // A slow client only misses intermediate percentages
+                        Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                        Err(tokio::sync::broadcast::error::RecvError::Closed) => return None,
+                    }
+                },
+            };
+            let closed = event.is_terminal();
+            let frame = format!(
+                "event: progress\ndata: {}\n\n",
+                serde_json::to_string(&event).unwrap_or_default()
+            );
+            Some((
+                Ok::<_, Error>(web::Bytes::from(frame)),
+                (receiver, None, closed),
+            ))
+        },
+    );
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .streaming(stream))
+}
+
+/// One page of the processed-video listing
+pub(crate) struct VideoPage {
+    pub videos: Vec<Video>,
+    pub total: i64,
+    pub page: i64,
+    pub per_page: i64,
+    pub seed: Option<String>,
+    pub facets: serde_json::Value,
+}
+
+impl VideoPage {
+    pub fn total_pages(&self) -> i64 {
+        (self.total as f64 / self.per_page as f64).ceil() as i64
+    }
+}
+
+pub async fn list_videos(
+    req: HttpRequest,
+    query: web::Query<ListQueryParams>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, Error> {
+    let base_url = format!(
+        "{}://{}",
+        req.connection_info().scheme(),
+        req.connection_info().host()
+    );
+    let page = load_video_page(&query, pool).await?;
+    let total_pages = page.total_pages();
+
+    let videos_with_thumbnail: Vec<VideoWithThumbnail> = page
+        .videos
+        .into_iter()
+        .map(|video| VideoWithThumbnail {
+            thumbnail_url: thumbnail_url(&video, &base_url),
+            video: video.into(),
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(json!({
+        "videos": videos_with_thumbnail,
+        "facets": page.facets,
+        "meta": {
+            "total": page.total,
+            "page": page.page,
+            "per_page": page.per_page,
+            "total_pages": total_pages,
+            "base": base_url,
+            "seed": page.seed,
+        }
+    })))
+}
+
+pub(crate) async fn load_video_page(
+    query: &ListQueryParams,
+    pool: web::Data<DbPool>,
+) -> Result<VideoPage, Error> {
+    use crate::db::schema::videos::dsl::*;
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+
+    let page = query.page.unwrap_or(1);
+    let per_page = query.per_page.unwrap_or(10).min(100); // Maximum 100 items per page
+    let offset = (page - 1) * per_page;
+
+    let now = chrono::Utc::now().naive_utc();
+    let mut video_query = listable_videos(now);
+    let mut seed = None;
+    match query.sort.as_deref() {
+        None | Some("newest") => video_query = video_query.order_by(created_at.desc()),
+        Some("random") => {
+            // Hashing the id with a seed gives a shuffle that is stable across pages
+            let s = query
+                .seed
+                .clone()
+                .unwrap_or_else(|| Uuid::new_v4().simple().to_string());
+            video_query = video_query.order_by(
+                sql::<Text>("md5(id::text || ")
+                    .bind::<Text, _>(s.clone())
+                    .sql(")"),
+            );
+            seed = Some(s);
+        }
+        Some(_) => return Err(actix_web::error::ErrorBadRequest("Invalid sort parameter")),
+    }
+
+    let video_list = video_query
+        .offset(offset)
+        .limit(per_page)
+        .load::<Video>(conn)
+        .await
+        .map_err(|e| {
+            eprintln!("Error loading videos: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+
+    let total_count: i64 = listable_videos(now)
+        .count()
+        .get_result(conn)
+        .await
+        .map_err(|e| {
+            eprintln!("Error getting total count: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+
+    let facets = load_facets(conn).await.map_err(|e| {
+        eprintln!("Error loading facets: {}", e);
+        actix_web::error::ErrorInternalServerError("Database error")
+    })?;
+
+    Ok(VideoPage {
+        videos: video_list,
+        total: total_count,
+        page,
+        per_page,
+        seed,
+        facets,
+    })
+}
+
+/// The videos listings show at `now`, for both a page and the total
+fn listable_videos(
+    now: chrono::NaiveDateTime,
+) -> crate::db::schema::videos::BoxedQuery<'static, diesel::pg::Pg> {
+    use crate::db::schema::videos::dsl::*;
+
+    videos
+        .filter(status.eq("processed"))
+        .filter(unlisted.eq(false))
+        // Scheduled videos appear once their publish time has passed
+        .filter(publish_at.is_null().or(publish_at.le(now)))
+        .into_boxed()
+}
+
+#[derive(Debug, Serialize, QueryableByName)]
+struct FacetCount {
+    #[diesel(sql_type = Text)]
+    value: String,
+    #[diesel(sql_type = BigInt)]
+    count: i64,
+}
+
+/// Counts of listable videos grouped by duration bucket, upload year and
+/// tag, so gallery UIs can render filter sidebars from the listing response
+/// alone.
+async fn load_facets(
+    conn: &mut AsyncPgConnection,
+) -> Result<serde_json::Value, diesel::result::Error> {
+    let duration = diesel::sql_query(
+        "SELECT CASE
+                WHEN duration IS NULL THEN 'unknown'
+                WHEN duration < 60 THEN 'under_1m'
+                WHEN duration < 300 THEN '1m_5m'
+                WHEN duration < 1200 THEN '5m_20m'
+                ELSE 'over_20m'
+            END AS value,
+            COUNT(*) AS count
+        FROM videos
+        WHERE status = 'processed' AND NOT unlisted
+            AND (publish_at IS NULL OR publish_at <= NOW() AT TIME ZONE 'UTC')
+        GROUP BY value
+        ORDER BY MIN(COALESCE(duration, -1))",
+    )
+    .load::<FacetCount>(conn)
+    .await?;
+
+    let year = diesel::sql_query(
+        "SELECT EXTRACT(YEAR FROM created_at)::INT::TEXT AS value,
+            COUNT(*) AS count
+        FROM videos
+        WHERE status = 'processed' AND NOT unlisted
+            AND (publish_at IS NULL OR publish_at <= NOW() AT TIME ZONE 'UTC')
+        GROUP BY value
+        ORDER BY value DESC",
+    )
+    .load::<FacetCount>(conn)
+    .await?;
+
+    let tag = diesel::sql_query(
+        "SELECT tag AS value, COUNT(*) AS count
+        FROM videos, unnest(tags) AS tag
+        WHERE status = 'processed' AND NOT unlisted
+            AND (publish_at IS NULL OR publish_at <= NOW() AT TIME ZONE 'UTC')
+        GROUP BY value
+        ORDER BY count DESC, value",
+    )
+    .load::<FacetCount>(conn)
+    .await?;
+
+    Ok(json!({
+        "duration": duration,
+        "year": year,
+        "tag": tag,
+    }))
+}
+
+pub async fn video_details(
+    req: HttpRequest,
+    path: web::Path<String>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+) -> Result<HttpResponse, Error> {
+    let video_id = match Uuid::from_str(&path.into_inner()) {
+        Ok(v) => v,
+        Err(_) => {
+            return Err(api_error(
+                StatusCode::BAD_REQUEST,
+                ErrorCode::InvalidId,
+                "video_id".to_string(),
+                "Failed to parse video id".to_string(),
+            ))
+        }
+    };
+    let base_url = format!(
+        "{}://{}",
+        req.connection_info().scheme(),
+        req.connection_info().host()
+    );
+    let reviewer = is_reviewer(&req, &config);
+    let admin = moderation::is_admin(&req, &config);
+    let token = grants::playback_token(&req);
+    let Some((video, video_qualities)) =
+        load_video_details(video_id, pool.clone(), reviewer, admin, token.as_deref()).await?
+    else {
+        return Err(api_error(
+            StatusCode::NOT_FOUND,
+            ErrorCode::VideoNotFound,
+            "video_id".to_string(),
+            "Video not found".to_string(),
+        ));
+    };
+    let audio_tracks = load_audio_tracks(video_id, &pool).await?;
+    let subtitles = load_subtitles(video_id, &pool).await?;
+    let details = video_with_meta(video, video_qualities, audio_tracks, subtitles, &base_url).await;
+
+    Ok(
+        HttpResponse::Ok().json(json!(ResponseType::<VideoWithMeta> {
+            data: Some(details),
+            error: None
+        })),
+    )
+}
+
+/// The details of a video as `GET /videos/{id}` returns them
+async fn video_with_meta(
+    video: Video,
+    qualities: Vec<VideoQuality>,
+    audio_tracks: Vec<AudioTrack>,
+    subtitles: Vec<Subtitle>,
+    base_url: &str,
+) -> VideoWithMeta {
+    let video_id = video.id;
+    VideoWithMeta {
+        progress: processing_progress(&video, &qualities),
+        thumbnail_url: thumbnail_url(&video, base_url),
+        video: video.into(),
+        qualities: qualities.into_iter().map(Into::into).collect(),
+        audio_tracks: audio_tracks.into_iter().map(Into::into).collect(),
+        subtitles: subtitles
+            .into_iter()
+            .map(|subtitle| subtitle_resource(subtitle, base_url))
+            .collect(),
+        stream_url: format!("{}/uploads/{}/hls/master.m3u8", base_url, video_id),
+        data_saver_url: data_saver_url(video_id, base_url).await,
+        storyboard_url: storyboard_url(video_id, base_url).await,
+        preview_url: preview_url(video_id, base_url).await,
+    }
+}
+
+async fn patch_video(
+    req: HttpRequest,
+    video_id: web::Path<Uuid>,
+    body: web::Json<UpdateVideoRequest>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+) -> Result<HttpResponse, Error> {
+    let admin = moderation::is_admin(&req, &config);
+    let video = update_video(*video_id, body.into_inner(), admin, pool).await?;
+    Ok(
+        HttpResponse::Ok().json(json!(ResponseType::<VideoResponse> {
+            data: Some(video.into()),
+            error: None
+        })),
+    )
+}
+
+/// Changes the fields present in the request. A ladder override takes
+/// effect the next time the video is processed, a restriction immediately.
+/// Null clears a ladder override or licensing field. Ladder overrides and
+/// access settings can only be changed by admins.
+pub(crate) async fn update_video(
+    video_id: Uuid,
+    body: UpdateVideoRequest,
+    admin: bool,
+    pool: web::Data<DbPool>,
+) -> Result<Video, Error> {
+    use crate::db::schema::videos;
+
+    #[derive(AsChangeset)]
+    #[diesel(table_name = videos)]
+    struct VideoChanges {
+        processing_profile: Option<Option<serde_json::Value>>,
+        restricted: Option<bool>,
+        embed_domains: Option<Vec<String>>,
+        license: Option<Option<String>>,
+        attribution: Option<Option<String>>,
+        source_url: Option<Option<String>>,
+        updated_at: chrono::NaiveDateTime,
+    }
+
+    admin_only("restricted", body.restricted.is_some(), admin)?;
+    admin_only("embed_domains", body.embed_domains.is_some(), admin)?;
+    admin_only(
+        "processing_profile",
+        body.processing_profile.is_some(),
+        admin,
+    )?;
+
+    let processing_profile = match body.processing_profile {
+        Some(Some(profile)) => {
+            let invalid = |message: String| {
+                validation_error(
+                    "processing_profile".to_string(),
+                    message,
+                    ErrorCode::ValidationFailed,
+                )
+            };
+            TranscodingConfig::validate_renditions(&crate::services::profiles::renditions(
+                &profile.renditions,
+            ))
+            .map_err(|e| invalid(e.to_string()))?;
+            Some(Some(
+                serde_json::to_value(&profile).map_err(|e| invalid(e.to_string()))?,
+            ))
+        }
+        Some(None) => Some(None),
+        None => None,
+    };
+    let embed_domains = body
+        .embed_domains
+        .map(embed::normalize_domains)
+        .transpose()?;
+    let license = body
+        .license
+        .map(|license| license.map(licenses::normalize_license).transpose())
+        .transpose()?;
+    let attribution = body
+        .attribution
+        .map(|attribution| attribution.map(licenses::normalize_attribution).transpose())
+        .transpose()?;
+    let source_url = body
+        .source_url
+        .map(|source_url| source_url.map(licenses::normalize_source_url).transpose())
+        .transpose()?;
+
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    diesel::update(videos::table.filter(videos::id.eq(video_id)))
+        .set(&VideoChanges {
+            processing_profile,
+            restricted: body.restricted,
+            embed_domains,
+            license,
+            attribution,
+            source_url,
+            updated_at: chrono::Utc::now().naive_utc(),
+        })
+        .get_result::<Video>(conn)
+        .await
+        .optional()
+        .map_err(|e| {
+            log::error!("Error updating video {}: {}", video_id, e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Video not found"))
+}
+
+/// Rejects a change to `field` unless it comes from an admin
+fn admin_only(field: &str, changed: bool, admin: bool) -> Result<(), Error> {
+    if changed && !admin {
+        return Err(api_error(
+            StatusCode::FORBIDDEN,
+            ErrorCode::Forbidden,
+            field.to_string(),
+            format!("Only admins can change {}", field),
+        ));
+    }
+    Ok(())
+}
+
+/// Statuses whose videos have details. Processing videos are included so
+/// clients can show per-quality progress, and failed ones so they can show
+/// `status_reason` and `error_code`.
+const DETAIL_STATUSES: [&str; 5] = ["processing", "processed", "failed", "rejected", "canceled"];
+
+/// Loads a video in one of `DETAIL_STATUSES` with its qualities, or None if
+/// there is no such video. The review proxy is only included for reviewers.
+/// Videos scheduled for later are only loaded for admins and the `token` of
+/// one of their preview links.
+pub(crate) async fn load_video_details(
+    video_id: Uuid,
+    pool: web::Data<DbPool>,
+    reviewer: bool,
+    admin: bool,
+    token: Option<&str>,
+) -> Result<Option<(Video, Vec<VideoQuality>)>, Error> {
+    use crate::db::schema::{video_qualities, videos};
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+
+    let video = match videos::table
+        .filter(
+            videos::id
+                .eq(video_id)
+                .and(videos::status.eq_any(DETAIL_STATUSES)),
+        )
+        .first::<Video>(conn)
+        .await
+        .optional()
+    {
+        Ok(Some(v)) => v,
+        Ok(None) => return Ok(None),
+        Err(_) => {
+            return Err(parse_error(
+                "db_video_data".to_string(),
+                "Failed to load video data".to_string(),
+                ErrorCode::InternalError,
+            ))
+        }
+    };
+    let scheduled = video
+        .publish_at
+        .is_some_and(|at| at > chrono::Utc::now().naive_utc());
+    if scheduled && !admin {
+        let previewing = match token {
+            Some(token) => crate::services::grants::previewing(conn, video_id, token)
+                .await
+                .map_err(|e| {
+                    log::error!("Error checking preview links of {}: {}", video_id, e);
+                    actix_web::error::ErrorInternalServerError("Database error")
+                })?,
+            None => false,
+        };
+        if !previewing {
+            return Ok(None);
+        }
+    }
+
+    let mut video_qualities = video_qualities::table
+        .filter(video_qualities::video_id.eq(video_id))
+        .load::<VideoQuality>(conn)
+        .await
+        .map_err(|e| {
+            eprintln!("Error loading video qualities: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+    if !reviewer {
+        video_qualities.retain(|quality| quality.resolution != video_processor::REVIEW_QUALITY.0);
+    }
+
+    Ok(Some((video, video_qualities)))
+}
+
+pub(crate) async fn load_audio_tracks(
+    video_id: Uuid,
+    pool: &DbPool,
+) -> Result<Vec<AudioTrack>, Error> {
+    use crate::db::schema::audio_tracks;
+
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    audio_tracks::table
+        .filter(audio_tracks::video_id.eq(video_id))
+        .order(audio_tracks::position.asc())
+        .load::<AudioTrack>(conn)
+        .await
+        .map_err(|e| {
+            log::error!("Error loading audio tracks: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })
+}
+
+pub(crate) async fn load_subtitles(video_id: Uuid, pool: &DbPool) -> Result<Vec<Subtitle>, Error> {
+    use crate::db::schema::subtitles;
+
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    subtitles::table
+        .filter(subtitles::video_id.eq(video_id))
+        .order(subtitles::created_at.asc())
+        .load::<Subtitle>(conn)
+        .await
+        .map_err(|e| {
+            log::error!("Error loading subtitles: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })
+}
+
+/// Keyframe and scene change times of a processed video, so editors can snap
+/// cuts to them. They need the same access as the video's playlists.
+pub async fn video_keyframes(
+    req: HttpRequest,
+    video_id: web::Path<Uuid>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+    entitlements: web::Data<Entitlements>,
+) -> Result<HttpResponse, Error> {
+    use crate::db::schema::videos;
+
+    embed::check_embedder(&req, *video_id, &pool).await?;
+    grants::authorize_playback(&req, *video_id, &pool, &entitlements).await?;
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    videos::table
+        .find(*video_id)
+        .filter(videos::status.eq("processed"))
+        .select(videos::id)
+        .first::<Uuid>(conn)
+        .await
+        .optional()
+        .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Video not found"))?;
+
+    let video_dir = video_processor::get_video_dir(*video_id);
+    let index = keyframes::load(&video_dir, &config.ffmpeg)
+        .await
+        .map_err(|e| {
+            log::error!("Error indexing keyframes of video {}: {}", video_id, e);
+            actix_web::error::ErrorInternalServerError("Keyframe analysis failed")
+        })?;
+
+    Ok(HttpResponse::Ok().json(json!(ResponseType::<Keyframes> {
+        data: Some(index),
+        error: None
+    })))
+}
+
+/// Admin re-hash of every packaged segment, reporting any that differ from
+/// the checksums recorded at packaging time, e.g. after a storage migration
+pub async fn check_integrity(
+    req: HttpRequest,
+    video_id: web::Path<Uuid>,
+    blocking: web::Data<BlockingPool>,
+    config: web::Data<Arc<AppConfig>>,
+) -> Result<HttpResponse, Error> {
+    moderation::require_admin(&req, &config)?;
+    let hls_dir = video_processor::get_video_dir(*video_id).join("hls");
+    if !hls_dir.is_dir() {
+        return Err(actix_web::error::ErrorNotFound("Video not found"));
+    }
+
+    let verify = async { blocking.run(move || integrity::verify(&hls_dir)).await? };
+    let report = verify.await.map_err(|e| {
+        log::error!("Error verifying video {}: {}", video_id, e);
+        actix_web::error::ErrorInternalServerError("Storage error")
+    })?;
+
+    Ok(
+        HttpResponse::Ok().json(json!(ResponseType::<integrity::IntegrityReport> {
+            data: Some(report),
+            error: None
+        })),
+    )
+}
+
+/// Admin check of a video's HLS package for problems players would hit:
+/// unreadable playlists, missing segments, durations off from the probed
+/// one and implausible bandwidths
+pub async fn validate_package(
+    req: HttpRequest,
+    video_id: web::Path<Uuid>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+) -> Result<HttpResponse, Error> {
+    use crate::db::schema::videos;
+
+    moderation::require_admin(&req, &config)?;
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    let duration = videos::table
+        .find(*video_id)
+        .filter(videos::status.ne("removed"))
+        .select(videos::duration)
+        .first::<Option<f64>>(conn)
+        .await
+        .optional()
+        .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Video not found"))?;
+
+    let hls_dir = video_processor::get_video_dir(*video_id).join("hls");
+    let report = validator::validate(&hls_dir, duration).await;
+    Ok(
+        HttpResponse::Ok().json(json!(ResponseType::<validator::ValidationReport> {
+            data: Some(report),
+            error: None
+        })),
+    )
+}
+
+/// Admin view of a video's processing as the graph of its jobs: each step
+/// with its state and the steps it waits on
+pub async fn processing_jobs(
+    req: HttpRequest,
+    video_id: web::Path<Uuid>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+) -> Result<HttpResponse, Error> {
+    use crate::db::schema::{job_dependencies, processing_jobs};
+
+    moderation::require_admin(&req, &config)?;
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    let steps = processing_jobs::table
+        .filter(processing_jobs::video_id.eq(*video_id))
+        .order(processing_jobs::created_at.asc())
+        .load::<ProcessingJob>(conn)
+        .await
+        .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?;
+    let dependencies = job_dependencies::table
+        .filter(job_dependencies::job_id.eq_any(steps.iter().map(|step| step.id)))
+        .load::<JobDependency>(conn)
+        .await
+        .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+    let nodes = steps
+        .into_iter()
+        .map(|step| JobNodeResource {
+            id: step.id,
+            // Jobs from before processing was split into steps did it all
+            stage: step.stage.unwrap_or_else(|| jobs::PROBE.to_string()),
+            quality: step.quality,
+            state: step.state,
+            attempts: step.attempts,
+            error: step.error,
+            depends_on: dependencies
+                .iter()
+                .filter(|d| d.job_id == step.id)
+                .map(|d| d.depends_on)
+                .collect(),
+            created_at: step.created_at.and_utc(),
+            updated_at: step.updated_at.and_utc(),
+        })
+        .collect::<Vec<_>>();
+    Ok(
+        HttpResponse::Ok().json(json!(ResponseType::<Vec<JobNodeResource>> {
+            data: Some(nodes),
+            error: None
+        })),
+    )
+}
+
+/// Stops the processing of a video. Its jobs are canceled, which kills the
+/// ffmpeg processes of those running, and the output written so far is
+/// removed. Only admins may cancel.
+async fn cancel_video(
+    req: HttpRequest,
+    video_id: web::Path<Uuid>,
+    pool: web::Data<DbPool>,
+    events: web::Data<ProgressEvents>,
+    config: web::Data<Arc<AppConfig>>,
+) -> Result<HttpResponse, Error> {
+    moderation::require_admin(&req, &config)?;
+    let video = cancel_processing(video_id.into_inner(), &pool, &events).await?;
+    Ok(
+        HttpResponse::Ok().json(json!(ResponseType::<VideoResponse> {
+            data: Some(video.into()),
+            error: None
+        })),
+    )
+}
+
+/// Cancels the processing of a video, for `cancel_video` and the gRPC API
+pub(crate) async fn cancel_processing(
+    video_id: Uuid,
+    pool: &DbPool,
+    events: &ProgressEvents,
+) -> Result<Video, Error> {
+    use crate::db::schema::videos;
+
+    let db_error = |e: diesel::result::Error| {
+        log::error!("Error canceling video {}: {}", video_id, e);
+        actix_web::error::ErrorInternalServerError("Database error")
+    };
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    let canceled = conn
+        .transaction::<_, diesel::result::Error, _>(|conn| {
+            async move {
+                let video = diesel::update(videos::table.find(video_id))
+                    .filter(videos::status.eq("processing"))
+                    .set((
+                        videos::status.eq("canceled"),
+                        videos::status_reason.eq("Canceled"),
+                        videos::updated_at.eq(chrono::Utc::now().naive_utc()),
+                    ))
+                    .get_result::<Video>(conn)
+                    .await
+                    .optional()?;
+                if video.is_some() {
+                    jobs::cancel(conn, video_id).await?;
+                }
+                Ok(video)
+            }
+            .scope_boxed()
+        })
+        .await
+        .map_err(db_error)?;
+    let Some(video) = canceled else {
+        let exists = videos::table
+            .find(video_id)
+            .select(videos::id)
+            .first::<Uuid>(conn)
+            .await
+            .optional()
+            .map_err(db_error)?;
+        return Err(match exists {
+            Some(_) => {
+                actix_web::error::ErrorConflict("Only videos being processed can be canceled")
+            }
+            None => actix_web::error::ErrorNotFound("Video not found"),
+        });
+    };
+
+    // Workers remove whatever their aborted jobs write after this too
+    if let Err(e) = recovery::reset_video(conn, video_id).await {
+        log::error!(
+            "Failed to remove output of canceled video {}: {}",
+            video_id,
+            e
+        );
+    }
+    if let Err(e) = chunks::remove(conn, video_id).await {
+        log::error!(
+            "Failed to remove chunks of canceled video {}: {}",
+            video_id,
+            e
+        );
+    }
+    events.publish(
+        video_id,
+        ProgressEvent::Failed {
+            reason: "Canceled".to_string(),
+        },
+    );
+    webhooks::notify(conn, video_id).await;
+
+    Ok(video)
+}
+
+async fn reprocess_video(
+    req: HttpRequest,
+    video_id: web::Path<Uuid>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+) -> Result<HttpResponse, Error> {
+    moderation::require_admin(&req, &config)?;
+    let video = reprocess(video_id.into_inner(), &pool).await?;
+    Ok(
+        HttpResponse::Accepted().json(json!(ResponseType::<VideoResponse> {
+            data: Some(video.into()),
+            error: None
+        })),
+    )
+}
+
+/// Processes a video again from its original, as after a change to its
+/// profile or the ladder, or to retry a failure. Its package is removed
+/// first, so a processed video can't be played until the new one is done.
+pub(crate) async fn reprocess(video_id: Uuid, pool: &DbPool) -> Result<Video, Error> {
+    use crate::db::schema::{processing_jobs, videos};
+
+    let db_error = |e: diesel::result::Error| {
+        log::error!("Error reprocessing video {}: {}", video_id, e);
+        actix_web::error::ErrorInternalServerError("Database error")
+    };
+    let video_dir = video_processor::get_video_dir(video_id);
+    if video_processor::find_original(&video_dir).await.is_err() {
+        return Err(actix_web::error::ErrorConflict(
+            "The original of the video is no longer stored",
+        ));
+    }
+
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    let restarted = conn
+        .transaction::<_, diesel::result::Error, _>(|conn| {
+            async move {
+                let video = diesel::update(videos::table.find(video_id))
+                    .filter(videos::status.eq_any(["processed", "failed", "canceled"]))
+                    .set((
+                        videos::status.eq("processing"),
+                        videos::status_reason.eq(None::<String>),
+                        videos::error_code.eq(None::<String>),
+                        videos::updated_at.eq(chrono::Utc::now().naive_utc()),
+                    ))
+                    .get_result::<Video>(conn)
+                    .await
+                    .optional()?;
+                // Workers still aborting the jobs of a cancellation find
+                // them gone, and leave the new run's output alone
+                if video.is_some() {
+                    diesel::delete(
+                        processing_jobs::table
+                            .filter(processing_jobs::video_id.eq(video_id))
+                            .filter(processing_jobs::state.eq("canceled")),
+                    )
+                    .execute(conn)
+                    .await?;
+                }
+                Ok(video)
+            }
+            .scope_boxed()
+        })
+        .await
+        .map_err(db_error)?;
+    let Some(video) = restarted else {
+        let exists = videos::table
+            .find(video_id)
+            .select(videos::id)
+            .first::<Uuid>(conn)
+            .await
+            .optional()
+            .map_err(db_error)?;
+        return Err(match exists {
+            Some(_) => actix_web::error::ErrorConflict(
+                "Only processed, failed or canceled videos can be reprocessed",
+            ),
+            None => actix_web::error::ErrorNotFound("Video not found"),
+        });
+    };
+
+    // Should any of this fail, the video is left processing without a job,
+    // which recovery queues again on the next start
+    recovery::reset_video(conn, video_id).await.map_err(|e| {
+        log::error!("Failed to remove package of video {}: {}", video_id, e);
+        actix_web::error::ErrorInternalServerError("Storage error")
+    })?;
+    if let Err(e) = chunks::remove(conn, video_id).await {
+        log::error!("Failed to remove chunks of video {}: {}", video_id, e);
+    }
+    jobs::enqueue(video_id, conn).await.map_err(db_error)?;
+    webhooks::notify(conn, video_id).await;
+
+    Ok(video)
+}
+
+/// Query of the master playlist routes
+#[derive(Deserialize)]
+pub struct MasterQuery {
+    /// Leaves out variants taller than this, e.g. 720 for mobile data
+    max_height: Option<u32>,
+}
+
+pub async fn serve_master_playlist(
+    req: HttpRequest,
+    video_id: web::Path<Uuid>,
+    query: web::Query<MasterQuery>,
+    pool: web::Data<DbPool>,
+    entitlements: web::Data<Entitlements>,
+) -> Result<HttpResponse, Error> {
+    serve_master(req, *video_id, "master.m3u8", query, pool, entitlements).await
+}
+
+/// Serves the master playlist without the high-bitrate variants, for data
+/// saver modes
+pub async fn serve_low_master_playlist(
+    req: HttpRequest,
+    video_id: web::Path<Uuid>,
+    query: web::Query<MasterQuery>,
+    pool: web::Data<DbPool>,
+    entitlements: web::Data<Entitlements>,
+) -> Result<HttpResponse, Error> {
+    let file = video_processor::LOW_MASTER;
+    serve_master(req, *video_id, file, query, pool, entitlements).await
+}
+
+/// Serves a master playlist, without the variants above `?max_height` or,
+/// for clients sending `Save-Data: on`, above `SAVE_DATA_MAX_HEIGHT`. For
+/// restricted videos the viewer's token is appended to the variant and
+/// rendition URIs, as players don't carry the master's query string over to
+/// them.
+async fn serve_master(
+    req: HttpRequest,
+    video_id: Uuid,
+    file: &str,
+    query: web::Query<MasterQuery>,
+    pool: web::Data<DbPool>,
+    entitlements: web::Data<Entitlements>,
+) -> Result<HttpResponse, Error> {
+    let path = PathBuf::from("uploads")
+        .join(video_id.to_string())
+        .join("hls")
+        .join(file);
+
+    let save_data = req
+        .headers()
+        .get("Save-Data")
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.trim().eq_ignore_ascii_case("on"));
+    let max_height = [query.max_height, save_data.then_some(SAVE_DATA_MAX_HEIGHT)]
+        .into_iter()
+        .flatten()
+        .min();
+
+    embed::check_embedder(&req, video_id, &pool).await?;
+    let token = grants::authorize_playback(&req, video_id, &pool, &entitlements).await?;
+    if token.is_none() && max_height.is_none() {
+        let mut response = NamedFile::open(path)
+            .map_err(|_| actix_web::error::ErrorNotFound("Playlist not found"))?
+            // .set_content_type("application/vnd.apple.mpegurl")
+            .use_last_modified(true)
+            .into_response(&req);
+        response
+            .headers_mut()
+            .append(header::VARY, header::HeaderValue::from_static("Save-Data"));
+        return Ok(response);
+    }
+
+    let mut master = tokio::fs::read_to_string(path)
+        .await
+        .map_err(|_| actix_web::error::ErrorNotFound("Playlist not found"))?;
+    if let Some(max_height) = max_height {
+        master = playlist::limit_height(&master, max_height);
+    }
+    let Some(token) = token else {
+        return Ok(HttpResponse::Ok()
+            .content_type("application/vnd.apple.mpegurl")
+            .insert_header((header::VARY, "Save-Data"))
+            .body(master));
+    };
+    let body = playlist::with_token(&master, &token);
+    Ok(HttpResponse::Ok()
+        .content_type("application/vnd.apple.mpegurl")
+        // Personalised, so shared caches must not keep it
+        .insert_header((header::CACHE_CONTROL, "private, no-store"))
+        .insert_header((header::VARY, "Save-Data"))
+        .body(body))
+}
+
+pub async fn serve_quality_playlist(
+    req: HttpRequest,
+    params: web::Path<(Uuid, String)>,
+    pool: web::Data<DbPool>,
+    entitlements: web::Data<Entitlements>,
+) -> Result<HttpResponse, Error> {
+    let (video_id, quality) = params.into_inner();
+    if !is_file_name(&quality) {
+        return Err(actix_web::error::ErrorNotFound("Playlist not found"));
+    }
+    embed::check_embedder(&req, video_id, &pool).await?;
+    let token = grants::authorize_playback(&req, video_id, &pool, &entitlements).await?;
+    let path = PathBuf::from("uploads")
+        .join(video_id.to_string())
+        .join("hls")
+        .join(quality)
+        .join("playlist.m3u8");
+    serve_media_playlist(&req, path, token).await
+}
+
+/// Serves a media playlist, with the viewer's token appended to its segment
+/// URIs for restricted videos, since the segments need it too
+async fn serve_media_playlist(
+    req: &HttpRequest,
+    path: PathBuf,
+    token: Option<String>,
+) -> Result<HttpResponse, Error> {
+    let Some(token) = token else {
+        return Ok(NamedFile::open_async(path)
+            .await
+            .map_err(|_| actix_web::error::ErrorNotFound("Playlist not found"))?
+            .use_last_modified(true)
+            .into_response(req));
+    };
+    let media = tokio::fs::read_to_string(path)
+        .await
+        .map_err(|_| actix_web::error::ErrorNotFound("Playlist not found"))?;
+    Ok(HttpResponse::Ok()
+        .content_type("application/vnd.apple.mpegurl")
+        .insert_header((header::CACHE_CONTROL, "private, no-store"))
+        .body(playlist::with_token(&media, &token)))
+}
+
+/// Whether the request carries one of the `review.tokens` bearer tokens
+pub(crate) fn is_reviewer(req: &HttpRequest, config: &AppConfig) -> bool {
+    has_bearer_token(req, &config.review.tokens)
+}
+
+/// Serves the review proxy's playlist and segments, to reviewers only.
+/// Registered ahead of `serve_upload`, which never serves the review directory.
+pub async fn serve_review(
+    req: HttpRequest,
+    params: web::Path<(Uuid, String)>,
+    config: web::Data<Arc<AppConfig>>,
+) -> Result<NamedFile, Error> {
+    if !is_reviewer(&req, &config) {
+        return Err(actix_web::error::ErrorForbidden(
+            "Review proxies are restricted to reviewers",
+        ));
+    }
+    let (video_id, file) = params.into_inner();
+    let Some(file) = file.strip_prefix('/').filter(|file| is_file_name(file)) else {
+        return Err(actix_web::error::ErrorNotFound("File not found"));
+    };
+
+    let path = video_processor::get_video_dir(video_id)
+        .join("review")
+        .join(file);
+    Ok(NamedFile::open_async(path)
+        .await
+        .map_err(|_| actix_web::error::ErrorNotFound("File not found"))?
+        .use_last_modified(true))
+}
+
+/// Plain requests for segments are served from `SegmentCache`; range and
+/// conditional requests go through `NamedFile`, which implements them.
+pub async fn serve_segment(
+    req: HttpRequest,
+    params: web::Path<(Uuid, String, String)>,
+    cache: web::Data<SegmentCache>,
+    pool: web::Data<DbPool>,
+    entitlements: web::Data<Entitlements>,
+) -> Result<HttpResponse, Error> {
+    let (video_id, quality, segment) = params.into_inner();
+    if !is_file_name(&quality) || !is_file_name(&segment) {
+        return Err(actix_web::error::ErrorNotFound("Segment not found"));
+    }
+    // Segments aren't encrypted, so they need the same access as the
+    // rendition playlists that also come through here
+    embed::check_embedder(&req, video_id, &pool).await?;
+    let token = grants::authorize_playback(&req, video_id, &pool, &entitlements).await?;
     let path = PathBuf::from("uploads")
         .join(video_id.to_string())
         .join("hls")
         .join(quality)
         .join(segment);
+    if path.extension().is_some_and(|ext| ext == "m3u8") {
+        return serve_media_playlist(&req, path, token).await;
+    }
+
+    let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+    let content_type = segment_content_type(extension);
+    let plain_request = ![
+        header::RANGE,
+        header::IF_RANGE,
+        header::IF_NONE_MATCH,
+        header::IF_MODIFIED_SINCE,
+    ]
+    .iter()
+    .any(|name| req.headers().contains_key(name));
+
+    if plain_request && SEGMENT_EXTENSIONS.contains(&extension) {
+        if let Some(Ok(segment)) = cache.open(&path).await {
+            let mut response = HttpResponse::Ok();
+            response
+                .content_type(content_type)
+                .insert_header((header::ACCEPT_RANGES, "bytes"));
+            if let Some(modified) = segment.modified {
+                response.insert_header(header::LastModified(modified.into()));
+            }
+            let len = segment.len;
+            return Ok(response.body(SizedStream::new(len, segment.into_stream())));
+        }
+    }
 
     Ok(NamedFile::open(path)
         .map_err(|_| actix_web::error::ErrorNotFound("Segment not found"))?
-        .use_last_modified(true))
+        .set_content_type(content_type)
+        .use_last_modified(true)
+        .into_response(&req))
+}
+
+/// Whether a path segment names a file or directory inside its parent, and
+/// not the parent itself, another directory or a hidden file
+fn is_file_name(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with('.') && !name.contains(['/', '\\'])
+}
+
+/// Serves the files of `PUBLIC_DIRS` the routes above don't, such as
+/// thumbnails, posters, storyboards and subtitles. Every segment of the path
+/// must be a plain name, so empty segments or `..` can't reach the original
+/// or the review proxy. Packages and storyboards show the video, so they
+/// need the same access as its playlists.
+pub async fn serve_upload(
+    req: HttpRequest,
+    params: web::Path<(Uuid, String)>,
+    pool: web::Data<DbPool>,
+    entitlements: web::Data<Entitlements>,
+) -> Result<HttpResponse, Error> {
+    let (video_id, path) = params.into_inner();
+    let names: Vec<&str> = path.split('/').collect();
+    // `split` always yields at least one name
+    let public = PUBLIC_DIRS.contains(&names[0]);
+    if !public || names.len() < 2 || !names.iter().all(|name| is_file_name(name)) {
+        return Err(actix_web::error::ErrorNotFound("File not found"));
+    }
+    let mut token = None;
+    if matches!(names[0], "hls" | "storyboard") {
+        embed::check_embedder(&req, video_id, &pool).await?;
+        token = grants::authorize_playback(&req, video_id, &pool, &entitlements).await?;
+    }
+
+    let file = names
+        .iter()
+        .fold(video_processor::get_video_dir(video_id), |dir, name| {
+            dir.join(name)
+        });
+    // Audio renditions are nested a level deeper than the variants
+    if names[0] == "hls" && file.extension().is_some_and(|ext| ext == "m3u8") {
+        return serve_media_playlist(&req, file, token).await;
+    }
+    Ok(NamedFile::open_async(file)
+        .await
+        .map_err(|_| actix_web::error::ErrorNotFound("File not found"))?
+        .use_last_modified(true)
+        .into_response(&req))
+}
+
+/// MIME type of a segment; CMAF media segments aren't in the usual tables
+fn segment_content_type(extension: &str) -> Mime {
+    match extension {
+        "m4s" => "video/iso.segment".parse().expect("Invalid MIME type"),
+        "ts" => "video/mp2t".parse().expect("Invalid MIME type"),
+        _ => actix_files::file_extension_to_mime(extension),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn failed_video_details_include_the_error_code() {
+        let now = chrono::Utc::now().naive_utc();
+        let video = Video {
+            id: Uuid::new_v4(),
+            title: "Upload".to_string(),
+            description: None,
+            duration: None,
+            status: "failed".to_string(),
+            created_at: now,
+            updated_at: now,
+            status_reason: Some("All renditions failed".to_string()),
+            upload_token: None,
+            checksum: None,
+            processing_profile: None,
+            encoding_profile: None,
+            restricted: false,
+            embed_domains: Vec::new(),
+            unlisted: false,
+            error_code: Some(ErrorCode::TranscodeFailed.as_str().to_string()),
+            watermark_position: None,
+            license: None,
+            attribution: None,
+            source_url: None,
+            tags: Vec::new(),
+            publish_at: None,
+            original_filename: None,
+            poster: None,
+            priority: "normal".to_string(),
+        };
+        let details = video_with_meta(
+            video,
+            Vec::new(),
+            Vec::new(),
+            Vec::new(),
+            "http://localhost",
+        )
+        .await;
+        let details = serde_json::to_value(details).unwrap();
+        assert_eq!(details["status"], "failed");
+        assert_eq!(details["error_code"], "TRANSCODE_FAILED");
+        assert_eq!(details["status_reason"], "All renditions failed");
+        assert!(details["progress"].is_null());
+    }
 }
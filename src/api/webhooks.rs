@@ -0,0 +1,231 @@
+use crate::api::moderation::require_admin;
+use crate::api::shared::{validation_error, ErrorCode, ResponseType};
+use crate::config::AppConfig;
+use crate::db::models::{Video, Webhook};
+use crate::db::DbPool;
+use crate::services::outbound;
+use crate::services::webhooks::{EVENTS, FORMATS};
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl};
+use diesel_async::RunQueryDsl;
+use serde_json::json;
+use std::sync::Arc;
+use uuid::Uuid;
+use vid_storage_models::{
+    CreateWebhookRequest, TestWebhookRequest, WebhookResource, WebhookTestResult,
+};
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/webhooks")
+            .route("", web::post().to(create_webhook))
+            .route("", web::get().to(list_webhooks))
+            .route("/{id}", web::delete().to(delete_webhook))
+            .route("/{id}/test", web::post().to(test_webhook)),
+    );
+}
+
+fn webhook_resource(webhook: Webhook, with_secret: bool) -> WebhookResource {
+    WebhookResource {
+        id: webhook.id,
+        url: webhook.url,
+        secret: with_secret.then_some(webhook.secret),
+        events: webhook.events,
+        format: webhook.format,
+        template: webhook.template,
+        created_at: webhook.created_at.and_utc(),
+    }
+}
+
+/// Registers a URL to be notified of video status changes, or of those it
+/// subscribes to. The response is the only place the signing secret is shown.
+async fn create_webhook(
+    req: HttpRequest,
+    body: web::Json<CreateWebhookRequest>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+) -> Result<HttpResponse, Error> {
+    use crate::db::schema::webhooks;
+
+    require_admin(&req, &config)?;
+    let body = body.into_inner();
+    if let Err(e) = body
+        .url
+        .parse()
+        .map_err(anyhow::Error::from)
+        .and_then(|url| outbound::check_url(&url))
+    {
+        return Err(validation_error(
+            "url".to_string(),
+            format!("The URL can't receive webhooks: {}", e),
+            ErrorCode::ValidationFailed,
+        ));
+    }
+    if body.secret.as_deref() == Some("") {
+        return Err(validation_error(
+            "secret".to_string(),
+            "Secret must not be empty".to_string(),
+            ErrorCode::ValidationFailed,
+        ));
+    }
+
+    if let Some(event) = body
+        .events
+        .iter()
+        .find(|event| !EVENTS.contains(&event.as_str()))
+    {
+        return Err(validation_error(
+            "events".to_string(),
+            format!("Unknown event {}; events are {}", event, EVENTS.join(", ")),
+            ErrorCode::ValidationFailed,
+        ));
+    }
+    if let Some(format) = &body.format {
+        if !FORMATS.contains(&format.as_str()) {
+            return Err(validation_error(
+                "format".to_string(),
+                format!("Format must be one of {}", FORMATS.join(", ")),
+                ErrorCode::ValidationFailed,
+            ));
+        }
+        if body.template.is_some() {
+            return Err(validation_error(
+                "format".to_string(),
+                "Templated webhooks have no format".to_string(),
+                ErrorCode::ValidationFailed,
+            ));
+        }
+    }
+
+    let mut events = body.events;
+    events.sort();
+    events.dedup();
+    let webhook = Webhook {
+        id: Uuid::new_v4(),
+        url: body.url,
+        secret: body
+            .secret
+            .unwrap_or_else(|| Uuid::new_v4().simple().to_string()),
+        created_at: chrono::Utc::now().naive_utc(),
+        events,
+        format: body.format.unwrap_or_else(|| FORMATS[0].to_string()),
+        template: body.template,
+    };
+
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    diesel::insert_into(webhooks::table)
+        .values(&webhook)
+        .execute(conn)
+        .await
+        .map_err(|e| {
+            log::error!("Error inserting webhook: {}", e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+
+    Ok(
+        HttpResponse::Created().json(json!(ResponseType::<WebhookResource> {
+            data: Some(webhook_resource(webhook, true)),
+            error: None
+        })),
+    )
+}
+
+async fn list_webhooks(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+) -> Result<HttpResponse, Error> {
+    use crate::db::schema::webhooks;
+
+    require_admin(&req, &config)?;
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    let hooks = webhooks::table
+        .order(webhooks::created_at.asc())
+        .load::<Webhook>(conn)
+        .await
+        .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+    Ok(
+        HttpResponse::Ok().json(json!(ResponseType::<Vec<WebhookResource>> {
+            data: Some(
+                hooks
+                    .into_iter()
+                    .map(|hook| webhook_resource(hook, false))
+                    .collect()
+            ),
+            error: None
+        })),
+    )
+}
+
+async fn delete_webhook(
+    req: HttpRequest,
+    webhook_id: web::Path<Uuid>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+) -> Result<HttpResponse, Error> {
+    use crate::db::schema::webhooks;
+
+    require_admin(&req, &config)?;
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    let deleted = diesel::delete(webhooks::table.filter(webhooks::id.eq(*webhook_id)))
+        .execute(conn)
+        .await
+        .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?;
+    if deleted == 0 {
+        return Err(actix_web::error::ErrorNotFound("Webhook not found"));
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Sends the webhook a `webhook.test` payload about a video, in its format or
+/// from its template, and reports whether the endpoint accepted it
+async fn test_webhook(
+    req: HttpRequest,
+    webhook_id: web::Path<Uuid>,
+    body: Option<web::Json<TestWebhookRequest>>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+) -> Result<HttpResponse, Error> {
+    use crate::db::schema::{videos, webhooks};
+
+    require_admin(&req, &config)?;
+    let body = body.map(web::Json::into_inner).unwrap_or_default();
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    let hook = webhooks::table
+        .find(*webhook_id)
+        .first::<Webhook>(conn)
+        .await
+        .optional()
+        .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Webhook not found"))?;
+    // Tests never send out the details of a video moderators removed
+    let listed = videos::table.filter(videos::status.ne("removed"));
+    let video = match body.video_id {
+        Some(v_id) => listed.find(v_id).first::<Video>(conn).await,
+        None => {
+            listed
+                .order(videos::updated_at.desc())
+                .first::<Video>(conn)
+                .await
+        }
+    }
+    .optional()
+    .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?
+    .ok_or_else(|| actix_web::error::ErrorNotFound("No video to describe in the test payload"))?;
+
+    let result = crate::services::webhooks::test(&hook, video)
+        .await
+        .map_err(|e| {
+            log::error!("Error testing webhook {}: {}", hook.id, e);
+            actix_web::error::ErrorInternalServerError("Failed to send test payload")
+        })?;
+
+    Ok(
+        HttpResponse::Ok().json(json!(ResponseType::<WebhookTestResult> {
+            data: Some(result),
+            error: None
+        })),
+    )
+}
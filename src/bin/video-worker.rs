@@ -0,0 +1,41 @@
+use actix_web::web;
+use dotenv::dotenv;
+use std::sync::Arc;
+use video::{config, db, services};
+
+/// Runs the jobs API nodes queue, for processing to scale out over as many
+/// workers as needed. Takes the same configuration as the API nodes, usually
+/// with `worker.embedded` off there, and needs their uploads at the same path.
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    dotenv().ok();
+    env_logger::init();
+
+    let config = config::AppConfig::new().expect("Failed to load configuration");
+    let config = Arc::new(config);
+    services::outbound::init(&config.outbound);
+
+    services::ffmpeg::verify(&config.ffmpeg)
+        .await
+        .expect("ffmpeg check failed");
+
+    let pool = web::Data::new(db::create_pool(&config.database.url).await);
+    // Nobody subscribes here, so progress goes to the API nodes
+    let events = web::Data::new(services::notifications::relay_events(pool.clone()));
+    let blocking = web::Data::new(
+        services::blocking::BlockingPool::new(config.storage.blocking_threads)
+            .expect("Failed to start blocking pool"),
+    );
+
+    log::info!(
+        "Starting worker for up to {} jobs at a time",
+        config.ffmpeg.max_concurrent_jobs.max(1)
+    );
+    services::jobs::start_worker(pool, config, events, blocking);
+
+    // Jobs still running are taken over by other workers once their lease
+    // expires
+    tokio::signal::ctrl_c().await?;
+    log::info!("Stopping worker");
+    Ok(())
+}
@@ -1,6 +1,8 @@
-use config::{Config, ConfigError, Environment, File};
+use crate::services::video_processor::{HDR_QUALITY, REVIEW_QUALITY};
+use config::{Config, ConfigError, Environment, File, Map, Value};
 use serde::Deserialize;
 use std::env;
+use std::net::IpAddr;
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct AppConfig {
@@ -8,12 +10,30 @@ pub struct AppConfig {
     pub database: DatabaseConfig,
     pub storage: StorageConfig,
     pub ffmpeg: FfmpegConfig,
+    pub transcoding: TranscodingConfig,
+    pub validation: ValidationConfig,
+    pub upload_limits: UploadLimitConfig,
+    pub scan: ScanConfig,
+    pub review: ReviewConfig,
+    pub frames: FrameConfig,
+    pub entitlement: EntitlementConfig,
+    pub moderation: ModerationConfig,
+    pub watermark: WatermarkConfig,
+    pub loudness: LoudnessConfig,
+    pub worker: WorkerConfig,
+    pub captions: CaptionsConfig,
+    pub previews: PreviewConfig,
+    pub outbound: OutboundConfig,
+    pub imports: ImportConfig,
+    pub convert: ConvertConfig,
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct ServerConfig {
     pub host: String,
     pub port: u16,
+    /// Port of the gRPC management API; requires the `grpc` feature
+    pub grpc_port: Option<u16>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -26,12 +46,435 @@ pub struct DatabaseConfig {
 pub struct StorageConfig {
     pub upload_path: String,
     pub max_file_size: usize, // in bytes
+    /// Segment files kept open for fast serving; 0 disables
+    pub segment_cache_size: usize,
+    /// Threads for checksum audits and other long filesystem traversals
+    pub blocking_threads: usize,
+    /// Videos still uploading this long after their last change are failed
+    /// and their partial files removed; 0 keeps them forever
+    pub stalled_upload_timeout_secs: u64,
+    /// Largest file that can be attached to a video, in bytes
+    pub max_attachment_size: usize,
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct FfmpegConfig {
+    /// ffmpeg to run, looked up on `PATH` unless it is a path
+    pub binary_path: String,
+    /// ffprobe to run, looked up on `PATH` unless it is a path
+    pub ffprobe_path: String,
     pub thread_count: usize,
     pub preset: String,
+    /// Videos transcoded at the same time; further jobs wait in the queue
+    pub max_concurrent_jobs: usize,
+    /// Tries per rendition before it is marked failed
+    pub transcode_attempts: u32,
+    /// Wait before the first retry of a rendition, doubled for each further one
+    pub retry_backoff_secs: u64,
+    /// Sources at least twice this long are encoded in chunks of about this
+    /// many seconds, so an interrupted encode resumes from the last finished
+    /// chunk rather than the start. 0 encodes every source in one go.
+    pub chunk_secs: u64,
+    /// Queue the chunks as jobs any worker sharing the database can take,
+    /// rather than encoding them one after another. Workers must see the
+    /// uploads directory at the same path.
+    pub distribute_chunks: bool,
+    /// Encode the re-encoded rungs of the ladder with one ffmpeg, decoding
+    /// the source once for all of them, rather than a job per rung that
+    /// workers may run side by side. Sources encoded in chunks aren't.
+    pub shared_decode: bool,
+    /// Wrapper that ffmpeg/ffprobe are launched through, e.g.
+    /// `["bwrap", "--unshare-net", "--ro-bind", "/", "/", "--bind", "uploads", "uploads"]`
+    pub sandbox_command: Option<Vec<String>>,
+    /// Address space limit for each ffmpeg/ffprobe process, in megabytes
+    pub max_memory_mb: Option<u64>,
+    /// CPU time limit for each ffmpeg/ffprobe process, in seconds
+    pub max_cpu_seconds: Option<u64>,
+    /// Add an HEVC rendition that keeps HDR for HDR10/HLG sources, next to the
+    /// tone-mapped SDR ladder
+    pub hdr_rendition: bool,
+    /// Reuse source streams that already match a rendition instead of re-encoding
+    pub stream_copy: bool,
+    /// Pipe uploads into ffprobe as they arrive, so probing doesn't have to
+    /// re-read the stored file afterwards
+    pub probe_while_uploading: bool,
+    /// Also pipe uploads into ffmpeg to segment them with their streams
+    /// copied, for a rendition the source fits to take instead of reading the
+    /// stored file again. Only used with the default transcoding profile.
+    pub remux_while_uploading: bool,
+    /// `mpegts`, or `fmp4` for CMAF segments that can also back a DASH
+    /// manifest. HDR renditions are always fMP4.
+    pub hls_segment_type: String,
+}
+
+pub const HLS_SEGMENT_TYPES: &[&str] = &["mpegts", "fmp4"];
+
+impl FfmpegConfig {
+    /// Configured binary of `program`, i.e. `ffmpeg` or `ffprobe`
+    pub fn binary(&self, program: &str) -> &str {
+        match program {
+            "ffprobe" => &self.ffprobe_path,
+            _ => &self.binary_path,
+        }
+    }
+
+    /// Whether SDR renditions are packaged as fMP4
+    pub fn fmp4(&self) -> bool {
+        self.hls_segment_type == "fmp4"
+    }
+}
+
+/// x264 presets an encoding profile may use
+pub const X264_PRESETS: &[&str] = &[
+    "ultrafast",
+    "superfast",
+    "veryfast",
+    "faster",
+    "fast",
+    "medium",
+    "slow",
+    "slower",
+    "veryslow",
+];
+
+/// The default encoding profile, used for videos uploaded without a named
+/// one. The rendition ladder, highest quality first, is set as a list of
+/// `[[transcoding.renditions]]` tables; a list in the config file replaces
+/// the default ladder entirely.
+#[derive(Debug, Deserialize, Clone)]
+pub struct TranscodingConfig {
+    pub renditions: Vec<RenditionConfig>,
+    /// Overrides `ffmpeg.preset`
+    pub preset: Option<String>,
+    /// HLS segment length in seconds, a multiple of the 2 second keyframe
+    /// interval
+    pub segment_duration: u32,
+    /// Per-title encoding: a quick constant-quality test encode of the
+    /// source scales the ladder's bitrates, which become caps, down for
+    /// simple content
+    pub per_title: bool,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct RenditionConfig {
+    /// Names the rendition's directory and quality entry, e.g. `1080p`
+    pub name: String,
+    /// Frame size as `WIDTHxHEIGHT`
+    pub resolution: String,
+    pub video_bitrate: String,
+    pub audio_bitrate: String,
+    /// Sources above this frame rate are reduced to it for the rendition
+    pub max_fps: f64,
+    /// One of `VIDEO_CODECS`, h264 when unset. HEVC and AV1 renditions are
+    /// always packaged as fMP4, the only way HLS carries them.
+    pub codec: Option<String>,
+}
+
+/// Codecs a rendition can be encoded with, in the order their variants are
+/// listed in the master playlist
+pub const VIDEO_CODECS: &[&str] = &["h264", "hevc", "av1"];
+
+impl RenditionConfig {
+    fn new(name: &str, resolution: &str, video_bitrate: &str, max_fps: f64) -> Self {
+        Self {
+            name: name.to_string(),
+            resolution: resolution.to_string(),
+            video_bitrate: video_bitrate.to_string(),
+            audio_bitrate: "128k".to_string(),
+            max_fps,
+            codec: None,
+        }
+    }
+
+    pub fn codec(&self) -> &str {
+        self.codec.as_deref().unwrap_or("h264")
+    }
+
+    /// Whether segments are fMP4 whatever `ffmpeg.hls_segment_type` says
+    pub fn needs_fmp4(&self) -> bool {
+        self.codec() != "h264"
+    }
+
+    /// Width and height from `resolution`
+    pub fn dimensions(&self) -> Option<(u32, u32)> {
+        let (width, height) = self.resolution.split_once('x')?;
+        Some((width.parse().ok()?, height.parse().ok()?))
+    }
+}
+
+impl From<RenditionConfig> for Value {
+    fn from(rendition: RenditionConfig) -> Self {
+        let mut table = Map::new();
+        table.insert("name".to_string(), Value::from(rendition.name));
+        table.insert("resolution".to_string(), Value::from(rendition.resolution));
+        table.insert(
+            "video_bitrate".to_string(),
+            Value::from(rendition.video_bitrate),
+        );
+        table.insert(
+            "audio_bitrate".to_string(),
+            Value::from(rendition.audio_bitrate),
+        );
+        table.insert("max_fps".to_string(), Value::from(rendition.max_fps));
+        if let Some(codec) = rendition.codec {
+            table.insert("codec".to_string(), Value::from(codec));
+        }
+        Value::from(table)
+    }
+}
+
+impl TranscodingConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |message: String| Err(ConfigError::Message(message));
+        Self::validate_renditions(&self.renditions)?;
+        if let Some(preset) = &self.preset {
+            if !X264_PRESETS.contains(&preset.as_str()) {
+                return invalid(format!("Preset must be one of {}", X264_PRESETS.join(", ")));
+            }
+        }
+        if !(2..=30).contains(&self.segment_duration) || !self.segment_duration.is_multiple_of(2) {
+            return invalid(
+                "Segment duration must be an even number of seconds from 2 to 30".to_string(),
+            );
+        }
+        Ok(())
+    }
+
+    /// Checks a ladder on its own, as used for per-video overrides
+    pub fn validate_renditions(renditions: &[RenditionConfig]) -> Result<(), ConfigError> {
+        let invalid = |message: String| Err(ConfigError::Message(message));
+        if renditions.is_empty() {
+            return invalid("At least one rendition is required".to_string());
+        }
+        for (i, rendition) in renditions.iter().enumerate() {
+            let name = &rendition.name;
+            let safe_name = name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            if name.is_empty() || !safe_name {
+                return invalid(format!(
+                    "Rendition name {:?} may only contain letters, digits, - and _",
+                    name
+                ));
+            }
+            // The HDR rendition and review proxy are packaged under these
+            if [HDR_QUALITY.0, REVIEW_QUALITY.0].contains(&name.as_str()) {
+                return invalid(format!("Rendition name {} is reserved", name));
+            }
+            if renditions[..i].iter().any(|r| &r.name == name) {
+                return invalid(format!("Rendition {} is listed twice", name));
+            }
+            if rendition.dimensions().is_none() {
+                return invalid(format!(
+                    "Rendition {} resolution must be WIDTHxHEIGHT",
+                    name
+                ));
+            }
+            for bitrate in [&rendition.video_bitrate, &rendition.audio_bitrate] {
+                let valid = bitrate
+                    .strip_suffix('k')
+                    .is_some_and(|n| n.parse::<u32>().is_ok_and(|n| n > 0));
+                if !valid {
+                    return invalid(format!("Rendition {} bitrates must look like 2800k", name));
+                }
+            }
+            if rendition.max_fps <= 0.0 {
+                return invalid(format!("Rendition {} max_fps must be positive", name));
+            }
+            if !VIDEO_CODECS.contains(&rendition.codec()) {
+                return invalid(format!(
+                    "Rendition {} codec must be one of {}",
+                    name,
+                    VIDEO_CODECS.join(", ")
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ValidationConfig {
+    pub max_duration_secs: f64,
+    /// Resolution caps apply to the long and short edge, so portrait sources
+    /// are held to the same limit as landscape ones
+    pub max_width: u32,
+    pub max_height: u32,
+}
+
+/// Per-client (peer IP) upload limits. A value of 0 disables that limit.
+#[derive(Debug, Deserialize, Clone)]
+pub struct UploadLimitConfig {
+    pub max_concurrent_uploads: usize,
+    /// Upload bytes allowed per window; should be well above `max_file_size`
+    pub max_upload_bytes: u64,
+    pub window_secs: u64,
+    /// Reverse proxies whose `X-Forwarded-For` names the client uploads are
+    /// counted against; requests from anywhere else count against their peer
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+/// Malware/content scan run on every upload before it is processed. At most
+/// one of `command` and `url` should be set; with neither, uploads aren't scanned.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ScanConfig {
+    /// Scanner run with the upload's path appended, e.g. `["clamdscan", "--no-summary"]`.
+    /// Exit code 0 means clean and 1 means rejected; anything else is an error.
+    pub command: Option<Vec<String>>,
+    /// Endpoint the upload is POSTed to, answering `{"clean": bool, "reason": "..."}`
+    pub url: Option<String>,
+    pub timeout_secs: u64,
+}
+
+/// Low resolution review proxies for editorial workflows, with the timecode
+/// and a watermark burned in. They are kept out of the master playlist and
+/// only listed and served to reviewers.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ReviewConfig {
+    /// Encode a review proxy for every processed video
+    pub proxy: bool,
+    pub watermark: String,
+    /// Bearer tokens that grant the reviewer role
+    pub tokens: Vec<String>,
+}
+
+/// Frame extraction for ML pipelines. Requests are rate limited per client
+/// IP; a limit of 0 disables it.
+#[derive(Debug, Deserialize, Clone)]
+pub struct FrameConfig {
+    /// Most frames one request may ask for
+    pub max_frames: usize,
+    /// Videos up to this long are answered with the frames directly; longer
+    /// ones get a job to poll
+    pub sync_max_duration_secs: f64,
+    pub max_requests: u32,
+    pub window_secs: u64,
+}
+
+/// External check of who may watch restricted videos, for commerce systems
+/// that stay the source of truth instead of creating playback grants. Only
+/// asked about tokens without an active local grant.
+#[derive(Debug, Deserialize, Clone)]
+pub struct EntitlementConfig {
+    /// Endpoint POSTed `{"video_id": ..., "token": ...}`, answering
+    /// `{"allowed": bool}`; unset disables the check
+    pub url: Option<String>,
+    pub timeout_secs: u64,
+    /// How long an answer is reused for the same video and token
+    pub cache_secs: u64,
+}
+
+/// Handling of viewer reports
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct ModerationConfig {
+    /// Bearer tokens that may work the report queue and read the audit log
+    pub admin_tokens: Vec<String>,
+}
+
+/// Image overlaid on every rendition. Uploads may bring their own image and
+/// position, which take precedence.
+#[derive(Debug, Deserialize, Clone)]
+pub struct WatermarkConfig {
+    /// PNG or JPEG applied to videos without their own; None leaves them
+    /// unbranded
+    pub image: Option<String>,
+    /// One of `WATERMARK_POSITIONS`
+    pub position: String,
+    /// From 0 (invisible) to 1 (opaque)
+    pub opacity: f64,
+    /// Distance from the frame edges, in pixels of the source video
+    pub margin: u32,
+}
+
+pub const WATERMARK_POSITIONS: &[&str] = &[
+    "top-left",
+    "top-right",
+    "bottom-left",
+    "bottom-right",
+    "center",
+];
+
+/// EBU R128 loudness normalization of the audio of every rendition, from a
+/// measurement of the whole source
+#[derive(Debug, Deserialize, Clone)]
+pub struct LoudnessConfig {
+    pub normalize: bool,
+    /// Integrated loudness, in LUFS; R128 broadcast is -23, streaming
+    /// services use around -16
+    pub target_lufs: f64,
+    /// Maximum true peak, in dBTP
+    pub true_peak: f64,
+    /// Loudness range, in LU
+    pub loudness_range: f64,
+}
+
+/// Where queued jobs run: in the API process, or in `video-worker` processes
+/// sharing the database and uploads, any number of which can run side by side
+#[derive(Debug, Deserialize, Clone)]
+pub struct WorkerConfig {
+    /// Run jobs in the API process too; off for API nodes that only queue them
+    pub embedded: bool,
+    /// A running job whose worker hasn't renewed its lease for this long is
+    /// taken over by another, or by its own after a restart
+    pub lease_secs: u64,
+}
+
+/// Captioning vendors allowed to return captions to
+/// `/captions/callback/{name}`
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct CaptionsConfig {
+    pub providers: Vec<CaptionProviderConfig>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct CaptionProviderConfig {
+    pub name: String,
+    /// Key the vendor signs its callbacks with
+    pub secret: String,
+}
+
+/// Links for reviewing unpublished videos, from `POST /videos/{id}/preview-links`
+#[derive(Debug, Deserialize, Clone)]
+pub struct PreviewConfig {
+    /// How long a link works for when the request doesn't say
+    pub default_duration_secs: u64,
+    /// Longest a link may work for
+    pub max_duration_secs: u64,
+}
+
+/// Requests to URLs users supply, i.e. imports and webhooks
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct OutboundConfig {
+    /// Let them reach loopback, private and link-local addresses, e.g. for
+    /// development. Off, so callers can't reach internal services.
+    pub allow_private_networks: bool,
+}
+
+/// Server-side imports from `POST /videos/import`
+#[derive(Debug, Deserialize, Clone)]
+pub struct ImportConfig {
+    /// Imports still downloading after this long fail
+    pub timeout_secs: u64,
+}
+
+/// Conversions from `POST /convert`
+#[derive(Debug, Deserialize, Clone)]
+pub struct ConvertConfig {
+    /// Finished conversions, their output and any uploaded source are deleted
+    /// this long after their job ended; 0 keeps them
+    pub retention_secs: u64,
+}
+
+impl CaptionsConfig {
+    pub fn provider(&self, name: &str) -> Option<&CaptionProviderConfig> {
+        self.providers.iter().find(|provider| provider.name == name)
+    }
+}
+
+impl ScanConfig {
+    pub fn enabled(&self) -> bool {
+        self.command.as_ref().is_some_and(|c| !c.is_empty()) || self.url.is_some()
+    }
 }
 
 impl AppConfig {
@@ -44,8 +487,68 @@ impl AppConfig {
             .set_default("server.port", 8080)?
             .set_default("database.max_connections", 5)?
             .set_default("storage.max_file_size", 1024 * 1024 * 1024)? // 1GB
+            .set_default("storage.segment_cache_size", 1024)?
+            .set_default("storage.blocking_threads", 2)?
+            .set_default("storage.stalled_upload_timeout_secs", 24 * 60 * 60)? // 1 day
+            .set_default("storage.max_attachment_size", 100 * 1024 * 1024)? // 100MB
+            .set_default("ffmpeg.binary_path", "ffmpeg")?
+            .set_default("ffmpeg.ffprobe_path", "ffprobe")?
             .set_default("ffmpeg.thread_count", 2)?
             .set_default("ffmpeg.preset", "fast")?
+            .set_default("ffmpeg.max_concurrent_jobs", 2)?
+            .set_default("ffmpeg.transcode_attempts", 3)?
+            .set_default("ffmpeg.retry_backoff_secs", 10)?
+            .set_default("ffmpeg.chunk_secs", 30 * 60)? // 30 minutes
+            .set_default("ffmpeg.distribute_chunks", false)?
+            .set_default("ffmpeg.shared_decode", true)?
+            .set_default("ffmpeg.hdr_rendition", false)?
+            .set_default("ffmpeg.stream_copy", true)?
+            .set_default("ffmpeg.probe_while_uploading", true)?
+            .set_default("ffmpeg.remux_while_uploading", false)?
+            .set_default("ffmpeg.hls_segment_type", "mpegts")?
+            .set_default(
+                "transcoding.renditions",
+                TranscodingConfig::default()
+                    .renditions
+                    .into_iter()
+                    .map(Value::from)
+                    .collect::<Vec<_>>(),
+            )?
+            .set_default("transcoding.segment_duration", 6)?
+            .set_default("transcoding.per_title", false)?
+            .set_default("validation.max_duration_secs", 4 * 60 * 60)? // 4 hours
+            .set_default("validation.max_width", 3840)?
+            .set_default("validation.max_height", 2160)?
+            .set_default("upload_limits.max_concurrent_uploads", 2)?
+            .set_default("upload_limits.max_upload_bytes", 10u64 * 1024 * 1024 * 1024)? // 10GB
+            .set_default("upload_limits.window_secs", 60 * 60)? // 1 hour
+            .set_default("upload_limits.trusted_proxies", Vec::<String>::new())?
+            .set_default("scan.timeout_secs", 10 * 60)? // 10 minutes
+            .set_default("review.proxy", false)?
+            .set_default("review.watermark", "FOR REVIEW ONLY")?
+            .set_default("review.tokens", Vec::<String>::new())?
+            .set_default("frames.max_frames", 500)?
+            .set_default("frames.sync_max_duration_secs", 120)?
+            .set_default("frames.max_requests", 30)?
+            .set_default("frames.window_secs", 60 * 60)? // 1 hour
+            .set_default("entitlement.timeout_secs", 5)?
+            .set_default("entitlement.cache_secs", 60)?
+            .set_default("moderation.admin_tokens", Vec::<String>::new())?
+            .set_default("watermark.position", "bottom-right")?
+            .set_default("watermark.opacity", 0.8)?
+            .set_default("watermark.margin", 20)?
+            .set_default("loudness.normalize", false)?
+            .set_default("loudness.target_lufs", -23.0)?
+            .set_default("loudness.true_peak", -1.0)?
+            .set_default("loudness.loudness_range", 7.0)?
+            .set_default("worker.embedded", true)?
+            .set_default("worker.lease_secs", 60)?
+            .set_default("captions.providers", Vec::<String>::new())?
+            .set_default("previews.default_duration_secs", 7 * 24 * 60 * 60)?
+            .set_default("previews.max_duration_secs", 30 * 24 * 60 * 60)?
+            .set_default("outbound.allow_private_networks", false)?
+            .set_default("imports.timeout_secs", 60 * 60)? // 1 hour
+            .set_default("convert.retention_secs", 24 * 60 * 60)? // 1 day
             // Layer on the environment-specific values
             .add_source(File::with_name(&format!("config/{}", run_mode)).required(false))
             // Add in settings from the environment
@@ -54,7 +557,47 @@ impl AppConfig {
             .build()?;
 
         // Deserialize the configuration
-        s.try_deserialize()
+        let config: Self = s.try_deserialize()?;
+        config.transcoding.validate()?;
+        if !HLS_SEGMENT_TYPES.contains(&config.ffmpeg.hls_segment_type.as_str()) {
+            return Err(ConfigError::Message(format!(
+                "ffmpeg.hls_segment_type must be one of {}",
+                HLS_SEGMENT_TYPES.join(", ")
+            )));
+        }
+        if !WATERMARK_POSITIONS.contains(&config.watermark.position.as_str()) {
+            return Err(ConfigError::Message(format!(
+                "watermark.position must be one of {}",
+                WATERMARK_POSITIONS.join(", ")
+            )));
+        }
+        if !(0.0..=1.0).contains(&config.watermark.opacity) {
+            return Err(ConfigError::Message(
+                "watermark.opacity must be from 0 to 1".to_string(),
+            ));
+        }
+        config.loudness.validate()?;
+        if config.worker.lease_secs == 0 {
+            return Err(ConfigError::Message(
+                "worker.lease_secs must be positive".to_string(),
+            ));
+        }
+        if config.imports.timeout_secs == 0 {
+            return Err(ConfigError::Message(
+                "imports.timeout_secs must be positive".to_string(),
+            ));
+        }
+        let previews = &config.previews;
+        if previews.default_duration_secs == 0
+            || previews.default_duration_secs > previews.max_duration_secs
+        {
+            return Err(ConfigError::Message(
+                "previews.default_duration_secs must be positive and at most \
+                 previews.max_duration_secs"
+                    .to_string(),
+            ));
+        }
+        Ok(config)
     }
 
     pub fn from_env() -> Result<Self, ConfigError> {
@@ -68,6 +611,7 @@ impl Default for ServerConfig {
         Self {
             host: "127.0.0.1".to_string(),
             port: 8080,
+            grpc_port: None,
         }
     }
 }
@@ -86,6 +630,10 @@ impl Default for StorageConfig {
         Self {
             upload_path: "uploads".to_string(),
             max_file_size: 1024 * 1024 * 1024, // 1GB
+            segment_cache_size: 1024,
+            blocking_threads: 2,
+            stalled_upload_timeout_secs: 24 * 60 * 60, // 1 day
+            max_attachment_size: 100 * 1024 * 1024,    // 100MB
         }
     }
 }
@@ -93,8 +641,175 @@ impl Default for StorageConfig {
 impl Default for FfmpegConfig {
     fn default() -> Self {
         Self {
+            binary_path: "ffmpeg".to_string(),
+            ffprobe_path: "ffprobe".to_string(),
             thread_count: 2,
             preset: "fast".to_string(),
+            max_concurrent_jobs: 2,
+            transcode_attempts: 3,
+            retry_backoff_secs: 10,
+            chunk_secs: 30 * 60,
+            distribute_chunks: false,
+            shared_decode: true,
+            sandbox_command: None,
+            max_memory_mb: None,
+            max_cpu_seconds: None,
+            hdr_rendition: false,
+            stream_copy: true,
+            probe_while_uploading: true,
+            remux_while_uploading: false,
+            hls_segment_type: "mpegts".to_string(),
+        }
+    }
+}
+
+impl Default for TranscodingConfig {
+    fn default() -> Self {
+        Self {
+            renditions: vec![
+                RenditionConfig::new("1080p", "1920x1080", "5000k", 60.0),
+                RenditionConfig::new("720p", "1280x720", "2800k", 60.0),
+                RenditionConfig::new("480p", "854x480", "1400k", 30.0),
+                RenditionConfig::new("360p", "640x360", "800k", 30.0),
+            ],
+            preset: None,
+            segment_duration: 6,
+            per_title: false,
+        }
+    }
+}
+
+impl Default for ValidationConfig {
+    fn default() -> Self {
+        Self {
+            max_duration_secs: 4.0 * 60.0 * 60.0, // 4 hours
+            max_width: 3840,
+            max_height: 2160,
+        }
+    }
+}
+
+impl Default for UploadLimitConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrent_uploads: 2,
+            max_upload_bytes: 10 * 1024 * 1024 * 1024, // 10GB
+            window_secs: 60 * 60,                      // 1 hour
+            trusted_proxies: Vec::new(),
+        }
+    }
+}
+
+impl Default for ScanConfig {
+    fn default() -> Self {
+        Self {
+            command: None,
+            url: None,
+            timeout_secs: 10 * 60, // 10 minutes
+        }
+    }
+}
+
+impl Default for ReviewConfig {
+    fn default() -> Self {
+        Self {
+            proxy: false,
+            watermark: "FOR REVIEW ONLY".to_string(),
+            tokens: Vec::new(),
+        }
+    }
+}
+
+impl Default for FrameConfig {
+    fn default() -> Self {
+        Self {
+            max_frames: 500,
+            sync_max_duration_secs: 120.0,
+            max_requests: 30,
+            window_secs: 60 * 60, // 1 hour
+        }
+    }
+}
+
+impl Default for EntitlementConfig {
+    fn default() -> Self {
+        Self {
+            url: None,
+            timeout_secs: 5,
+            cache_secs: 60,
+        }
+    }
+}
+
+impl Default for WatermarkConfig {
+    fn default() -> Self {
+        Self {
+            image: None,
+            position: "bottom-right".to_string(),
+            opacity: 0.8,
+            margin: 20,
+        }
+    }
+}
+
+impl LoudnessConfig {
+    /// The ranges ffmpeg's loudnorm filter accepts
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |message: &str| Err(ConfigError::Message(message.to_string()));
+        if !(-70.0..=-5.0).contains(&self.target_lufs) {
+            return invalid("loudness.target_lufs must be from -70 to -5");
+        }
+        if !(-9.0..=0.0).contains(&self.true_peak) {
+            return invalid("loudness.true_peak must be from -9 to 0");
+        }
+        if !(1.0..=50.0).contains(&self.loudness_range) {
+            return invalid("loudness.loudness_range must be from 1 to 50");
+        }
+        Ok(())
+    }
+}
+
+impl Default for LoudnessConfig {
+    fn default() -> Self {
+        Self {
+            normalize: false,
+            target_lufs: -23.0,
+            true_peak: -1.0,
+            loudness_range: 7.0,
+        }
+    }
+}
+
+impl Default for WorkerConfig {
+    fn default() -> Self {
+        Self {
+            embedded: true,
+            lease_secs: 60,
+        }
+    }
+}
+
+impl Default for PreviewConfig {
+    fn default() -> Self {
+        Self {
+            default_duration_secs: 7 * 24 * 60 * 60,
+            max_duration_secs: 30 * 24 * 60 * 60,
+        }
+    }
+}
+
+impl Default for ImportConfig {
+    fn default() -> Self {
+        Self {
+            timeout_secs: 60 * 60, // 1 hour
+        }
+    }
+}
+
+impl Default for ConvertConfig {
+    fn default() -> Self {
+        Self {
+            retention_secs: 24 * 60 * 60, // 1 day
         }
     }
 }
@@ -1,9 +1,8 @@
 use chrono::NaiveDateTime;
 use diesel::prelude::*;
-use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-#[derive(Debug, Serialize, Deserialize, Queryable, Insertable, Clone)]
+#[derive(Debug, Queryable, Insertable, Clone)]
 #[diesel(table_name = crate::db::schema::videos)]
 pub struct Video {
     pub id: Uuid,
@@ -13,9 +12,48 @@ pub struct Video {
     pub status: String,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
+    pub status_reason: Option<String>,
+    /// Authorizes `PUT /videos/{id}/content` for records created without a
+    /// file; cleared while the content streams in and once it's stored
+    pub upload_token: Option<String>,
+    /// SHA-256 of the original upload
+    pub checksum: Option<String>,
+    /// Ladder used instead of the encoding profile's, as a serialized
+    /// `ProcessingProfile`
+    pub processing_profile: Option<serde_json::Value>,
+    /// Name of the encoding profile the video was uploaded with; None uses
+    /// `transcoding`
+    pub encoding_profile: Option<String>,
+    /// Playlists are only served to holders of an active `PlaybackGrant`
+    pub restricted: bool,
+    /// Sites whose pages may embed the video, matched with their subdomains
+    /// against the Referer or Origin; empty allows any
+    pub embed_domains: Vec<String>,
+    /// Left out of listings but still playable, e.g. after moderation
+    pub unlisted: bool,
+    /// Why a failed or rejected video stopped, e.g. `transcode_failed`;
+    /// `status_reason` has the human-readable explanation
+    pub error_code: Option<String>,
+    /// Overrides `watermark.position` for this video
+    pub watermark_position: Option<String>,
+    /// One of `LICENSES`, e.g. `CC-BY-4.0`
+    pub license: Option<String>,
+    /// Credit the license asks for, e.g. the author's name
+    pub attribution: Option<String>,
+    /// Where the original work was published
+    pub source_url: Option<String>,
+    pub tags: Vec<String>,
+    /// Left out of listings until then
+    pub publish_at: Option<NaiveDateTime>,
+    /// Name of the uploaded file, without any directories
+    pub original_filename: Option<String>,
+    /// Chosen poster image, relative to the video's directory
+    pub poster: Option<String>,
+    /// One of `PRIORITIES`; its jobs are queued with the level's rank
+    pub priority: String,
 }
 
-#[derive(Debug, Serialize, Deserialize, Queryable, Insertable, Clone)]
+#[derive(Debug, Queryable, Insertable, Clone)]
 #[diesel(table_name = crate::db::schema::video_qualities)]
 pub struct VideoQuality {
     pub id: Uuid,
@@ -24,13 +62,308 @@ pub struct VideoQuality {
     pub bitrate: String,
     pub file_path: String,
     pub created_at: NaiveDateTime,
+    /// pending, encoding, ready or failed
+    pub state: String,
+    /// Percent encoded, 0-100
+    pub progress: i32,
+    /// Transcode attempts so far, and the error of the last failed one
+    pub attempts: i32,
+    pub error: Option<String>,
+    /// Quality menu label, e.g. `1080p60`
+    pub label: Option<String>,
+    /// Frames per second the rendition is encoded at
+    pub frame_rate: Option<f64>,
+    /// Frame size the rendition is encoded at
+    pub width: Option<i32>,
+    pub height: Option<i32>,
 }
 
-#[derive(Debug, Serialize)]
-pub struct VideoWithMeta {
-    #[serde(flatten)]
-    pub video: Video,
-    pub qualities: Vec<VideoQuality>,
-    pub thumbnail_url: String,
-    pub stream_url: String,
+/// Lets `token` play a restricted video between `starts_at` and
+/// `expires_at`, e.g. a 48-hour rental
+#[derive(Debug, Queryable, Insertable, Clone)]
+#[diesel(table_name = crate::db::schema::playback_grants)]
+pub struct PlaybackGrant {
+    pub id: Uuid,
+    pub video_id: Uuid,
+    pub token: String,
+    pub starts_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    pub created_at: NaiveDateTime,
+}
+
+/// Lets anyone with the link review a video that isn't published yet, until
+/// `expires_at`. The token plays the video like a playback grant.
+#[derive(Debug, Queryable, Insertable, Clone)]
+#[diesel(table_name = crate::db::schema::preview_links)]
+pub struct PreviewLink {
+    pub id: Uuid,
+    pub video_id: Uuid,
+    pub token: String,
+    pub expires_at: NaiveDateTime,
+    pub created_at: NaiveDateTime,
+}
+
+/// Client-supplied `Idempotency-Key` of an upload and the video it created
+#[derive(Debug, Queryable, Insertable)]
+#[diesel(table_name = crate::db::schema::idempotency_keys)]
+pub struct IdempotencyKey {
+    pub key: String,
+    pub video_id: Uuid,
+    pub created_at: NaiveDateTime,
+}
+
+/// One run of the processing pipeline for a video, a conversion, a frame
+/// extraction or a chunk of a rendition: queued, running, failed or done.
+/// Exactly one of the targets is set. A video is processed by several jobs,
+/// one per `stage`, which wait on each other through `job_dependencies`.
+#[derive(Debug, Clone, Queryable, QueryableByName, Insertable)]
+#[diesel(table_name = crate::db::schema::processing_jobs)]
+pub struct ProcessingJob {
+    pub id: Uuid,
+    pub video_id: Option<Uuid>,
+    pub state: String,
+    pub attempts: i32,
+    pub error: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub conversion_id: Option<Uuid>,
+    pub frame_extraction_id: Option<Uuid>,
+    pub chunk_encode_id: Option<Uuid>,
+    /// Step of a video's processing: probe, rendition, ladder, thumbnails or
+    /// finalize
+    pub stage: Option<String>,
+    /// The rendition a rendition step encodes
+    pub quality: Option<String>,
+    /// Worker process running the job, which holds it while it renews
+    /// `heartbeat_at`
+    pub worker_id: Option<Uuid>,
+    pub heartbeat_at: Option<NaiveDateTime>,
+    /// Workers take the highest first, e.g. 1 for a high-priority video
+    pub priority: i32,
+}
+
+/// `job_id` doesn't run until `depends_on` is done or failed
+#[derive(Debug, Queryable, Insertable)]
+#[diesel(table_name = crate::db::schema::job_dependencies)]
+pub struct JobDependency {
+    pub job_id: Uuid,
+    pub depends_on: Uuid,
+}
+
+/// A chunk of a long rendition for whichever worker claims its job to
+/// encode, by running ffmpeg with `args` in the chunk's directory
+#[derive(Debug, Queryable, Insertable, Clone)]
+#[diesel(table_name = crate::db::schema::chunk_encodes)]
+pub struct ChunkEncode {
+    pub id: Uuid,
+    pub video_id: Uuid,
+    pub quality: String,
+    pub chunk: i32,
+    pub start: f64,
+    pub length: Option<f64>,
+    pub args: Vec<String>,
+    pub created_at: NaiveDateTime,
+}
+
+/// One-off conversion of an uploaded file, or of a video's original, to
+/// another container and codecs
+#[derive(Debug, Queryable, Insertable, Clone)]
+#[diesel(table_name = crate::db::schema::conversions)]
+pub struct Conversion {
+    pub id: Uuid,
+    pub video_id: Option<Uuid>,
+    pub container: String,
+    pub video_codec: String,
+    pub audio_codec: String,
+    pub created_at: NaiveDateTime,
+}
+
+/// Frames sampled from a video's original, as a zip of JPEGs or NDJSON of
+/// frame URLs
+#[derive(Debug, Queryable, Insertable, Clone)]
+#[diesel(table_name = crate::db::schema::frame_extractions)]
+pub struct FrameExtraction {
+    pub id: Uuid,
+    pub video_id: Uuid,
+    pub interval_secs: Option<f64>,
+    pub timestamps: Vec<f64>,
+    pub format: String,
+    pub created_at: NaiveDateTime,
+}
+
+/// Reusable encoding preset: a rendition ladder (serialized
+/// `RenditionConfig`s), x264 preset, segment length and bitrate mode
+#[derive(Debug, Queryable, Insertable, Clone)]
+#[diesel(table_name = crate::db::schema::encoding_profiles)]
+pub struct EncodingProfile {
+    pub name: String,
+    pub renditions: serde_json::Value,
+    /// Overrides `ffmpeg.preset` when set
+    pub preset: Option<String>,
+    pub segment_duration: i32,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    /// The ladder's bitrates are caps, scaled down for simple sources
+    pub per_title: bool,
+}
+
+/// A viewer's complaint about a video, queued for moderators
+/// A file attached to a video, stored as `attachments/<id>` in its directory
+#[derive(Debug, Queryable, Insertable, Clone)]
+#[diesel(table_name = crate::db::schema::video_attachments)]
+pub struct VideoAttachment {
+    pub id: Uuid,
+    pub video_id: Uuid,
+    /// The uploader's name for the file, used for downloads
+    pub filename: String,
+    pub content_type: String,
+    pub size: i64,
+    /// SHA-256 of the content
+    pub checksum: String,
+    pub created_at: NaiveDateTime,
+}
+
+/// An entry of the sync outbox, written by a trigger on `videos`
+#[derive(Debug, Queryable, Clone)]
+#[diesel(table_name = crate::db::schema::video_changes)]
+pub struct VideoChange {
+    /// Increasing within a transaction
+    pub id: i64,
+    pub video_id: Uuid,
+    /// created, updated or deleted
+    pub change: String,
+    pub created_at: NaiveDateTime,
+    /// Transaction that made the change
+    pub xid: i64,
+}
+
+/// An audio stream of a video's source, `position` being its index among
+/// the source's audio streams
+#[derive(Debug, Queryable, Insertable, Clone)]
+#[diesel(table_name = crate::db::schema::audio_tracks)]
+pub struct AudioTrack {
+    pub id: Uuid,
+    pub video_id: Uuid,
+    pub position: i32,
+    /// ISO 639-2 code from the source, e.g. `eng`
+    pub language: Option<String>,
+    pub name: String,
+    pub channels: Option<i32>,
+    pub is_default: bool,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Queryable, Insertable, Clone)]
+#[diesel(table_name = crate::db::schema::subtitles)]
+pub struct Subtitle {
+    pub id: Uuid,
+    pub video_id: Uuid,
+    pub language: Option<String>,
+    pub name: String,
+    /// The WebVTT file's one-segment playlist, relative to the video's
+    /// directory; the file itself is next to it
+    pub file_path: String,
+    /// embedded, for subtitles extracted from the source, upload, or provider
+    /// for those a captioning vendor returned
+    pub source: String,
+    pub is_default: bool,
+    pub forced: bool,
+    pub created_at: NaiveDateTime,
+}
+
+/// Captions ordered from a vendor, matched with the ones it returns by
+/// `provider` and `reference`
+#[derive(Debug, Queryable, Insertable, Clone)]
+#[diesel(table_name = crate::db::schema::caption_requests)]
+pub struct CaptionRequest {
+    pub id: Uuid,
+    pub video_id: Uuid,
+    pub provider: String,
+    pub reference: String,
+    pub language: String,
+    pub name: Option<String>,
+    /// pending, completed or failed
+    pub state: String,
+    /// The subtitle the returned captions were attached as
+    pub subtitle_id: Option<Uuid>,
+    pub error: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+/// What ffmpeg's loudnorm filter measured on a video's source audio
+#[derive(Debug, Queryable, Insertable, Clone)]
+#[diesel(table_name = crate::db::schema::loudness_measurements)]
+pub struct LoudnessMeasurement {
+    pub video_id: Uuid,
+    pub integrated_lufs: f64,
+    pub true_peak_dbtp: f64,
+    pub loudness_range_lu: f64,
+    pub threshold_lufs: f64,
+    /// Gain loudnorm's second pass adds to reach the target exactly
+    pub target_offset_lu: f64,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Queryable, Insertable, Clone)]
+#[diesel(table_name = crate::db::schema::video_reports)]
+pub struct VideoReport {
+    pub id: Uuid,
+    pub video_id: Uuid,
+    pub reason: String,
+    pub details: Option<String>,
+    /// open, dismissed or actioned
+    pub status: String,
+    pub created_at: NaiveDateTime,
+    pub resolved_at: Option<NaiveDateTime>,
+}
+
+/// A moderation action taken on a video, optionally in answer to a report
+#[derive(Debug, Queryable, Insertable, Clone)]
+#[diesel(table_name = crate::db::schema::audit_log)]
+pub struct AuditEntry {
+    pub id: Uuid,
+    /// dismiss, unlist or delete
+    pub action: String,
+    pub video_id: Uuid,
+    pub report_id: Option<Uuid>,
+    pub note: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+/// Endpoint notified of video status changes, with payloads signed by `secret`
+#[derive(Debug, Queryable, Insertable, Clone)]
+#[diesel(table_name = crate::db::schema::webhooks)]
+pub struct Webhook {
+    pub id: Uuid,
+    pub url: String,
+    pub secret: String,
+    pub created_at: NaiveDateTime,
+    /// Events it is sent; empty sends them all
+    pub events: Vec<String>,
+    /// full or compact
+    pub format: String,
+    /// Rendered into the payload instead of `format`, see `webhooks::render`
+    pub template: Option<serde_json::Value>,
+}
+
+/// A list of videos played back to back, in a loop, as one live stream
+#[derive(Debug, Queryable, Insertable, Clone)]
+#[diesel(table_name = crate::db::schema::channels)]
+pub struct Channel {
+    pub id: Uuid,
+    pub name: String,
+    /// When the schedule started; it restarts when the videos change
+    pub starts_at: NaiveDateTime,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Debug, Queryable, Insertable, Clone)]
+#[diesel(table_name = crate::db::schema::channel_videos)]
+pub struct ChannelVideo {
+    pub channel_id: Uuid,
+    /// Order in the schedule, from 0
+    pub position: i32,
+    pub video_id: Uuid,
 }
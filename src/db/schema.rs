@@ -1,3 +1,212 @@
+diesel::table! {
+    audio_tracks (id) {
+        id -> Uuid,
+        video_id -> Uuid,
+        position -> Int4,
+        language -> Nullable<Varchar>,
+        name -> Varchar,
+        channels -> Nullable<Int4>,
+        is_default -> Bool,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    audit_log (id) {
+        id -> Uuid,
+        action -> Varchar,
+        video_id -> Uuid,
+        report_id -> Nullable<Uuid>,
+        note -> Nullable<Text>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    caption_requests (id) {
+        id -> Uuid,
+        video_id -> Uuid,
+        provider -> Varchar,
+        reference -> Varchar,
+        language -> Varchar,
+        name -> Nullable<Varchar>,
+        state -> Varchar,
+        subtitle_id -> Nullable<Uuid>,
+        error -> Nullable<Text>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    channel_videos (channel_id, position) {
+        channel_id -> Uuid,
+        position -> Int4,
+        video_id -> Uuid,
+    }
+}
+
+diesel::table! {
+    channels (id) {
+        id -> Uuid,
+        name -> Varchar,
+        starts_at -> Timestamp,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    chunk_encodes (id) {
+        id -> Uuid,
+        video_id -> Uuid,
+        quality -> Varchar,
+        chunk -> Int4,
+        start -> Float8,
+        length -> Nullable<Float8>,
+        args -> Array<Text>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    conversions (id) {
+        id -> Uuid,
+        video_id -> Nullable<Uuid>,
+        container -> Varchar,
+        video_codec -> Varchar,
+        audio_codec -> Varchar,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    encoding_profiles (name) {
+        name -> Varchar,
+        renditions -> Jsonb,
+        preset -> Nullable<Varchar>,
+        segment_duration -> Int4,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        per_title -> Bool,
+    }
+}
+
+diesel::table! {
+    frame_extractions (id) {
+        id -> Uuid,
+        video_id -> Uuid,
+        interval_secs -> Nullable<Float8>,
+        timestamps -> Array<Float8>,
+        format -> Varchar,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    idempotency_keys (key) {
+        key -> Varchar,
+        video_id -> Uuid,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    job_dependencies (job_id, depends_on) {
+        job_id -> Uuid,
+        depends_on -> Uuid,
+    }
+}
+
+diesel::table! {
+    loudness_measurements (video_id) {
+        video_id -> Uuid,
+        integrated_lufs -> Float8,
+        true_peak_dbtp -> Float8,
+        loudness_range_lu -> Float8,
+        threshold_lufs -> Float8,
+        target_offset_lu -> Float8,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    playback_grants (id) {
+        id -> Uuid,
+        video_id -> Uuid,
+        token -> Varchar,
+        starts_at -> Timestamp,
+        expires_at -> Timestamp,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    preview_links (id) {
+        id -> Uuid,
+        video_id -> Uuid,
+        token -> Varchar,
+        expires_at -> Timestamp,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    processing_jobs (id) {
+        id -> Uuid,
+        video_id -> Nullable<Uuid>,
+        state -> Varchar,
+        attempts -> Int4,
+        error -> Nullable<Text>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        conversion_id -> Nullable<Uuid>,
+        frame_extraction_id -> Nullable<Uuid>,
+        chunk_encode_id -> Nullable<Uuid>,
+        stage -> Nullable<Varchar>,
+        quality -> Nullable<Varchar>,
+        worker_id -> Nullable<Uuid>,
+        heartbeat_at -> Nullable<Timestamp>,
+        priority -> Int4,
+    }
+}
+
+diesel::table! {
+    subtitles (id) {
+        id -> Uuid,
+        video_id -> Uuid,
+        language -> Nullable<Varchar>,
+        name -> Varchar,
+        file_path -> Varchar,
+        source -> Varchar,
+        is_default -> Bool,
+        forced -> Bool,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    video_attachments (id) {
+        id -> Uuid,
+        video_id -> Uuid,
+        filename -> Varchar,
+        content_type -> Varchar,
+        size -> Int8,
+        checksum -> Varchar,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    video_changes (id) {
+        id -> Int8,
+        video_id -> Uuid,
+        change -> Varchar,
+        created_at -> Timestamp,
+        xid -> Int8,
+    }
+}
+
 diesel::table! {
     video_qualities (id) {
         id -> Uuid,
@@ -6,6 +215,26 @@ diesel::table! {
         bitrate -> Varchar,
         file_path -> Varchar,
         created_at -> Timestamp,
+        state -> Varchar,
+        progress -> Int4,
+        attempts -> Int4,
+        error -> Nullable<Text>,
+        label -> Nullable<Varchar>,
+        frame_rate -> Nullable<Float8>,
+        width -> Nullable<Int4>,
+        height -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    video_reports (id) {
+        id -> Uuid,
+        video_id -> Uuid,
+        reason -> Varchar,
+        details -> Nullable<Text>,
+        status -> Varchar,
+        created_at -> Timestamp,
+        resolved_at -> Nullable<Timestamp>,
     }
 }
 
@@ -18,9 +247,83 @@ diesel::table! {
         status -> Varchar,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        status_reason -> Nullable<Text>,
+        upload_token -> Nullable<Varchar>,
+        checksum -> Nullable<Varchar>,
+        processing_profile -> Nullable<Jsonb>,
+        encoding_profile -> Nullable<Varchar>,
+        restricted -> Bool,
+        embed_domains -> Array<Text>,
+        unlisted -> Bool,
+        error_code -> Nullable<Varchar>,
+        watermark_position -> Nullable<Varchar>,
+        license -> Nullable<Varchar>,
+        attribution -> Nullable<Text>,
+        source_url -> Nullable<Varchar>,
+        tags -> Array<Text>,
+        publish_at -> Nullable<Timestamp>,
+        original_filename -> Nullable<Varchar>,
+        poster -> Nullable<Varchar>,
+        priority -> Varchar,
+    }
+}
+
+diesel::table! {
+    webhooks (id) {
+        id -> Uuid,
+        url -> Varchar,
+        secret -> Varchar,
+        created_at -> Timestamp,
+        events -> Array<Text>,
+        format -> Varchar,
+        template -> Nullable<Jsonb>,
     }
 }
 
+diesel::joinable!(audio_tracks -> videos (video_id));
+diesel::joinable!(audit_log -> video_reports (report_id));
+diesel::joinable!(audit_log -> videos (video_id));
+diesel::joinable!(caption_requests -> subtitles (subtitle_id));
+diesel::joinable!(caption_requests -> videos (video_id));
+diesel::joinable!(channel_videos -> channels (channel_id));
+diesel::joinable!(channel_videos -> videos (video_id));
+diesel::joinable!(chunk_encodes -> videos (video_id));
+diesel::joinable!(conversions -> videos (video_id));
+diesel::joinable!(frame_extractions -> videos (video_id));
+diesel::joinable!(loudness_measurements -> videos (video_id));
+diesel::joinable!(playback_grants -> videos (video_id));
+diesel::joinable!(preview_links -> videos (video_id));
+diesel::joinable!(processing_jobs -> chunk_encodes (chunk_encode_id));
+diesel::joinable!(processing_jobs -> conversions (conversion_id));
+diesel::joinable!(processing_jobs -> frame_extractions (frame_extraction_id));
+diesel::joinable!(processing_jobs -> videos (video_id));
+diesel::joinable!(subtitles -> videos (video_id));
+diesel::joinable!(video_attachments -> videos (video_id));
 diesel::joinable!(video_qualities -> videos (video_id));
+diesel::joinable!(video_reports -> videos (video_id));
+diesel::joinable!(videos -> encoding_profiles (encoding_profile));
 
-diesel::allow_tables_to_appear_in_same_query!(video_qualities, videos,);
+diesel::allow_tables_to_appear_in_same_query!(
+    audio_tracks,
+    audit_log,
+    caption_requests,
+    channel_videos,
+    channels,
+    chunk_encodes,
+    conversions,
+    encoding_profiles,
+    frame_extractions,
+    idempotency_keys,
+    job_dependencies,
+    loudness_measurements,
+    playback_grants,
+    preview_links,
+    processing_jobs,
+    subtitles,
+    video_attachments,
+    video_changes,
+    video_qualities,
+    video_reports,
+    videos,
+    webhooks,
+);
@@ -0,0 +1,565 @@
+// src/grpc/mod.rs
+//! gRPC management API (proto/vid_storage.proto) for internal services that
+//! prefer it over multipart HTTP. Served on `server.grpc_port` next to the
+//! HTTP API and backed by the same handlers. Calls need an admin token, and
+//! uploads count against the same limits as HTTP ones.
+pub mod proto;
+
+mod generated {
+    include!(concat!(env!("OUT_DIR"), "/vid_storage.VideoStorage.rs"));
+}
+
+use crate::api::rate_limit::{self, UploadLimiter, UploadPermit};
+use crate::api::shared::{is_bearer_token, ResponseType};
+use crate::api::videos;
+use crate::config::AppConfig;
+use crate::db::models::Video;
+use crate::db::DbPool;
+use crate::services::events::{self, ProgressEvents};
+use crate::services::import::ImportTracker;
+use crate::services::video_processor;
+use actix_web::http::StatusCode;
+use actix_web::{web, Error};
+use diesel::{ExpressionMethods, QueryDsl};
+use diesel_async::RunQueryDsl;
+use futures::future::LocalBoxFuture;
+use futures::stream::BoxStream;
+use futures::{StreamExt, TryStreamExt};
+use generated::video_storage_server::{VideoStorage, VideoStorageServer};
+use proto::upload_chunk::Part;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot};
+use tonic::metadata::MetadataValue;
+use tonic::service::Interceptor;
+use tonic::{Request, Response, Status, Streaming};
+use uuid::Uuid;
+use vid_storage_models::{ImportRequest, ListQueryParams, VideoResource};
+
+type LocalTask = Box<dyn FnOnce() -> LocalBoxFuture<'static, ()> + Send>;
+
+/// Runs futures on the actix runtime. The HTTP handlers return actix errors,
+/// which aren't Send, so they can't be awaited from tonic's tasks directly.
+#[derive(Clone)]
+struct LocalTasks(mpsc::UnboundedSender<LocalTask>);
+
+impl LocalTasks {
+    /// Must be called from within the actix system
+    fn start() -> Self {
+        let (sender, mut receiver) = mpsc::unbounded_channel::<LocalTask>();
+        actix_web::rt::spawn(async move {
+            while let Some(task) = receiver.recv().await {
+                actix_web::rt::spawn(task());
+            }
+        });
+        LocalTasks(sender)
+    }
+
+    async fn run<T, F, Fut>(&self, task: F) -> Result<T, Status>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = T> + 'static,
+    {
+        let (result_sender, result) = oneshot::channel();
+        self.0
+            .send(Box::new(move || {
+                Box::pin(async move {
+                    let _ = result_sender.send(task().await);
+                })
+            }))
+            .map_err(|_| Status::unavailable("Server is shutting down"))?;
+        result
+            .await
+            .map_err(|_| Status::internal("Request was dropped"))
+    }
+}
+
+struct VideoStorageService {
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+    tracker: web::Data<ImportTracker>,
+    events: web::Data<ProgressEvents>,
+    limiter: web::Data<UploadLimiter>,
+    local: LocalTasks,
+}
+
+/// Starts the gRPC server on `port` in the background
+pub fn spawn(
+    port: u16,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+    tracker: web::Data<ImportTracker>,
+    events: web::Data<ProgressEvents>,
+    limiter: web::Data<UploadLimiter>,
+) {
+    let addr = SocketAddr::new(
+        config
+            .server
+            .host
+            .parse()
+            .expect("server.host must be an IP address to serve gRPC"),
+        port,
+    );
+    let authorize = authorize(config.moderation.admin_tokens.clone());
+    let service = VideoStorageService {
+        pool,
+        config,
+        tracker,
+        events,
+        limiter,
+        local: LocalTasks::start(),
+    };
+
+    log::info!("Starting gRPC server on {}", addr);
+    tokio::spawn(async move {
+        if let Err(e) = tonic::transport::Server::builder()
+            .add_service(VideoStorageServer::with_interceptor(service, authorize))
+            .serve(addr)
+            .await
+        {
+            log::error!("gRPC server failed: {}", e);
+        }
+    });
+}
+
+impl VideoStorageService {
+    /// The address a call's uploads are counted against, see
+    /// `UploadLimiter::client_ip`
+    fn client_ip<T>(&self, request: &Request<T>) -> Option<IpAddr> {
+        let forwarded_for = request
+            .metadata()
+            .get("x-forwarded-for")
+            .and_then(|v| v.to_str().ok());
+        let peer = request.remote_addr()?.ip();
+        Some(self.limiter.client_ip(peer, forwarded_for))
+    }
+
+    /// Starts an upload or import of `size` bytes, if known, for `ip`
+    #[allow(clippy::result_large_err)] // `Status` is what every handler returns
+    fn acquire_permit(
+        &self,
+        ip: Option<IpAddr>,
+        size: Option<u64>,
+    ) -> Result<Option<UploadPermit>, Status> {
+        let Some(ip) = ip else {
+            return Ok(None);
+        };
+        UploadLimiter::acquire(&self.limiter, ip, size)
+            .map(Some)
+            .map_err(|(code, retry_after)| {
+                log::warn!("gRPC upload from {} rejected by rate limit", ip);
+                let mut status = Status::resource_exhausted(rate_limit::rejection_message(code));
+                status.metadata_mut().insert(
+                    "retry-after",
+                    MetadataValue::from(retry_after.as_secs().max(1)),
+                );
+                status
+            })
+    }
+}
+
+#[tonic::async_trait]
+impl VideoStorage for VideoStorageService {
+    async fn upload_video(
+        &self,
+        request: Request<Streaming<proto::UploadChunk>>,
+    ) -> Result<Response<proto::Video>, Status> {
+        let ip = self.client_ip(&request);
+        let mut stream = request.into_inner();
+        let Some(Part::Metadata(metadata)) = stream.message().await?.and_then(|chunk| chunk.part)
+        else {
+            return Err(Status::invalid_argument(
+                "The first message must carry the upload metadata",
+            ));
+        };
+        let permit = self.acquire_permit(ip, metadata.size)?;
+
+        let (pool, config, events) = (self.pool.clone(), self.config.clone(), self.events.clone());
+        let video = self
+            .local
+            .run(move || async move {
+                receive_upload(metadata, stream, permit, pool, config, events)
+                    .await
+                    .map_err(status)
+            })
+            .await??;
+        Ok(Response::new(video_message(video)))
+    }
+
+    async fn import_video(
+        &self,
+        request: Request<proto::ImportVideoRequest>,
+    ) -> Result<Response<proto::Video>, Status> {
+        let permit = self.acquire_permit(self.client_ip(&request), None)?;
+        let request = request.into_inner();
+        let body = ImportRequest {
+            url: request.url,
+            title: request.title,
+            description: request.description,
+            encoding_profile: None,
+            priority: None,
+        };
+
+        let (pool, config, tracker, events) = (
+            self.pool.clone(),
+            self.config.clone(),
+            self.tracker.clone(),
+            self.events.clone(),
+        );
+        let video = self
+            .local
+            .run(move || async move {
+                videos::start_import(body, permit, pool, config, tracker, events)
+                    .await
+                    .map_err(status)
+            })
+            .await??;
+        Ok(Response::new(video_message(video)))
+    }
+
+    async fn get_video(
+        &self,
+        request: Request<proto::GetVideoRequest>,
+    ) -> Result<Response<proto::VideoDetails>, Status> {
+        let video_id = Uuid::parse_str(&request.into_inner().id).map_err(|_| invalid_id())?;
+
+        let pool = self.pool.clone();
+        let details = self
+            .local
+            .run(move || async move {
+                // Calls are made with admin tokens
+                videos::load_video_details(video_id, pool, false, true, None)
+                    .await
+                    .map_err(status)
+            })
+            .await??;
+        let Some((video, qualities)) = details else {
+            return Err(Status::not_found("Video not found"));
+        };
+
+        let thumbnail_url = crate::api::dto::thumbnail_url(&video, "");
+        Ok(Response::new(proto::VideoDetails {
+            progress: crate::api::dto::processing_progress(&video, &qualities).map(u32::from),
+            video: Some(video_message(video)),
+            qualities: qualities
+                .into_iter()
+                .map(|quality| {
+                    let quality = crate::api::dto::quality_resource(quality, "");
+                    proto::Quality {
+                        resolution: quality.resolution,
+                        bitrate: quality.bitrate,
+                        state: quality.state,
+                        playlist_url: quality.playlist_url,
+                        progress: quality.progress.into(),
+                        label: quality.label,
+                        frame_rate: quality.frame_rate,
+                        width: quality.width,
+                        height: quality.height,
+                    }
+                })
+                .collect(),
+            stream_url: format!("/uploads/{}/hls/master.m3u8", video_id),
+            thumbnail_url,
+        }))
+    }
+
+    async fn list_videos(
+        &self,
+        request: Request<proto::ListVideosRequest>,
+    ) -> Result<Response<proto::ListVideosResponse>, Status> {
+        let request = request.into_inner();
+        let query = ListQueryParams {
+            page: (request.page > 0).then_some(request.page),
+            per_page: (request.per_page > 0).then_some(request.per_page),
+            ..Default::default()
+        };
+
+        let pool = self.pool.clone();
+        let page = self
+            .local
+            .run(move || async move { videos::load_video_page(&query, pool).await.map_err(status) })
+            .await??;
+
+        Ok(Response::new(proto::ListVideosResponse {
+            total_pages: page.total_pages(),
+            total: page.total,
+            page: page.page,
+            per_page: page.per_page,
+            videos: page.videos.into_iter().map(video_message).collect(),
+        }))
+    }
+
+    type WatchVideoStream = BoxStream<'static, Result<proto::ProgressEvent, Status>>;
+
+    async fn watch_video(
+        &self,
+        request: Request<proto::WatchVideoRequest>,
+    ) -> Result<Response<Self::WatchVideoStream>, Status> {
+        use crate::db::schema::videos;
+
+        let video_id = Uuid::parse_str(&request.into_inner().id).map_err(|_| invalid_id())?;
+        // Subscribe before reading the status so nothing is missed in between
+        let receiver = self.events.subscribe(video_id);
+
+        let conn = &mut self.pool.get().await.expect("Failed to get DB connection");
+        let (status, status_reason) = videos::table
+            .filter(videos::id.eq(video_id))
+            .select((videos::status, videos::status_reason))
+            .first::<(String, Option<String>)>(conn)
+            .await
+            .map_err(|_| Status::not_found("Video not found"))?;
+
+        let finished = match status.as_str() {
+            "processed" => Some(events::ProgressEvent::Done),
+            "failed" | "rejected" | "canceled" => Some(events::ProgressEvent::Failed {
+                reason: status_reason.unwrap_or_else(|| "Processing failed".to_string()),
+            }),
+            _ => None,
+        };
+
+        let stream = futures::stream::unfold(
+            (receiver, finished, false),
+            |(mut receiver, finished, closed)| async move {
+                if closed {
+                    return None;
+                }
+                let event = match finished {
+                    Some(event) => event,
+                    None => loop {
+                        match receiver.recv().await {
+                            Ok(event) => break event,
+                            // A slow client only misses intermediate percentages
+                            Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                            Err(tokio::sync::broadcast::error::RecvError::Closed) => return None,
+                        }
+                    },
+                };
+                let closed = event.is_terminal();
+                Some((Ok(event_message(event)), (receiver, None, closed)))
+            },
+        );
+        Ok(Response::new(stream.boxed()))
+    }
+
+    async fn cancel_video(
+        &self,
+        request: Request<proto::CancelVideoRequest>,
+    ) -> Result<Response<proto::Video>, Status> {
+        let video_id = Uuid::parse_str(&request.into_inner().id).map_err(|_| invalid_id())?;
+
+        let (pool, events) = (self.pool.clone(), self.events.clone());
+        let video = self
+            .local
+            .run(move || async move {
+                videos::cancel_processing(video_id, &pool, &events)
+                    .await
+                    .map_err(status)
+            })
+            .await??;
+        Ok(Response::new(video_message(video)))
+    }
+
+    async fn reprocess_video(
+        &self,
+        request: Request<proto::ReprocessVideoRequest>,
+    ) -> Result<Response<proto::Video>, Status> {
+        let video_id = Uuid::parse_str(&request.into_inner().id).map_err(|_| invalid_id())?;
+
+        let pool = self.pool.clone();
+        let video = self
+            .local
+            .run(move || async move { videos::reprocess(video_id, &pool).await.map_err(status) })
+            .await??;
+        Ok(Response::new(video_message(video)))
+    }
+}
+
+/// Lets through calls with one of `tokens` as their `authorization: Bearer`
+/// metadata. The API can upload, cancel and reprocess any video, so it's
+/// limited to admins like the HTTP endpoints that do.
+#[allow(clippy::result_large_err)] // The signature of `Interceptor`
+fn authorize(tokens: Vec<String>) -> impl Interceptor + Clone {
+    move |request: Request<()>| {
+        let authorized = request
+            .metadata()
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| is_bearer_token(v, &tokens));
+        if !authorized {
+            return Err(Status::unauthenticated("An admin token is required"));
+        }
+        Ok(request)
+    }
+}
+
+/// Same as a multipart upload, with the data coming from the gRPC stream.
+/// The size in the metadata is only charged up front, so the data is counted
+/// against the limits as it arrives.
+async fn receive_upload(
+    metadata: proto::UploadMetadata,
+    stream: Streaming<proto::UploadChunk>,
+    permit: Option<UploadPermit>,
+    pool: web::Data<DbPool>,
+    config: web::Data<Arc<AppConfig>>,
+    events: web::Data<ProgressEvents>,
+) -> Result<Video, Error> {
+    let video_id = Uuid::new_v4();
+    let received = permit.as_ref().map(UploadPermit::received);
+    let mut body = Box::pin(
+        stream
+            .map_err(|e| actix_web::error::ErrorBadRequest(e.message().to_string()))
+            .try_filter_map(move |chunk| {
+                let received = received.clone();
+                async move {
+                    match chunk.part {
+                        Some(Part::Data(data)) => {
+                            if let Some(received) = received {
+                                received.fetch_add(data.len() as u64, Ordering::Relaxed);
+                            }
+                            Ok(Some(data))
+                        }
+                        Some(Part::Metadata(_)) => Err(actix_web::error::ErrorBadRequest(
+                            "Metadata must only be sent in the first message",
+                        )),
+                        None => Ok(None),
+                    }
+                }
+            }),
+    );
+
+    let saved = video_processor::save_upload(
+        &mut body,
+        video_id,
+        config.storage.max_file_size,
+        metadata.size,
+        metadata.filename.as_deref(),
+        &config,
+        &events,
+    )
+    .await?;
+    let checksum = saved.checksum;
+
+    let video = Video {
+        id: video_id,
+        title: metadata.title.unwrap_or_else(|| "Untitled".to_string()),
+        description: metadata.description,
+        duration: None,
+        status: "uploading".to_string(),
+        created_at: chrono::Utc::now().naive_utc(),
+        updated_at: chrono::Utc::now().naive_utc(),
+        status_reason: None,
+        upload_token: None,
+        checksum: Some(checksum.clone()),
+        processing_profile: None,
+        encoding_profile: None,
+        restricted: false,
+        embed_domains: Vec::new(),
+        unlisted: false,
+        error_code: None,
+        watermark_position: None,
+        license: None,
+        attribution: None,
+        source_url: None,
+        tags: Vec::new(),
+        publish_at: None,
+        original_filename: None,
+        poster: None,
+        priority: "normal".to_string(),
+    };
+
+    let conn = &mut pool.get().await.expect("Failed to get DB connection");
+    diesel::insert_into(crate::db::schema::videos::table)
+        .values(&video)
+        .execute(conn)
+        .await
+        .map_err(|e| {
+            log::error!("Error inserting video {}: {}", video_id, e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+
+    video_processor::verify_checksum(video_id, &checksum, metadata.sha256.as_deref(), conn).await?;
+    let config = config.get_ref().clone();
+    video_processor::ingest(video_id, saved.info, conn, pool, config, events).await?;
+
+    Ok(video)
+}
+
+fn invalid_id() -> Status {
+    Status::invalid_argument("Invalid video id")
+}
+
+/// Maps the HTTP handlers' errors to the closest gRPC status
+fn status(e: Error) -> Status {
+    // Most errors are rendered as the JSON envelope; only its message is useful here
+    let message = e.to_string();
+    let message = serde_json::from_str::<ResponseType>(&message)
+        .ok()
+        .and_then(|body| body.error)
+        .map_or(message, |error| error.message);
+    match e.as_response_error().status_code() {
+        StatusCode::BAD_REQUEST | StatusCode::UNPROCESSABLE_ENTITY => {
+            Status::invalid_argument(message)
+        }
+        StatusCode::NOT_FOUND => Status::not_found(message),
+        StatusCode::CONFLICT => Status::aborted(message),
+        StatusCode::PAYLOAD_TOO_LARGE | StatusCode::TOO_MANY_REQUESTS => {
+            Status::resource_exhausted(message)
+        }
+        _ => Status::internal(message),
+    }
+}
+
+fn video_message(video: Video) -> proto::Video {
+    let video = VideoResource::from(video);
+    proto::Video {
+        id: video.id.to_string(),
+        title: video.title,
+        description: video.description,
+        duration: video.duration,
+        status: video.status,
+        status_reason: video.status_reason,
+        checksum: video.checksum,
+        created_at: video.created_at.to_rfc3339(),
+        updated_at: video.updated_at.to_rfc3339(),
+        error_code: video.error_code,
+    }
+}
+
+fn event_message(event: events::ProgressEvent) -> proto::ProgressEvent {
+    use events::ProgressEvent::*;
+
+    let mut message = proto::ProgressEvent::default();
+    match event {
+        Upload {
+            received_bytes,
+            total_bytes,
+        } => {
+            message.r#type = "upload".to_string();
+            message.received_bytes = Some(received_bytes);
+            message.total_bytes = total_bytes;
+        }
+        Transcode { quality, percent } => {
+            message.r#type = "transcode".to_string();
+            message.quality = Some(quality);
+            message.percent = Some(percent.into());
+        }
+        QualityReady { quality } => {
+            message.r#type = "quality_ready".to_string();
+            message.quality = Some(quality);
+        }
+        QualityFailed { quality } => {
+            message.r#type = "quality_failed".to_string();
+            message.quality = Some(quality);
+        }
+        Thumbnails => message.r#type = "thumbnails".to_string(),
+        Done => message.r#type = "done".to_string(),
+        Failed { reason } => {
+            message.r#type = "failed".to_string();
+            message.reason = Some(reason);
+        }
+    }
+    message
+}
@@ -0,0 +1,165 @@
+// src/grpc/proto.rs
+//! Messages of proto/vid_storage.proto, written out by hand so building
+//! doesn't need protoc. Field tags must match the .proto file.
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct UploadMetadata {
+    #[prost(string, optional, tag = "1")]
+    pub title: Option<String>,
+    #[prost(string, optional, tag = "2")]
+    pub description: Option<String>,
+    #[prost(string, optional, tag = "3")]
+    pub filename: Option<String>,
+    #[prost(string, optional, tag = "4")]
+    pub sha256: Option<String>,
+    #[prost(uint64, optional, tag = "5")]
+    pub size: Option<u64>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct UploadChunk {
+    #[prost(oneof = "upload_chunk::Part", tags = "1, 2")]
+    pub part: Option<upload_chunk::Part>,
+}
+
+pub mod upload_chunk {
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum Part {
+        #[prost(message, tag = "1")]
+        Metadata(super::UploadMetadata),
+        #[prost(bytes = "bytes", tag = "2")]
+        Data(prost::bytes::Bytes),
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ImportVideoRequest {
+    #[prost(string, tag = "1")]
+    pub url: String,
+    #[prost(string, optional, tag = "2")]
+    pub title: Option<String>,
+    #[prost(string, optional, tag = "3")]
+    pub description: Option<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GetVideoRequest {
+    #[prost(string, tag = "1")]
+    pub id: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListVideosRequest {
+    #[prost(int64, tag = "1")]
+    pub page: i64,
+    #[prost(int64, tag = "2")]
+    pub per_page: i64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct WatchVideoRequest {
+    #[prost(string, tag = "1")]
+    pub id: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct CancelVideoRequest {
+    #[prost(string, tag = "1")]
+    pub id: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ReprocessVideoRequest {
+    #[prost(string, tag = "1")]
+    pub id: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Video {
+    #[prost(string, tag = "1")]
+    pub id: String,
+    #[prost(string, tag = "2")]
+    pub title: String,
+    #[prost(string, optional, tag = "3")]
+    pub description: Option<String>,
+    #[prost(double, optional, tag = "4")]
+    pub duration: Option<f64>,
+    #[prost(string, tag = "5")]
+    pub status: String,
+    #[prost(string, optional, tag = "6")]
+    pub status_reason: Option<String>,
+    #[prost(string, optional, tag = "7")]
+    pub checksum: Option<String>,
+    #[prost(string, tag = "8")]
+    pub created_at: String,
+    #[prost(string, tag = "9")]
+    pub updated_at: String,
+    #[prost(string, optional, tag = "10")]
+    pub error_code: Option<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Quality {
+    #[prost(string, tag = "1")]
+    pub resolution: String,
+    #[prost(string, tag = "2")]
+    pub bitrate: String,
+    #[prost(string, tag = "3")]
+    pub state: String,
+    #[prost(string, tag = "4")]
+    pub playlist_url: String,
+    #[prost(uint32, tag = "5")]
+    pub progress: u32,
+    #[prost(string, tag = "6")]
+    pub label: String,
+    #[prost(double, optional, tag = "7")]
+    pub frame_rate: Option<f64>,
+    #[prost(uint32, optional, tag = "8")]
+    pub width: Option<u32>,
+    #[prost(uint32, optional, tag = "9")]
+    pub height: Option<u32>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct VideoDetails {
+    #[prost(message, optional, tag = "1")]
+    pub video: Option<Video>,
+    #[prost(message, repeated, tag = "2")]
+    pub qualities: Vec<Quality>,
+    #[prost(string, tag = "3")]
+    pub stream_url: String,
+    #[prost(string, tag = "4")]
+    pub thumbnail_url: String,
+    #[prost(uint32, optional, tag = "5")]
+    pub progress: Option<u32>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListVideosResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub videos: Vec<Video>,
+    #[prost(int64, tag = "2")]
+    pub total: i64,
+    #[prost(int64, tag = "3")]
+    pub page: i64,
+    #[prost(int64, tag = "4")]
+    pub per_page: i64,
+    #[prost(int64, tag = "5")]
+    pub total_pages: i64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ProgressEvent {
+    #[prost(string, tag = "1")]
+    pub r#type: String,
+    #[prost(uint64, optional, tag = "2")]
+    pub received_bytes: Option<u64>,
+    #[prost(uint64, optional, tag = "3")]
+    pub total_bytes: Option<u64>,
+    #[prost(string, optional, tag = "4")]
+    pub quality: Option<String>,
+    #[prost(uint32, optional, tag = "5")]
+    pub percent: Option<u32>,
+    #[prost(string, optional, tag = "6")]
+    pub reason: Option<String>,
+}
@@ -0,0 +1,8 @@
+pub mod api;
+pub mod config;
+pub mod db;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod loadtest;
+pub mod services;
+// pub mod utils;
@@ -0,0 +1,324 @@
+// src/loadtest.rs
+//! `video loadtest`: drives a running instance with concurrent uploads and
+//! HLS viewers and reports latency percentiles per request kind.
+//!
+//! Uploads create real videos through the two-phase v2 API, so point it at a
+//! disposable instance. Viewers replay the HLS packages of processed videos,
+//! fetching segments back to back rather than in real time.
+use actix_web::web::Bytes;
+use futures::Future;
+use reqwest::Url;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::JoinSet;
+use vid_storage_models::{
+    CreateVideoRequest, Page, PendingUpload, ResponseType, VideoListItem, VideoResource,
+};
+
+const USAGE: &str = "usage: video loadtest [--url http://127.0.0.1:8080] [--viewers 10] \
+[--uploads 0 --file clip.mp4] [--duration 30]";
+
+struct Options {
+    url: Url,
+    viewers: usize,
+    uploads: usize,
+    file: Option<PathBuf>,
+    duration: Duration,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut options = Options {
+            url: Url::parse("http://127.0.0.1:8080").unwrap(),
+            viewers: 10,
+            uploads: 0,
+            file: None,
+            duration: Duration::from_secs(30),
+        };
+
+        let mut args = args.iter();
+        while let Some(flag) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| format!("Missing value for {}", flag))?;
+            let invalid = || format!("Invalid value for {}: {}", flag, value);
+            match flag.as_str() {
+                "--url" => options.url = Url::parse(value).map_err(|_| invalid())?,
+                "--viewers" => options.viewers = value.parse().map_err(|_| invalid())?,
+                "--uploads" => options.uploads = value.parse().map_err(|_| invalid())?,
+                "--file" => options.file = Some(PathBuf::from(value)),
+                "--duration" => {
+                    options.duration = Duration::from_secs(value.parse().map_err(|_| invalid())?)
+                }
+                _ => return Err(format!("Unknown option {}", flag)),
+            }
+        }
+
+        if options.uploads > 0 && options.file.is_none() {
+            return Err("--uploads needs a --file to upload".to_string());
+        }
+        Ok(options)
+    }
+}
+
+#[derive(Default)]
+struct Samples {
+    latencies: Vec<Duration>,
+    errors: usize,
+}
+
+/// Latencies by request kind, shared by all simulated clients
+#[derive(Clone, Default)]
+struct Stats(Arc<Mutex<BTreeMap<&'static str, Samples>>>);
+
+impl Stats {
+    /// Runs and times one request, recording it as an error if it fails
+    async fn time<T, E: std::fmt::Display>(
+        &self,
+        kind: &'static str,
+        request: impl Future<Output = Result<T, E>>,
+    ) -> Option<T> {
+        let started = Instant::now();
+        let result = request.await;
+        let elapsed = started.elapsed();
+
+        let mut samples = self.0.lock().unwrap();
+        let samples = samples.entry(kind).or_default();
+        match result {
+            Ok(value) => {
+                samples.latencies.push(elapsed);
+                Some(value)
+            }
+            Err(e) => {
+                log::debug!("{} request failed: {}", kind, e);
+                samples.errors += 1;
+                None
+            }
+        }
+    }
+
+    fn report(&self, elapsed: Duration) {
+        println!(
+            "{:<10} {:>8} {:>7} {:>8} {:>9} {:>9} {:>9} {:>9}",
+            "request", "count", "errors", "req/s", "p50 ms", "p90 ms", "p99 ms", "max ms"
+        );
+        for (kind, samples) in self.0.lock().unwrap().iter_mut() {
+            samples.latencies.sort();
+            let percentile = |q: f64| {
+                let index = ((samples.latencies.len() - 1) as f64 * q).round() as usize;
+                samples.latencies[index].as_secs_f64() * 1000.0
+            };
+            let count = samples.latencies.len();
+            let (p50, p90, p99, max) = if count == 0 {
+                (0.0, 0.0, 0.0, 0.0)
+            } else {
+                (
+                    percentile(0.5),
+                    percentile(0.9),
+                    percentile(0.99),
+                    percentile(1.0),
+                )
+            };
+            println!(
+                "{:<10} {:>8} {:>7} {:>8.1} {:>9.1} {:>9.1} {:>9.1} {:>9.1}",
+                kind,
+                count,
+                samples.errors,
+                count as f64 / elapsed.as_secs_f64(),
+                p50,
+                p90,
+                p99,
+                max
+            );
+        }
+    }
+}
+
+pub async fn run(args: &[String]) -> io::Result<()> {
+    let options = Options::parse(args).map_err(|e| {
+        eprintln!("{}\n{}", e, USAGE);
+        io::Error::new(io::ErrorKind::InvalidInput, e)
+    })?;
+    let file = match &options.file {
+        Some(path) => Bytes::from(tokio::fs::read(path).await?),
+        None => Bytes::new(),
+    };
+
+    let client = reqwest::Client::new();
+    let stats = Stats::default();
+    let deadline = Instant::now() + options.duration;
+
+    let mut videos = Vec::new();
+    if options.viewers > 0 {
+        videos = processed_videos(&client, &options.url)
+            .await
+            .map_err(io::Error::other)?;
+        if videos.is_empty() {
+            return Err(io::Error::other(
+                "No processed videos to watch; upload some or pass --viewers 0",
+            ));
+        }
+    }
+
+    println!(
+        "Running {} viewers and {} uploaders against {} for {}s",
+        options.viewers,
+        options.uploads,
+        options.url,
+        options.duration.as_secs()
+    );
+    let started = Instant::now();
+    let mut clients = JoinSet::new();
+    for viewer in 0..options.viewers {
+        let (client, stats, url) = (client.clone(), stats.clone(), options.url.clone());
+        // Spread viewers over the catalog
+        let video = videos[viewer % videos.len()].clone();
+        clients.spawn(watch(client, stats, url, video, viewer, deadline));
+    }
+    for _ in 0..options.uploads {
+        let (client, stats, url) = (client.clone(), stats.clone(), options.url.clone());
+        clients.spawn(upload(client, stats, url, file.clone(), deadline));
+    }
+    while clients.join_next().await.is_some() {}
+
+    stats.report(started.elapsed());
+    Ok(())
+}
+
+async fn processed_videos(
+    client: &reqwest::Client,
+    base: &Url,
+) -> Result<Vec<VideoResource>, String> {
+    let url = base
+        .join("/api/v2/videos?per_page=100")
+        .map_err(|e| e.to_string())?;
+    let body: ResponseType<Page<VideoListItem>> = client
+        .get(url)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| format!("Failed to list videos: {}", e))?
+        .bytes()
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|b| serde_json::from_slice(&b).map_err(|e| e.to_string()))?;
+    Ok(body
+        .data
+        .map(|page| page.items.into_iter().map(|item| item.video).collect())
+        .unwrap_or_default())
+}
+
+/// Fetches the master playlist, one rendition playlist and then its segments,
+/// starting over with the next rendition until the deadline
+async fn watch(
+    client: reqwest::Client,
+    stats: Stats,
+    base: Url,
+    video: VideoResource,
+    viewer: usize,
+    deadline: Instant,
+) {
+    let get = |url: Url| {
+        let client = client.clone();
+        async move {
+            client
+                .get(url)
+                .send()
+                .await?
+                .error_for_status()?
+                .bytes()
+                .await
+        }
+    };
+    let Ok(master_url) = base.join(&format!("/uploads/{}/hls/master.m3u8", video.id)) else {
+        return;
+    };
+
+    let mut rendition = viewer;
+    while Instant::now() < deadline {
+        let Some(master) = stats.time("master", get(master_url.clone())).await else {
+            continue;
+        };
+        let variants = uris(&master);
+        if variants.is_empty() {
+            return;
+        }
+        rendition += 1;
+        let Ok(playlist_url) = master_url.join(&variants[rendition % variants.len()]) else {
+            return;
+        };
+
+        let Some(playlist) = stats.time("playlist", get(playlist_url.clone())).await else {
+            continue;
+        };
+        for segment in uris(&playlist) {
+            if Instant::now() >= deadline {
+                return;
+            }
+            if let Ok(segment_url) = playlist_url.join(&segment) {
+                stats.time("segment", get(segment_url)).await;
+            }
+        }
+    }
+}
+
+/// Creates videos and sends their content back to back until the deadline
+async fn upload(client: reqwest::Client, stats: Stats, base: Url, file: Bytes, deadline: Instant) {
+    let Ok(create_url) = base.join("/api/v2/videos") else {
+        return;
+    };
+
+    while Instant::now() < deadline {
+        let create = async {
+            let body = client
+                .post(create_url.clone())
+                .header("Content-Type", "application/json")
+                .body(
+                    serde_json::to_vec(&CreateVideoRequest {
+                        title: Some("Load test".to_string()),
+                        description: None,
+                        encoding_profile: None,
+                        watermark_position: None,
+                        priority: None,
+                    })
+                    .unwrap_or_default(),
+                )
+                .send()
+                .await?
+                .error_for_status()?
+                .bytes()
+                .await?;
+            Ok::<_, reqwest::Error>(
+                serde_json::from_slice::<ResponseType<PendingUpload>>(&body)
+                    .ok()
+                    .and_then(|response| response.data),
+            )
+        };
+        let Some(Some(pending)) = stats.time("create", create).await else {
+            // Don't spin on a server that is refusing uploads
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            continue;
+        };
+
+        let content = client
+            .put(&pending.upload_url)
+            .header("X-Upload-Token", &pending.upload_token)
+            .body(file.clone())
+            .send();
+        let content = async { content.await?.error_for_status() };
+        stats.time("upload", content).await;
+    }
+}
+
+/// URIs in a playlist, i.e. its non-empty lines that aren't tags or comments
+fn uris(playlist: &[u8]) -> Vec<String> {
+    String::from_utf8_lossy(playlist)
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
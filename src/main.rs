@@ -1,13 +1,9 @@
-use actix_files::Files;
 use actix_web::{web, App, HttpServer};
 use dotenv::dotenv;
 use std::sync::Arc;
-
-mod api;
-mod config;
-mod db;
-mod services;
-// mod utils;
+#[cfg(feature = "grpc")]
+use video::grpc;
+use video::{api, config, db, loadtest, services};
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
@@ -17,9 +13,21 @@ async fn main() -> std::io::Result<()> {
     // Initialize logger
     env_logger::init();
 
+    // `video loadtest ...` drives a running instance instead of serving
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("loadtest") {
+        return loadtest::run(&args[1..]).await;
+    }
+
     // Load configuration
     let config = config::AppConfig::new().expect("Failed to load configuration");
     let config = Arc::new(config);
+    services::outbound::init(&config.outbound);
+
+    // Fail fast rather than on the first upload
+    services::ffmpeg::verify(&config.ffmpeg)
+        .await
+        .expect("ffmpeg check failed");
 
     log::info!(
         "Starting server on {}:{}",
@@ -31,17 +39,108 @@ async fn main() -> std::io::Result<()> {
     tokio::fs::create_dir_all(&config.storage.upload_path)
         .await
         .expect("Failed to create upload directory");
+    api::attachments::move_legacy_attachments()
+        .await
+        .expect("Failed to move attachments out of the upload directory");
 
     // Create DB pool
     let pool = db::create_pool(&config.database.url).await;
 
+    let imports = web::Data::new(services::import::ImportTracker::default());
+    let events = web::Data::new(services::events::ProgressEvents::default());
+    let segment_cache = web::Data::new(services::segment_cache::SegmentCache::new(
+        config.storage.segment_cache_size,
+    ));
+    let blocking = web::Data::new(
+        services::blocking::BlockingPool::new(config.storage.blocking_threads)
+            .expect("Failed to start blocking pool"),
+    );
+    let entitlements = web::Data::new(
+        services::entitlement::Entitlements::new(&config.entitlement)
+            .expect("Failed to create entitlement client"),
+    );
+    let upload_limiter = web::Data::new(api::rate_limit::UploadLimiter::new(
+        config.upload_limits.clone(),
+    ));
+    let frame_limiter = web::Data::new(api::rate_limit::RequestLimiter::new(
+        config.frames.max_requests,
+        config.frames.window_secs,
+    ));
+
+    #[cfg(feature = "grpc")]
+    if let Some(port) = config.server.grpc_port {
+        grpc::spawn(
+            port,
+            web::Data::new(pool.clone()),
+            web::Data::new(config.clone()),
+            imports.clone(),
+            events.clone(),
+            upload_limiter.clone(),
+        );
+    }
+    #[cfg(not(feature = "grpc"))]
+    if config.server.grpc_port.is_some() {
+        log::warn!("server.grpc_port is set but the server was built without the grpc feature");
+    }
+
+    // Before the worker starts, so it never sees a job as still running
+    services::recovery::recover(web::Data::new(pool.clone()), config.clone(), events.clone())
+        .await
+        .expect("Failed to recover interrupted processing");
+
+    if config.worker.embedded {
+        services::jobs::start_worker(
+            web::Data::new(pool.clone()),
+            config.clone(),
+            events.clone(),
+            blocking.clone(),
+        );
+    } else {
+        log::info!("Leaving queued jobs to video-worker processes");
+    }
+    // Progress of the jobs video-worker processes run
+    services::notifications::receive_events(&config.database.url, events.clone());
+    services::stalled::start_sweeper(web::Data::new(pool.clone()), config.clone());
+    services::convert::start_cleanup(web::Data::new(pool.clone()), config.clone());
+
     let c = config.clone();
     // Start HTTP server
     HttpServer::new(move || {
         App::new()
-            .service(Files::new("/uploads", "uploads/").show_files_listing())
+            // Segments of the packages linked as `stream_url`, ahead of the
+            // static files so they get the cached serving path
+            .route(
+                "/uploads/{video_id}/hls/{quality}/{segment}",
+                web::get().to(api::videos::serve_segment),
+            )
+            // Rewritten with the viewer's token for restricted videos
+            .route(
+                "/uploads/{video_id}/hls/master.m3u8",
+                web::get().to(api::videos::serve_master_playlist),
+            )
+            .route(
+                "/uploads/{video_id}/hls/master_low.m3u8",
+                web::get().to(api::videos::serve_low_master_playlist),
+            )
+            .route("/embed/{video_id}", web::get().to(api::embed::embed_page))
+            .route(
+                "/uploads/{video_id}/review{file:.*}",
+                web::get().to(api::videos::serve_review),
+            )
+            // Only the directories meant for clients, never a listing
+            .route(
+                "/uploads/{video_id}/{path:.*}",
+                web::get().to(api::videos::serve_upload),
+            )
             .app_data(web::Data::new(pool.clone()))
             .app_data(web::Data::new(c.clone()))
+            .app_data(imports.clone())
+            .app_data(events.clone())
+            .app_data(upload_limiter.clone())
+            .app_data(frame_limiter.clone())
+            .app_data(segment_cache.clone())
+            .app_data(entitlements.clone())
+            .app_data(blocking.clone())
             .wrap(actix_cors::Cors::permissive()) // Configure properly in production
             .configure(api::configure)
     })
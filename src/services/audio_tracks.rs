@@ -0,0 +1,102 @@
+// src/services/audio_tracks.rs
+use crate::db::models::AudioTrack;
+use crate::services::probe;
+use anyhow::Result;
+use chrono::Utc;
+use diesel::{ExpressionMethods, QueryDsl};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use uuid::Uuid;
+
+/// `GROUP-ID` of the audio renditions in the master playlist
+pub const GROUP_ID: &str = "audio";
+
+/// The source's audio streams in file order. Exactly one is the default: the
+/// first the source flags as such, or else the first.
+pub fn detect(v_id: Uuid, info: &probe::MediaInfo) -> Vec<AudioTrack> {
+    let default = info
+        .audio_streams()
+        .position(|stream| stream.disposition.default == 1)
+        .unwrap_or(0);
+
+    let mut tracks: Vec<AudioTrack> = Vec::new();
+    for (position, stream) in info.audio_streams().enumerate() {
+        let language = stream.language();
+        let mut name = stream
+            .tags
+            .title
+            .as_deref()
+            .map(str::trim)
+            .filter(|title| !title.is_empty())
+            .or(language.as_deref())
+            .map_or_else(|| format!("Track {}", position + 1), str::to_string);
+        // Players tell renditions apart by name
+        if tracks.iter().any(|track| track.name == name) {
+            name = format!("{} ({})", name, position + 1);
+        }
+        tracks.push(AudioTrack {
+            id: Uuid::new_v4(),
+            video_id: v_id,
+            position: position as i32,
+            language,
+            name,
+            channels: stream.channels.map(|channels| channels as i32),
+            is_default: position == default,
+            created_at: Utc::now().naive_utc(),
+        });
+    }
+    tracks
+}
+
+/// Replaces the tracks recorded for the video
+pub async fn save(conn: &mut AsyncPgConnection, v_id: Uuid, tracks: &[AudioTrack]) -> Result<()> {
+    use crate::db::schema::audio_tracks;
+
+    diesel::delete(audio_tracks::table.filter(audio_tracks::video_id.eq(v_id)))
+        .execute(conn)
+        .await?;
+    diesel::insert_into(audio_tracks::table)
+        .values(tracks)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// The tracks recorded for the video, in the source's order
+pub async fn load(conn: &mut AsyncPgConnection, v_id: Uuid) -> Result<Vec<AudioTrack>> {
+    use crate::db::schema::audio_tracks;
+
+    Ok(audio_tracks::table
+        .filter(audio_tracks::video_id.eq(v_id))
+        .order(audio_tracks::position.asc())
+        .load::<AudioTrack>(conn)
+        .await?)
+}
+
+/// Rendition name of a track, which is also its directory under `hls`
+pub fn quality(track: &AudioTrack) -> String {
+    format!("audio-{}", track.position)
+}
+
+/// `EXT-X-MEDIA` entry for a track packaged at `uri`
+pub fn media_tag(track: &AudioTrack, default: bool, uri: &str) -> String {
+    // Quoted strings can't contain quotes or line breaks
+    let quoted = |value: &str| value.replace(['"', '\r', '\n'], "");
+    let mut tag = format!(
+        "#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"{}\",NAME=\"{}\"",
+        GROUP_ID,
+        quoted(&track.name)
+    );
+    if let Some(language) = &track.language {
+        tag.push_str(&format!(",LANGUAGE=\"{}\"", quoted(language)));
+    }
+    tag.push_str(if default {
+        ",DEFAULT=YES,AUTOSELECT=YES"
+    } else {
+        ",DEFAULT=NO,AUTOSELECT=YES"
+    });
+    if let Some(channels) = track.channels {
+        tag.push_str(&format!(",CHANNELS=\"{}\"", channels));
+    }
+    tag.push_str(&format!(",URI=\"{}\"\n", uri));
+    tag
+}
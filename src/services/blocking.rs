@@ -0,0 +1,42 @@
+// src/services/blocking.rs
+use std::io;
+use tokio::runtime::{Builder, Runtime};
+
+/// Threads reserved for long filesystem traversals (checksum audits and the
+/// like), kept apart from the runtime's own blocking pool so a large audit
+/// can't hold up the file reads that serve playback.
+pub struct BlockingPool {
+    runtime: Option<Runtime>,
+}
+
+impl BlockingPool {
+    /// Runs at most `threads` jobs at once; further jobs wait for a free thread
+    pub fn new(threads: usize) -> io::Result<Self> {
+        let runtime = Builder::new_multi_thread()
+            .worker_threads(1)
+            .max_blocking_threads(threads.max(1))
+            .thread_name("blocking-fs")
+            .build()?;
+        Ok(BlockingPool {
+            runtime: Some(runtime),
+        })
+    }
+
+    pub async fn run<F, T>(&self, f: F) -> io::Result<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let runtime = self.runtime.as_ref().expect("Blocking pool shut down");
+        runtime.spawn_blocking(f).await.map_err(io::Error::other)
+    }
+}
+
+impl Drop for BlockingPool {
+    fn drop(&mut self) {
+        // Dropping a runtime from async code panics; running jobs finish detached
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
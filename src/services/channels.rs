@@ -0,0 +1,219 @@
+// src/services/channels.rs
+use crate::services::playlist::{MediaPlaylist, Segment, Variant};
+use chrono::{DateTime, Duration, Utc};
+
+/// Segments in a channel's playlist. Players start about three target
+/// durations from the end, so this leaves some room behind them.
+const WINDOW_SEGMENTS: u64 = 6;
+
+/// The window of a channel that started playing `videos`, each given as its
+/// segments, back to back and in a loop at `starts_at`. It ends with the
+/// segment playing `now`, and carries the wall-clock times segments air at.
+/// None when there is nothing to play.
+pub fn live_window(
+    videos: &[Vec<Segment>],
+    starts_at: DateTime<Utc>,
+    now: DateTime<Utc>,
+) -> Option<MediaPlaylist> {
+    // Each segment with whether it starts a video, and when it starts into
+    // the loop
+    let mut schedule = Vec::new();
+    let mut total = 0.0;
+    for segments in videos {
+        for (i, segment) in segments.iter().enumerate() {
+            schedule.push((segment, i == 0, total));
+            total += segment.duration;
+        }
+    }
+    if schedule.is_empty() || total <= 0.0 {
+        return None;
+    }
+
+    let count = schedule.len() as u64;
+    let elapsed = ((now - starts_at).num_milliseconds() as f64 / 1000.0).max(0.0);
+    let loops = (elapsed / total).floor();
+    let offset = elapsed - loops * total;
+    let current = schedule
+        .iter()
+        .rposition(|&(_, _, start)| start <= offset)
+        .unwrap_or(0) as u64;
+    let last = loops as u64 * count + current;
+    let first = last.saturating_sub(WINDOW_SEGMENTS - 1);
+
+    // Every video start but the very first is a discontinuity
+    let starts = |range: std::ops::RangeInclusive<u64>| {
+        range.filter(|&n| schedule[(n % count) as usize].1).count() as u64
+    };
+    let per_loop = starts(0..=count - 1);
+    let discontinuity_sequence = first / count * per_loop + starts(0..=first % count) - 1;
+    let first_start = (first / count) as f64 * total + schedule[(first % count) as usize].2;
+
+    Some(MediaPlaylist {
+        media_sequence: first,
+        discontinuity_sequence,
+        target_duration: schedule
+            .iter()
+            .map(|(s, _, _)| s.duration.ceil() as u64)
+            .max()
+            .unwrap_or(1)
+            .max(1),
+        segments: (first..=last)
+            .map(|n| {
+                let (segment, starts_video, _) = schedule[(n % count) as usize];
+                (segment.clone(), starts_video && n > 0)
+            })
+            .collect(),
+        program_date_time: Some(
+            starts_at + Duration::milliseconds((first_start * 1000.0).round() as i64),
+        ),
+        ended: false,
+    })
+}
+
+/// Rendition name of a variant whose URI is `<quality>/stream.m3u8`
+pub fn variant_quality(variant: &Variant) -> Option<&str> {
+    variant.uri.strip_suffix("/stream.m3u8")
+}
+
+/// Master playlist of a channel playing videos with these variants. It
+/// offers the qualities every video has, each at `<quality>.m3u8`.
+pub fn master(videos: &[Vec<Variant>]) -> String {
+    let mut master = String::from("#EXTM3U\n#EXT-X-VERSION:3\n");
+    let Some((first, others)) = videos.split_first() else {
+        return master;
+    };
+    for variant in first {
+        let Some(quality) = variant_quality(variant) else {
+            continue;
+        };
+        let mut same = vec![variant];
+        for variants in others {
+            match variants
+                .iter()
+                .find(|v| variant_quality(v) == Some(quality))
+            {
+                Some(v) => same.push(v),
+                None => break,
+            }
+        }
+        if same.len() < videos.len() {
+            continue;
+        }
+
+        let bandwidth = same
+            .iter()
+            .filter_map(|v| v.attribute("BANDWIDTH")?.parse::<u64>().ok())
+            .max()
+            .unwrap_or_default();
+        master.push_str(&format!("#EXT-X-STREAM-INF:BANDWIDTH={}", bandwidth));
+        if let Some(resolution) = variant.attribute("RESOLUTION") {
+            master.push_str(&format!(",RESOLUTION={}", resolution));
+        }
+        // Left out rather than wrong for some of the videos
+        for (name, quoted) in [("CODECS", true), ("FRAME-RATE", false), ("NAME", true)] {
+            let value = variant
+                .attribute(name)
+                .filter(|&value| same.iter().all(|v| v.attribute(name) == Some(value)));
+            match value {
+                Some(value) if quoted => master.push_str(&format!(",{}=\"{}\"", name, value)),
+                Some(value) => master.push_str(&format!(",{}={}", name, value)),
+                None => {}
+            }
+        }
+        master.push_str(&format!("\n{}.m3u8\n", quality));
+    }
+    master
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn video(name: &str, durations: &[f64]) -> Vec<Segment> {
+        durations
+            .iter()
+            .enumerate()
+            .map(|(i, &duration)| Segment {
+                duration,
+                uri: format!("{}/{}.ts", name, i),
+                map: None,
+            })
+            .collect()
+    }
+
+    fn start() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn starts_at_the_beginning() {
+        let videos = [video("a", &[4.0, 4.0]), video("b", &[4.0])];
+        let window = live_window(&videos, start(), start()).unwrap();
+        assert_eq!(window.media_sequence, 0);
+        assert_eq!(window.discontinuity_sequence, 0);
+        assert_eq!(window.segments.len(), 1);
+        assert!(!window.segments[0].1);
+        assert_eq!(window.program_date_time, Some(start()));
+        assert!(!window.ended);
+    }
+
+    #[test]
+    fn counts_discontinuities_across_loops() {
+        // Three single segment videos of ten seconds, 60 s in: the seventh
+        // segment plays, and each one before the window began a video
+        let videos = [
+            video("a", &[10.0]),
+            video("b", &[10.0]),
+            video("c", &[10.0]),
+        ];
+        let now = start() + Duration::seconds(65);
+        let window = live_window(&videos, start(), now).unwrap();
+        assert_eq!(window.media_sequence, 1);
+        assert_eq!(window.discontinuity_sequence, 1);
+        assert_eq!(window.segments.len(), WINDOW_SEGMENTS as usize);
+        assert_eq!(window.segments[0].0.uri, "b/0.ts");
+        assert_eq!(window.segments[5].0.uri, "a/0.ts");
+        assert!(window
+            .segments
+            .iter()
+            .all(|(_, discontinuity)| *discontinuity));
+        assert_eq!(
+            window.program_date_time,
+            Some(start() + Duration::seconds(10))
+        );
+    }
+
+    #[test]
+    fn keeps_segments_of_a_video_together() {
+        let videos = [video("a", &[4.0, 4.0, 2.0]), video("b", &[3.0])];
+        // Third loop, a second into a
+        let now = start() + Duration::seconds(2 * 13 + 1);
+        let window = live_window(&videos, start(), now).unwrap();
+        let uris: Vec<_> = window
+            .segments
+            .iter()
+            .map(|(s, _)| s.uri.as_str())
+            .collect();
+        assert_eq!(
+            uris,
+            ["b/0.ts", "a/0.ts", "a/1.ts", "a/2.ts", "b/0.ts", "a/0.ts"]
+        );
+        let discontinuities: Vec<_> = window.segments.iter().map(|(_, d)| *d).collect();
+        assert_eq!(discontinuities, [true, true, false, false, true, true]);
+        // b/0.ts at sequence 3 is the only video start after the first
+        assert_eq!(window.media_sequence, 3);
+        assert_eq!(window.discontinuity_sequence, 1);
+        assert_eq!(window.target_duration, 4);
+        assert_eq!(
+            window.program_date_time,
+            Some(start() + Duration::seconds(10))
+        );
+    }
+
+    #[test]
+    fn nothing_to_play() {
+        assert_eq!(live_window(&[], start(), start()), None);
+        assert_eq!(live_window(&[Vec::new()], start(), start()), None);
+    }
+}
@@ -0,0 +1,262 @@
+// src/services/chunks.rs
+use crate::config::app_config::FfmpegConfig;
+use crate::db::models::ChunkEncode;
+use crate::services::{ffmpeg, jobs, playlist, storage, validator, video_processor};
+use anyhow::{Context, Result};
+use diesel::dsl::{exists, not};
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::fs;
+use uuid::Uuid;
+
+/// Directory under the video's holding the chunks of long encodes until
+/// their renditions are packaged. Recovery leaves it in place, so a rerun of
+/// an interrupted job picks up from the last finished chunk.
+const DIR: &str = "chunks";
+/// How often a job waiting on chunks other workers encode checks on them
+const WAIT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A time range of the source encoded on its own
+#[derive(Debug, Clone, Copy)]
+pub struct Chunk {
+    pub index: usize,
+    pub start: f64,
+    /// None for the last chunk, which runs to the end of the source
+    pub length: Option<f64>,
+}
+
+impl Chunk {
+    /// Name of the chunk's directory, and the prefix of its segment names,
+    /// which stay unique once the chunks are joined
+    pub fn name(&self) -> String {
+        format!("{:03}", self.index)
+    }
+
+    /// Seconds the chunk lasts of a source of `duration` seconds
+    pub fn duration(&self, duration: f64) -> f64 {
+        self.length.unwrap_or(duration - self.start)
+    }
+}
+
+/// Splits a source of `duration` seconds into chunks of about `chunk_secs`,
+/// each a whole number of segments so they all start a new segment on a
+/// keyframe. Empty for sources shorter than two chunks, which aren't split.
+pub fn plan(duration: f64, chunk_secs: u64, segment_duration: u32) -> Vec<Chunk> {
+    let segment_duration = u64::from(segment_duration.max(1));
+    let length = ((chunk_secs / segment_duration).max(1) * segment_duration) as f64;
+    // The remainder goes to the last chunk, so none is too short to encode
+    let count = (duration / length).floor() as usize;
+    if count < 2 {
+        return Vec::new();
+    }
+    (0..count)
+        .map(|index| Chunk {
+            index,
+            start: index as f64 * length,
+            length: (index + 1 < count).then_some(length),
+        })
+        .collect()
+}
+
+/// Where the chunks of rendition `quality` are kept
+pub fn rendition_dir(video_dir: &Path, quality: &str) -> PathBuf {
+    video_dir.join(DIR).join(quality)
+}
+
+/// Directory a chunk is encoded into. `finish` moves it to its final name, so
+/// only complete chunks are ever reused.
+pub async fn start(dir: &Path, chunk: &Chunk) -> Result<PathBuf> {
+    let partial = dir.join(format!("{}.partial", chunk.name()));
+    remove_dir(&partial).await?;
+    fs::create_dir_all(&partial).await?;
+    Ok(partial)
+}
+
+/// Whether an earlier run already encoded `chunk`
+pub async fn is_done(dir: &Path, chunk: &Chunk) -> Result<bool> {
+    Ok(fs::try_exists(dir.join(chunk.name())).await?)
+}
+
+/// Keeps a chunk encoded into `partial` once its playlist and segments check
+/// out
+pub async fn finish(dir: &Path, chunk: &Chunk, partial: &Path) -> Result<()> {
+    validator::check_rendition(partial)
+        .await
+        .map_err(|problems| anyhow::anyhow!("Incomplete chunk {}: {}", chunk.name(), problems))?;
+    fs::rename(partial, dir.join(chunk.name())).await?;
+    Ok(())
+}
+
+/// Puts the segments of every chunk in `dir` next to `output` and writes it
+/// as the playlist listing them all, then removes `dir`
+pub async fn join(dir: &Path, chunks: &[Chunk], output: &Path) -> Result<()> {
+    let output_dir = output.parent().context("Invalid playlist path")?;
+    let mut joined = playlist::MediaPlaylist {
+        ended: true,
+        ..Default::default()
+    };
+    for chunk in chunks {
+        let chunk_dir = dir.join(chunk.name());
+        let playlist_path = chunk_dir.join("stream.m3u8");
+        let media = playlist::parse_media(&fs::read_to_string(&playlist_path).await?)
+            .with_context(|| format!("Invalid playlist of chunk {}", chunk.name()))?;
+        joined.target_duration = joined.target_duration.max(media.target_duration);
+        joined.segments.extend(media.segments);
+
+        // Linked rather than moved, so the chunks stay whole until the
+        // playlist is written
+        let mut entries = fs::read_dir(&chunk_dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.path() != playlist_path {
+                storage::retry(output_dir, || {
+                    fs::hard_link(entry.path(), output_dir.join(entry.file_name()))
+                })
+                .await?;
+            }
+        }
+    }
+    storage::write(output, playlist::write_media(&joined)).await?;
+    remove_dir(dir).await
+}
+
+/// Queues the encodes of `chunks` of rendition `quality`, each with the
+/// ffmpeg arguments to run in its directory, for any worker to take. Chunks
+/// still queued or running from before an interruption are left to finish.
+pub async fn enqueue(
+    conn: &mut AsyncPgConnection,
+    v_id: Uuid,
+    quality: &str,
+    chunks: Vec<(Chunk, Vec<String>)>,
+) -> Result<()> {
+    use crate::db::schema::{chunk_encodes, processing_jobs};
+
+    // Failed ones are tried again
+    let active_job = processing_jobs::table
+        .filter(processing_jobs::chunk_encode_id.eq(chunk_encodes::id.nullable()))
+        .filter(processing_jobs::state.eq_any(["queued", "running"]));
+    diesel::delete(
+        chunk_encodes::table
+            .filter(chunk_encodes::video_id.eq(v_id))
+            .filter(chunk_encodes::quality.eq(quality))
+            .filter(not(exists(active_job))),
+    )
+    .execute(conn)
+    .await?;
+
+    for (chunk, args) in chunks {
+        let encode = ChunkEncode {
+            id: Uuid::new_v4(),
+            video_id: v_id,
+            quality: quality.to_string(),
+            chunk: chunk.index as i32,
+            start: chunk.start,
+            length: chunk.length,
+            args,
+            created_at: chrono::Utc::now().naive_utc(),
+        };
+        let inserted = diesel::insert_into(chunk_encodes::table)
+            .values(&encode)
+            .on_conflict_do_nothing()
+            .execute(conn)
+            .await?;
+        if inserted > 0 {
+            jobs::enqueue_chunk(encode.id, conn).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Waits for the queued chunks of rendition `quality` to be encoded, taking
+/// those no other worker has started yet, so they get done even without
+/// another worker free. `on_progress` gets the percentage of chunks done.
+pub async fn wait(
+    conn: &mut AsyncPgConnection,
+    v_id: Uuid,
+    quality: &str,
+    ffmpeg: &FfmpegConfig,
+    mut on_progress: impl FnMut(f64),
+) -> Result<()> {
+    use crate::db::schema::{chunk_encodes, processing_jobs};
+
+    loop {
+        if let Some(job) = jobs::claim_chunk(conn, v_id, quality).await? {
+            let ce_id = job.chunk_encode_id.context("Job isn't a chunk")?;
+            let result = encode(ce_id, conn, ffmpeg, |_| {}).await;
+            jobs::finish(conn, job.id, result.err().map(|e| format!("{:#}", e))).await;
+            continue;
+        }
+
+        let states = processing_jobs::table
+            .inner_join(chunk_encodes::table)
+            .filter(chunk_encodes::video_id.eq(v_id))
+            .filter(chunk_encodes::quality.eq(quality))
+            .select((processing_jobs::state, processing_jobs::error))
+            .load::<(String, Option<String>)>(conn)
+            .await?;
+        if let Some((_, error)) = states.iter().find(|(state, _)| state == "failed") {
+            anyhow::bail!(
+                "A chunk failed to encode: {}",
+                error.as_deref().unwrap_or("unknown error")
+            );
+        }
+        let done = states.iter().filter(|(state, _)| state == "done").count();
+        on_progress(done as f64 / states.len().max(1) as f64 * 100.0);
+        if done == states.len() {
+            return Ok(());
+        }
+        tokio::time::sleep(WAIT_INTERVAL).await;
+    }
+}
+
+/// Encodes a chunk queued by `enqueue`, on whichever worker claimed it.
+/// Every worker must see the uploads at the same path, as the arguments
+/// refer to the original by its absolute path.
+pub async fn encode(
+    ce_id: Uuid,
+    conn: &mut AsyncPgConnection,
+    config: &FfmpegConfig,
+    on_progress: impl FnMut(f64),
+) -> Result<()> {
+    use crate::db::schema::chunk_encodes;
+
+    let encode = chunk_encodes::table
+        .find(ce_id)
+        .first::<ChunkEncode>(conn)
+        .await?;
+    let chunk = Chunk {
+        index: encode.chunk as usize,
+        start: encode.start,
+        length: encode.length,
+    };
+    let video_dir = fs::canonicalize(video_processor::get_video_dir(encode.video_id)).await?;
+    let dir = rendition_dir(&video_dir, &encode.quality);
+    let partial = start(&dir, &chunk).await?;
+    let mut cmd = ffmpeg::command("ffmpeg", config, &partial);
+    cmd.args(&encode.args);
+    let status = ffmpeg::run_with_progress(&mut cmd, chunk.length, on_progress).await?;
+    if !status.success() {
+        anyhow::bail!("FFmpeg failed to encode chunk {}", chunk.name());
+    }
+    finish(&dir, &chunk, &partial).await
+}
+
+/// Drops the chunks left of a video's encodes and their jobs, once its job
+/// is over
+pub async fn remove(conn: &mut AsyncPgConnection, v_id: Uuid) -> Result<()> {
+    use crate::db::schema::chunk_encodes;
+
+    diesel::delete(chunk_encodes::table.filter(chunk_encodes::video_id.eq(v_id)))
+        .execute(conn)
+        .await?;
+    remove_dir(&video_processor::get_video_dir(v_id).join(DIR)).await
+}
+
+async fn remove_dir(dir: &Path) -> Result<()> {
+    match fs::remove_dir_all(dir).await {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,161 @@
+// src/services/clips.rs
+use crate::config::app_config::FfmpegConfig;
+use crate::config::AppConfig;
+use crate::db::schema::videos;
+use crate::db::DbPool;
+use crate::services::blocking::BlockingPool;
+use crate::services::events::ProgressEvents;
+use crate::services::{ffmpeg, integrity, keyframes, video_processor};
+use actix_web::web;
+use anyhow::{Context, Result};
+use diesel::ExpressionMethods;
+use diesel_async::RunQueryDsl;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::fs;
+use uuid::Uuid;
+
+/// How far from a keyframe a clip may start and still be stream-copied;
+/// copying always starts at a keyframe
+const KEYFRAME_TOLERANCE_SECS: f64 = 0.05;
+
+/// Part of a processed video to cut into a new one
+#[derive(Debug, Clone, Copy)]
+pub struct Clip {
+    pub source_id: Uuid,
+    pub start: f64,
+    pub end: f64,
+}
+
+/// Cuts the clip in the background into the original of an existing
+/// "uploading" video row, then hands it to the regular processing pipeline.
+pub fn spawn_clip(
+    v_id: Uuid,
+    clip: Clip,
+    pool: web::Data<DbPool>,
+    config: Arc<AppConfig>,
+    events: web::Data<ProgressEvents>,
+    blocking: web::Data<BlockingPool>,
+) {
+    // ingest returns actix errors, which aren't Send, so stay on this worker
+    actix_web::rt::spawn(async move {
+        let video_dir = video_processor::get_video_dir(v_id);
+        match cut(v_id, clip, &video_dir, &config.ffmpeg).await {
+            Ok(output) => {
+                let checksum = blocking
+                    .run(move || integrity::sha256_file(&output))
+                    .await
+                    .map_err(anyhow::Error::from)
+                    .and_then(|checksum| checksum);
+                let conn = &mut pool.get().await.expect("Failed to get DB connection");
+                let stored = match checksum {
+                    Ok(checksum) => diesel::update(videos::table)
+                        .filter(videos::id.eq(v_id))
+                        .set(videos::checksum.eq(checksum))
+                        .execute(conn)
+                        .await
+                        .map_err(anyhow::Error::from),
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = stored {
+                    log::error!("Error storing checksum for {}: {}", v_id, e);
+                }
+            }
+            Err(e) => {
+                log::error!(
+                    "Failed to cut clip {} from video {}: {}",
+                    v_id,
+                    clip.source_id,
+                    e
+                );
+                if let Err(e) = fs::remove_dir_all(&video_dir).await {
+                    log::error!("Failed to remove partial clip {}: {}", v_id, e);
+                }
+            }
+        }
+
+        // Validation and status updates are shared with direct uploads; a failed
+        // cut shows up here as a missing file
+        let conn = &mut pool.get().await.expect("Failed to get DB connection");
+        let _ = video_processor::ingest(v_id, None, conn, pool.clone(), config, events).await;
+    });
+}
+
+/// Writes the clip as the original of the video in `video_dir`. Clips
+/// starting on a keyframe of the source are stream-copied, as are its
+/// codecs and container; others, or copies ffmpeg refuses, are re-encoded.
+async fn cut(v_id: Uuid, clip: Clip, video_dir: &Path, ffmpeg: &FfmpegConfig) -> Result<PathBuf> {
+    let source_dir = fs::canonicalize(video_processor::get_video_dir(clip.source_id)).await?;
+    let input = video_processor::find_original(&source_dir).await?;
+    fs::create_dir_all(video_dir).await?;
+    let video_dir = fs::canonicalize(video_dir).await?;
+
+    let on_keyframe = match keyframes::load(&source_dir, ffmpeg).await {
+        Ok(index) => {
+            clip.start == 0.0
+                || index
+                    .keyframes
+                    .iter()
+                    .any(|k| (k - clip.start).abs() <= KEYFRAME_TOLERANCE_SECS)
+        }
+        Err(e) => {
+            log::warn!("No keyframes of {} to cut at: {}", clip.source_id, e);
+            false
+        }
+    };
+    if on_keyframe {
+        let name =
+            video_processor::original_file_name(input.file_name().and_then(|name| name.to_str()));
+        let output = video_dir.join(name);
+        match run_cut(&input, &output, clip, true, ffmpeg).await {
+            Ok(()) => return Ok(output),
+            Err(e) => {
+                log::info!("Re-encoding clip {} as it can't be copied: {}", v_id, e);
+                let _ = fs::remove_file(&output).await;
+            }
+        }
+    }
+
+    let output = video_dir.join("original.mp4");
+    run_cut(&input, &output, clip, false, ffmpeg).await?;
+    Ok(output)
+}
+
+async fn run_cut(
+    input: &Path,
+    output: &Path,
+    clip: Clip,
+    copy: bool,
+    ffmpeg: &FfmpegConfig,
+) -> Result<()> {
+    let mut cmd = ffmpeg::command("ffmpeg", ffmpeg, output.parent().context("Invalid path")?);
+    // Seeking before the input is fast, and exact when re-encoding
+    cmd.args(ffmpeg::input_protocol_args())
+        .arg("-ss")
+        .arg(format!("{:.3}", clip.start))
+        .arg("-i")
+        .arg(input)
+        .arg("-t")
+        .arg(format!("{:.3}", clip.end - clip.start))
+        .args(["-map", "0:v:0", "-map", "0:a?"]);
+    if copy {
+        cmd.args(["-c", "copy", "-avoid_negative_ts", "make_zero"]);
+    } else {
+        // Near-lossless, as processing encodes it again
+        cmd.args(["-c:v", "libx264", "-crf", "18"])
+            .arg("-preset")
+            .arg(&ffmpeg.preset)
+            .arg("-threads")
+            .arg(ffmpeg.thread_count.to_string())
+            .args(["-c:a", "aac", "-b:a", "192k"]);
+    }
+    let status = cmd
+        .args(["-y", "-loglevel", "quiet"])
+        .arg(output)
+        .status()
+        .await?;
+    if !status.success() {
+        anyhow::bail!("FFmpeg cut failed");
+    }
+    Ok(())
+}
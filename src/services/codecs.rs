@@ -0,0 +1,138 @@
+// src/services/codecs.rs
+use crate::services::probe;
+
+/// AAC-LC, the only audio the ladder is encoded to
+pub const AAC_LC: &str = "mp4a.40.2";
+
+/// A codec level: its identifier in codec strings, the largest frame in luma
+/// samples and the most luma samples per second
+type Level = (u32, u64, u64);
+
+/// `level_idc`, e.g. 40 for level 4.0
+const H264_LEVELS: &[Level] = &[
+    (30, 414_720, 10_368_000),
+    (31, 921_600, 27_648_000),
+    (32, 1_310_720, 55_296_000),
+    (40, 2_097_152, 62_914_560),
+    (42, 2_228_224, 133_693_440),
+    (50, 5_652_480, 150_994_944),
+    (51, 9_437_184, 251_658_240),
+    (52, 9_437_184, 530_841_600),
+];
+
+/// `general_level_idc`, thirty times the level, e.g. 123 for level 4.1
+const HEVC_LEVELS: &[Level] = &[
+    (90, 552_960, 16_588_800),
+    (93, 983_040, 33_177_600),
+    (120, 2_228_224, 66_846_720),
+    (123, 2_228_224, 133_693_440),
+    (150, 8_912_896, 267_386_880),
+    (153, 8_912_896, 534_773_760),
+    (156, 8_912_896, 1_069_547_520),
+];
+
+/// `seq_level_idx`, e.g. 8 for level 4.0
+const AV1_LEVELS: &[Level] = &[
+    (4, 665_856, 20_736_000),
+    (5, 1_065_024, 31_950_720),
+    (8, 2_359_296, 70_778_880),
+    (9, 2_359_296, 141_557_760),
+    (12, 8_912_896, 267_386_880),
+    (13, 8_912_896, 534_773_760),
+    (14, 8_912_896, 1_069_547_520),
+];
+
+/// The lowest level a frame size and rate fit in, or the highest listed
+fn level(levels: &[Level], (width, height): (u32, u32), fps: f64) -> u32 {
+    let frame = width as u64 * height as u64;
+    let rate = (frame as f64 * fps).ceil() as u64;
+    levels
+        .iter()
+        .find(|&&(_, max_frame, max_rate)| frame <= max_frame && rate <= max_rate)
+        .unwrap_or(levels.last().unwrap())
+        .0
+}
+
+/// The level an encoder is told to target, as ffmpeg's `-level` option
+/// expects it: 40 for H.264 4.0, 41 for HEVC 4.1. None for AV1, whose
+/// encoder picks its own.
+pub fn encoder_level(codec: &str, dimensions: (u32, u32), fps: f64) -> Option<u32> {
+    match codec {
+        "h264" => Some(level(H264_LEVELS, dimensions, fps)),
+        "hevc" => Some(level(HEVC_LEVELS, dimensions, fps) / 3),
+        _ => None,
+    }
+}
+
+/// RFC 6381 name of an encoded 8-bit 4:2:0 video stream: H.264 High,
+/// HEVC Main or AV1 Main profile
+pub fn video(codec: &str, dimensions: (u32, u32), fps: f64) -> String {
+    match codec {
+        "hevc" => format!("hvc1.1.6.L{}.90", level(HEVC_LEVELS, dimensions, fps)),
+        "av1" => format!("av01.0.{:02}M.08", level(AV1_LEVELS, dimensions, fps)),
+        _ => format!("avc1.6400{:02x}", level(H264_LEVELS, dimensions, fps)),
+    }
+}
+
+/// RFC 6381 name of a source H.264 stream that is copied into a rendition,
+/// if ffprobe reported its profile and level
+pub fn copied_h264(stream: &probe::Stream) -> Option<String> {
+    // profile_idc and constraint flags
+    let profile = match stream.profile.as_deref()? {
+        "Constrained Baseline" => "42e0",
+        "Baseline" => "4200",
+        "Main" => "4d40",
+        "High" => "6400",
+        _ => return None,
+    };
+    let level = u8::try_from(stream.level?).ok()?;
+    Some(format!("avc1.{}{:02x}", profile, level))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn h264_levels_fit_frame_size_and_rate() {
+        assert_eq!(video("h264", (1280, 720), 30.0), "avc1.64001f");
+        assert_eq!(video("h264", (1920, 1080), 30.0), "avc1.640028");
+        assert_eq!(video("h264", (1920, 1080), 60.0), "avc1.64002a");
+        assert_eq!(video("h264", (3840, 2160), 60.0), "avc1.640034");
+        // Beyond every level, the highest is used
+        assert_eq!(video("h264", (7680, 4320), 60.0), "avc1.640034");
+        assert_eq!(encoder_level("h264", (1920, 1080), 30.0), Some(40));
+    }
+
+    #[test]
+    fn hevc_and_av1_levels() {
+        assert_eq!(video("hevc", (1920, 1080), 30.0), "hvc1.1.6.L120.90");
+        assert_eq!(video("hevc", (1920, 1080), 60.0), "hvc1.1.6.L123.90");
+        assert_eq!(encoder_level("hevc", (1920, 1080), 60.0), Some(41));
+        assert_eq!(video("av1", (1920, 1080), 30.0), "av01.0.08M.08");
+        assert_eq!(video("av1", (3840, 2160), 60.0), "av01.0.13M.08");
+        assert_eq!(encoder_level("av1", (3840, 2160), 60.0), None);
+    }
+
+    #[test]
+    fn copied_h264_uses_the_source_profile_and_level() {
+        let stream = |profile: &str, level: i64| -> probe::Stream {
+            serde_json::from_value(serde_json::json!({
+                "codec_type": "video",
+                "codec_name": "h264",
+                "profile": profile,
+                "level": level,
+            }))
+            .unwrap()
+        };
+        assert_eq!(
+            copied_h264(&stream("High", 40)).as_deref(),
+            Some("avc1.640028")
+        );
+        assert_eq!(
+            copied_h264(&stream("Constrained Baseline", 30)).as_deref(),
+            Some("avc1.42e01e")
+        );
+        assert_eq!(copied_h264(&stream("High 10", 40)), None);
+    }
+}
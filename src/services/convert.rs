@@ -0,0 +1,317 @@
+// src/services/convert.rs
+use crate::config::app_config::{FfmpegConfig, ScanConfig};
+use crate::config::AppConfig;
+use crate::db::models::Conversion;
+use crate::db::DbPool;
+use crate::services::scan::{self, ScanVerdict};
+use crate::services::{ffmpeg, probe, video_processor};
+use actix_web::web;
+use anyhow::{Context, Result};
+use chrono::{TimeDelta, Utc};
+use diesel::{ExpressionMethods, NullableExpressionMethods, QueryDsl};
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::fs;
+use uuid::Uuid;
+
+/// How often finished conversions past their retention are looked for
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Output containers with the video and audio codecs they accept; the first
+/// of each is the default
+const CONTAINERS: &[(&str, &[&str], &[&str])] = &[
+    (
+        "mp4",
+        &["h264", "hevc", "av1", "copy"],
+        &["aac", "mp3", "copy"],
+    ),
+    ("mov", &["h264", "hevc", "copy"], &["aac", "copy"]),
+    (
+        "mkv",
+        &["h264", "hevc", "vp9", "av1", "copy"],
+        &["aac", "opus", "mp3", "copy"],
+    ),
+    ("webm", &["vp9", "av1"], &["opus"]),
+];
+
+/// Container and codecs a conversion produces
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    pub container: String,
+    pub video_codec: String,
+    pub audio_codec: String,
+}
+
+impl Target {
+    /// Checks the requested combination, filling in the container's default
+    /// codecs. The error names the offending field.
+    pub fn parse(
+        container: &str,
+        video_codec: Option<&str>,
+        audio_codec: Option<&str>,
+    ) -> Result<Self, (&'static str, String)> {
+        let container = container.to_ascii_lowercase();
+        let Some((_, video_codecs, audio_codecs)) =
+            CONTAINERS.iter().find(|(name, _, _)| *name == container)
+        else {
+            return Err((
+                "container",
+                format!(
+                    "Container must be one of {}",
+                    names(CONTAINERS.iter().map(|c| c.0))
+                ),
+            ));
+        };
+
+        let pick = |field: &'static str, requested: Option<&str>, allowed: &[&str]| {
+            let codec = requested.map_or(allowed[0].to_string(), str::to_ascii_lowercase);
+            if allowed.contains(&codec.as_str()) {
+                Ok(codec)
+            } else {
+                Err((
+                    field,
+                    format!(
+                        "{} output supports {} of {}",
+                        container,
+                        field,
+                        names(allowed.iter().copied())
+                    ),
+                ))
+            }
+        };
+        Ok(Target {
+            video_codec: pick("video_codec", video_codec, video_codecs)?,
+            audio_codec: pick("audio_codec", audio_codec, audio_codecs)?,
+            container,
+        })
+    }
+}
+
+fn names<'a>(names: impl Iterator<Item = &'a str>) -> String {
+    names.collect::<Vec<_>>().join(", ")
+}
+
+/// Where a conversion's uploaded source and its output are kept. Like the
+/// upload directory, it must be writable inside `ffmpeg.sandbox_command`.
+pub fn conversion_dir(c_id: Uuid) -> PathBuf {
+    PathBuf::from("conversions").join(c_id.to_string())
+}
+
+/// Path of a finished conversion's output
+pub fn output_path(conversion: &Conversion) -> PathBuf {
+    conversion_dir(conversion.id).join(format!("output.{}", conversion.container))
+}
+
+/// Runs a conversion job: scans an uploaded source like a video upload, then
+/// converts it into `output_path`
+pub async fn run(
+    c_id: Uuid,
+    conn: &mut AsyncPgConnection,
+    ffmpeg: &FfmpegConfig,
+    scan_config: &ScanConfig,
+) -> Result<()> {
+    use crate::db::schema::conversions;
+
+    let conversion = conversions::table
+        .find(c_id)
+        .first::<Conversion>(conn)
+        .await?;
+    let dir = fs::canonicalize(conversion_dir(c_id)).await?;
+    let input = match conversion.video_id {
+        Some(v_id) => {
+            let video_dir = fs::canonicalize(video_processor::get_video_dir(v_id)).await?;
+            video_processor::find_original(&video_dir).await?
+        }
+        None => video_processor::find_original(&dir).await?,
+    };
+    // Sources of existing videos were scanned when they were uploaded
+    if conversion.video_id.is_none() && scan_config.enabled() {
+        match scan::scan(c_id, &input, scan_config).await? {
+            ScanVerdict::Clean => {}
+            ScanVerdict::Rejected(report) => {
+                log::warn!(
+                    "Conversion {} rejected by content scan: {}",
+                    c_id,
+                    report.as_deref().unwrap_or("no details")
+                );
+                fs::remove_file(&input).await?;
+                anyhow::bail!("File was rejected by the content scan");
+            }
+        }
+    }
+    probe::probe(&input, ffmpeg)
+        .await
+        .context("Source is not a readable media file")?;
+
+    // Written under a temporary name so a download never sees a partial file
+    let output = dir.join(output_path(&conversion).file_name().unwrap());
+    let partial = dir.join(format!("partial.{}", conversion.container));
+    transcode(&input, &partial, &conversion, ffmpeg).await?;
+    fs::rename(&partial, &output).await?;
+
+    // An uploaded source is no longer needed
+    if conversion.video_id.is_none() {
+        fs::remove_file(&input).await?;
+    }
+    Ok(())
+}
+
+async fn transcode(
+    input: &Path,
+    output: &Path,
+    conversion: &Conversion,
+    ffmpeg: &FfmpegConfig,
+) -> Result<()> {
+    let mut cmd = ffmpeg::command("ffmpeg", ffmpeg, output.parent().unwrap());
+    cmd.args(ffmpeg::input_protocol_args())
+        .arg("-i")
+        .arg(input)
+        .arg("-y");
+
+    match conversion.video_codec.as_str() {
+        "copy" => cmd.args(["-c:v", "copy"]),
+        "h264" | "hevc" => {
+            let encoder = if conversion.video_codec == "h264" {
+                "libx264"
+            } else {
+                "libx265"
+            };
+            cmd.args(["-c:v", encoder])
+                .arg("-preset")
+                .arg(&ffmpeg.preset)
+                .arg("-threads")
+                .arg(ffmpeg.thread_count.to_string())
+        }
+        // Constant quality; libvpx otherwise targets a very low bitrate
+        "vp9" => cmd.args(["-c:v", "libvpx-vp9", "-crf", "32", "-b:v", "0"]),
+        "av1" => cmd.args(["-c:v", "libsvtav1", "-preset", "8"]),
+        codec => anyhow::bail!("Unsupported video codec {}", codec),
+    };
+    let audio_encoder = match conversion.audio_codec.as_str() {
+        "copy" => "copy",
+        "aac" => "aac",
+        "opus" => "libopus",
+        "mp3" => "libmp3lame",
+        codec => anyhow::bail!("Unsupported audio codec {}", codec),
+    };
+    cmd.args(["-c:a", audio_encoder]);
+    if conversion.container == "mp4" || conversion.container == "mov" {
+        // Index at the front so the download can start playing right away
+        cmd.args(["-movflags", "+faststart"]);
+    }
+
+    let status = cmd
+        .args(["-loglevel", "quiet"])
+        .arg(output)
+        .status()
+        .await?;
+    if !status.success() {
+        anyhow::bail!("FFmpeg conversion failed");
+    }
+    Ok(())
+}
+
+/// Starts deleting conversions whose job ended more than
+/// `convert.retention_secs` ago, with their output and any uploaded source
+pub fn start_cleanup(pool: web::Data<DbPool>, config: Arc<AppConfig>) {
+    let retention = config.convert.retention_secs;
+    let Some(retention) = i64::try_from(retention)
+        .ok()
+        .and_then(TimeDelta::try_seconds)
+        .filter(|_| retention > 0)
+    else {
+        return;
+    };
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CLEANUP_INTERVAL);
+        loop {
+            interval.tick().await;
+            let result = match pool.get().await {
+                Ok(mut conn) => cleanup(&mut conn, retention).await,
+                Err(e) => Err(e.into()),
+            };
+            if let Err(e) = result {
+                log::error!("Failed to clean up finished conversions: {}", e);
+            }
+        }
+    });
+}
+
+async fn cleanup(conn: &mut AsyncPgConnection, retention: TimeDelta) -> Result<()> {
+    use crate::db::schema::{conversions, processing_jobs};
+
+    let cutoff = Utc::now().naive_utc() - retention;
+    let expired = processing_jobs::table
+        .filter(processing_jobs::state.eq_any(["done", "failed", "canceled"]))
+        .filter(processing_jobs::updated_at.lt(cutoff))
+        .filter(processing_jobs::conversion_id.is_not_null())
+        .select(processing_jobs::conversion_id.assume_not_null())
+        .load::<Uuid>(conn)
+        .await?;
+
+    for c_id in expired {
+        conn.transaction::<_, anyhow::Error, _>(|conn| {
+            async move {
+                diesel::delete(processing_jobs::table)
+                    .filter(processing_jobs::conversion_id.eq(c_id))
+                    .execute(conn)
+                    .await?;
+                diesel::delete(conversions::table.find(c_id))
+                    .execute(conn)
+                    .await?;
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await?;
+
+        match fs::remove_dir_all(conversion_dir(c_id)).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => log::error!("Failed to remove conversion {}: {}", c_id, e),
+        }
+        log::info!("Removed expired conversion {}", c_id);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_codecs_default_to_the_containers_first() {
+        let target = Target::parse("WebM", None, None).unwrap();
+        assert_eq!(
+            target,
+            Target {
+                container: "webm".to_string(),
+                video_codec: "vp9".to_string(),
+                audio_codec: "opus".to_string(),
+            }
+        );
+
+        let target = Target::parse("mkv", Some("AV1"), Some("copy")).unwrap();
+        assert_eq!(target.video_codec, "av1");
+        assert_eq!(target.audio_codec, "copy");
+    }
+
+    #[test]
+    fn unsupported_combinations_name_the_field() {
+        let cases = [
+            ("avi", None, None, "container"),
+            ("webm", Some("h264"), None, "video_codec"),
+            ("mov", Some("hevc"), Some("opus"), "audio_codec"),
+            ("mp4", Some("vp9"), Some("aac"), "video_codec"),
+        ];
+        for (container, video, audio, field) in cases {
+            let err = Target::parse(container, video, audio).unwrap_err();
+            assert_eq!(err.0, field, "{} {:?} {:?}", container, video, audio);
+        }
+    }
+}
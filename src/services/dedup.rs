@@ -0,0 +1,270 @@
+// src/services/dedup.rs
+use crate::db::models::{AudioTrack, Subtitle, Video, VideoQuality};
+use crate::services::video_processor::{find_original, get_video_dir, LOW_MASTER};
+use crate::services::{audio_tracks, storyboard, subtitles, watermark};
+use anyhow::{Context, Result};
+use chrono::Utc;
+use diesel::{ExpressionMethods, OptionalExtension, PgExpressionMethods, QueryDsl};
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+use std::io::ErrorKind;
+use std::path::Path;
+use tokio::fs;
+use uuid::Uuid;
+
+/// Directories a duplicate gets from the video it duplicates
+const REUSED_DIRS: &[&str] = &["hls", "thumbnails", storyboard::DIR, "review"];
+
+/// If an already processed video has the same content checksum, gives this
+/// video that video's HLS package instead of transcoding it again. Only
+/// videos with the same encoding and processing profiles and watermark
+/// position, and neither with its own watermark image, are reused. Returns
+/// whether the video was linked (and is now "processed").
+///
+/// Files are hard linked where the filesystem allows and copied where it
+/// doesn't, so either video can be deleted or reprocessed without breaking
+/// the other. Anything that fails midway is undone, leaving the video to be
+/// processed as usual.
+pub async fn link_duplicate(v_id: Uuid, conn: &mut AsyncPgConnection) -> Result<bool> {
+    use crate::db::schema::videos;
+
+    let (checksum, profile, processing_profile, position): (
+        Option<String>,
+        Option<String>,
+        Option<serde_json::Value>,
+        Option<String>,
+    ) = videos::table
+        .filter(videos::id.eq(v_id))
+        .select((
+            videos::checksum,
+            videos::encoding_profile,
+            videos::processing_profile,
+            videos::watermark_position,
+        ))
+        .first(conn)
+        .await?;
+    let Some(checksum) = checksum else {
+        return Ok(false);
+    };
+    if watermark::has_own_image(v_id).await {
+        return Ok(false);
+    }
+
+    let Some(source) = videos::table
+        .filter(videos::checksum.eq(&checksum))
+        .filter(videos::encoding_profile.is_not_distinct_from(profile))
+        .filter(videos::processing_profile.is_not_distinct_from(processing_profile))
+        .filter(videos::watermark_position.is_not_distinct_from(position))
+        .filter(videos::id.ne(v_id))
+        .filter(videos::status.eq("processed"))
+        .order_by(videos::created_at.asc())
+        .first::<Video>(conn)
+        .await
+        .optional()?
+    else {
+        return Ok(false);
+    };
+    if watermark::has_own_image(source.id).await {
+        return Ok(false);
+    }
+
+    log::info!(
+        "Video {} duplicates {}, reusing its package",
+        v_id,
+        source.id
+    );
+
+    let source_dir = fs::canonicalize(get_video_dir(source.id)).await?;
+    let video_dir = fs::canonicalize(get_video_dir(v_id)).await?;
+    if let Err(e) = reuse_package(v_id, &source, &source_dir, &video_dir, conn).await {
+        for dir in REUSED_DIRS {
+            match fs::remove_dir_all(video_dir.join(dir)).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => log::error!("Failed to remove {} of {}: {}", dir, v_id, e),
+            }
+        }
+        return Err(e);
+    }
+    if let Err(e) = share_original(&source_dir, &video_dir).await {
+        log::warn!("Video {} keeps its own original: {}", v_id, e);
+    }
+
+    Ok(true)
+}
+
+/// Links the source's files into the video directory, then copies its rows
+/// and marks the video processed in one transaction
+async fn reuse_package(
+    v_id: Uuid,
+    source: &Video,
+    source_dir: &Path,
+    video_dir: &Path,
+    conn: &mut AsyncPgConnection,
+) -> Result<()> {
+    use crate::db::schema::{video_qualities, videos};
+
+    link_package(&source_dir.join("hls"), &video_dir.join("hls")).await?;
+    link_tree(
+        &source_dir.join("thumbnails"),
+        &video_dir.join("thumbnails"),
+    )
+    .await?;
+    // Not there if the source's storyboard failed
+    if fs::try_exists(storyboard::vtt_path(source_dir)).await? {
+        link_tree(
+            &source_dir.join(storyboard::DIR),
+            &video_dir.join(storyboard::DIR),
+        )
+        .await?;
+    }
+    // Only there if the source was processed with review proxies enabled
+    if fs::try_exists(source_dir.join("review")).await? {
+        link_tree(&source_dir.join("review"), &video_dir.join("review")).await?;
+    }
+
+    conn.transaction::<_, anyhow::Error, _>(|conn| {
+        async move {
+            let qualities = video_qualities::table
+                .filter(video_qualities::video_id.eq(source.id))
+                .load::<VideoQuality>(conn)
+                .await?
+                .into_iter()
+                .map(|quality| VideoQuality {
+                    id: Uuid::new_v4(),
+                    video_id: v_id,
+                    created_at: Utc::now().naive_utc(),
+                    ..quality
+                })
+                .collect::<Vec<_>>();
+
+            diesel::insert_into(video_qualities::table)
+                .values(&qualities)
+                .execute(conn)
+                .await?;
+
+            let tracks = crate::db::schema::audio_tracks::table
+                .filter(crate::db::schema::audio_tracks::video_id.eq(source.id))
+                .load::<AudioTrack>(conn)
+                .await?
+                .into_iter()
+                .map(|track| AudioTrack {
+                    id: Uuid::new_v4(),
+                    video_id: v_id,
+                    created_at: Utc::now().naive_utc(),
+                    ..track
+                })
+                .collect::<Vec<_>>();
+            audio_tracks::save(conn, v_id, &tracks).await?;
+
+            // Their files are in the linked package
+            let subtitles = crate::db::schema::subtitles::table
+                .filter(crate::db::schema::subtitles::video_id.eq(source.id))
+                .filter(crate::db::schema::subtitles::source.eq("embedded"))
+                .load::<Subtitle>(conn)
+                .await?
+                .into_iter()
+                .map(|subtitle| Subtitle {
+                    id: Uuid::new_v4(),
+                    video_id: v_id,
+                    created_at: Utc::now().naive_utc(),
+                    ..subtitle
+                })
+                .collect::<Vec<_>>();
+            subtitles::save_embedded(conn, v_id, &subtitles).await?;
+            // The copied master playlist lists the source's uploaded subtitles
+            let subtitles = subtitles::load(conn, v_id).await?;
+            let duration = source.duration.unwrap_or_default();
+            subtitles::publish_uploaded(video_dir, &subtitles, duration).await?;
+            subtitles::rebuild_master(&video_dir.join("hls"), &subtitles).await?;
+
+            diesel::update(videos::table)
+                .filter(videos::id.eq(v_id))
+                .set((
+                    videos::status.eq("processed"),
+                    videos::duration.eq(source.duration),
+                ))
+                .execute(conn)
+                .await?;
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+/// Replaces the video's original, identical to the source's, with a link to
+/// it, so the content is only stored once
+async fn share_original(source_dir: &Path, video_dir: &Path) -> Result<()> {
+    let own = find_original(video_dir).await?;
+    let source = fs::canonicalize(find_original(source_dir).await?).await?;
+    let name = source.file_name().context("Invalid original path")?;
+    // Linked under a name `find_original` skips, then renamed into place
+    let partial = video_dir.join(format!(".{}", name.to_string_lossy()));
+    fs::hard_link(&source, &partial).await?;
+    fs::rename(&partial, video_dir.join(name)).await?;
+    if own.file_name() != Some(name) {
+        fs::remove_file(own).await?;
+    }
+    Ok(())
+}
+
+/// Links everything in the source's package except its uploaded subtitles.
+/// The master playlists are copied, as they list the video's own subtitles.
+async fn link_package(source_hls: &Path, hls: &Path) -> Result<()> {
+    fs::create_dir(hls).await?;
+    let mut entries = fs::read_dir(source_hls).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name();
+        if name == "master.m3u8" || name == LOW_MASTER {
+            fs::copy(entry.path(), hls.join(&name)).await?;
+        } else if name != subtitles::UPLOADS_DIR {
+            link_entry(&entry.path(), &hls.join(&name)).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Gives a video whose `hls` directory is a link to a duplicate's, as linked
+/// before packages were linked entry by entry, a directory of its own, so its
+/// subtitles can differ
+pub async fn unshare_package(video_dir: &Path) -> Result<()> {
+    let hls = video_dir.join("hls");
+    match fs::symlink_metadata(&hls).await {
+        Ok(metadata) if metadata.file_type().is_symlink() => {}
+        Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+        _ => return Ok(()),
+    }
+    let source_hls = fs::read_link(&hls).await?;
+    // Directory links are directories on Windows
+    if fs::remove_file(&hls).await.is_err() {
+        fs::remove_dir(&hls).await?;
+    }
+    link_package(&source_hls, &hls).await
+}
+
+/// Hard links every file under `source` into a new `dest` directory,
+/// copying those that can't be linked, e.g. across filesystems. Files are
+/// always replaced through a rename, so a linked file never changes under
+/// the other video.
+async fn link_tree(source: &Path, dest: &Path) -> Result<()> {
+    fs::create_dir(dest).await?;
+    let mut entries = fs::read_dir(source).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        link_entry(&entry.path(), &dest.join(entry.file_name())).await?;
+    }
+    Ok(())
+}
+
+/// Links a file or, recursively, a directory. Symbolic links, as in
+/// packages linked by earlier versions, are followed.
+async fn link_entry(source: &Path, dest: &Path) -> Result<()> {
+    let source = fs::canonicalize(source).await?;
+    if fs::metadata(&source).await?.is_dir() {
+        return Box::pin(link_tree(&source, dest)).await;
+    }
+    if fs::hard_link(&source, dest).await.is_err() {
+        fs::copy(&source, dest).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,80 @@
+// src/services/entitlement.rs
+use crate::config::app_config::EntitlementConfig;
+use anyhow::{Context, Result};
+use lru::LruCache;
+use serde::Deserialize;
+use serde_json::json;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// Video and token pairs whose answers are remembered
+const CACHE_CAPACITY: usize = 10_000;
+
+#[derive(Deserialize)]
+struct EntitlementResponse {
+    allowed: bool,
+}
+
+/// Asks the configured entitlement endpoint whether a token may watch a
+/// restricted video. Answers are reused for `cache_secs`, so a player
+/// fetching the master and variant playlists causes a single call.
+pub struct Entitlements {
+    url: Option<String>,
+    ttl: Duration,
+    client: reqwest::Client,
+    answers: Mutex<LruCache<(Uuid, String), (bool, Instant)>>,
+}
+
+impl Entitlements {
+    pub fn new(config: &EntitlementConfig) -> Result<Self> {
+        Ok(Entitlements {
+            url: config.url.clone(),
+            ttl: Duration::from_secs(config.cache_secs),
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(config.timeout_secs))
+                .build()?,
+            answers: Mutex::new(LruCache::new(NonZeroUsize::new(CACHE_CAPACITY).unwrap())),
+        })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.url.is_some()
+    }
+
+    /// Whether the endpoint lets `token` watch the video; false when no
+    /// endpoint is configured. Failed calls are errors and aren't cached.
+    pub async fn allows(&self, v_id: Uuid, token: &str) -> Result<bool> {
+        let Some(url) = &self.url else {
+            return Ok(false);
+        };
+        let key = (v_id, token.to_string());
+        if let Some(&(allowed, checked_at)) = self.answers.lock().unwrap().get(&key) {
+            if checked_at.elapsed() < self.ttl {
+                return Ok(allowed);
+            }
+        }
+
+        let body = self
+            .client
+            .post(url)
+            .header("Content-Type", "application/json")
+            .body(json!({ "video_id": v_id, "token": token }).to_string())
+            .send()
+            .await
+            .context("Entitlement request failed")?
+            .error_for_status()
+            .context("Entitlement endpoint returned an error")?
+            .bytes()
+            .await?;
+        let response: EntitlementResponse = serde_json::from_slice(&body)
+            .context("Entitlement endpoint returned an invalid response")?;
+
+        self.answers
+            .lock()
+            .unwrap()
+            .put(key, (response.allowed, Instant::now()));
+        Ok(response.allowed)
+    }
+}
@@ -0,0 +1,85 @@
+// src/services/events.rs
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio::sync::{broadcast, mpsc};
+use uuid::Uuid;
+
+const CHANNEL_CAPACITY: usize = 64;
+/// Bytes received between upload progress events
+pub const UPLOAD_PROGRESS_STEP: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ProgressEvent {
+    Upload {
+        received_bytes: u64,
+        total_bytes: Option<u64>,
+    },
+    Transcode {
+        quality: String,
+        percent: u8,
+    },
+    QualityReady {
+        quality: String,
+    },
+    QualityFailed {
+        quality: String,
+    },
+    Thumbnails,
+    Done,
+    Failed {
+        reason: String,
+    },
+}
+
+impl ProgressEvent {
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, ProgressEvent::Done | ProgressEvent::Failed { .. })
+    }
+}
+
+/// Fans out upload and processing progress to subscribers, per video.
+/// Channels only exist while someone is listening.
+#[derive(Default)]
+pub struct ProgressEvents {
+    channels: Mutex<HashMap<Uuid, broadcast::Sender<ProgressEvent>>>,
+    /// Set in worker processes, which nobody subscribes to, to pass their
+    /// events on to the API nodes
+    relay: Option<mpsc::UnboundedSender<(Uuid, ProgressEvent)>>,
+}
+
+impl ProgressEvents {
+    /// Events sent to `relay` rather than to subscribers
+    pub fn relaying(relay: mpsc::UnboundedSender<(Uuid, ProgressEvent)>) -> Self {
+        Self {
+            channels: Mutex::default(),
+            relay: Some(relay),
+        }
+    }
+
+    pub fn subscribe(&self, v_id: Uuid) -> broadcast::Receiver<ProgressEvent> {
+        let mut channels = self.channels.lock().unwrap();
+        channels.retain(|_, sender| sender.receiver_count() > 0);
+        channels
+            .entry(v_id)
+            .or_insert_with(|| broadcast::channel(CHANNEL_CAPACITY).0)
+            .subscribe()
+    }
+
+    pub fn publish(&self, v_id: Uuid, event: ProgressEvent) {
+        if let Some(relay) = &self.relay {
+            let _ = relay.send((v_id, event));
+            return;
+        }
+        let mut channels = self.channels.lock().unwrap();
+        let terminal = event.is_terminal();
+        if let Some(sender) = channels.get(&v_id) {
+            // No receivers is fine, the channel is pruned on the next subscribe
+            let _ = sender.send(event);
+        }
+        if terminal {
+            channels.remove(&v_id);
+        }
+    }
+}
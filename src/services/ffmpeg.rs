@@ -0,0 +1,231 @@
+// src/services/ffmpeg.rs
+use crate::config::app_config::FfmpegConfig;
+use actix_web::web::Bytes;
+use anyhow::{Context, Result};
+use std::path::Path;
+use std::process::{ExitStatus, Stdio};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{ChildStdin, Command};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::task::JoinHandle;
+
+/// Oldest ffmpeg release processing works with, e.g. for its libsvtav1 encoder
+const MIN_VERSION: (u32, u32) = (4, 4);
+/// Chunks buffered for a `PipeFeed` before its process is considered too slow
+const PIPE_FEED_BUFFER: usize = 64;
+
+/// Builds a `Command` for running ffmpeg/ffprobe against untrusted uploads.
+/// `program` is `ffmpeg` or `ffprobe`, run from its configured path.
+///
+/// The process runs inside `work_dir` with a cleared environment and no stdin,
+/// optionally through the configured sandbox wrapper and with resource limits
+/// applied. Callers must pass absolute paths since the working directory changes.
+pub fn command(program: &str, config: &FfmpegConfig, work_dir: &Path) -> Command {
+    let program = config.binary(program);
+    let mut cmd = match config.sandbox_command.as_deref() {
+        Some([wrapper, args @ ..]) => {
+            let mut cmd = Command::new(wrapper);
+            cmd.args(args).arg(program);
+            cmd
+        }
+        _ => Command::new(program),
+    };
+
+    cmd.current_dir(work_dir)
+        .env_clear()
+        .env("PATH", std::env::var_os("PATH").unwrap_or_default())
+        .stdin(Stdio::null())
+        .kill_on_drop(true);
+
+    #[cfg(unix)]
+    apply_limits(&mut cmd, config);
+
+    cmd
+}
+
+/// Arguments that stop ffmpeg from following references in the input (e.g. a
+/// crafted playlist) to anything other than local files. Must precede `-i`.
+pub fn input_protocol_args() -> [&'static str; 2] {
+    ["-protocol_whitelist", "file,pipe"]
+}
+
+/// Arguments that make ffmpeg report machine-readable progress on stdout, for
+/// use with `run_with_progress`. Must precede the output file.
+pub fn progress_args() -> [&'static str; 3] {
+    ["-progress", "pipe:1", "-nostats"]
+}
+
+/// Runs an ffmpeg command built with `progress_args`, calling `on_progress`
+/// with the percentage of `duration` encoded so far.
+pub async fn run_with_progress(
+    cmd: &mut Command,
+    duration: Option<f64>,
+    mut on_progress: impl FnMut(f64),
+) -> std::io::Result<ExitStatus> {
+    cmd.stdout(Stdio::piped());
+    let mut child = cmd.spawn()?;
+
+    if let Some(stdout) = child.stdout.take() {
+        let mut lines = BufReader::new(stdout).lines();
+        while let Some(line) = lines.next_line().await? {
+            let (Some(out_time), Some(duration)) = (line.strip_prefix("out_time_us="), duration)
+            else {
+                continue;
+            };
+            if let Ok(micros) = out_time.parse::<f64>() {
+                on_progress((micros / 1_000_000.0 / duration * 100.0).clamp(0.0, 100.0));
+            }
+        }
+    }
+
+    child.wait().await
+}
+
+/// Writes content to a process's stdin as it arrives, e.g. an upload while it
+/// is stored. Feeding never waits on the process: if it falls behind, the
+/// feed is abandoned rather than slowing the sender down.
+pub struct PipeFeed {
+    input: Option<mpsc::Sender<Bytes>>,
+    /// Whether everything sent reached the process
+    writer: JoinHandle<bool>,
+    abandoned: bool,
+}
+
+impl PipeFeed {
+    pub fn start(mut stdin: ChildStdin) -> Self {
+        let (input, mut chunks) = mpsc::channel::<Bytes>(PIPE_FEED_BUFFER);
+        let writer = tokio::spawn(async move {
+            while let Some(chunk) = chunks.recv().await {
+                // The process may stop reading once it has seen enough
+                if stdin.write_all(&chunk).await.is_err() {
+                    return false;
+                }
+            }
+            true
+        });
+        PipeFeed {
+            input: Some(input),
+            writer,
+            abandoned: false,
+        }
+    }
+
+    pub fn feed(&mut self, chunk: &Bytes) {
+        let Some(input) = &self.input else {
+            return;
+        };
+        match input.try_send(chunk.clone()) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                self.abandoned = true;
+                self.input = None;
+            }
+            Err(TrySendError::Closed(_)) => self.input = None,
+        }
+    }
+
+    pub fn abandoned(&self) -> bool {
+        self.abandoned
+    }
+
+    /// Ends the input once what was fed is written, returning whether all of
+    /// it reached the process
+    pub async fn close(self) -> bool {
+        let PipeFeed {
+            input,
+            writer,
+            abandoned,
+        } = self;
+        drop(input);
+        if abandoned {
+            // The writer may be stuck on a process that stopped reading
+            writer.abort();
+            return false;
+        }
+        writer.await.unwrap_or(false)
+    }
+}
+
+/// Checks that the configured ffmpeg and ffprobe run, the way processing
+/// runs them, and are at least `MIN_VERSION`, so a missing or outdated
+/// install fails at startup rather than on the first upload. Development
+/// builds, versioned by revision, are let through with a warning.
+pub async fn verify(config: &FfmpegConfig) -> Result<()> {
+    for program in ["ffmpeg", "ffprobe"] {
+        let path = config.binary(program);
+        let output = command(program, config, Path::new("."))
+            .arg("-version")
+            .output()
+            .await
+            .with_context(|| format!("Can't run {} at {:?}", program, path))?;
+        if !output.status.success() {
+            anyhow::bail!("{} at {:?} failed to report its version", program, path);
+        }
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        match parse_version(&stdout) {
+            Some((major, minor)) if (major, minor) < MIN_VERSION => anyhow::bail!(
+                "{} at {:?} is version {}.{}, but at least {}.{} is required",
+                program,
+                path,
+                major,
+                minor,
+                MIN_VERSION.0,
+                MIN_VERSION.1
+            ),
+            Some((major, minor)) => log::info!("Using {} {}.{} at {}", program, major, minor, path),
+            None => log::warn!(
+                "Can't tell the version of {} at {}, assuming it is recent: {}",
+                program,
+                path,
+                stdout.lines().next().unwrap_or_default()
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// Major and minor version from the first line of `-version` output, e.g.
+/// `ffmpeg version 6.1.1-3ubuntu5 Copyright ...` or `... version n7.0 ...`
+fn parse_version(output: &str) -> Option<(u32, u32)> {
+    let version = output.lines().next()?.split_whitespace().nth(2)?;
+    let version = version.strip_prefix('n').unwrap_or(version);
+    let mut parts = version.split(|c: char| !c.is_ascii_digit());
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    Some((major, minor))
+}
+
+#[cfg(unix)]
+fn apply_limits(cmd: &mut Command, config: &FfmpegConfig) {
+    let memory = config.max_memory_mb.map(|mb| mb * 1024 * 1024);
+    let cpu = config.max_cpu_seconds;
+    if memory.is_none() && cpu.is_none() {
+        return;
+    }
+
+    // SAFETY: the closure runs in the forked child before exec and only calls
+    // setrlimit, which is async-signal-safe.
+    unsafe {
+        cmd.pre_exec(move || {
+            if let Some(bytes) = memory {
+                if libc::setrlimit(libc::RLIMIT_AS, &rlimit(bytes)) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            if let Some(seconds) = cpu {
+                if libc::setrlimit(libc::RLIMIT_CPU, &rlimit(seconds)) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+}
+
+#[cfg(unix)]
+fn rlimit(value: u64) -> libc::rlimit {
+    libc::rlimit {
+        rlim_cur: value as libc::rlim_t,
+        rlim_max: value as libc::rlim_t,
+    }
+}
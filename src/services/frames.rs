@@ -0,0 +1,261 @@
+// src/services/frames.rs
+use crate::config::app_config::FfmpegConfig;
+use crate::db::models::FrameExtraction;
+use crate::services::blocking::BlockingPool;
+use crate::services::{ffmpeg, video_processor};
+use anyhow::{Context, Result};
+use diesel::QueryDsl;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use uuid::Uuid;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+/// Output formats: a zip of the JPEGs, or NDJSON with a URL per frame
+pub const FORMATS: &[&str] = &["zip", "ndjson"];
+
+/// Times to sample, from either an interval or explicit timestamps. The
+/// error names the offending field.
+pub fn resolve_timestamps(
+    interval_secs: Option<f64>,
+    timestamps: Option<&[f64]>,
+    duration: f64,
+    max_frames: usize,
+) -> Result<Vec<f64>, (&'static str, String)> {
+    let resolved = match (interval_secs, timestamps) {
+        (Some(interval), None) => {
+            if !(interval.is_finite() && interval > 0.0) {
+                return Err(("interval_secs", "Interval must be positive".to_string()));
+            }
+            let count = (duration / interval).ceil() as usize;
+            if count > max_frames {
+                return Err((
+                    "interval_secs",
+                    format!(
+                        "Interval gives {} frames, at most {} are allowed",
+                        count, max_frames
+                    ),
+                ));
+            }
+            (0..count).map(|i| i as f64 * interval).collect()
+        }
+        (None, Some(timestamps)) => {
+            if timestamps.is_empty() || timestamps.len() > max_frames {
+                return Err((
+                    "timestamps",
+                    format!("Between 1 and {} timestamps are allowed", max_frames),
+                ));
+            }
+            // A frame exactly at the end doesn't exist
+            if let Some(t) = timestamps.iter().find(|t| !(0.0..duration).contains(*t)) {
+                return Err((
+                    "timestamps",
+                    format!("{} is outside the video (0 to {})", t, duration),
+                ));
+            }
+            timestamps.to_vec()
+        }
+        _ => {
+            return Err((
+                "interval_secs",
+                "Exactly one of interval_secs and timestamps must be set".to_string(),
+            ))
+        }
+    };
+    Ok(resolved)
+}
+
+/// Where an extraction's frames and archive are kept. Like the upload
+/// directory, it must be writable inside `ffmpeg.sandbox_command`.
+pub fn frames_dir(f_id: Uuid) -> PathBuf {
+    PathBuf::from("frames").join(f_id.to_string())
+}
+
+/// File name of the frame at `index` in the extraction's timestamps
+pub fn frame_name(index: usize) -> String {
+    format!("frame_{:05}.jpg", index + 1)
+}
+
+/// Where the frame `tenths` tenths of a second into a video, `width` pixels
+/// wide or at full size, is kept once extracted
+pub fn thumbnail_path(video_dir: &Path, tenths: u64, width: Option<u32>) -> PathBuf {
+    let width = width.map_or_else(|| "full".to_string(), |width| width.to_string());
+    video_dir
+        .join("thumbnails")
+        .join("at")
+        .join(format!("{}_{}.jpg", tenths, width))
+}
+
+/// Where a chosen poster is kept, relative to the video directory. Each gets
+/// a new name so cached copies of the previous one aren't served.
+pub fn poster_path(poster_id: Uuid) -> PathBuf {
+    PathBuf::from("posters").join(format!("{}.jpg", poster_id))
+}
+
+/// Path of a zip extraction's archive
+pub fn archive_path(extraction: &FrameExtraction) -> PathBuf {
+    frames_dir(extraction.id).join("frames.zip")
+}
+
+/// Runs a frame extraction job: writes each frame, then the archive for zip
+/// extractions
+pub async fn run(
+    f_id: Uuid,
+    conn: &mut AsyncPgConnection,
+    ffmpeg: &FfmpegConfig,
+    blocking: &BlockingPool,
+) -> Result<()> {
+    use crate::db::schema::frame_extractions;
+
+    let extraction = frame_extractions::table
+        .find(f_id)
+        .first::<FrameExtraction>(conn)
+        .await?;
+    let video_dir = fs::canonicalize(video_processor::get_video_dir(extraction.video_id)).await?;
+    let input = video_processor::find_original(&video_dir).await?;
+    let dir = fs::canonicalize(frames_dir(f_id)).await?;
+
+    for (index, timestamp) in extraction.timestamps.iter().enumerate() {
+        extract_frame(
+            &input,
+            &dir.join(frame_name(index)),
+            *timestamp,
+            None,
+            ffmpeg,
+        )
+        .await
+        .with_context(|| format!("Failed to extract the frame at {}s", timestamp))?;
+    }
+
+    if extraction.format == "zip" {
+        let archive = dir.join("frames.zip");
+        let timestamps = extraction.timestamps.clone();
+        blocking
+            .run(move || write_archive(&dir, &archive, &timestamps))
+            .await??;
+    }
+    Ok(())
+}
+
+/// Writes the frame at `timestamp` as a JPEG, scaled to `width` if given
+pub(crate) async fn extract_frame(
+    input: &Path,
+    output: &Path,
+    timestamp: f64,
+    width: Option<u32>,
+    ffmpeg: &FfmpegConfig,
+) -> Result<()> {
+    // Seeking before the input is fast and still frame accurate
+    let mut cmd = ffmpeg::command("ffmpeg", ffmpeg, output.parent().unwrap());
+    cmd.args(ffmpeg::input_protocol_args())
+        .arg("-ss")
+        .arg(format!("{:.3}", timestamp))
+        .arg("-i")
+        .arg(input);
+    if let Some(width) = width {
+        cmd.arg("-vf").arg(format!("scale={}:-2", width));
+    }
+    let status = cmd
+        .args(["-frames:v", "1", "-q:v", "2", "-y", "-loglevel", "quiet"])
+        .arg(output)
+        .status()
+        .await?;
+    if !status.success() || !fs::try_exists(output).await? {
+        anyhow::bail!("FFmpeg frame extraction failed");
+    }
+    Ok(())
+}
+
+/// Re-encodes an uploaded image as a JPEG at most `max_width` pixels wide,
+/// which also rejects anything that isn't an image
+pub(crate) async fn convert_image(
+    input: &Path,
+    output: &Path,
+    max_width: u32,
+    ffmpeg: &FfmpegConfig,
+) -> Result<()> {
+    let status = ffmpeg::command("ffmpeg", ffmpeg, output.parent().unwrap())
+        .args(ffmpeg::input_protocol_args())
+        .arg("-i")
+        .arg(input)
+        .arg("-vf")
+        .arg(format!("scale='min({},iw)':-2", max_width))
+        .args(["-frames:v", "1", "-q:v", "2", "-y", "-loglevel", "quiet"])
+        .arg(output)
+        .status()
+        .await?;
+    if !status.success() || !fs::try_exists(output).await? {
+        anyhow::bail!("FFmpeg image conversion failed");
+    }
+    Ok(())
+}
+
+/// Zips the frames with an `index.ndjson` of their timestamps. JPEGs don't
+/// compress further, so they are stored as-is.
+fn write_archive(dir: &Path, archive: &Path, timestamps: &[f64]) -> Result<()> {
+    let partial = dir.join("frames.zip.partial");
+    let mut zip = ZipWriter::new(File::create(&partial)?);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+
+    let mut index = String::new();
+    for (i, timestamp) in timestamps.iter().enumerate() {
+        let name = frame_name(i);
+        zip.start_file(name.as_str(), options)?;
+        io::copy(&mut File::open(dir.join(&name))?, &mut zip)?;
+        index.push_str(&serde_json::json!({ "file": name, "timestamp": timestamp }).to_string());
+        index.push('\n');
+    }
+    zip.start_file("index.ndjson", options)?;
+    zip.write_all(index.as_bytes())?;
+    zip.finish()?;
+
+    std::fs::rename(partial, archive)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intervals_sample_from_the_start_up_to_the_end() {
+        assert_eq!(
+            resolve_timestamps(Some(2.5), None, 10.0, 10),
+            Ok(vec![0.0, 2.5, 5.0, 7.5])
+        );
+        // A partial interval at the end still gets a frame
+        assert_eq!(
+            resolve_timestamps(Some(4.0), None, 10.0, 10),
+            Ok(vec![0.0, 4.0, 8.0])
+        );
+    }
+
+    #[test]
+    fn timestamps_are_kept_as_given() {
+        assert_eq!(
+            resolve_timestamps(None, Some(&[3.0, 0.0, 9.5]), 10.0, 10),
+            Ok(vec![3.0, 0.0, 9.5])
+        );
+    }
+
+    #[test]
+    fn invalid_requests_name_the_field() {
+        let cases = [
+            (None, None, "interval_secs"),
+            (Some(1.0), Some(&[1.0][..]), "interval_secs"),
+            (Some(0.0), None, "interval_secs"),
+            (Some(f64::NAN), None, "interval_secs"),
+            (Some(0.5), None, "interval_secs"),
+            (None, Some(&[]), "timestamps"),
+            (None, Some(&[10.0]), "timestamps"),
+            (None, Some(&[-1.0]), "timestamps"),
+        ];
+        for (interval, timestamps, field) in cases {
+            let err = resolve_timestamps(interval, timestamps, 10.0, 10).unwrap_err();
+            assert_eq!(err.0, field, "{:?} {:?}", interval, timestamps);
+        }
+    }
+}
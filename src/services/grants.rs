@@ -0,0 +1,88 @@
+// src/services/grants.rs
+use anyhow::Result;
+use chrono::{NaiveDateTime, Utc};
+use diesel::dsl::exists;
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use uuid::Uuid;
+
+/// Whether a playback request may see a video's playlists
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// The video is neither restricted nor scheduled for later (or doesn't
+    /// exist, which serving reports)
+    Public,
+    /// The token belongs to an unexpired preview link, or the video is
+    /// restricted and the token has a grant covering the current time
+    Granted,
+    /// Scheduled for later, and the token isn't a preview link's. Grants
+    /// don't open videos before they are published.
+    Unpublished,
+    Denied,
+}
+
+pub async fn check(
+    conn: &mut AsyncPgConnection,
+    v_id: Uuid,
+    token: Option<&str>,
+) -> Result<Access> {
+    use crate::db::schema::{playback_grants, videos};
+
+    let video = videos::table
+        .find(v_id)
+        .select((videos::restricted, videos::publish_at))
+        .first::<(bool, Option<NaiveDateTime>)>(conn)
+        .await
+        .optional()?;
+    let Some((restricted, publish_at)) = video else {
+        return Ok(Access::Public);
+    };
+    let now = Utc::now().naive_utc();
+    let scheduled = publish_at.is_some_and(|at| at > now);
+    if !restricted && !scheduled {
+        return Ok(Access::Public);
+    }
+    let Some(token) = token else {
+        return Ok(if scheduled {
+            Access::Unpublished
+        } else {
+            Access::Denied
+        });
+    };
+    if previewing(conn, v_id, token).await? {
+        return Ok(Access::Granted);
+    }
+    if scheduled {
+        return Ok(Access::Unpublished);
+    }
+
+    let granted = diesel::select(exists(
+        playback_grants::table
+            .filter(playback_grants::video_id.eq(v_id))
+            .filter(playback_grants::token.eq(token))
+            .filter(playback_grants::starts_at.le(now))
+            .filter(playback_grants::expires_at.gt(now)),
+    ))
+    .get_result::<bool>(conn)
+    .await?;
+    Ok(if granted {
+        Access::Granted
+    } else {
+        Access::Denied
+    })
+}
+
+/// Whether `token` belongs to an unexpired preview link of the video
+pub async fn previewing(conn: &mut AsyncPgConnection, v_id: Uuid, token: &str) -> Result<bool> {
+    use crate::db::schema::preview_links;
+
+    let now = Utc::now().naive_utc();
+    Ok(diesel::select(exists(
+        preview_links::table
+            .filter(preview_links::video_id.eq(v_id))
+            .filter(preview_links::token.eq(token))
+            .filter(preview_links::expires_at.gt(now)),
+    ))
+    .get_result::<bool>(conn)
+    .await?)
+}
@@ -0,0 +1,214 @@
+// src/services/import.rs
+use crate::api::rate_limit::UploadPermit;
+use crate::config::AppConfig;
+use crate::db::schema::videos;
+use crate::db::DbPool;
+use crate::services::events::{ProgressEvent, ProgressEvents, UPLOAD_PROGRESS_STEP};
+use crate::services::{outbound, video_processor};
+use actix_web::web;
+use anyhow::{Context, Result};
+use diesel::ExpressionMethods;
+use diesel_async::RunQueryDsl;
+use futures::TryStreamExt;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportProgress {
+    pub url: String,
+    pub downloaded_bytes: u64,
+    pub total_bytes: Option<u64>,
+    pub done: bool,
+    pub error: Option<String>,
+    #[serde(skip)]
+    finished_at: Option<Instant>,
+}
+
+/// How long the outcome of an import can still be looked up
+const FINISHED_RETENTION: Duration = Duration::from_secs(60 * 60);
+
+/// In-memory progress of server-side imports, keyed by video id. Finished
+/// imports are forgotten after `FINISHED_RETENTION`.
+#[derive(Default)]
+pub struct ImportTracker {
+    imports: Mutex<HashMap<Uuid, ImportProgress>>,
+}
+
+impl ImportTracker {
+    pub fn get(&self, v_id: Uuid) -> Option<ImportProgress> {
+        self.imports.lock().unwrap().get(&v_id).cloned()
+    }
+
+    fn update(&self, v_id: Uuid, f: impl FnOnce(&mut ImportProgress)) {
+        if let Some(progress) = self.imports.lock().unwrap().get_mut(&v_id) {
+            f(progress);
+        }
+    }
+}
+
+/// Starts downloading `url` in the background for an existing "uploading"
+/// video row, then hands the file to the regular processing pipeline. The
+/// rate limit `permit` is held, and charged, until the download ends.
+pub(crate) fn spawn_import(
+    v_id: Uuid,
+    url: String,
+    permit: Option<UploadPermit>,
+    pool: web::Data<DbPool>,
+    config: Arc<AppConfig>,
+    tracker: web::Data<ImportTracker>,
+    events: web::Data<ProgressEvents>,
+) {
+    {
+        let mut imports = tracker.imports.lock().unwrap();
+        imports.retain(|_, progress| {
+            progress
+                .finished_at
+                .is_none_or(|at| at.elapsed() < FINISHED_RETENTION)
+        });
+        imports.insert(
+            v_id,
+            ImportProgress {
+                url: url.clone(),
+                downloaded_bytes: 0,
+                total_bytes: None,
+                done: false,
+                error: None,
+                finished_at: None,
+            },
+        );
+    }
+
+    // ingest returns actix errors, which aren't Send, so stay on this worker
+    actix_web::rt::spawn(async move {
+        let video_dir = video_processor::get_video_dir(v_id);
+        let received = permit.as_ref().map(UploadPermit::received);
+        let download = download(
+            &url,
+            config.storage.max_file_size as u64,
+            Duration::from_secs(config.imports.timeout_secs),
+            v_id,
+            received.as_deref(),
+            &tracker,
+            &events,
+        )
+        .await;
+        drop(permit);
+
+        match download {
+            Ok(checksum) => {
+                let conn = &mut pool.get().await.expect("Failed to get DB connection");
+                if let Err(e) = diesel::update(videos::table)
+                    .filter(videos::id.eq(v_id))
+                    .set(videos::checksum.eq(checksum))
+                    .execute(conn)
+                    .await
+                {
+                    log::error!("Error storing checksum for {}: {}", v_id, e);
+                }
+            }
+            Err(e) => {
+                log::error!("Failed to import {} for video {}: {}", url, v_id, e);
+                if let Err(e) = fs::remove_dir_all(&video_dir).await {
+                    log::error!("Failed to remove partial import {}: {}", v_id, e);
+                }
+                tracker.update(v_id, |p| p.error = Some(e.to_string()));
+            }
+        }
+
+        // Validation and status updates are shared with direct uploads; a failed
+        // download shows up here as a missing file
+        let conn = &mut pool.get().await.expect("Failed to get DB connection");
+        if video_processor::ingest(v_id, None, conn, pool.clone(), config, events)
+            .await
+            .is_err()
+        {
+            tracker.update(v_id, |p| {
+                p.error
+                    .get_or_insert_with(|| "Video could not be queued for processing".to_string());
+            });
+        }
+        tracker.update(v_id, |p| {
+            p.done = true;
+            p.finished_at = Some(Instant::now());
+        });
+    });
+}
+
+/// Downloads `url` into the video's directory within `timeout`, from public
+/// addresses only (see `outbound::client`)
+async fn download(
+    url: &str,
+    max_file_size: u64,
+    timeout: Duration,
+    v_id: Uuid,
+    received: Option<&AtomicU64>,
+    tracker: &ImportTracker,
+    events: &ProgressEvents,
+) -> Result<String> {
+    let url = url.parse().context("Invalid URL")?;
+    outbound::check_url(&url)?;
+    let response = outbound::client(timeout)?
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()
+        .context("Remote server returned an error")?;
+
+    let total = response.content_length();
+    if total.is_some_and(|total| total > max_file_size) {
+        return Err(anyhow::anyhow!("Remote file exceeds maximum upload size"));
+    }
+    tracker.update(v_id, |p| p.total_bytes = total);
+
+    // Name the file after the last path segment of the (possibly redirected) URL
+    let filename = video_processor::original_file_name(
+        response
+            .url()
+            .path_segments()
+            .and_then(|mut segments| segments.next_back()),
+    );
+    let video_dir = video_processor::get_video_dir(v_id);
+    fs::create_dir_all(&video_dir).await?;
+    let mut f = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(video_dir.join(filename))
+        .await?;
+
+    let mut downloaded = 0;
+    let mut hasher = Sha256::new();
+    let mut stream = response.bytes_stream();
+    while let Some(chunk) = stream.try_next().await? {
+        let previous = downloaded;
+        downloaded += chunk.len() as u64;
+        if let Some(received) = received {
+            received.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+        }
+        if downloaded > max_file_size {
+            return Err(anyhow::anyhow!("Remote file exceeds maximum upload size"));
+        }
+        f.write_all(&chunk).await?;
+        hasher.update(&chunk);
+        tracker.update(v_id, |p| p.downloaded_bytes = downloaded);
+        if downloaded / UPLOAD_PROGRESS_STEP != previous / UPLOAD_PROGRESS_STEP {
+            events.publish(
+                v_id,
+                ProgressEvent::Upload {
+                    received_bytes: downloaded,
+                    total_bytes: total,
+                },
+            );
+        }
+    }
+
+    f.sync_all().await?;
+    Ok(hex::encode(hasher.finalize()))
+}
@@ -0,0 +1,114 @@
+// src/services/integrity.rs
+use anyhow::{Context, Result};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+
+/// Comment prefix placed before each segment URI. Lines starting with `#` that
+/// aren't tags are ignored by players.
+const CHECKSUM_PREFIX: &str = "# sha256:";
+
+#[derive(Debug, Serialize)]
+pub struct IntegrityReport {
+    pub checked: usize,
+    pub mismatches: Vec<Mismatch>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Mismatch {
+    pub file: String,
+    pub expected: String,
+    /// None when the file is missing
+    pub actual: Option<String>,
+}
+
+// Everything here walks and hashes whole renditions with blocking I/O, so
+// callers run it on the `BlockingPool`.
+
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// The file a playlist line refers to: a segment URI, or the init segment of
+/// an `EXT-X-MAP` tag in fMP4 playlists
+fn segment_uri(line: &str) -> Option<&str> {
+    if let Some(attributes) = line.strip_prefix("#EXT-X-MAP:") {
+        return attributes
+            .split(',')
+            .find_map(|attribute| attribute.strip_prefix("URI="))
+            .map(|uri| uri.trim_matches('"'));
+    }
+    (!line.is_empty() && !line.starts_with('#')).then_some(line)
+}
+
+/// A rendition playlist with every segment URI, and the init segment if
+/// there is one, preceded by a comment holding the file's SHA-256, computed
+/// from the files just packaged.
+pub fn with_checksums(playlist: &Path) -> Result<String> {
+    let dir = playlist.parent().context("Invalid playlist path")?;
+    let contents = fs::read_to_string(playlist)?;
+
+    let mut output = String::with_capacity(contents.len() * 2);
+    for line in contents.lines() {
+        if line.starts_with(CHECKSUM_PREFIX) {
+            continue;
+        }
+        if let Some(uri) = segment_uri(line) {
+            let checksum = sha256_file(&dir.join(uri))?;
+            output.push_str(CHECKSUM_PREFIX);
+            output.push_str(&checksum);
+            output.push('\n');
+        }
+        output.push_str(line);
+        output.push('\n');
+    }
+    Ok(output)
+}
+
+/// Re-hashes every segment referenced by the rendition playlists under
+/// `hls_dir` and reports those that no longer match their recorded checksum.
+pub fn verify(hls_dir: &Path) -> Result<IntegrityReport> {
+    let mut report = IntegrityReport {
+        checked: 0,
+        mismatches: Vec::new(),
+    };
+
+    for entry in fs::read_dir(hls_dir)? {
+        let entry = entry?;
+        let playlist = entry.path().join("stream.m3u8");
+        let Ok(contents) = fs::read_to_string(&playlist) else {
+            continue;
+        };
+        let quality = entry.file_name().to_string_lossy().to_string();
+
+        let mut expected = None;
+        for line in contents.lines() {
+            if let Some(checksum) = line.strip_prefix(CHECKSUM_PREFIX) {
+                expected = Some(checksum.to_string());
+                continue;
+            }
+            let Some(uri) = segment_uri(line) else {
+                continue;
+            };
+            let Some(expected) = expected.take() else {
+                continue;
+            };
+
+            report.checked += 1;
+            let actual = sha256_file(&entry.path().join(uri)).ok();
+            if actual.as_deref() != Some(expected.as_str()) {
+                report.mismatches.push(Mismatch {
+                    file: format!("{}/{}", quality, uri),
+                    expected,
+                    actual,
+                });
+            }
+        }
+    }
+
+    Ok(report)
+}
@@ -0,0 +1,718 @@
+// src/services/jobs.rs
+use crate::api::shared::{validation_error, ErrorCode};
+use crate::config::AppConfig;
+use crate::db::models::{JobDependency, ProcessingJob};
+use crate::db::DbPool;
+use crate::services::blocking::BlockingPool;
+use crate::services::events::{ProgressEvent, ProgressEvents};
+use crate::services::video_processor::Plan;
+use crate::services::{
+    chunks, convert, frames, notifications, recovery, video_processor, webhooks,
+};
+use actix_web::web;
+use chrono::Utc;
+use diesel::result::QueryResult;
+use diesel::{
+    BoolExpressionMethods, ExpressionMethods, JoinOnDsl, NullableExpressionMethods,
+    OptionalExtension, QueryDsl,
+};
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+use futures::future::{self, AbortHandle};
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+use tokio::sync::{Notify, Semaphore};
+use uuid::Uuid;
+
+/// How often an idle worker checks for new jobs it wasn't notified of, e.g.
+/// while its listening connection is down
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Identifies the jobs this process claimed, whose leases it renews
+static WORKER_ID: LazyLock<Uuid> = LazyLock::new(Uuid::new_v4);
+
+/// Jobs this process runs, by id. Aborting one drops its future, and with it
+/// the ffmpeg processes it spawned, which are killed on drop.
+static RUNNING: LazyLock<Mutex<HashMap<Uuid, AbortHandle>>> = LazyLock::new(Mutex::default);
+
+/// Steps a video is processed in, each a job of its own. The probe plans
+/// the renditions, which are encoded by a job each, or together by the
+/// ladder job, alongside the thumbnails, and finalize, which depends on all
+/// of them, packages what came out.
+pub const PROBE: &str = "probe";
+pub const RENDITION: &str = "rendition";
+pub const LADDER: &str = "ladder";
+pub const THUMBNAILS: &str = "thumbnails";
+pub const FINALIZE: &str = "finalize";
+
+/// Levels an upload can be given, lowest first. Workers take the jobs of
+/// higher levels first, so a backfill queued as low doesn't hold up fresh
+/// uploads.
+pub const PRIORITIES: [&str; 3] = ["low", "normal", "high"];
+
+/// Rank of a level in `processing_jobs.priority`, 0 for normal
+pub fn rank(priority: &str) -> i32 {
+    PRIORITIES
+        .iter()
+        .position(|level| *level == priority)
+        .map_or(0, |i| i as i32 - 1)
+}
+
+/// Rejects levels outside `PRIORITIES`
+pub fn validate_priority(priority: &str) -> Result<(), actix_web::Error> {
+    if PRIORITIES.contains(&priority) {
+        return Ok(());
+    }
+    Err(validation_error(
+        "priority".to_string(),
+        format!("Priority must be one of {}", PRIORITIES.join(", ")),
+        ErrorCode::ValidationFailed,
+    ))
+}
+
+/// Queues a video for processing at its priority; the `processing_jobs`
+/// trigger wakes a worker to pick it up. The jobs of its earlier runs are
+/// dropped, so it only ever has one graph.
+pub async fn enqueue(v_id: Uuid, conn: &mut AsyncPgConnection) -> QueryResult<()> {
+    use crate::db::schema::{processing_jobs, videos};
+
+    let priority: String = videos::table
+        .find(v_id)
+        .select(videos::priority)
+        .first(conn)
+        .await?;
+    diesel::delete(
+        processing_jobs::table
+            .filter(processing_jobs::video_id.eq(v_id))
+            .filter(processing_jobs::state.eq_any(["done", "failed"])),
+    )
+    .execute(conn)
+    .await?;
+    insert(
+        conn,
+        &ProcessingJob {
+            video_id: Some(v_id),
+            stage: Some(PROBE.to_string()),
+            priority: rank(&priority),
+            ..queued()
+        },
+    )
+    .await
+}
+
+/// Queues a conversion, sharing the workers (and their limit) with processing
+pub async fn enqueue_conversion(c_id: Uuid, conn: &mut AsyncPgConnection) -> QueryResult<()> {
+    insert(
+        conn,
+        &ProcessingJob {
+            conversion_id: Some(c_id),
+            ..queued()
+        },
+    )
+    .await
+}
+
+/// Queues a frame extraction
+pub async fn enqueue_frames(f_id: Uuid, conn: &mut AsyncPgConnection) -> QueryResult<()> {
+    insert(
+        conn,
+        &ProcessingJob {
+            frame_extraction_id: Some(f_id),
+            ..queued()
+        },
+    )
+    .await
+}
+
+/// Queues the encode of a chunk, which workers take before other jobs
+pub async fn enqueue_chunk(ce_id: Uuid, conn: &mut AsyncPgConnection) -> QueryResult<()> {
+    insert(
+        conn,
+        &ProcessingJob {
+            chunk_encode_id: Some(ce_id),
+            ..queued()
+        },
+    )
+    .await
+}
+
+/// Queues the steps after a video's probe: the jobs encoding its renditions
+/// and one for the thumbnails, which run side by side, and finalize once
+/// they're over, at the priority of the `probe` job. All or none are queued,
+/// so a probe rerun after a crash starts afresh.
+async fn enqueue_steps(
+    conn: &mut AsyncPgConnection,
+    probe: &ProcessingJob,
+    v_id: Uuid,
+    plan: &Plan,
+) -> QueryResult<()> {
+    use crate::db::schema::{job_dependencies, processing_jobs};
+
+    let step = |stage: &str, quality: Option<&String>| ProcessingJob {
+        video_id: Some(v_id),
+        stage: Some(stage.to_string()),
+        quality: quality.cloned(),
+        priority: probe.priority,
+        ..queued()
+    };
+    let mut steps: Vec<_> = plan
+        .qualities
+        .iter()
+        .map(|quality| step(RENDITION, Some(quality)))
+        .collect();
+    if !plan.ladder.is_empty() {
+        steps.push(step(LADDER, None));
+    }
+    steps.push(step(THUMBNAILS, None));
+    let finalize = step(FINALIZE, None);
+    let dependencies: Vec<_> = steps
+        .iter()
+        .map(|step| JobDependency {
+            job_id: finalize.id,
+            depends_on: step.id,
+        })
+        .collect();
+    steps.push(finalize);
+
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        async move {
+            diesel::insert_into(processing_jobs::table)
+                .values(&steps)
+                .execute(conn)
+                .await?;
+            diesel::insert_into(job_dependencies::table)
+                .values(&dependencies)
+                .execute(conn)
+                .await?;
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+/// Cancels the queued and running jobs of a video, its chunks' included. The
+/// workers running them are told by a trigger on `processing_jobs`, and
+/// abort them.
+pub async fn cancel(conn: &mut AsyncPgConnection, v_id: Uuid) -> QueryResult<usize> {
+    use crate::db::schema::{chunk_encodes, processing_jobs};
+
+    let chunks = chunk_encodes::table
+        .filter(chunk_encodes::video_id.eq(v_id))
+        .select(chunk_encodes::id.nullable());
+    diesel::update(processing_jobs::table)
+        .filter(processing_jobs::state.eq_any(["queued", "running"]))
+        .filter(
+            processing_jobs::video_id
+                .eq(v_id)
+                .or(processing_jobs::chunk_encode_id.eq_any(chunks)),
+        )
+        .set((
+            processing_jobs::state.eq("canceled"),
+            processing_jobs::updated_at.eq(Utc::now().naive_utc()),
+        ))
+        .execute(conn)
+        .await
+}
+
+/// A queued job, for the `enqueue` functions to set the target of
+fn queued() -> ProcessingJob {
+    let now = Utc::now().naive_utc();
+    ProcessingJob {
+        id: Uuid::new_v4(),
+        video_id: None,
+        state: "queued".to_string(),
+        attempts: 0,
+        error: None,
+        created_at: now,
+        updated_at: now,
+        conversion_id: None,
+        frame_extraction_id: None,
+        chunk_encode_id: None,
+        stage: None,
+        quality: None,
+        worker_id: None,
+        heartbeat_at: None,
+        priority: 0,
+    }
+}
+
+async fn insert(conn: &mut AsyncPgConnection, job: &ProcessingJob) -> QueryResult<()> {
+    use crate::db::schema::processing_jobs;
+
+    diesel::insert_into(processing_jobs::table)
+        .values(job)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// Marks the oldest queued job of the highest priority whose dependencies are
+/// over as running, leased to this process, and returns it, taking chunks
+/// first as a running job waits for them. SKIP LOCKED lets several workers
+/// claim concurrently without getting the same job.
+async fn claim(conn: &mut AsyncPgConnection) -> QueryResult<Option<ProcessingJob>> {
+    use diesel::sql_types::Uuid as SqlUuid;
+
+    let jobs = diesel::sql_query(
+        "UPDATE processing_jobs \
+         SET state = 'running', attempts = attempts + 1, updated_at = now() AT TIME ZONE 'utc', \
+             worker_id = $1, heartbeat_at = now() AT TIME ZONE 'utc' \
+         WHERE id = (\
+             SELECT j.id FROM processing_jobs j WHERE j.state = 'queued' AND NOT EXISTS (\
+                 SELECT 1 FROM job_dependencies d JOIN processing_jobs p ON p.id = d.depends_on \
+                 WHERE d.job_id = j.id AND p.state IN ('queued', 'running')\
+             ) \
+             ORDER BY j.chunk_encode_id IS NULL, j.priority DESC, j.created_at LIMIT 1 FOR UPDATE SKIP LOCKED\
+         ) RETURNING *",
+    )
+    .bind::<SqlUuid, _>(*WORKER_ID)
+    .load::<ProcessingJob>(conn)
+    .await?;
+    Ok(jobs.into_iter().next())
+}
+
+/// Claims the first queued chunk of rendition `quality` of a video, for the
+/// job waiting on its chunks to encode itself
+pub(crate) async fn claim_chunk(
+    conn: &mut AsyncPgConnection,
+    v_id: Uuid,
+    quality: &str,
+) -> QueryResult<Option<ProcessingJob>> {
+    use diesel::sql_types::{Text, Uuid as SqlUuid};
+
+    let jobs = diesel::sql_query(
+        "UPDATE processing_jobs \
+         SET state = 'running', attempts = attempts + 1, updated_at = now() AT TIME ZONE 'utc', \
+             worker_id = $3, heartbeat_at = now() AT TIME ZONE 'utc' \
+         WHERE id = (\
+             SELECT j.id FROM processing_jobs j JOIN chunk_encodes c ON c.id = j.chunk_encode_id \
+             WHERE j.state = 'queued' AND c.video_id = $1 AND c.quality = $2 \
+             ORDER BY c.chunk LIMIT 1 FOR UPDATE OF j SKIP LOCKED\
+         ) RETURNING *",
+    )
+    .bind::<SqlUuid, _>(v_id)
+    .bind::<Text, _>(quality)
+    .bind::<SqlUuid, _>(*WORKER_ID)
+    .load::<ProcessingJob>(conn)
+    .await?;
+    Ok(jobs.into_iter().next())
+}
+
+pub(crate) async fn finish(conn: &mut AsyncPgConnection, job_id: Uuid, error: Option<String>) {
+    use crate::db::schema::processing_jobs;
+
+    let state = if error.is_some() { "failed" } else { "done" };
+    // Unless its lease expired and another worker took it over, or it was
+    // canceled, meanwhile
+    match diesel::update(processing_jobs::table)
+        .filter(processing_jobs::id.eq(job_id))
+        .filter(processing_jobs::state.eq("running"))
+        .filter(processing_jobs::worker_id.eq(*WORKER_ID))
+        .set((
+            processing_jobs::state.eq(state),
+            processing_jobs::error.eq(error),
+            processing_jobs::updated_at.eq(Utc::now().naive_utc()),
+        ))
+        .execute(conn)
+        .await
+    {
+        Ok(0) => log::warn!(
+            "Job {} was taken over or canceled before it finished",
+            job_id
+        ),
+        Ok(_) => {}
+        Err(e) => log::error!("Error recording result of job {}: {}", job_id, e),
+    }
+}
+
+/// Starts the loop that claims queued jobs and runs them in the background,
+/// at most `ffmpeg.max_concurrent_jobs` at a time, along with the renewal of
+/// their leases and the takeover of jobs whose lease expired. Jobs canceled
+/// or taken over while they run are aborted.
+pub fn start_worker(
+    pool: web::Data<DbPool>,
+    config: Arc<AppConfig>,
+    events: web::Data<ProgressEvents>,
+    blocking: web::Data<BlockingPool>,
+) {
+    let wake = Arc::new(Notify::new());
+    let notify = wake.clone();
+    notifications::listen(
+        &config.database.url,
+        notifications::JOBS_CHANNEL,
+        move |_| notify.notify_one(),
+    );
+    notifications::listen(
+        &config.database.url,
+        notifications::CANCEL_CHANNEL,
+        |payload| match Uuid::parse_str(payload) {
+            Ok(job_id) => abort(job_id),
+            Err(_) => log::warn!("Ignoring cancellation of invalid job {:?}", payload),
+        },
+    );
+    start_leases(pool.clone(), config.clone());
+
+    let slots = Arc::new(Semaphore::new(config.ffmpeg.max_concurrent_jobs.max(1)));
+    tokio::spawn(async move {
+        loop {
+            // Only claim what can run now, so waiting jobs stay queued
+            let slot = slots
+                .clone()
+                .acquire_owned()
+                .await
+                .expect("Job semaphore closed");
+            let claimed = match pool.get().await {
+                Ok(mut conn) => claim(&mut conn).await,
+                Err(e) => {
+                    log::error!("Job worker failed to get DB connection: {}", e);
+                    Ok(None)
+                }
+            };
+            match claimed {
+                Ok(Some(job)) => {
+                    let (pool, config) = (pool.clone(), config.clone());
+                    let (events, blocking) = (events.clone(), blocking.clone());
+                    let (task, handle) = future::abortable(run(
+                        job.clone(),
+                        pool.clone(),
+                        config,
+                        events.clone(),
+                        blocking,
+                    ));
+                    RUNNING.lock().unwrap().insert(job.id, handle);
+                    // Spawned on its own so a panic ends up here as an error
+                    let task = tokio::spawn(task);
+                    tokio::spawn(async move {
+                        let result = task.await;
+                        RUNNING.lock().unwrap().remove(&job.id);
+                        match result {
+                            Ok(Ok(())) => {}
+                            Ok(Err(_aborted)) => discard(&job, &pool).await,
+                            Err(e) => crashed(&job, &pool, &events, e).await,
+                        }
+                        drop(slot);
+                    });
+                }
+                Ok(None) => {
+                    tokio::select! {
+                        _ = wake.notified() => {}
+                        _ = tokio::time::sleep(POLL_INTERVAL) => {}
+                    }
+                }
+                Err(e) => {
+                    log::error!("Failed to claim processing job: {}", e);
+                    tokio::time::sleep(POLL_INTERVAL).await;
+                }
+            }
+        }
+    });
+}
+
+/// Renews the leases of the jobs this process runs a few times per
+/// `worker.lease_secs`, and takes over those of workers that stopped
+fn start_leases(pool: web::Data<DbPool>, config: Arc<AppConfig>) {
+    let lease = Duration::from_secs(config.worker.lease_secs);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(lease / 4);
+        let mut renewals = 0u32;
+        loop {
+            interval.tick().await;
+            let mut conn = match pool.get().await {
+                Ok(conn) => conn,
+                Err(e) => {
+                    log::error!("Job leases failed to get DB connection: {}", e);
+                    continue;
+                }
+            };
+            if let Err(e) = renew_leases(&mut conn).await {
+                log::error!("Failed to renew job leases: {}", e);
+            }
+            // In case the cancellation's notification was lost, or the lease
+            // ran out before it could be renewed
+            if let Err(e) = abort_lost(&mut conn).await {
+                log::error!("Failed to check for lost jobs: {}", e);
+            }
+            if renewals.is_multiple_of(4) {
+                if let Err(e) = recovery::requeue_expired(&mut conn, lease).await {
+                    log::error!("Failed to take over expired jobs: {}", e);
+                }
+            }
+            renewals = renewals.wrapping_add(1);
+        }
+    });
+}
+
+/// Renews the leases of the jobs in `RUNNING` only, so one whose task ended
+/// without recording a result lapses and is taken over
+async fn renew_leases(conn: &mut AsyncPgConnection) -> QueryResult<usize> {
+    use crate::db::schema::processing_jobs;
+
+    let running: Vec<Uuid> = RUNNING.lock().unwrap().keys().copied().collect();
+    if running.is_empty() {
+        return Ok(0);
+    }
+    diesel::update(processing_jobs::table)
+        .filter(processing_jobs::id.eq_any(&running))
+        .filter(processing_jobs::state.eq("running"))
+        .filter(processing_jobs::worker_id.eq(*WORKER_ID))
+        .set(processing_jobs::heartbeat_at.eq(Utc::now().naive_utc()))
+        .execute(conn)
+        .await
+}
+
+/// Aborts job `job_id` if this process runs it
+fn abort(job_id: Uuid) {
+    if let Some(handle) = RUNNING.lock().unwrap().get(&job_id) {
+        log::info!("Aborting job {}", job_id);
+        handle.abort();
+    }
+}
+
+/// Aborts the jobs this process runs that are no longer leased to it:
+/// canceled, or taken over by another worker after the lease expired, e.g.
+/// while this process couldn't reach the database
+async fn abort_lost(conn: &mut AsyncPgConnection) -> QueryResult<()> {
+    use crate::db::schema::processing_jobs;
+
+    let running: Vec<Uuid> = RUNNING.lock().unwrap().keys().copied().collect();
+    if running.is_empty() {
+        return Ok(());
+    }
+    let leased = processing_jobs::table
+        .filter(processing_jobs::id.eq_any(&running))
+        .filter(processing_jobs::state.eq("running"))
+        .filter(processing_jobs::worker_id.eq(*WORKER_ID))
+        .select(processing_jobs::id)
+        .load::<Uuid>(conn)
+        .await?;
+    running
+        .into_iter()
+        .filter(|job_id| !leased.contains(job_id))
+        .for_each(abort);
+    Ok(())
+}
+
+/// Removes the partial output of an aborted job, whose processes may have
+/// written more since it was canceled. A job that was taken over is left
+/// alone, as its output belongs to the worker running it now, and so is one
+/// that finished just before it was aborted.
+async fn discard(job: &ProcessingJob, pool: &DbPool) {
+    use crate::db::schema::processing_jobs;
+
+    let reset = match pool.get().await {
+        Ok(mut conn) => {
+            let state = processing_jobs::table
+                .find(job.id)
+                .select(processing_jobs::state)
+                .first::<String>(&mut conn)
+                .await
+                .optional();
+            match state {
+                Ok(Some(state)) if state == "canceled" => recovery::reset_job(&mut conn, job).await,
+                Ok(_) => Ok(()),
+                Err(e) => Err(e.into()),
+            }
+        }
+        Err(e) => Err(e.into()),
+    };
+    if let Err(e) = reset {
+        log::error!("Failed to remove output of canceled job {}: {}", job.id, e);
+    }
+}
+
+/// Fails a job whose task panicked, and its video if the step was one that
+/// fails the video, rather than leaving them running for good
+async fn crashed(
+    job: &ProcessingJob,
+    pool: &DbPool,
+    events: &ProgressEvents,
+    e: tokio::task::JoinError,
+) {
+    log::error!("Job {} panicked: {}", job.id, e);
+    let mut conn = match pool.get().await {
+        Ok(conn) => conn,
+        Err(db_err) => {
+            // Its lease is no longer renewed, so it's queued again
+            log::error!("Failed to record the failure of job {}: {}", job.id, db_err);
+            return;
+        }
+    };
+    finish(&mut conn, job.id, Some("The job crashed".to_string())).await;
+    if let Some(v_id) = job.video_id {
+        if matches!(job.stage.as_deref().unwrap_or(PROBE), PROBE | FINALIZE) {
+            let error = anyhow::anyhow!("Job {} crashed", job.id);
+            fail_video(&mut conn, events, v_id, &error).await;
+        }
+    }
+}
+
+async fn run(
+    job: ProcessingJob,
+    pool: web::Data<DbPool>,
+    config: Arc<AppConfig>,
+    events: web::Data<ProgressEvents>,
+    blocking: web::Data<BlockingPool>,
+) {
+    let mut conn = match pool.get().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            // Left running without a lease, so another worker takes it over
+            log::error!("Job {} failed to get DB connection: {}", job.id, e);
+            return;
+        }
+    };
+    let targets = (
+        job.video_id,
+        job.conversion_id,
+        job.frame_extraction_id,
+        job.chunk_encode_id,
+    );
+    let result = match targets {
+        (Some(v_id), _, _, _) => {
+            let stage = job.stage.as_deref().unwrap_or(PROBE);
+            log::info!("Processing video {}: {} (job {})", v_id, stage, job.id);
+            process(&job, v_id, &mut conn, &pool, &config, &events, &blocking).await
+        }
+        (None, Some(c_id), _, _) => {
+            log::info!("Running conversion {} (job {})", c_id, job.id);
+            let result = convert::run(c_id, &mut conn, &config.ffmpeg, &config.scan).await;
+            if let Err(e) = &result {
+                log::error!("Error running conversion {}: {}", c_id, e);
+            }
+            result
+        }
+        (None, None, Some(f_id), _) => {
+            log::info!("Extracting frames {} (job {})", f_id, job.id);
+            let result = frames::run(f_id, &mut conn, &config.ffmpeg, &blocking).await;
+            if let Err(e) = &result {
+                log::error!("Error extracting frames {}: {}", f_id, e);
+            }
+            result
+        }
+        (None, None, None, Some(ce_id)) => {
+            log::info!("Encoding chunk {} (job {})", ce_id, job.id);
+            let result = chunks::encode(ce_id, &mut conn, &config.ffmpeg, |_| {}).await;
+            if let Err(e) = &result {
+                log::error!("Error encoding chunk {}: {}", ce_id, e);
+            }
+            result
+        }
+        (None, None, None, None) => Err(anyhow::anyhow!("Job has no target")),
+    };
+    finish(&mut conn, job.id, result.err().map(|e| format!("{:#}", e))).await;
+}
+
+/// Runs a step of a video's processing. The video fails with its probe or
+/// finalize step; a failed rendition only fails the rendition.
+async fn process(
+    job: &ProcessingJob,
+    v_id: Uuid,
+    conn: &mut AsyncPgConnection,
+    pool: &DbPool,
+    config: &AppConfig,
+    events: &ProgressEvents,
+    blocking: &BlockingPool,
+) -> anyhow::Result<()> {
+    // Renditions and thumbnails are left to finalize to fail the video
+    let stage = job.stage.as_deref().unwrap_or(PROBE);
+    let result = match stage {
+        PROBE => match video_processor::plan_video(v_id, conn, config).await {
+            Ok(plan) => enqueue_steps(conn, job, v_id, &plan)
+                .await
+                .map_err(anyhow::Error::from),
+            Err(e) => Err(e),
+        },
+        RENDITION => {
+            let quality = job.quality.as_deref().unwrap_or_default();
+            return video_processor::encode_quality(
+                v_id, quality, conn, pool, config, events, blocking,
+            )
+            .await;
+        }
+        LADDER => {
+            return video_processor::encode_ladder(v_id, conn, config, events, blocking).await;
+        }
+        THUMBNAILS => {
+            let generated = video_processor::generate_extras(v_id, config, events).await;
+            if let Err(e) = &generated {
+                log::error!("Error generating thumbnails of video {}: {:#}", v_id, e);
+            }
+            return generated;
+        }
+        FINALIZE => {
+            let thumbnails_error = dependency_error(conn, job.id, THUMBNAILS).await?;
+            video_processor::finalize_video(v_id, conn, config, thumbnails_error).await
+        }
+        stage => Err(anyhow::anyhow!("Unknown processing step {}", stage)),
+    };
+    if stage == PROBE && result.is_ok() {
+        return result;
+    }
+
+    // Kept only for a rerun after a crash, which doesn't get here
+    if let Err(e) = chunks::remove(conn, v_id).await {
+        log::error!("Failed to remove chunks of video {}: {}", v_id, e);
+    }
+    match &result {
+        Ok(()) => events.publish(v_id, ProgressEvent::Done),
+        Err(e) => fail_video(conn, events, v_id, e).await,
+    }
+    result
+}
+
+/// The error of the `stage` job `job_id` depends on, if it failed
+async fn dependency_error(
+    conn: &mut AsyncPgConnection,
+    job_id: Uuid,
+    stage: &str,
+) -> QueryResult<Option<String>> {
+    use crate::db::schema::{job_dependencies, processing_jobs};
+
+    let failed = processing_jobs::table
+        .inner_join(
+            job_dependencies::table.on(job_dependencies::depends_on.eq(processing_jobs::id)),
+        )
+        .filter(job_dependencies::job_id.eq(job_id))
+        .filter(processing_jobs::stage.eq(stage))
+        .filter(processing_jobs::state.eq("failed"))
+        .select(processing_jobs::error)
+        .first::<Option<String>>(conn)
+        .await
+        .optional()?;
+    Ok(failed.map(|error| error.unwrap_or_else(|| format!("The {} step failed", stage))))
+}
+
+async fn fail_video(
+    conn: &mut AsyncPgConnection,
+    events: &ProgressEvents,
+    v_id: Uuid,
+    e: &anyhow::Error,
+) {
+    use crate::db::schema::videos;
+
+    log::error!("Error processing video {}: {:#}", v_id, e);
+    let failure = video_processor::ProcessingFailure::of(e);
+    events.publish(
+        v_id,
+        ProgressEvent::Failed {
+            reason: failure.reason.to_string(),
+        },
+    );
+    // Unless it was canceled meanwhile
+    match diesel::update(videos::table)
+        .filter(videos::id.eq(v_id))
+        .filter(videos::status.eq("processing"))
+        .set((
+            videos::status.eq("failed"),
+            videos::status_reason.eq(failure.reason),
+            videos::error_code.eq(failure.code.as_str()),
+        ))
+        .execute(conn)
+        .await
+    {
+        Ok(_) => webhooks::notify(conn, v_id).await,
+        Err(db_err) => log::error!("Error updating video status: {}", db_err),
+    }
+}
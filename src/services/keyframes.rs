@@ -0,0 +1,97 @@
+// src/services/keyframes.rs
+use crate::config::app_config::FfmpegConfig;
+use crate::services::{ffmpeg, video_processor};
+use anyhow::{Context, Result};
+use std::path::Path;
+use tokio::fs;
+use vid_storage_models::Keyframes;
+
+/// Written next to the original once analysed
+const INDEX_FILE: &str = "keyframes.json";
+/// Minimum scene score (0 to 1) counted as a cut
+const SCENE_THRESHOLD: f64 = 0.4;
+
+/// Analyses the original and stores the result for `load`. Part of
+/// processing, but not needed for playback, so callers only log failures.
+pub async fn write_index(
+    video_dir: &Path,
+    input: &Path,
+    ffmpeg: &FfmpegConfig,
+) -> Result<Keyframes> {
+    let index = analyze(input, ffmpeg).await?;
+    fs::write(video_dir.join(INDEX_FILE), serde_json::to_vec(&index)?).await?;
+    Ok(index)
+}
+
+/// The stored analysis, or a fresh one for videos processed before it was
+/// added
+pub async fn load(video_dir: &Path, ffmpeg: &FfmpegConfig) -> Result<Keyframes> {
+    match fs::read(video_dir.join(INDEX_FILE)).await {
+        Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let video_dir = fs::canonicalize(video_dir).await?;
+            let input = video_processor::find_original(&video_dir).await?;
+            write_index(&video_dir, &input, ffmpeg).await
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn analyze(input: &Path, ffmpeg: &FfmpegConfig) -> Result<Keyframes> {
+    let work_dir = input.parent().context("Invalid video path")?;
+
+    // Only keyframes are decoded, so this is quick even for long videos
+    let output = ffmpeg::command("ffprobe", ffmpeg, work_dir)
+        .args(ffmpeg::input_protocol_args())
+        .args([
+            "-v",
+            "error",
+            "-select_streams",
+            "v:0",
+            "-skip_frame",
+            "nokey",
+        ])
+        .args(["-show_entries", "frame=pts_time", "-of", "csv=p=0"])
+        .arg(input)
+        .output()
+        .await?;
+    if !output.status.success() {
+        anyhow::bail!("ffprobe keyframe listing failed");
+    }
+    let keyframes = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.trim().trim_end_matches(',').parse::<f64>().ok())
+        .collect();
+
+    let output = ffmpeg::command("ffmpeg", ffmpeg, work_dir)
+        .args(ffmpeg::input_protocol_args())
+        .arg("-i")
+        .arg(input)
+        .arg("-vf")
+        .arg(format!(
+            "select='gt(scene,{})',metadata=print:file=-",
+            SCENE_THRESHOLD
+        ))
+        .args(["-an", "-f", "null", "-loglevel", "quiet", "-"])
+        .output()
+        .await?;
+    if !output.status.success() {
+        anyhow::bail!("FFmpeg scene detection failed");
+    }
+    // Lines look like `frame:12  pts:6144  pts_time:0.48`
+    let scene_changes = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split_once("pts_time:"))
+        .filter_map(|(_, time)| time.trim().parse::<f64>().ok())
+        .collect();
+
+    let mut index = Keyframes {
+        keyframes,
+        scene_changes,
+    };
+    for times in [&mut index.keyframes, &mut index.scene_changes] {
+        times.sort_by(f64::total_cmp);
+        times.dedup();
+    }
+    Ok(index)
+}
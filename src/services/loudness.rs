@@ -0,0 +1,144 @@
+// src/services/loudness.rs
+use crate::config::app_config::{FfmpegConfig, LoudnessConfig};
+use crate::db::models::LoudnessMeasurement;
+use crate::services::ffmpeg;
+use anyhow::{Context, Result};
+use chrono::Utc;
+use diesel::upsert::excluded;
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::Deserialize;
+use std::path::Path;
+use uuid::Uuid;
+
+/// Rate audio is resampled to after normalization, as loudnorm works at
+/// 192 kHz
+const OUTPUT_SAMPLE_RATE: u32 = 48_000;
+
+/// The summary loudnorm prints with `print_format=json`; values are strings
+/// and are "-inf" for silence
+#[derive(Deserialize)]
+struct Summary {
+    input_i: String,
+    input_tp: String,
+    input_lra: String,
+    input_thresh: String,
+    target_offset: String,
+}
+
+/// Runs loudnorm's analysis pass over the whole of the source's `track`-th
+/// audio stream
+pub async fn measure(
+    v_id: Uuid,
+    input: &Path,
+    track: i32,
+    config: &LoudnessConfig,
+    ffmpeg: &FfmpegConfig,
+) -> Result<LoudnessMeasurement> {
+    let output = ffmpeg::command("ffmpeg", ffmpeg, input.parent().unwrap())
+        .args(ffmpeg::input_protocol_args())
+        .arg("-i")
+        .arg(input)
+        .arg("-map")
+        .arg(format!("0:a:{}", track))
+        .arg("-af")
+        .arg(format!(
+            "loudnorm=I={}:TP={}:LRA={}:print_format=json",
+            config.target_lufs, config.true_peak, config.loudness_range
+        ))
+        .args(["-vn", "-sn", "-hide_banner", "-nostats", "-f", "null", "-"])
+        .output()
+        .await?;
+    if !output.status.success() {
+        anyhow::bail!("FFmpeg loudness measurement failed");
+    }
+
+    // The summary is the last thing written to stderr
+    let log = String::from_utf8_lossy(&output.stderr);
+    let summary = log
+        .rfind('{')
+        .and_then(|start| Some(&log[start..=start + log[start..].find('}')?]))
+        .context("No loudness summary in the FFmpeg output")?;
+    let summary: Summary = serde_json::from_str(summary)?;
+    let value = |value: &str| -> Result<f64> {
+        let value = value
+            .trim()
+            .parse::<f64>()
+            .context("Invalid loudness value")?;
+        if !value.is_finite() {
+            anyhow::bail!("The audio is silent");
+        }
+        Ok(value)
+    };
+
+    Ok(LoudnessMeasurement {
+        video_id: v_id,
+        integrated_lufs: value(&summary.input_i)?,
+        true_peak_dbtp: value(&summary.input_tp)?,
+        loudness_range_lu: value(&summary.input_lra)?,
+        threshold_lufs: value(&summary.input_thresh)?,
+        target_offset_lu: value(&summary.target_offset)?,
+        created_at: Utc::now().naive_utc(),
+    })
+}
+
+/// Stores a measurement, replacing any from an earlier processing run
+pub async fn save(conn: &mut AsyncPgConnection, measurement: &LoudnessMeasurement) -> Result<()> {
+    use crate::db::schema::loudness_measurements::dsl::*;
+
+    diesel::insert_into(loudness_measurements)
+        .values(measurement)
+        .on_conflict(video_id)
+        .do_update()
+        .set((
+            integrated_lufs.eq(excluded(integrated_lufs)),
+            true_peak_dbtp.eq(excluded(true_peak_dbtp)),
+            loudness_range_lu.eq(excluded(loudness_range_lu)),
+            threshold_lufs.eq(excluded(threshold_lufs)),
+            target_offset_lu.eq(excluded(target_offset_lu)),
+            created_at.eq(excluded(created_at)),
+        ))
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// The measurement stored for the video, if its loudness could be measured
+pub async fn load(conn: &mut AsyncPgConnection, v_id: Uuid) -> Result<Option<LoudnessMeasurement>> {
+    use crate::db::schema::loudness_measurements::dsl::*;
+
+    Ok(loudness_measurements
+        .filter(video_id.eq(v_id))
+        .first::<LoudnessMeasurement>(conn)
+        .await
+        .optional()?)
+}
+
+/// Drops the measurement of an earlier processing run, so a failed one
+/// leaves the audio as it is
+pub async fn remove(conn: &mut AsyncPgConnection, v_id: Uuid) -> Result<()> {
+    use crate::db::schema::loudness_measurements::dsl::*;
+
+    diesel::delete(loudness_measurements.filter(video_id.eq(v_id)))
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// The second, linear loudnorm pass applying a measurement, followed by
+/// resampling back to a rate AAC encoders take
+pub fn filter(measurement: &LoudnessMeasurement, config: &LoudnessConfig) -> String {
+    format!(
+        "loudnorm=I={}:TP={}:LRA={}:measured_I={}:measured_TP={}:measured_LRA={}:\
+measured_thresh={}:offset={}:linear=true,aresample={}",
+        config.target_lufs,
+        config.true_peak,
+        config.loudness_range,
+        measurement.integrated_lufs,
+        measurement.true_peak_dbtp,
+        measurement.loudness_range_lu,
+        measurement.threshold_lufs,
+        measurement.target_offset_lu,
+        OUTPUT_SAMPLE_RATE
+    )
+}
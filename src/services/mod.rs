@@ -1 +1,37 @@
+pub mod audio_tracks;
+pub mod blocking;
+pub mod channels;
+pub mod chunks;
+pub mod clips;
+pub mod codecs;
+pub mod convert;
+pub mod dedup;
+pub mod entitlement;
+pub mod events;
+pub mod ffmpeg;
+pub mod frames;
+pub mod grants;
+pub mod import;
+pub mod integrity;
+pub mod jobs;
+pub mod keyframes;
+pub mod loudness;
+pub mod notifications;
+pub mod outbound;
+pub mod per_title;
+pub mod playlist;
+pub mod preview;
+pub mod probe;
+pub mod profiles;
+pub mod recovery;
+pub mod remux;
+pub mod scan;
+pub mod segment_cache;
+pub mod stalled;
+pub mod storage;
+pub mod storyboard;
+pub mod subtitles;
+pub mod validator;
 pub mod video_processor;
+pub mod watermark;
+pub mod webhooks;
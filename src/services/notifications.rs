@@ -0,0 +1,112 @@
+// src/services/notifications.rs
+use crate::db::DbPool;
+use crate::services::events::{ProgressEvent, ProgressEvents};
+use actix_web::web;
+use anyhow::Result;
+use diesel::sql_types::Text;
+use diesel_async::RunQueryDsl;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_postgres::{AsyncMessage, NoTls};
+use uuid::Uuid;
+
+/// Sent by a trigger on `processing_jobs` when a job may be ready to claim
+pub const JOBS_CHANNEL: &str = "processing_jobs";
+/// Sent by a trigger on `processing_jobs` with the id of a running job that
+/// was canceled
+pub const CANCEL_CHANNEL: &str = "processing_cancels";
+/// Progress of the jobs worker processes run, for the API nodes to publish
+const PROGRESS_CHANNEL: &str = "progress_events";
+/// Wait before a listener whose connection dropped connects again
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Serialize, Deserialize)]
+struct RelayedEvent {
+    video_id: Uuid,
+    event: ProgressEvent,
+}
+
+/// Calls `on_notification` with the payload of every notification on
+/// `channel`, in the background. Notifications sent while its connection is
+/// down are lost, so nothing may rely on them alone.
+pub fn listen(
+    url: &str,
+    channel: &'static str,
+    on_notification: impl Fn(&str) + Send + Sync + 'static,
+) {
+    let url = url.to_string();
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = receive(&url, channel, &on_notification).await {
+                log::error!("Stopped listening on {}: {}", channel, e);
+            }
+            tokio::time::sleep(RECONNECT_DELAY).await;
+        }
+    });
+}
+
+async fn receive(url: &str, channel: &str, on_notification: &impl Fn(&str)) -> Result<()> {
+    let (client, mut connection) = tokio_postgres::connect(url, NoTls).await?;
+    // The connection only runs the LISTEN while its messages are polled
+    let mut messages = futures::stream::poll_fn(move |cx| connection.poll_message(cx));
+    let statement = format!("LISTEN {}", channel);
+    let listen = client.batch_execute(&statement);
+    tokio::pin!(listen);
+    let mut listening = false;
+    loop {
+        tokio::select! {
+            result = &mut listen, if !listening => {
+                result?;
+                listening = true;
+            }
+            message = messages.next() => match message {
+                Some(Ok(AsyncMessage::Notification(notification))) => {
+                    on_notification(notification.payload())
+                }
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(e.into()),
+                None => anyhow::bail!("Connection closed"),
+            },
+        }
+    }
+}
+
+/// Progress events of a worker process, which it passes on to the API nodes
+/// in the background for `receive_events` to publish there
+pub fn relay_events(pool: web::Data<DbPool>) -> ProgressEvents {
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        while let Some((video_id, event)) = receiver.recv().await {
+            let payload = serde_json::to_string(&RelayedEvent { video_id, event })
+                .expect("Progress events serialize");
+            let sent = match pool.get().await {
+                Ok(mut conn) => diesel::sql_query("SELECT pg_notify($1, $2)")
+                    .bind::<Text, _>(PROGRESS_CHANNEL)
+                    .bind::<Text, _>(payload)
+                    .execute(&mut conn)
+                    .await
+                    .map_err(anyhow::Error::from),
+                Err(e) => Err(e.into()),
+            };
+            // Progress isn't worth holding up the job for
+            if let Err(e) = sent {
+                log::warn!("Failed to relay progress of video {}: {}", video_id, e);
+            }
+        }
+    });
+    ProgressEvents::relaying(sender)
+}
+
+/// Publishes the progress worker processes relay to this node's subscribers
+pub fn receive_events(url: &str, events: web::Data<ProgressEvents>) {
+    listen(
+        url,
+        PROGRESS_CHANNEL,
+        move |payload| match serde_json::from_str::<RelayedEvent>(payload) {
+            Ok(relayed) => events.publish(relayed.video_id, relayed.event),
+            Err(e) => log::warn!("Ignoring relayed progress {:?}: {}", payload, e),
+        },
+    );
+}
@@ -0,0 +1,181 @@
+// src/services/outbound.rs
+use anyhow::{anyhow, Result};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::redirect::{self, Policy};
+use reqwest::Url;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Redirects followed before a request is given up
+const MAX_REDIRECTS: usize = 10;
+
+/// Set from `outbound.allow_private_networks` at startup
+static ALLOW_PRIVATE: AtomicBool = AtomicBool::new(false);
+
+/// Applies the `outbound` config to every client made afterwards
+pub fn init(config: &crate::config::app_config::OutboundConfig) {
+    ALLOW_PRIVATE.store(config.allow_private_networks, Ordering::Relaxed);
+}
+
+fn allow_private() -> bool {
+    ALLOW_PRIVATE.load(Ordering::Relaxed)
+}
+
+/// Whether `ip` is a public unicast address, rather than e.g. loopback, a
+/// private network, link-local (which includes cloud metadata endpoints) or
+/// reserved. IPv4 addresses embedded in IPv6 are judged as IPv4.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => {
+            if let Some(v4) = ip.to_ipv4_mapped() {
+                return is_public_v4(v4);
+            }
+            let segments = ip.segments();
+            // NAT64 (64:ff9b::/96) reaches the IPv4 address in the low bits
+            if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+                let [.., a, b, c, d] = ip.octets();
+                return is_public_v4(Ipv4Addr::new(a, b, c, d));
+            }
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_multicast()
+                || is_in_v6(segments[0], 0xfe00, 0xfc00) // unique local
+                || is_in_v6(segments[0], 0xffc0, 0xfe80) // link-local
+                || is_in_v6(segments[0], 0xffc0, 0xfec0) // site-local
+                || (segments[0] == 0x2001 && segments[1] == 0xdb8)) // documentation
+        }
+    }
+}
+
+fn is_in_v6(segment: u16, mask: u16, prefix: u16) -> bool {
+    segment & mask == prefix
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || ip.is_documentation()
+        || a == 0
+        || a >= 240
+        || (a == 100 && (64..128).contains(&b)) // carrier-grade NAT
+        || (a == 192 && b == 0 && ip.octets()[2] == 0) // IETF protocol assignments
+        || (a == 198 && (18..20).contains(&b))) // benchmarking
+}
+
+/// Checks a user-supplied URL before anything is sent to it: http or https,
+/// and not an address literal on a private network. Host names are checked
+/// once resolved, by the clients of `client`.
+pub fn check_url(url: &Url) -> Result<()> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(anyhow!("Only http and https URLs are allowed"));
+    }
+    let host = url
+        .host_str()
+        .ok_or_else(|| anyhow!("The URL has no host"))?;
+    // Address literals come normalized, IPv6 ones in brackets
+    let Ok(ip) = host.trim_start_matches('[').trim_end_matches(']').parse() else {
+        return Ok(());
+    };
+    if !allow_private() && !is_public(ip) {
+        return Err(anyhow!("The URL's host is not a public address"));
+    }
+    Ok(())
+}
+
+/// Resolves host names like the system resolver, leaving out addresses that
+/// aren't public. Since every connection resolves through it, including
+/// those of redirects, a name can't point at the internal network later.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0))
+                .await?
+                .filter(|addr| is_public(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("{} has no public address", host).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// A client for user-supplied URLs that gives up after `timeout` in total,
+/// and only connects to public addresses unless private networks are
+/// allowed, on every redirect too
+pub fn client(timeout: Duration) -> reqwest::Result<reqwest::Client> {
+    let redirects = Policy::custom(|attempt: redirect::Attempt| {
+        if attempt.previous().len() >= MAX_REDIRECTS {
+            return attempt.error("Too many redirects");
+        }
+        match check_url(attempt.url()) {
+            Ok(()) => attempt.follow(),
+            Err(e) => attempt.error(e.to_string()),
+        }
+    });
+    let builder = reqwest::Client::builder()
+        .timeout(timeout)
+        .redirect(redirects);
+    if allow_private() {
+        return builder.build();
+    }
+    builder.dns_resolver(Arc::new(PublicResolver)).build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn private_and_reserved_addresses_are_not_public() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.100.100.200",
+            "0.0.0.0",
+            "255.255.255.255",
+            "::1",
+            "::",
+            "fd00:ec2::254",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "64:ff9b::a9fe:a9fe",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn internet_addresses_are_public() {
+        for ip in [
+            "93.184.216.34",
+            "8.8.8.8",
+            "2606:4700::1111",
+            "::ffff:8.8.8.8",
+        ] {
+            assert!(is_public(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn address_literals_are_checked() {
+        assert!(check_url(&"http://169.254.169.254/latest".parse().unwrap()).is_err());
+        assert!(check_url(&"http://[::1]:8080/".parse().unwrap()).is_err());
+        assert!(check_url(&"ftp://example.com/video.mp4".parse().unwrap()).is_err());
+        assert!(check_url(&"https://example.com/video.mp4".parse().unwrap()).is_ok());
+    }
+}
@@ -0,0 +1,109 @@
+// src/services/per_title.rs
+use crate::config::app_config::{FfmpegConfig, RenditionConfig};
+use crate::services::{ffmpeg, probe, video_processor};
+use anyhow::{Context, Result};
+use std::path::Path;
+use tokio::fs;
+
+/// Constant quality of the test encodes, about where x264 looks transparent
+const REFERENCE_CRF: &str = "23";
+/// Seconds encoded per sample
+const SAMPLE_SECS: f64 = 5.0;
+/// Where samples are taken, as fractions of the duration, so intros and
+/// credits don't decide alone
+const SAMPLE_POSITIONS: [f64; 3] = [0.2, 0.5, 0.8];
+/// Lowest fraction of its configured bitrate a rung is given, as samples
+/// can miss a video's most complex scenes
+const MIN_SCALE: f64 = 0.3;
+/// Written next to the original and removed after measuring
+const SAMPLE_FILE: &str = "per_title_sample.mkv";
+
+/// The ladder with every bitrate scaled by what a constant-quality encode of
+/// samples at the top rung's size needs, compared to that rung's configured
+/// bitrate. Bitrates are only ever lowered.
+pub async fn scale_ladder(
+    input: &Path,
+    info: &probe::MediaInfo,
+    ladder: &[&RenditionConfig],
+    ffmpeg: &FfmpegConfig,
+    work_dir: &Path,
+) -> Result<Vec<RenditionConfig>> {
+    let top = ladder.first().context("The ladder is empty")?;
+    let configured = video_processor::parse_bitrate(&top.video_bitrate)? as f64;
+
+    let sample = work_dir.join(SAMPLE_FILE);
+    let needed = sample_bitrate(input, info, top, ffmpeg, &sample).await;
+    let _ = fs::remove_file(&sample).await;
+    let scale = (needed? / configured).clamp(MIN_SCALE, 1.0);
+
+    ladder
+        .iter()
+        .map(|&rendition| {
+            let bitrate = video_processor::parse_bitrate(&rendition.video_bitrate)? as f64;
+            let kbps = (bitrate * scale / 1000.0).round().max(1.0);
+            Ok(RenditionConfig {
+                video_bitrate: format!("{}k", kbps),
+                ..rendition.clone()
+            })
+        })
+        .collect()
+}
+
+/// Average bits per second of the samples encoded at `REFERENCE_CRF`
+async fn sample_bitrate(
+    input: &Path,
+    info: &probe::MediaInfo,
+    top: &RenditionConfig,
+    ffmpeg: &FfmpegConfig,
+    sample: &Path,
+) -> Result<f64> {
+    let duration = info.duration().context("Unknown duration")?;
+    let dimensions = video_processor::output_dimensions(top, info)
+        .context("Rendition has no valid resolution")?;
+    let samples: Vec<(f64, f64)> = if duration <= SAMPLE_SECS * SAMPLE_POSITIONS.len() as f64 {
+        vec![(0.0, duration)]
+    } else {
+        SAMPLE_POSITIONS
+            .iter()
+            .map(|position| (duration * position, SAMPLE_SECS))
+            .collect()
+    };
+
+    let (mut bytes, mut seconds) = (0, 0.0);
+    for (start, length) in samples {
+        let status = ffmpeg::command("ffmpeg", ffmpeg, sample.parent().unwrap())
+            .args(ffmpeg::input_protocol_args())
+            .arg("-ss")
+            .arg(format!("{:.3}", start))
+            .arg("-t")
+            .arg(format!("{:.3}", length))
+            .arg("-i")
+            .arg(input)
+            .arg("-an")
+            .arg("-vf")
+            .arg(video_processor::scale_filter(dimensions))
+            .args([
+                "-c:v",
+                "libx264",
+                "-preset",
+                "veryfast",
+                "-crf",
+                REFERENCE_CRF,
+            ])
+            .arg("-threads")
+            .arg(ffmpeg.thread_count.to_string())
+            .args(["-loglevel", "quiet", "-y"])
+            .arg(sample)
+            .status()
+            .await?;
+        if !status.success() {
+            anyhow::bail!("Per-title test encode failed");
+        }
+        bytes += fs::metadata(sample).await?.len();
+        seconds += length;
+    }
+    if seconds <= 0.0 {
+        anyhow::bail!("Nothing to sample");
+    }
+    Ok(bytes as f64 * 8.0 / seconds)
+}
@@ -0,0 +1,563 @@
+// src/services/playlist.rs
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+
+/// A media segment of a VOD playlist
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub duration: f64,
+    pub uri: String,
+    /// `EXT-X-MAP` URI of fMP4 segments
+    pub map: Option<String>,
+}
+
+/// A variant stream of a master playlist
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variant {
+    /// `EXT-X-STREAM-INF` attributes, with quoted values unquoted
+    pub attributes: Vec<(String, String)>,
+    pub uri: String,
+}
+
+impl Variant {
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        find_attribute(&self.attributes, name)
+    }
+}
+
+/// An alternative rendition of a master playlist, i.e. an `EXT-X-MEDIA`
+/// tag, such as an audio track or subtitles
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rendition {
+    pub attributes: Vec<(String, String)>,
+}
+
+impl Rendition {
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        find_attribute(&self.attributes, name)
+    }
+}
+
+fn find_attribute<'a>(attributes: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    attributes
+        .iter()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.as_str())
+}
+
+/// Splits an attribute list such as `BANDWIDTH=800000,CODECS="avc1,mp4a"`,
+/// unquoting quoted values
+pub fn attributes(list: &str) -> Vec<(String, String)> {
+    let mut attributes = Vec::new();
+    let mut rest = list;
+    while let Some((name, tail)) = rest.split_once('=') {
+        let (value, tail) = match tail.strip_prefix('"') {
+            Some(quoted) => {
+                let (value, tail) = quoted.split_once('"').unwrap_or((quoted, ""));
+                (value, tail.strip_prefix(',').unwrap_or(tail))
+            }
+            None => tail.split_once(',').unwrap_or((tail, "")),
+        };
+        attributes.push((name.trim().to_string(), value.to_string()));
+        rest = tail;
+    }
+    attributes
+}
+
+/// A media playlist, as parsed or to write, often a window onto a longer
+/// stream
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct MediaPlaylist {
+    /// Media sequence number of the first segment
+    pub media_sequence: u64,
+    /// Discontinuities before the first segment, including one right before
+    /// it, which is why the first segment's flag is never written
+    pub discontinuity_sequence: u64,
+    /// Longest segment duration the stream ever has, rounded up
+    pub target_duration: u64,
+    /// Each with whether a discontinuity precedes it
+    pub segments: Vec<(Segment, bool)>,
+    /// Wall-clock time the first segment starts at. It is written as
+    /// `EXT-X-PROGRAM-DATE-TIME` there and after every discontinuity, the
+    /// later times following from the segment durations.
+    pub program_date_time: Option<DateTime<Utc>>,
+    /// Whether the stream is complete, as for VOD; live windows aren't
+    pub ended: bool,
+}
+
+/// Parses a media playlist. URIs are left as they are, i.e. usually relative
+/// to the playlist. Tags this module doesn't write are skipped.
+pub fn parse_media(text: &str) -> Result<MediaPlaylist> {
+    let mut lines = text.lines().map(str::trim);
+    if lines.next() != Some("#EXTM3U") {
+        anyhow::bail!("Playlist doesn't start with #EXTM3U");
+    }
+    let number = |tag: &str, value: &str| {
+        value
+            .parse::<u64>()
+            .with_context(|| format!("Invalid {} {:?}", tag, value))
+    };
+
+    let mut playlist = MediaPlaylist::default();
+    let mut target_duration = None;
+    let mut duration = None;
+    let mut discontinuity = false;
+    let mut map = None;
+    for line in lines {
+        if let Some(value) = line.strip_prefix("#EXT-X-TARGETDURATION:") {
+            target_duration = Some(number("target duration", value)?);
+        } else if let Some(value) = line.strip_prefix("#EXT-X-MEDIA-SEQUENCE:") {
+            playlist.media_sequence = number("media sequence", value)?;
+        } else if let Some(value) = line.strip_prefix("#EXT-X-DISCONTINUITY-SEQUENCE:") {
+            playlist.discontinuity_sequence = number("discontinuity sequence", value)?;
+        } else if line == "#EXT-X-DISCONTINUITY" {
+            discontinuity = true;
+        } else if line == "#EXT-X-ENDLIST" {
+            playlist.ended = true;
+        } else if let Some(value) = line.strip_prefix("#EXT-X-PROGRAM-DATE-TIME:") {
+            // Later ones follow from the durations
+            if playlist.segments.is_empty() {
+                let time = DateTime::parse_from_rfc3339(value)
+                    .with_context(|| format!("Invalid program date time {:?}", value))?;
+                playlist.program_date_time = Some(time.with_timezone(&Utc));
+            }
+        } else if let Some(info) = line.strip_prefix("#EXTINF:") {
+            let value = info.split(',').next().unwrap_or_default();
+            duration = Some(
+                value
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|duration| duration.is_finite() && *duration >= 0.0)
+                    .with_context(|| format!("Invalid segment duration {:?}", value))?,
+            );
+        } else if let Some(list) = line.strip_prefix("#EXT-X-MAP:") {
+            map = attributes(list)
+                .into_iter()
+                .find(|(name, _)| name == "URI")
+                .map(|(_, uri)| uri);
+        } else if !line.is_empty() && !line.starts_with('#') {
+            let segment = Segment {
+                duration: duration
+                    .take()
+                    .with_context(|| format!("Segment {} has no duration", line))?,
+                uri: line.to_string(),
+                map: map.clone(),
+            };
+            playlist
+                .segments
+                .push((segment, std::mem::take(&mut discontinuity)));
+        }
+    }
+    playlist.target_duration = target_duration.context("Playlist has no target duration")?;
+    Ok(playlist)
+}
+
+/// Variant streams of a master playlist, in order
+pub fn parse_variants(master: &str) -> Vec<Variant> {
+    let mut variants = Vec::new();
+    let mut stream_inf = None;
+    for line in master.lines().map(str::trim) {
+        if let Some(list) = line.strip_prefix("#EXT-X-STREAM-INF:") {
+            stream_inf = Some(attributes(list));
+        } else if !line.is_empty() && !line.starts_with('#') {
+            if let Some(attributes) = stream_inf.take() {
+                variants.push(Variant {
+                    attributes,
+                    uri: line.to_string(),
+                });
+            }
+        }
+    }
+    variants
+}
+
+/// Alternative renditions of a master playlist, in order
+pub fn parse_renditions(master: &str) -> Vec<Rendition> {
+    master
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("#EXT-X-MEDIA:"))
+        .map(|list| Rendition {
+            attributes: attributes(list),
+        })
+        .collect()
+}
+
+/// A master playlist without the variants whose short edge is above
+/// `max_height`, so portrait variants count by their width as quality labels
+/// do. Variants without a RESOLUTION stay, and if every variant is too large
+/// the smallest is kept so players still get a stream.
+pub fn limit_height(master: &str, max_height: u32) -> String {
+    retain_variants(master, max_height, |variant| {
+        let (width, height) = variant.attribute("RESOLUTION")?.split_once('x')?;
+        Some(width.parse::<u32>().ok()?.min(height.parse::<u32>().ok()?))
+    })
+}
+
+/// A master playlist without the variants whose BANDWIDTH is above
+/// `max_bandwidth`, keeping the smallest if none is below it
+pub fn limit_bandwidth(master: &str, max_bandwidth: u64) -> String {
+    retain_variants(master, max_bandwidth, |variant| {
+        variant.attribute("BANDWIDTH")?.parse().ok()
+    })
+}
+
+/// Drops the variants whose `size` is above `max`, unless that would drop
+/// them all, in which case the smallest stays. Variants of unknown size stay.
+fn retain_variants<T: Ord + Copy>(
+    master: &str,
+    max: T,
+    size: impl Fn(&Variant) -> Option<T>,
+) -> String {
+    let variants = parse_variants(master);
+    let fits = |variant: &Variant| size(variant).is_none_or(|size| size <= max);
+    let smallest = variants
+        .iter()
+        .filter_map(|variant| Some((size(variant)?, variant.uri.as_str())))
+        .min()
+        .map(|(_, uri)| uri);
+    let dropped = variants
+        .iter()
+        .filter(|variant| !fits(variant))
+        .filter(|variant| variants.iter().any(fits) || Some(variant.uri.as_str()) != smallest)
+        .map(|variant| variant.uri.as_str())
+        .collect::<Vec<_>>();
+
+    let mut text = String::new();
+    let mut stream_inf = None;
+    for line in master.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("#EXT-X-STREAM-INF:") {
+            stream_inf = Some(line);
+            continue;
+        }
+        if !trimmed.is_empty() && !trimmed.starts_with('#') {
+            if let Some(tag) = stream_inf.take() {
+                if dropped.contains(&trimmed) {
+                    continue;
+                }
+                text.push_str(tag);
+                text.push('\n');
+            }
+        }
+        text.push_str(line);
+        text.push('\n');
+    }
+    text
+}
+
+/// A master or media playlist with `?token=` appended to every URI, both
+/// the URI lines and the `URI` attributes of tags such as `EXT-X-MEDIA` and
+/// `EXT-X-MAP`, as players don't carry a playlist's query string over to
+/// the files it lists
+pub fn with_token(text: &str, token: &str) -> String {
+    let with_query = |uri: &str| {
+        let separator = if uri.contains('?') { '&' } else { '?' };
+        format!("{}{}token={}", uri, separator, token)
+    };
+    let mut out = String::new();
+    for line in text.lines() {
+        if line.trim().is_empty() {
+            out.push_str(line);
+        } else if line.starts_with('#') {
+            let uri = line
+                .split_once("URI=\"")
+                .and_then(|(head, rest)| Some((head, rest.split_once('"')?)));
+            match uri {
+                Some((head, (uri, tail))) => {
+                    out.push_str(&format!("{}URI=\"{}\"{}", head, with_query(uri), tail))
+                }
+                None => out.push_str(line),
+            }
+        } else {
+            out.push_str(&with_query(line));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Text of a media playlist
+pub fn write_media(playlist: &MediaPlaylist) -> String {
+    let version = if playlist.segments.iter().any(|(s, _)| s.map.is_some()) {
+        6
+    } else {
+        3
+    };
+    let mut text = format!(
+        "#EXTM3U\n#EXT-X-VERSION:{}\n#EXT-X-TARGETDURATION:{}\n#EXT-X-MEDIA-SEQUENCE:{}\n",
+        version, playlist.target_duration, playlist.media_sequence
+    );
+    if playlist.discontinuity_sequence > 0 {
+        text.push_str(&format!(
+            "#EXT-X-DISCONTINUITY-SEQUENCE:{}\n",
+            playlist.discontinuity_sequence
+        ));
+    }
+
+    let mut map = None;
+    let mut elapsed = 0.0;
+    for (i, (segment, discontinuity)) in playlist.segments.iter().enumerate() {
+        let discontinuity = *discontinuity && i > 0;
+        if discontinuity {
+            text.push_str("#EXT-X-DISCONTINUITY\n");
+        }
+        if let Some(start) = playlist
+            .program_date_time
+            .filter(|_| i == 0 || discontinuity)
+        {
+            let at = start + Duration::milliseconds((elapsed * 1000.0_f64).round() as i64);
+            text.push_str(&format!(
+                "#EXT-X-PROGRAM-DATE-TIME:{}\n",
+                at.to_rfc3339_opts(SecondsFormat::Millis, true)
+            ));
+        }
+        // Decoders are reset at discontinuities, so the map is needed again
+        if segment.map.is_some() && (segment.map != map || discontinuity) {
+            let uri = segment.map.as_deref().unwrap_or_default();
+            text.push_str(&format!("#EXT-X-MAP:URI=\"{}\"\n", uri));
+        }
+        map = segment.map.clone();
+        text.push_str(&format!(
+            "#EXTINF:{:.3},\n{}\n",
+            segment.duration, segment.uri
+        ));
+        elapsed += segment.duration;
+    }
+    if playlist.ended {
+        text.push_str("#EXT-X-ENDLIST\n");
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn segment(duration: f64, uri: &str, map: Option<&str>) -> Segment {
+        Segment {
+            duration,
+            uri: uri.to_string(),
+            map: map.map(str::to_string),
+        }
+    }
+
+    fn start() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn writes_vod_playlist() {
+        let playlist = MediaPlaylist {
+            target_duration: 6,
+            segments: vec![
+                (segment(6.0, "segment_000.ts", None), false),
+                (segment(2.5, "segment_001.ts", None), false),
+            ],
+            ended: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            write_media(&playlist),
+            "#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-TARGETDURATION:6\n#EXT-X-MEDIA-SEQUENCE:0\n\
+             #EXTINF:6.000,\nsegment_000.ts\n#EXTINF:2.500,\nsegment_001.ts\n#EXT-X-ENDLIST\n"
+        );
+    }
+
+    #[test]
+    fn writes_discontinuities() {
+        let playlist = MediaPlaylist {
+            media_sequence: 7,
+            discontinuity_sequence: 3,
+            target_duration: 4,
+            segments: vec![
+                (segment(4.0, "a/1.ts", None), true),
+                (segment(4.0, "b/0.ts", None), true),
+                (segment(4.0, "b/1.ts", None), false),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            write_media(&playlist),
+            "#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-TARGETDURATION:4\n#EXT-X-MEDIA-SEQUENCE:7\n\
+             #EXT-X-DISCONTINUITY-SEQUENCE:3\n\
+             #EXTINF:4.000,\na/1.ts\n\
+             #EXT-X-DISCONTINUITY\n#EXTINF:4.000,\nb/0.ts\n\
+             #EXTINF:4.000,\nb/1.ts\n"
+        );
+    }
+
+    #[test]
+    fn writes_program_date_times() {
+        let playlist = MediaPlaylist {
+            target_duration: 4,
+            segments: vec![
+                (segment(4.0, "a/0.ts", None), false),
+                (segment(2.0004, "a/1.ts", None), false),
+                (segment(4.0, "b/0.ts", None), true),
+            ],
+            program_date_time: Some(start()),
+            ..Default::default()
+        };
+        let text = write_media(&playlist);
+        let times: Vec<_> = text
+            .lines()
+            .filter_map(|line| line.strip_prefix("#EXT-X-PROGRAM-DATE-TIME:"))
+            .collect();
+        assert_eq!(
+            times,
+            ["2024-03-01T12:00:00.000Z", "2024-03-01T12:00:06.000Z"]
+        );
+        assert!(text.contains(
+            "#EXT-X-DISCONTINUITY\n#EXT-X-PROGRAM-DATE-TIME:2024-03-01T12:00:06.000Z\n#EXTINF"
+        ));
+    }
+
+    #[test]
+    fn repeats_map_after_discontinuity() {
+        let playlist = MediaPlaylist {
+            target_duration: 4,
+            segments: vec![
+                (segment(4.0, "a/0.m4s", Some("a/init.mp4")), false),
+                (segment(4.0, "a/1.m4s", Some("a/init.mp4")), false),
+                (segment(4.0, "a/0.m4s", Some("a/init.mp4")), true),
+                (segment(4.0, "b/0.m4s", Some("b/init.mp4")), false),
+            ],
+            ..Default::default()
+        };
+        let text = write_media(&playlist);
+        assert!(text.contains("#EXT-X-VERSION:6\n"));
+        assert_eq!(text.matches("#EXT-X-MAP:URI=\"a/init.mp4\"").count(), 2);
+        assert_eq!(text.matches("#EXT-X-MAP:URI=\"b/init.mp4\"").count(), 1);
+        assert!(text.contains("#EXT-X-DISCONTINUITY\n#EXT-X-MAP:URI=\"a/init.mp4\"\n"));
+    }
+
+    #[test]
+    fn round_trips_playlists() {
+        let playlist = MediaPlaylist {
+            media_sequence: 12,
+            discontinuity_sequence: 2,
+            target_duration: 7,
+            segments: vec![
+                (segment(6.006, "0.m4s", Some("init.mp4")), false),
+                (segment(3.2, "1.m4s", Some("init.mp4")), false),
+                (segment(6.0, "other/0.m4s", Some("other/init.mp4")), true),
+            ],
+            program_date_time: Some(start()),
+            ended: true,
+        };
+        assert_eq!(parse_media(&write_media(&playlist)).unwrap(), playlist);
+    }
+
+    #[test]
+    fn skips_comments_and_unknown_tags() {
+        let text = "#EXTM3U\n#EXT-X-TARGETDURATION:4\n#EXT-X-PLAYLIST-TYPE:VOD\n\
+                    # sha256:abc\n#EXTINF:4.0,title\nsegment_000.ts\n";
+        let playlist = parse_media(text).unwrap();
+        assert_eq!(
+            playlist.segments,
+            [(segment(4.0, "segment_000.ts", None), false)]
+        );
+        assert!(!playlist.ended);
+    }
+
+    #[test]
+    fn rejects_invalid_playlists() {
+        let header = "#EXTM3U\n#EXT-X-TARGETDURATION:4\n";
+        assert!(parse_media("#EXT-X-TARGETDURATION:4\n").is_err());
+        assert!(parse_media("#EXTM3U\n#EXTINF:4,\nsegment_000.ts\n").is_err());
+        assert!(parse_media(&format!("{}segment_000.ts\n", header)).is_err());
+        assert!(parse_media(&format!("{}#EXTINF:abc,\nsegment_000.ts\n", header)).is_err());
+        assert!(parse_media(&format!("{}#EXTINF:-1,\nsegment_000.ts\n", header)).is_err());
+        assert!(parse_media("#EXTM3U\n#EXT-X-TARGETDURATION:four\n").is_err());
+    }
+
+    #[test]
+    fn parses_variants() {
+        let master = "#EXTM3U\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=800000,RESOLUTION=640x360,CODECS=\"avc1.4d401e,mp4a.40.2\"\n\
+            360p/stream.m3u8\n\
+            #EXT-X-MEDIA:TYPE=SUBTITLES,URI=\"captions/en.m3u8\"\n";
+        let variants = parse_variants(master);
+        assert_eq!(variants.len(), 1);
+        assert_eq!(variants[0].uri, "360p/stream.m3u8");
+        assert_eq!(variants[0].attribute("BANDWIDTH"), Some("800000"));
+        assert_eq!(
+            variants[0].attribute("CODECS"),
+            Some("avc1.4d401e,mp4a.40.2")
+        );
+        assert_eq!(variants[0].attribute("FRAME-RATE"), None);
+    }
+
+    #[test]
+    fn limits_variants() {
+        let master = "#EXTM3U\n#EXT-X-VERSION:3\n\
+                      #EXT-X-STREAM-INF:BANDWIDTH=5000000,RESOLUTION=1920x1080\n1080p/playlist.m3u8\n\
+                      #EXT-X-STREAM-INF:BANDWIDTH=2800000,RESOLUTION=1280x720\n720p/playlist.m3u8\n\
+                      #EXT-X-STREAM-INF:BANDWIDTH=800000,RESOLUTION=360x640\n360p/playlist.m3u8\n";
+        assert_eq!(
+            limit_height(master, 720),
+            "#EXTM3U\n#EXT-X-VERSION:3\n\
+             #EXT-X-STREAM-INF:BANDWIDTH=2800000,RESOLUTION=1280x720\n720p/playlist.m3u8\n\
+             #EXT-X-STREAM-INF:BANDWIDTH=800000,RESOLUTION=360x640\n360p/playlist.m3u8\n"
+        );
+        // Nothing fits, so the smallest variant is kept
+        let uris = |text: &str| {
+            parse_variants(text)
+                .into_iter()
+                .map(|variant| variant.uri)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(uris(&limit_height(master, 240)), ["360p/playlist.m3u8"]);
+        assert_eq!(uris(&limit_height(master, 2160)).len(), 3);
+        assert_eq!(
+            uris(&limit_bandwidth(master, 3_000_000)),
+            ["720p/playlist.m3u8", "360p/playlist.m3u8"]
+        );
+        assert_eq!(uris(&limit_bandwidth(master, 1)), ["360p/playlist.m3u8"]);
+    }
+
+    #[test]
+    fn parses_renditions() {
+        let master = "#EXTM3U\n\
+            #EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"audio\",NAME=\"English\",URI=\"audio/0/stream.m3u8\"\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=800000,AUDIO=\"audio\"\n\
+            360p/stream.m3u8\n";
+        let renditions = parse_renditions(master);
+        assert_eq!(renditions.len(), 1);
+        assert_eq!(renditions[0].attribute("TYPE"), Some("AUDIO"));
+        assert_eq!(renditions[0].attribute("URI"), Some("audio/0/stream.m3u8"));
+    }
+
+    #[test]
+    fn appends_token_to_every_uri() {
+        let media = "#EXTM3U\n\
+            #EXT-X-MAP:URI=\"init.mp4\"\n\
+            #EXTINF:6.000,\n\
+            segment_000.m4s\n\
+            #EXTINF:2.500,\n\
+            segment_001.m4s?v=2\n\
+            #EXT-X-ENDLIST\n";
+        assert_eq!(
+            with_token(media, "abc"),
+            "#EXTM3U\n\
+             #EXT-X-MAP:URI=\"init.mp4?token=abc\"\n\
+             #EXTINF:6.000,\n\
+             segment_000.m4s?token=abc\n\
+             #EXTINF:2.500,\n\
+             segment_001.m4s?v=2&token=abc\n\
+             #EXT-X-ENDLIST\n"
+        );
+
+        let master = "#EXTM3U\n\
+            #EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"audio\",URI=\"audio/0/stream.m3u8\",DEFAULT=YES\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=800000,AUDIO=\"audio\"\n\
+            360p/stream.m3u8\n";
+        let master = with_token(master, "abc");
+        assert_eq!(
+            parse_renditions(&master)[0].attribute("URI"),
+            Some("audio/0/stream.m3u8?token=abc")
+        );
+        assert_eq!(parse_variants(&master)[0].uri, "360p/stream.m3u8?token=abc");
+    }
+}
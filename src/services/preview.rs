@@ -0,0 +1,78 @@
+// src/services/preview.rs
+use crate::config::app_config::FfmpegConfig;
+use crate::services::ffmpeg;
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+/// Kept with the thumbnails, which duplicates share
+pub const FILE: &str = "preview.webp";
+const CLIPS: u32 = 4;
+const CLIP_SECS: f64 = 1.0;
+const WIDTH: u32 = 320;
+const FPS: u32 = 10;
+
+pub fn preview_path(video_dir: &Path) -> PathBuf {
+    video_dir.join("thumbnails").join(FILE)
+}
+
+/// Start of each clip, spread evenly and clear of the very start and end.
+/// Videos too short for that are previewed from the start.
+fn clip_starts(duration: f64) -> Vec<f64> {
+    if duration <= CLIPS as f64 * CLIP_SECS * 2.0 {
+        return vec![0.0];
+    }
+    (1..=CLIPS)
+        .map(|i| duration * i as f64 / (CLIPS + 1) as f64 - CLIP_SECS / 2.0)
+        .collect()
+}
+
+/// Writes a looping animated WebP of short clips sampled across the video,
+/// for hover previews in listings. Not needed for playback, so callers only
+/// log failures.
+pub async fn generate(
+    input: &Path,
+    video_dir: &Path,
+    duration: f64,
+    ffmpeg: &FfmpegConfig,
+) -> Result<()> {
+    if duration <= 0.0 {
+        anyhow::bail!("Source has no frames to sample");
+    }
+    let output = preview_path(video_dir);
+    let dir = output.parent().unwrap();
+    let starts = clip_starts(duration);
+    let length = if starts.len() == 1 {
+        duration.min(CLIPS as f64 * CLIP_SECS)
+    } else {
+        CLIP_SECS
+    };
+    // Frames outside the clips are dropped and the rest retimed back to back
+    let select = starts
+        .iter()
+        .map(|start| format!("between(t,{:.3},{:.3})", start, start + length))
+        .collect::<Vec<_>>()
+        .join("+");
+
+    let partial = dir.join(format!("{}.partial", FILE));
+    let status = ffmpeg::command("ffmpeg", ffmpeg, dir)
+        .args(ffmpeg::input_protocol_args())
+        .arg("-i")
+        .arg(input)
+        .arg("-vf")
+        .arg(format!(
+            "select='{}',setpts=N/FRAME_RATE/TB,fps={},scale={}:-2",
+            select, FPS, WIDTH
+        ))
+        .args(["-an", "-c:v", "libwebp", "-q:v", "60", "-loop", "0"])
+        .args(["-f", "webp", "-y", "-loglevel", "quiet"])
+        .arg(&partial)
+        .status()
+        .await?;
+    if !status.success() {
+        let _ = fs::remove_file(&partial).await;
+        anyhow::bail!("FFmpeg preview generation failed");
+    }
+    fs::rename(partial, output).await?;
+    Ok(())
+}
@@ -0,0 +1,320 @@
+// src/services/probe.rs
+use crate::config::app_config::FfmpegConfig;
+use crate::services::ffmpeg::{self, PipeFeed};
+use actix_web::web::Bytes;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::path::Path;
+use std::process::Stdio;
+use tokio::process::Child;
+
+const PROBE_ARGS: [&str; 6] = [
+    "-v",
+    "quiet",
+    "-print_format",
+    "json",
+    "-show_format",
+    "-show_streams",
+];
+
+/// Subset of `ffprobe -show_format -show_streams` output used by the pipeline.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MediaInfo {
+    pub format: Format,
+    #[serde(default)]
+    pub streams: Vec<Stream>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Format {
+    pub duration: Option<String>,
+    /// Comma-separated demuxer names, e.g. `mov,mp4,m4a,3gp,3g2,mj2`
+    pub format_name: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Stream {
+    pub codec_type: Option<String>,
+    pub codec_name: Option<String>,
+    /// e.g. `High` or `Main`
+    pub profile: Option<String>,
+    /// H.264 `level_idc`, e.g. 40 for level 4.0
+    pub level: Option<i32>,
+    pub pix_fmt: Option<String>,
+    pub bit_rate: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub r_frame_rate: Option<String>,
+    pub avg_frame_rate: Option<String>,
+    pub color_transfer: Option<String>,
+    pub field_order: Option<String>,
+    pub channels: Option<u32>,
+    #[serde(default)]
+    pub tags: Tags,
+    #[serde(default)]
+    pub disposition: Disposition,
+    #[serde(default)]
+    pub side_data_list: Vec<SideData>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Tags {
+    /// ISO 639-2 code, e.g. `eng`, or `und` when unknown
+    pub language: Option<String>,
+    pub title: Option<String>,
+    /// Clockwise degrees to turn the frames, as older ffprobe versions
+    /// report rotation
+    pub rotate: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SideData {
+    /// e.g. `Display Matrix`
+    pub side_data_type: Option<String>,
+    /// Counter-clockwise degrees a display matrix turns the frames
+    pub rotation: Option<f64>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Disposition {
+    /// 1 for the track players pick by default
+    #[serde(default)]
+    pub default: u8,
+    /// 1 for subtitles shown even when subtitles are off, e.g. for foreign
+    /// dialogue
+    #[serde(default)]
+    pub forced: u8,
+}
+
+/// High dynamic range transfer characteristics, named as in HLS `VIDEO-RANGE`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HdrFormat {
+    /// HDR10 / SMPTE ST 2084
+    Pq,
+    /// Hybrid log-gamma
+    Hlg,
+}
+
+impl HdrFormat {
+    pub fn video_range(&self) -> &'static str {
+        match self {
+            HdrFormat::Pq => "PQ",
+            HdrFormat::Hlg => "HLG",
+        }
+    }
+
+    /// ffmpeg `color_trc` name for this transfer
+    pub fn transfer(&self) -> &'static str {
+        match self {
+            HdrFormat::Pq => "smpte2084",
+            HdrFormat::Hlg => "arib-std-b67",
+        }
+    }
+}
+
+impl Stream {
+    /// Stream bitrate in bits per second
+    pub fn bit_rate(&self) -> Option<u64> {
+        self.bit_rate.as_deref()?.parse().ok()
+    }
+
+    /// Average frame rate in frames per second
+    pub fn frame_rate(&self) -> Option<f64> {
+        parse_rate(self.avg_frame_rate.as_deref()?)
+    }
+
+    /// A stream is treated as variable frame rate when its average rate
+    /// differs noticeably from the container's base rate, which is how screen
+    /// recorders and phones that drop frames show up in ffprobe.
+    pub fn is_variable_frame_rate(&self) -> bool {
+        let base = self.r_frame_rate.as_deref().and_then(parse_rate);
+        match (base, self.frame_rate()) {
+            (Some(base), Some(avg)) => (base - avg).abs() / base > 0.01,
+            _ => false,
+        }
+    }
+
+    /// Interlaced sources report their field order (tt, bb, tb, bt)
+    pub fn is_interlaced(&self) -> bool {
+        matches!(self.field_order.as_deref(), Some("tt" | "bb" | "tb" | "bt"))
+    }
+
+    /// Clockwise degrees (0, 90, 180 or 270) players turn the frames when
+    /// showing them, as phones store portrait recordings as landscape frames
+    pub fn rotation(&self) -> u32 {
+        let degrees = self
+            .side_data_list
+            .iter()
+            .find_map(|side_data| side_data.rotation)
+            .map(|rotation| -rotation)
+            .or_else(|| self.tags.rotate.as_deref()?.trim().parse().ok())
+            .unwrap_or(0.0);
+        ((degrees / 90.0).round() as i64 * 90).rem_euclid(360) as u32
+    }
+
+    /// Lower-cased language tag, unless it is missing or `und`
+    pub fn language(&self) -> Option<String> {
+        self.tags
+            .language
+            .as_deref()
+            .map(|language| language.trim().to_ascii_lowercase())
+            .filter(|language| !language.is_empty() && language != "und")
+    }
+
+    pub fn hdr_format(&self) -> Option<HdrFormat> {
+        match self.color_transfer.as_deref()? {
+            "smpte2084" => Some(HdrFormat::Pq),
+            "arib-std-b67" => Some(HdrFormat::Hlg),
+            _ => None,
+        }
+    }
+}
+
+impl MediaInfo {
+    pub fn duration(&self) -> Option<f64> {
+        self.format.duration.as_deref()?.parse().ok()
+    }
+
+    pub fn video_stream(&self) -> Option<&Stream> {
+        self.streams
+            .iter()
+            .find(|s| s.codec_type.as_deref() == Some("video"))
+    }
+
+    pub fn audio_stream(&self) -> Option<&Stream> {
+        self.audio_streams().next()
+    }
+
+    /// Audio streams in file order, so the n-th is ffmpeg's `0:a:n`
+    pub fn audio_streams(&self) -> impl Iterator<Item = &Stream> {
+        self.streams
+            .iter()
+            .filter(|s| s.codec_type.as_deref() == Some("audio"))
+    }
+
+    /// Subtitle streams in file order, so the n-th is ffmpeg's `0:s:n`
+    pub fn subtitle_streams(&self) -> impl Iterator<Item = &Stream> {
+        self.streams
+            .iter()
+            .filter(|s| s.codec_type.as_deref() == Some("subtitle"))
+    }
+
+    /// File extension for the detected container. The `hint` (usually the
+    /// uploaded file's extension) wins when the container accepts it, since
+    /// ffprobe reports e.g. WebM and Matroska the same way.
+    pub fn container_extension(&self, hint: Option<&str>) -> Option<String> {
+        let extensions = self
+            .format
+            .format_name
+            .as_deref()?
+            .split(',')
+            .map(|name| match name {
+                "matroska" => "mkv",
+                "mpegts" => "ts",
+                "mpeg" => "mpg",
+                other => other,
+            })
+            .collect::<Vec<_>>();
+
+        if let Some(hint) = hint.filter(|hint| extensions.contains(hint)) {
+            return Some(hint.to_string());
+        }
+        if extensions.contains(&"mp4") {
+            return Some("mp4".to_string());
+        }
+        extensions.first().map(|ext| ext.to_string())
+    }
+
+    /// Width and height of the first video stream
+    pub fn dimensions(&self) -> Option<(u32, u32)> {
+        let stream = self.video_stream()?;
+        Some((stream.width?, stream.height?))
+    }
+
+    /// Width and height of the first video stream as shown, i.e. after its
+    /// rotation. ffmpeg turns the frames upright the same way when decoding.
+    pub fn display_dimensions(&self) -> Option<(u32, u32)> {
+        let stream = self.video_stream()?;
+        let (width, height) = (stream.width?, stream.height?);
+        if stream.rotation() % 180 == 90 {
+            Some((height, width))
+        } else {
+            Some((width, height))
+        }
+    }
+}
+
+pub async fn probe(file_path: &Path, config: &FfmpegConfig) -> Result<MediaInfo> {
+    let work_dir = file_path.parent().context("Invalid video path")?;
+    let output = ffmpeg::command("ffprobe", config, work_dir)
+        .args(ffmpeg::input_protocol_args())
+        .args(PROBE_ARGS)
+        .arg(file_path)
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        return Err(anyhow::anyhow!("ffprobe failed"));
+    }
+
+    serde_json::from_slice(&output.stdout).context("Invalid ffprobe output")
+}
+
+/// Probes content while it is still arriving by piping it to ffprobe's stdin.
+///
+/// Feeding never waits on ffprobe: if it falls behind the upload, the probe is
+/// abandoned rather than slowing the client down. Containers that need seeking
+/// (e.g. MP4 with the index at the end) can't be probed from a pipe either, so
+/// callers fall back to `probe` on the stored file when `finish` returns None.
+pub struct StreamProbe {
+    child: Child,
+    feed: PipeFeed,
+}
+
+impl StreamProbe {
+    pub fn start(work_dir: &Path, config: &FfmpegConfig) -> Result<Self> {
+        let mut child = ffmpeg::command("ffprobe", config, work_dir)
+            .args(ffmpeg::input_protocol_args())
+            .args(PROBE_ARGS)
+            .arg("pipe:0")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+
+        let stdin = child.stdin.take().context("ffprobe has no stdin")?;
+        Ok(StreamProbe {
+            child,
+            feed: PipeFeed::start(stdin),
+        })
+    }
+
+    pub fn feed(&mut self, chunk: &Bytes) {
+        self.feed.feed(chunk);
+    }
+
+    /// Ends the input and returns what ffprobe found, if it saw all it needed
+    pub async fn finish(self) -> Option<MediaInfo> {
+        let StreamProbe { child, feed } = self;
+        if feed.abandoned() {
+            return None;
+        }
+
+        // ffprobe may have stopped reading early, having seen enough
+        feed.close().await;
+        let output = child.wait_with_output().await.ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        serde_json::from_slice(&output.stdout).ok()
+    }
+}
+
+/// Parses ffprobe rationals such as `30000/1001`
+fn parse_rate(rate: &str) -> Option<f64> {
+    let (num, den) = rate.split_once('/')?;
+    let (num, den): (f64, f64) = (num.parse().ok()?, den.parse().ok()?);
+    if num == 0.0 || den == 0.0 {
+        return None;
+    }
+    Some(num / den)
+}
@@ -0,0 +1,82 @@
+// src/services/profiles.rs
+use crate::config::app_config::{RenditionConfig, TranscodingConfig};
+use crate::config::AppConfig;
+use crate::db::models::EncodingProfile;
+use anyhow::{Context, Result};
+use diesel::{OptionalExtension, QueryDsl};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use uuid::Uuid;
+use vid_storage_models::{ProcessingProfile, RenditionSpec};
+
+/// Looks up a stored profile by name
+pub async fn load(conn: &mut AsyncPgConnection, name: &str) -> Result<Option<EncodingProfile>> {
+    use crate::db::schema::encoding_profiles;
+
+    Ok(encoding_profiles::table
+        .find(name)
+        .first::<EncodingProfile>(conn)
+        .await
+        .optional()?)
+}
+
+/// The ladder, preset, segment length and bitrate mode of a stored profile
+pub fn settings(profile: &EncodingProfile) -> Result<TranscodingConfig> {
+    Ok(TranscodingConfig {
+        renditions: serde_json::from_value(profile.renditions.clone())
+            .with_context(|| format!("Invalid renditions in profile {}", profile.name))?,
+        preset: profile.preset.clone(),
+        segment_duration: profile.segment_duration as u32,
+        per_title: profile.per_title,
+    })
+}
+
+pub fn renditions(specs: &[RenditionSpec]) -> Vec<RenditionConfig> {
+    specs
+        .iter()
+        .map(|spec| RenditionConfig {
+            name: spec.name.clone(),
+            resolution: spec.resolution.clone(),
+            video_bitrate: spec.video_bitrate.clone(),
+            audio_bitrate: spec.audio_bitrate.clone(),
+            max_fps: spec.max_fps,
+            codec: spec.codec.clone(),
+        })
+        .collect()
+}
+
+/// Settings a video is processed with: its named profile or the configured
+/// default, with the ladder replaced by the video's own override if it has one
+pub async fn for_video(
+    conn: &mut AsyncPgConnection,
+    v_id: Uuid,
+    config: &AppConfig,
+) -> Result<TranscodingConfig> {
+    use crate::db::schema::videos;
+
+    let (name, override_profile) = videos::table
+        .find(v_id)
+        .select((videos::encoding_profile, videos::processing_profile))
+        .first::<(Option<String>, Option<serde_json::Value>)>(conn)
+        .await?;
+
+    let mut settings = match name {
+        Some(name) => {
+            let profile = load(conn, &name)
+                .await?
+                .with_context(|| format!("Encoding profile {} not found", name))?;
+            settings(&profile)?
+        }
+        None => config.transcoding.clone(),
+    };
+
+    match override_profile.map(serde_json::from_value::<ProcessingProfile>) {
+        Some(Ok(profile)) => settings.renditions = renditions(&profile.renditions),
+        Some(Err(e)) => log::error!(
+            "Ignoring invalid processing profile of video {}: {}",
+            v_id,
+            e
+        ),
+        None => {}
+    }
+    Ok(settings)
+}
@@ -0,0 +1,308 @@
+// src/services/recovery.rs
+use crate::config::AppConfig;
+use crate::db::models::{Conversion, ProcessingJob};
+use crate::db::DbPool;
+use crate::services::events::ProgressEvents;
+use crate::services::stalled::HEARTBEAT_LEASE;
+use crate::services::{convert, jobs, video_processor};
+use actix_web::web;
+use anyhow::Result;
+use chrono::Utc;
+use diesel::dsl::{exists, not};
+use diesel::prelude::*;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+use std::io::ErrorKind;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::fs;
+use uuid::Uuid;
+
+/// Picks up work a previous run of the API node was interrupted in. Jobs
+/// whose lease expired are queued again, videos stuck processing without a
+/// job get a new one, and uploads whose scan stopped are scanned again, now
+/// and from then on. Must run before the job worker starts.
+pub async fn recover(
+    pool: web::Data<DbPool>,
+    config: Arc<AppConfig>,
+    events: web::Data<ProgressEvents>,
+) -> Result<()> {
+    use crate::db::schema::{processing_jobs, videos};
+
+    let conn = &mut pool.get().await?;
+    requeue_expired(conn, Duration::from_secs(config.worker.lease_secs)).await?;
+
+    // Processing videos whose job was lost, e.g. to a crash between the
+    // status change and the job finishing
+    let active_job = processing_jobs::table
+        .filter(processing_jobs::video_id.eq(videos::id.nullable()))
+        .filter(processing_jobs::state.eq_any(["queued", "running"]));
+    let orphaned = videos::table
+        .filter(videos::status.eq("processing"))
+        .filter(not(exists(active_job)))
+        .select(videos::id)
+        .load::<Uuid>(conn)
+        .await?;
+    for v_id in orphaned {
+        reset_video(conn, v_id).await?;
+        jobs::enqueue(v_id, conn).await?;
+        log::info!("Requeued processing of video {}", v_id);
+    }
+
+    start_rescans(pool, config, events);
+
+    Ok(())
+}
+
+/// Runs `rescan_expired` now and every `HEARTBEAT_LEASE`, so scans this node
+/// was interrupted in are picked up once their lease runs out even if it
+/// restarted sooner
+fn start_rescans(
+    pool: web::Data<DbPool>,
+    config: Arc<AppConfig>,
+    events: web::Data<ProgressEvents>,
+) {
+    // ingest returns actix errors, which aren't Send, so stay on this worker
+    actix_web::rt::spawn(async move {
+        let mut interval = tokio::time::interval(HEARTBEAT_LEASE);
+        loop {
+            interval.tick().await;
+            if let Err(e) = rescan_expired(&pool, &config, &events).await {
+                log::error!("Failed to restart interrupted scans: {}", e);
+            }
+        }
+    });
+}
+
+/// Scans again the uploads left "scanning" by a node that stopped, i.e.
+/// whose scan hasn't renewed its lease in `HEARTBEAT_LEASE`. Each is claimed
+/// by renewing the lease, so only one node restarts it. A connection is
+/// taken per video, so the pool isn't short one for the whole pass.
+async fn rescan_expired(
+    pool: &web::Data<DbPool>,
+    config: &Arc<AppConfig>,
+    events: &web::Data<ProgressEvents>,
+) -> Result<()> {
+    use crate::db::schema::videos;
+
+    let cutoff = Utc::now().naive_utc() - HEARTBEAT_LEASE;
+    let is_expired = || {
+        videos::status
+            .eq("scanning")
+            .and(videos::updated_at.lt(cutoff))
+    };
+    let expired = videos::table
+        .filter(is_expired())
+        .select(videos::id)
+        .load::<Uuid>(&mut pool.get().await?)
+        .await?;
+    for v_id in expired {
+        let conn = &mut pool.get().await?;
+        // Checked again under the lock, in case another node claimed it
+        // meanwhile
+        let claimed = conn
+            .transaction::<_, anyhow::Error, _>(|conn| {
+                async move {
+                    let locked = videos::table
+                        .find(v_id)
+                        .filter(is_expired())
+                        .select(videos::id)
+                        .for_update()
+                        .skip_locked()
+                        .first::<Uuid>(conn)
+                        .await
+                        .optional()?;
+                    if locked.is_none() {
+                        return Ok(false);
+                    }
+                    diesel::update(videos::table.find(v_id))
+                        .set(videos::updated_at.eq(Utc::now().naive_utc()))
+                        .execute(conn)
+                        .await?;
+                    Ok(true)
+                }
+                .scope_boxed()
+            })
+            .await?;
+        if !claimed {
+            continue;
+        }
+
+        log::info!("Restarting scan of video {}", v_id);
+        if let Err(e) = video_processor::ingest(
+            v_id,
+            None,
+            conn,
+            pool.clone(),
+            config.clone(),
+            events.clone(),
+        )
+        .await
+        {
+            log::error!("Failed to restart scan of video {}: {}", v_id, e);
+        }
+    }
+    Ok(())
+}
+
+/// Queues again the jobs left running by workers that haven't renewed their
+/// lease in `lease`, having stopped or lost the database. Partial output of
+/// the step interrupted is removed first so its rerun starts clean, except
+/// for the finished chunks of long encodes, which it resumes from; steps
+/// that were over are kept.
+pub async fn requeue_expired(conn: &mut AsyncPgConnection, lease: Duration) -> Result<()> {
+    use crate::db::schema::processing_jobs;
+
+    let cutoff = Utc::now().naive_utc() - lease;
+    let is_expired = || {
+        processing_jobs::state.eq("running").and(
+            processing_jobs::heartbeat_at
+                .is_null()
+                .or(processing_jobs::heartbeat_at.lt(cutoff)),
+        )
+    };
+    let expired = processing_jobs::table
+        .filter(is_expired())
+        .select(processing_jobs::id)
+        .load::<Uuid>(conn)
+        .await?;
+    for job_id in expired {
+        // Locked until it's queued again, so neither another worker taking
+        // it over nor one claiming it sees it half reset, and checked again
+        // in case its worker renewed the lease meanwhile
+        let requeued = conn
+            .transaction::<_, anyhow::Error, _>(|conn| {
+                async move {
+                    let Some(job) = processing_jobs::table
+                        .find(job_id)
+                        .filter(is_expired())
+                        .for_update()
+                        .skip_locked()
+                        .first::<ProcessingJob>(conn)
+                        .await
+                        .optional()?
+                    else {
+                        return Ok(false);
+                    };
+                    reset_job(conn, &job).await?;
+                    diesel::update(processing_jobs::table.find(job.id))
+                        .set((
+                            processing_jobs::state.eq("queued"),
+                            processing_jobs::worker_id.eq(None::<Uuid>),
+                            processing_jobs::updated_at.eq(Utc::now().naive_utc()),
+                        ))
+                        .execute(conn)
+                        .await?;
+                    Ok(true)
+                }
+                .scope_boxed()
+            })
+            .await?;
+        if requeued {
+            log::info!("Requeued interrupted job {}", job_id);
+        }
+    }
+    Ok(())
+}
+
+/// Removes the partial output of an interrupted job. Frame extractions
+/// overwrite whatever they wrote before, as does finalize.
+pub(crate) async fn reset_job(conn: &mut AsyncPgConnection, job: &ProcessingJob) -> Result<()> {
+    match (job.video_id, job.conversion_id) {
+        (Some(v_id), _) => match job.stage.as_deref() {
+            Some(jobs::RENDITION) => {
+                let quality = job.quality.as_deref().unwrap_or_default();
+                reset_quality(conn, v_id, quality).await
+            }
+            Some(jobs::LADDER) => reset_ladder(conn, v_id).await,
+            Some(jobs::THUMBNAILS) => {
+                remove_dir(&video_processor::get_video_dir(v_id).join("thumbnails")).await
+            }
+            Some(jobs::FINALIZE) => Ok(()),
+            _ => reset_video(conn, v_id).await,
+        },
+        (None, Some(c_id)) => reset_conversion(conn, c_id).await,
+        (None, None) => Ok(()),
+    }
+}
+
+/// Drops whatever an interrupted run produced: the renditions recorded up
+/// front and any HLS output, review proxy or thumbnails written so far
+pub(crate) async fn reset_video(conn: &mut AsyncPgConnection, v_id: Uuid) -> Result<()> {
+    use crate::db::schema::video_qualities;
+
+    diesel::delete(video_qualities::table.filter(video_qualities::video_id.eq(v_id)))
+        .execute(conn)
+        .await?;
+    let video_dir = video_processor::get_video_dir(v_id);
+    for name in ["hls", "review", "thumbnails"] {
+        remove_dir(&video_dir.join(name)).await?;
+    }
+    Ok(())
+}
+
+/// Drops the output of a rendition step, which encodes it again from the
+/// start
+async fn reset_quality(conn: &mut AsyncPgConnection, v_id: Uuid, quality: &str) -> Result<()> {
+    use crate::db::schema::video_qualities;
+
+    diesel::update(video_qualities::table)
+        .filter(video_qualities::video_id.eq(v_id))
+        .filter(video_qualities::resolution.eq(quality))
+        .set((
+            video_qualities::state.eq("pending"),
+            video_qualities::progress.eq(0),
+        ))
+        .execute(conn)
+        .await?;
+    let video_dir = video_processor::get_video_dir(v_id);
+    let quality_dir = if quality == video_processor::REVIEW_QUALITY.0 {
+        video_dir.join("review")
+    } else {
+        video_dir.join("hls").join(quality)
+    };
+    remove_dir(&quality_dir).await
+}
+
+/// Drops the output of the ladder step: the renditions of the video no
+/// rendition step encodes
+async fn reset_ladder(conn: &mut AsyncPgConnection, v_id: Uuid) -> Result<()> {
+    use crate::db::schema::{processing_jobs, video_qualities};
+
+    let own_step = processing_jobs::table
+        .filter(processing_jobs::video_id.eq(v_id))
+        .filter(processing_jobs::stage.eq(jobs::RENDITION))
+        .filter(processing_jobs::quality.eq(video_qualities::resolution.nullable()));
+    let qualities = video_qualities::table
+        .filter(video_qualities::video_id.eq(v_id))
+        .filter(not(exists(own_step)))
+        .select(video_qualities::resolution)
+        .load::<String>(conn)
+        .await?;
+    for quality in qualities {
+        reset_quality(conn, v_id, &quality).await?;
+    }
+    Ok(())
+}
+
+async fn reset_conversion(conn: &mut AsyncPgConnection, c_id: Uuid) -> Result<()> {
+    use crate::db::schema::conversions;
+
+    let conversion = conversions::table
+        .find(c_id)
+        .first::<Conversion>(conn)
+        .await?;
+    let partial = convert::conversion_dir(c_id).join(format!("partial.{}", conversion.container));
+    match fs::remove_file(partial).await {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+async fn remove_dir(dir: &Path) -> Result<()> {
+    match fs::remove_dir_all(dir).await {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
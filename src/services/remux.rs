@@ -0,0 +1,130 @@
+// src/services/remux.rs
+use crate::config::app_config::FfmpegConfig;
+use crate::services::chunks;
+use crate::services::ffmpeg::{self, PipeFeed};
+use actix_web::web::Bytes;
+use anyhow::{Context, Result};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::fs;
+use tokio::process::Child;
+
+/// Written next to a complete remux, with the ffmpeg arguments that made it
+const ARGS_FILE: &str = "args.json";
+
+/// Where a video's upload is remuxed. It is kept with the chunks, under a
+/// name no rendition can have, so it goes once processing is over like them.
+pub fn dir(video_dir: &Path) -> PathBuf {
+    chunks::rendition_dir(video_dir, "upload.remux")
+}
+
+/// Segments content into HLS while it is still arriving by piping it to
+/// ffmpeg with its streams copied, so a rendition that would copy the source
+/// anyway can `take` the result rather than read the stored file again.
+///
+/// Like `StreamProbe`, feeding never waits on ffmpeg, and the remux is given
+/// up if it falls behind. Containers that need seeking (e.g. MP4 with the
+/// index at the end) can't be read from a pipe, so they are never remuxed.
+pub struct StreamRemux {
+    child: Child,
+    feed: PipeFeed,
+    dir: PathBuf,
+    args: Vec<String>,
+}
+
+impl StreamRemux {
+    /// Runs ffmpeg with `args`, which read `pipe:0` and write into the remux
+    /// directory ffmpeg runs in
+    pub async fn start(video_dir: &Path, args: Vec<String>, config: &FfmpegConfig) -> Result<Self> {
+        let dir = dir(video_dir);
+        fs::create_dir_all(&dir).await?;
+        let mut child = ffmpeg::command("ffmpeg", config, &dir)
+            .args(&args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()?;
+
+        let stdin = child.stdin.take().context("ffmpeg has no stdin")?;
+        Ok(StreamRemux {
+            child,
+            feed: PipeFeed::start(stdin),
+            dir,
+            args,
+        })
+    }
+
+    pub fn feed(&mut self, chunk: &Bytes) {
+        self.feed.feed(chunk);
+    }
+
+    /// Ends the input and waits for ffmpeg. The remux is kept if all of the
+    /// content was segmented, and removed otherwise.
+    pub async fn finish(self) {
+        let StreamRemux {
+            mut child,
+            feed,
+            dir,
+            args,
+        } = self;
+        let complete = feed.close().await && child.wait().await.is_ok_and(|s| s.success());
+        let result = if complete {
+            let args = serde_json::to_string(&args).expect("Arguments serialize");
+            fs::write(dir.join(ARGS_FILE), args).await
+        } else {
+            drop(child);
+            fs::remove_dir_all(&dir).await
+        };
+        if let Err(e) = result {
+            log::warn!("Failed to finish remux in {}: {}", dir.display(), e);
+        }
+    }
+}
+
+/// Moves a complete remux into `output_dir` as the rendition `quality`, if
+/// it was made with the ffmpeg arguments `args` would make it with. Returns
+/// whether it was; only one rendition can take it.
+pub async fn take(
+    video_dir: &Path,
+    args: &[String],
+    quality: &str,
+    output_dir: &Path,
+) -> Result<bool> {
+    let dir = dir(video_dir);
+    let saved = match fs::read(dir.join(ARGS_FILE)).await {
+        Ok(saved) => saved,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e.into()),
+    };
+    if serde_json::from_slice::<Vec<String>>(&saved)
+        .ok()
+        .as_deref()
+        != Some(args)
+    {
+        return Ok(false);
+    }
+
+    // Claimed by moving it away, in case another rendition got here too
+    let claimed = dir.with_file_name(format!("upload.remux.{}", quality));
+    match fs::rename(&dir, &claimed).await {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e.into()),
+    }
+    fs::remove_file(claimed.join(ARGS_FILE)).await?;
+    let mut entries = fs::read_dir(&claimed).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        fs::rename(entry.path(), output_dir.join(entry.file_name())).await?;
+    }
+    fs::remove_dir(&claimed).await?;
+    Ok(true)
+}
+
+/// Drops a video's remux, for uploads that are never processed
+pub async fn remove(video_dir: &Path) {
+    match fs::remove_dir_all(dir(video_dir)).await {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => log::warn!("Failed to remove remux in {}: {}", video_dir.display(), e),
+    }
+}
@@ -0,0 +1,88 @@
+// src/services/scan.rs
+use crate::config::app_config::ScanConfig;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command;
+use tokio_util::io::ReaderStream;
+use uuid::Uuid;
+
+/// Outcome of scanning an upload
+pub enum ScanVerdict {
+    Clean,
+    /// The scanner flagged the upload, with its reason if it gave one
+    Rejected(Option<String>),
+}
+
+#[derive(Deserialize)]
+struct ScanResponse {
+    clean: bool,
+    reason: Option<String>,
+}
+
+/// Scans the upload at `path` with the configured command or HTTP endpoint.
+/// Errors (scanner missing, timed out, unexpected answer) are not verdicts;
+/// callers should treat them as a failed upload rather than a clean one.
+pub async fn scan(v_id: Uuid, path: &Path, config: &ScanConfig) -> Result<ScanVerdict> {
+    let timeout = Duration::from_secs(config.timeout_secs);
+    let scan = async {
+        match (config.command.as_deref(), config.url.as_deref()) {
+            (Some([program, args @ ..]), _) => scan_with_command(program, args, path).await,
+            (_, Some(url)) => scan_with_http(v_id, url, path).await,
+            _ => Ok(ScanVerdict::Clean),
+        }
+    };
+    tokio::time::timeout(timeout, scan)
+        .await
+        .context("Scan timed out")?
+}
+
+async fn scan_with_command(program: &str, args: &[String], path: &Path) -> Result<ScanVerdict> {
+    let output = Command::new(program)
+        .args(args)
+        .arg(path)
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await
+        .with_context(|| format!("Failed to run scanner {}", program))?;
+
+    match output.status.code() {
+        Some(0) => Ok(ScanVerdict::Clean),
+        Some(1) => {
+            let report = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            Ok(ScanVerdict::Rejected(
+                (!report.is_empty()).then_some(report),
+            ))
+        }
+        _ => Err(anyhow::anyhow!(
+            "Scanner exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )),
+    }
+}
+
+async fn scan_with_http(v_id: Uuid, url: &str, path: &Path) -> Result<ScanVerdict> {
+    let file = tokio::fs::File::open(path).await?;
+    let response = reqwest::Client::new()
+        .post(url)
+        .header("Content-Type", "application/octet-stream")
+        .header("X-Video-Id", v_id.to_string())
+        .body(reqwest::Body::wrap_stream(ReaderStream::new(file)))
+        .send()
+        .await?
+        .error_for_status()
+        .context("Scan service returned an error")?;
+
+    let body = response.bytes().await?;
+    let verdict: ScanResponse =
+        serde_json::from_slice(&body).context("Scan service returned an invalid response")?;
+    Ok(if verdict.clean {
+        ScanVerdict::Clean
+    } else {
+        ScanVerdict::Rejected(verdict.reason)
+    })
+}
@@ -0,0 +1,129 @@
+// src/services/segment_cache.rs
+use actix_web::web::{self, Bytes};
+use futures::Stream;
+use lru::LruCache;
+use std::fs::File;
+use std::io;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// Size of the reads a segment is streamed in, as for `NamedFile`
+const CHUNK_SIZE: u64 = 64 * 1024;
+
+/// Open handles to recently served segments, so a hot segment is streamed
+/// with positional reads instead of an open, seek and read per request.
+/// Positional reads don't move a shared file offset, so requests can share a
+/// handle.
+pub struct SegmentCache {
+    files: Option<Mutex<LruCache<PathBuf, Arc<File>>>>,
+}
+
+/// An open segment, read as it's streamed rather than up front
+pub struct Segment {
+    file: Arc<File>,
+    pub len: u64,
+    pub modified: Option<SystemTime>,
+}
+
+impl Segment {
+    /// Streams the segment in chunks of `CHUNK_SIZE`, each read off the
+    /// async runtime
+    pub fn into_stream(self) -> impl Stream<Item = io::Result<Bytes>> {
+        futures::stream::try_unfold(0, move |offset| {
+            let file = self.file.clone();
+            let len = self.len;
+            async move {
+                if offset >= len {
+                    return Ok(None);
+                }
+                let size = CHUNK_SIZE.min(len - offset);
+                let chunk = web::block(move || read_chunk(&file, offset, size))
+                    .await
+                    .map_err(io::Error::other)??;
+                Ok(Some((chunk, offset + size)))
+            }
+        })
+    }
+}
+
+impl SegmentCache {
+    /// Keeps up to `capacity` handles open; 0 disables the cache
+    pub fn new(capacity: usize) -> Self {
+        SegmentCache {
+            files: NonZeroUsize::new(capacity)
+                .filter(|_| cfg!(unix))
+                .map(|capacity| Mutex::new(LruCache::new(capacity))),
+        }
+    }
+
+    /// Opens a segment, or returns None if the cache is disabled
+    pub async fn open(&self, path: &Path) -> Option<io::Result<Segment>> {
+        let files = self.files.as_ref()?;
+        let cached = files.lock().unwrap().get(path).cloned();
+
+        let owned_path = path.to_path_buf();
+        let result = web::block(move || open_segment(&owned_path, cached)).await;
+        let segment = match result {
+            Ok(Ok(segment)) => segment,
+            Ok(Err(e)) => {
+                files.lock().unwrap().pop(path);
+                return Some(Err(e));
+            }
+            Err(e) => return Some(Err(io::Error::other(e))),
+        };
+
+        files
+            .lock()
+            .unwrap()
+            .put(path.to_path_buf(), segment.file.clone());
+        Some(Ok(segment))
+    }
+}
+
+#[cfg(unix)]
+fn open_segment(path: &Path, cached: Option<Arc<File>>) -> io::Result<Segment> {
+    use std::os::unix::fs::MetadataExt;
+
+    let file = match cached {
+        // A deleted (e.g. re-packaged) segment must be reopened to see its replacement
+        Some(file) if file.metadata()?.nlink() > 0 => file,
+        _ => Arc::new(File::open(path)?),
+    };
+    let metadata = file.metadata()?;
+    if !metadata.is_file() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Not a file"));
+    }
+
+    Ok(Segment {
+        file,
+        len: metadata.len(),
+        modified: metadata.modified().ok(),
+    })
+}
+
+#[cfg(unix)]
+fn read_chunk(file: &File, offset: u64, size: u64) -> io::Result<Bytes> {
+    use std::os::unix::fs::FileExt;
+
+    let mut data = vec![0; size as usize];
+    file.read_exact_at(&mut data, offset)?;
+    Ok(data.into())
+}
+
+#[cfg(not(unix))]
+fn open_segment(_path: &Path, _cached: Option<Arc<File>>) -> io::Result<Segment> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Segment cache requires positional reads",
+    ))
+}
+
+#[cfg(not(unix))]
+fn read_chunk(_file: &File, _offset: u64, _size: u64) -> io::Result<Bytes> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Segment cache requires positional reads",
+    ))
+}
@@ -0,0 +1,129 @@
+// src/services/stalled.rs
+use crate::config::AppConfig;
+use crate::db::DbPool;
+use crate::services::{video_processor, webhooks};
+use actix_web::web;
+use anyhow::Result;
+use chrono::{TimeDelta, Utc};
+use diesel::{ExpressionMethods, QueryDsl};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use std::io::ErrorKind;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::fs;
+use uuid::Uuid;
+use vid_storage_models::ErrorCode;
+
+/// How often stalled uploads are looked for
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10 * 60);
+/// How often an upload that is still streaming, or being scanned, marks its
+/// video as changed
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(60);
+/// How long after its last heartbeat a scan is taken to have been abandoned
+pub const HEARTBEAT_LEASE: Duration = Duration::from_secs(3 * 60);
+
+/// Marks work on a video in `status` as ongoing by touching the video's
+/// `updated_at` right away and then every `HEARTBEAT_INTERVAL`: keeps the
+/// sweeper away from an upload while its content streams in, and other nodes
+/// away from a scan. Stops when dropped, so an abandoned request stalls like
+/// any other upload.
+pub struct Heartbeat(tokio::task::AbortHandle);
+
+impl Heartbeat {
+    pub fn start(v_id: Uuid, status: &'static str, pool: web::Data<DbPool>) -> Heartbeat {
+        use crate::db::schema::videos;
+
+        let task = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+            loop {
+                interval.tick().await;
+                let Ok(mut conn) = pool.get().await else {
+                    continue;
+                };
+                if let Err(e) = diesel::update(videos::table.find(v_id))
+                    .filter(videos::status.eq(status))
+                    .set(videos::updated_at.eq(Utc::now().naive_utc()))
+                    .execute(&mut conn)
+                    .await
+                {
+                    log::warn!("Failed to mark video {} as active: {}", v_id, e);
+                }
+            }
+        });
+        Heartbeat(task.abort_handle())
+    }
+}
+
+impl Drop for Heartbeat {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Starts failing videos left "uploading" for longer than
+/// `storage.stalled_upload_timeout_secs`, e.g. by abandoned browser uploads
+/// or create calls never followed by their content
+pub fn start_sweeper(pool: web::Data<DbPool>, config: Arc<AppConfig>) {
+    let timeout = config.storage.stalled_upload_timeout_secs;
+    let Some(timeout) = i64::try_from(timeout)
+        .ok()
+        .and_then(TimeDelta::try_seconds)
+        .filter(|_| timeout > 0)
+    else {
+        return;
+    };
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            let result = match pool.get().await {
+                Ok(mut conn) => sweep(&mut conn, timeout).await,
+                Err(e) => Err(e.into()),
+            };
+            if let Err(e) = result {
+                log::error!("Failed to clean up stalled uploads: {}", e);
+            }
+        }
+    });
+}
+
+async fn sweep(conn: &mut AsyncPgConnection, timeout: TimeDelta) -> Result<()> {
+    use crate::db::schema::videos;
+
+    let cutoff = Utc::now().naive_utc() - timeout;
+    let stalled = videos::table
+        .filter(videos::status.eq("uploading"))
+        .filter(videos::updated_at.lt(cutoff))
+        .select(videos::id)
+        .load::<Uuid>(conn)
+        .await?;
+
+    for v_id in stalled {
+        // Only if it is still uploading and idle, in case it finished or
+        // its content started streaming meanwhile
+        let failed = diesel::update(videos::table.find(v_id))
+            .filter(videos::status.eq("uploading"))
+            .filter(videos::updated_at.lt(cutoff))
+            .set((
+                videos::status.eq("failed"),
+                videos::status_reason.eq("The upload did not complete in time"),
+                videos::error_code.eq(ErrorCode::UploadStalled.as_str()),
+                videos::updated_at.eq(Utc::now().naive_utc()),
+            ))
+            .execute(conn)
+            .await?;
+        if failed == 0 {
+            continue;
+        }
+
+        match fs::remove_dir_all(video_processor::get_video_dir(v_id)).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => log::error!("Failed to remove partial upload {}: {}", v_id, e),
+        }
+        log::info!("Failed stalled upload {}", v_id);
+        webhooks::notify(conn, v_id).await;
+    }
+    Ok(())
+}
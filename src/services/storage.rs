@@ -0,0 +1,111 @@
+// src/services/storage.rs
+use std::future::Future;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::fs;
+
+/// Tries at a packaging write before it fails the step
+const WRITE_ATTEMPTS: u32 = 4;
+/// Wait before the first retry, doubled for each one after it
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Whether an error may clear up by itself, as network filesystems and object
+/// storage mounts report brief outages. Errors that would only repeat, such
+/// as a missing directory or a full disk, are not retried.
+fn is_transient(e: &io::Error) -> bool {
+    !matches!(
+        e.kind(),
+        ErrorKind::NotFound
+            | ErrorKind::PermissionDenied
+            | ErrorKind::AlreadyExists
+            | ErrorKind::InvalidInput
+            | ErrorKind::InvalidData
+            | ErrorKind::Unsupported
+            | ErrorKind::StorageFull
+            | ErrorKind::QuotaExceeded
+            | ErrorKind::ReadOnlyFilesystem
+            | ErrorKind::IsADirectory
+            | ErrorKind::NotADirectory
+            | ErrorKind::FileTooLarge
+    )
+}
+
+/// Runs a storage operation on `path`, retrying transient errors with
+/// backoff so a brief hiccup doesn't fail a long transcode
+pub async fn retry<T, F, Fut>(path: &Path, mut op: F) -> io::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = io::Result<T>>,
+{
+    let mut delay = RETRY_DELAY;
+    let mut attempt = 1;
+    loop {
+        match op().await {
+            Ok(output) => return Ok(output),
+            Err(e) if attempt < WRITE_ATTEMPTS && is_transient(&e) => {
+                log::warn!(
+                    "Storage error on {} (attempt {} of {}), retrying: {}",
+                    path.display(),
+                    attempt,
+                    WRITE_ATTEMPTS,
+                    e
+                );
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Replaces `path` with `contents`, retrying transient errors. The data goes
+/// to a temporary file that is checked for its full length before it is
+/// renamed into place, so readers never see a file cut short.
+pub async fn write(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    let contents = contents.as_ref();
+    let partial = partial_path(path);
+    let written = retry(path, || async {
+        fs::write(&partial, contents).await?;
+        let len = fs::metadata(&partial).await?.len();
+        if len != contents.len() as u64 {
+            return Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                format!("only {} of {} bytes were written", len, contents.len()),
+            ));
+        }
+        fs::rename(&partial, path).await
+    })
+    .await;
+    if written.is_err() {
+        let _ = fs::remove_file(&partial).await;
+    }
+    written
+}
+
+/// Copies `from` over `to` the way `write` replaces files
+pub async fn copy(from: &Path, to: &Path) -> io::Result<()> {
+    let partial = partial_path(to);
+    let copied = retry(to, || async {
+        let len = fs::copy(from, &partial).await?;
+        if fs::metadata(&partial).await?.len() != len {
+            return Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                format!("copy of {} was cut short", from.display()),
+            ));
+        }
+        fs::rename(&partial, to).await
+    })
+    .await;
+    if copied.is_err() {
+        let _ = fs::remove_file(&partial).await;
+    }
+    copied
+}
+
+fn partial_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".partial");
+    path.with_file_name(name)
+}
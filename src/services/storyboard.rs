@@ -0,0 +1,121 @@
+// src/services/storyboard.rs
+use crate::config::app_config::FfmpegConfig;
+use crate::services::ffmpeg;
+use crate::services::probe::MediaInfo;
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+/// Kept in the video directory, so served under `/uploads`
+pub const DIR: &str = "storyboard";
+pub const VTT_FILE: &str = "storyboard.vtt";
+const TILE_WIDTH: u32 = 160;
+const COLUMNS: u32 = 10;
+const ROWS: u32 = 10;
+/// Shortest time a tile covers. Long videos get longer ones so the sheets
+/// stay few.
+const MIN_INTERVAL: f64 = 2.0;
+const MAX_SHEETS: u32 = 5;
+
+/// Where the storyboard of a video is, if it has one
+pub fn vtt_path(video_dir: &Path) -> PathBuf {
+    video_dir.join(DIR).join(VTT_FILE)
+}
+
+/// Seconds each tile covers for a video this long
+fn interval(duration: f64) -> f64 {
+    let tiles = (MAX_SHEETS * COLUMNS * ROWS) as f64;
+    (duration / tiles).ceil().max(MIN_INTERVAL)
+}
+
+/// Tile height keeping the source's aspect ratio, even as encoders want
+fn tile_height(width: u32, height: u32) -> u32 {
+    let scaled = (TILE_WIDTH as f64 * height as f64 / width as f64 / 2.0).round() as u32 * 2;
+    scaled.max(2)
+}
+
+/// Writes sprite sheets of frames sampled across the video, and a WebVTT
+/// storyboard mapping each time range to its tile with a `#xywh` fragment,
+/// as hover previews in Video.js and hls.js plugins expect. Not needed for
+/// playback, so callers only log failures.
+pub async fn generate(
+    input: &Path,
+    video_dir: &Path,
+    info: &MediaInfo,
+    duration: f64,
+    ffmpeg: &FfmpegConfig,
+) -> Result<()> {
+    // Frames are turned upright before they are tiled
+    let (width, height) = info
+        .display_dimensions()
+        .context("Source has no video stream")?;
+    if width == 0 || height == 0 || duration <= 0.0 {
+        anyhow::bail!("Source has no frames to sample");
+    }
+    let dir = video_dir.join(DIR);
+    // Sheets of an earlier processing may outnumber the new ones. For
+    // duplicates this is a link to the source's, which is left alone.
+    if fs::symlink_metadata(&dir).await.is_ok() {
+        fs::remove_dir_all(&dir).await?;
+    }
+    fs::create_dir_all(&dir).await?;
+
+    let interval = interval(duration);
+    let tile_height = tile_height(width, height);
+    let status = ffmpeg::command("ffmpeg", ffmpeg, &dir)
+        .args(ffmpeg::input_protocol_args())
+        .arg("-i")
+        .arg(input)
+        .arg("-vf")
+        .arg(format!(
+            "fps=1/{},scale={}:{},tile={}x{}",
+            interval, TILE_WIDTH, tile_height, COLUMNS, ROWS
+        ))
+        .args(["-an", "-q:v", "5", "-y", "-loglevel", "quiet"])
+        .arg(dir.join("sprite_%d.jpg"))
+        .status()
+        .await?;
+    if !status.success() {
+        anyhow::bail!("FFmpeg sprite generation failed");
+    }
+
+    let vtt = write_vtt(duration, interval, TILE_WIDTH, tile_height);
+    fs::write(dir.join(VTT_FILE), vtt).await?;
+    Ok(())
+}
+
+/// Storyboard of tiles `interval` seconds apart, filling each sheet row by
+/// row. Sheets are numbered from 1 like ffmpeg's output pattern.
+fn write_vtt(duration: f64, interval: f64, width: u32, height: u32) -> String {
+    let per_sheet = COLUMNS * ROWS;
+    let tiles = (duration / interval).ceil() as u32;
+    let mut vtt = String::from("WEBVTT\n");
+    for tile in 0..tiles {
+        let start = tile as f64 * interval;
+        let end = (start + interval).min(duration);
+        let index = tile % per_sheet;
+        vtt.push_str(&format!(
+            "\n{} --> {}\nsprite_{}.jpg#xywh={},{},{},{}\n",
+            timestamp(start),
+            timestamp(end),
+            tile / per_sheet + 1,
+            index % COLUMNS * width,
+            index / COLUMNS * height,
+            width,
+            height
+        ));
+    }
+    vtt
+}
+
+/// WebVTT timestamp, e.g. `01:02:03.400`
+fn timestamp(seconds: f64) -> String {
+    let millis = (seconds * 1000.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
@@ -0,0 +1,292 @@
+// src/services/subtitles.rs
+use crate::config::app_config::FfmpegConfig;
+use crate::db::models::Subtitle;
+use crate::services::{ffmpeg, probe, storage, video_processor};
+use anyhow::Result;
+use chrono::Utc;
+use diesel::{ExpressionMethods, QueryDsl};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use std::path::Path;
+use tokio::fs;
+use uuid::Uuid;
+
+/// `GROUP-ID` of the subtitle renditions in the master playlist
+pub const GROUP_ID: &str = "subs";
+/// Directory under `hls` holding every subtitle's WebVTT file and playlist
+const SUBTITLES_DIR: &str = "subtitles";
+/// Directory, under the video's and under `hls`, holding uploaded subtitles
+pub(crate) const UPLOADS_DIR: &str = "captions";
+/// Text formats ffmpeg converts to WebVTT. Bitmap subtitles, as on DVDs and
+/// Blu-rays, would need OCR.
+const TEXT_CODECS: &[&str] = &["mov_text", "subrip", "srt", "ass", "ssa", "webvtt", "text"];
+
+/// Converts the source's text subtitle streams to WebVTT under
+/// `hls/subtitles`. Streams that can't be converted are skipped, as the video
+/// plays fine without them.
+pub async fn extract(
+    v_id: Uuid,
+    input: &Path,
+    info: &probe::MediaInfo,
+    hls_dir: &Path,
+    config: &FfmpegConfig,
+) -> Vec<Subtitle> {
+    let subtitles_dir = hls_dir.join(SUBTITLES_DIR);
+    let mut subtitles: Vec<Subtitle> = Vec::new();
+    for (position, stream) in info.subtitle_streams().enumerate() {
+        let codec = stream.codec_name.as_deref().unwrap_or_default();
+        if !TEXT_CODECS.contains(&codec) {
+            log::info!(
+                "Skipping {} subtitle stream {} of video {}",
+                codec,
+                position,
+                v_id
+            );
+            continue;
+        }
+
+        let id = Uuid::new_v4();
+        let result = convert(input, position, &subtitles_dir, id, info, config).await;
+        if let Err(e) = result {
+            log::warn!(
+                "Failed to extract subtitle stream {} of video {}: {}",
+                position,
+                v_id,
+                e
+            );
+            continue;
+        }
+
+        let language = stream.language();
+        let mut name = stream
+            .tags
+            .title
+            .as_deref()
+            .map(str::trim)
+            .filter(|title| !title.is_empty())
+            .or(language.as_deref())
+            .map_or_else(|| format!("Subtitles {}", position + 1), str::to_string);
+        // Players tell renditions apart by name
+        if subtitles.iter().any(|subtitle| subtitle.name == name) {
+            name = format!("{} ({})", name, position + 1);
+        }
+        subtitles.push(Subtitle {
+            id,
+            video_id: v_id,
+            language,
+            name,
+            file_path: format!("hls/{}/{}.m3u8", SUBTITLES_DIR, id),
+            source: "embedded".to_string(),
+            is_default: stream.disposition.default == 1,
+            forced: stream.disposition.forced == 1,
+            created_at: Utc::now().naive_utc(),
+        });
+    }
+    subtitles
+}
+
+/// Writes `<id>.vtt` from the `position`-th subtitle stream, and the
+/// playlist with it as the only segment
+async fn convert(
+    input: &Path,
+    position: usize,
+    output_dir: &Path,
+    id: Uuid,
+    info: &probe::MediaInfo,
+    config: &FfmpegConfig,
+) -> Result<()> {
+    fs::create_dir_all(output_dir).await?;
+    let vtt = output_dir.join(format!("{}.vtt", id));
+    let status = ffmpeg::command("ffmpeg", config, output_dir)
+        .args(ffmpeg::input_protocol_args())
+        .arg("-i")
+        .arg(input)
+        .arg("-map")
+        .arg(format!("0:s:{}", position))
+        .args(["-c:s", "webvtt", "-f", "webvtt", "-loglevel", "quiet", "-y"])
+        .arg(&vtt)
+        .status()
+        .await?;
+    if !status.success() {
+        anyhow::bail!("FFmpeg subtitle conversion failed");
+    }
+
+    write_playlist(output_dir, id, info.duration().unwrap_or_default()).await
+}
+
+/// Writes `<id>.m3u8`, a playlist with `<id>.vtt` as the only segment
+async fn write_playlist(output_dir: &Path, id: Uuid, duration: f64) -> Result<()> {
+    let playlist = format!(
+        "#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-TARGETDURATION:{}\n#EXT-X-PLAYLIST-TYPE:VOD\n#EXTINF:{:.3},\n{}.vtt\n#EXT-X-ENDLIST\n",
+        duration.ceil().max(1.0),
+        duration,
+        id
+    );
+    storage::write(&output_dir.join(format!("{}.m3u8", id)), playlist).await?;
+    Ok(())
+}
+
+/// Converts an uploaded SRT or WebVTT file to the WebVTT file of uploaded
+/// subtitle `id`. It is kept outside `hls`, so repackaging the video doesn't
+/// lose it, and published there by `publish_uploaded`.
+pub async fn convert_upload(
+    input: &Path,
+    video_dir: &Path,
+    id: Uuid,
+    config: &FfmpegConfig,
+) -> Result<()> {
+    let output_dir = video_dir.join(UPLOADS_DIR);
+    fs::create_dir_all(&output_dir).await?;
+    // Both are plain text, so ffmpeg can't always tell them apart
+    let text = fs::read(input).await?;
+    let format = if String::from_utf8_lossy(&text)
+        .trim_start_matches('\u{feff}')
+        .starts_with("WEBVTT")
+    {
+        "webvtt"
+    } else {
+        "srt"
+    };
+
+    let status = ffmpeg::command("ffmpeg", config, &output_dir)
+        .args(ffmpeg::input_protocol_args())
+        .args(["-f", format, "-i"])
+        .arg(input)
+        .args(["-c:s", "webvtt", "-f", "webvtt", "-loglevel", "quiet", "-y"])
+        .arg(output_dir.join(format!("{}.vtt", id)))
+        .status()
+        .await?;
+    if !status.success() {
+        anyhow::bail!("FFmpeg could not read the file as {}", format);
+    }
+    Ok(())
+}
+
+/// Playlist path of uploaded subtitle `id`, in the form of `Subtitle::file_path`
+pub fn upload_file_path(id: Uuid) -> String {
+    format!("hls/{}/{}.m3u8", UPLOADS_DIR, id)
+}
+
+/// Copies uploaded subtitles, and those captioning vendors returned, into
+/// `hls/captions` with their playlists, e.g. after processing built a new
+/// package. Embedded subtitles are skipped.
+pub async fn publish_uploaded(
+    video_dir: &Path,
+    subtitles: &[Subtitle],
+    duration: f64,
+) -> Result<()> {
+    let output_dir = video_dir.join("hls").join(UPLOADS_DIR);
+    for subtitle in subtitles.iter().filter(|s| s.source != "embedded") {
+        fs::create_dir_all(&output_dir).await?;
+        let vtt = format!("{}.vtt", subtitle.id);
+        storage::copy(
+            &video_dir.join(UPLOADS_DIR).join(&vtt),
+            &output_dir.join(&vtt),
+        )
+        .await?;
+        write_playlist(&output_dir, subtitle.id, duration).await?;
+    }
+    Ok(())
+}
+
+/// Rewrites the master playlists of a packaged video to list `subtitles`.
+/// Does nothing before the video is packaged, as processing writes it with
+/// them.
+pub async fn rebuild_master(hls_dir: &Path, subtitles: &[Subtitle]) -> Result<()> {
+    let path = hls_dir.join("master.m3u8");
+    let master = match fs::read_to_string(&path).await {
+        Ok(master) => master,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    video_processor::write_masters(hls_dir, &with_subtitles(&master, subtitles)).await?;
+    Ok(())
+}
+
+/// Every subtitle of the video, oldest first
+pub async fn load(conn: &mut AsyncPgConnection, v_id: Uuid) -> Result<Vec<Subtitle>> {
+    use crate::db::schema::subtitles;
+
+    Ok(subtitles::table
+        .filter(subtitles::video_id.eq(v_id))
+        .order(subtitles::created_at.asc())
+        .load::<Subtitle>(conn)
+        .await?)
+}
+
+/// Replaces the subtitles extracted from the video's source
+pub async fn save_embedded(
+    conn: &mut AsyncPgConnection,
+    v_id: Uuid,
+    subtitles: &[Subtitle],
+) -> Result<()> {
+    use crate::db::schema::subtitles;
+
+    diesel::delete(
+        subtitles::table
+            .filter(subtitles::video_id.eq(v_id))
+            .filter(subtitles::source.eq("embedded")),
+    )
+    .execute(conn)
+    .await?;
+    diesel::insert_into(subtitles::table)
+        .values(subtitles)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// The master playlist with an `EXT-X-MEDIA` entry for each subtitle, and
+/// every variant referring to their group. Subtitles already in it are
+/// replaced.
+pub fn with_subtitles(master: &str, subtitles: &[Subtitle]) -> String {
+    let attribute = format!(",SUBTITLES=\"{}\"", GROUP_ID);
+    let mut media = subtitles.iter().map(media_tag).collect::<String>();
+    let mut playlist = String::with_capacity(master.len() + media.len());
+    for line in master.lines() {
+        if line.starts_with("#EXT-X-MEDIA:TYPE=SUBTITLES,") {
+            continue;
+        }
+        // Renditions go ahead of the variants
+        if line.starts_with("#EXT-X-MEDIA:") || line.starts_with("#EXT-X-STREAM-INF:") {
+            playlist.push_str(&std::mem::take(&mut media));
+        }
+        if line.starts_with("#EXT-X-STREAM-INF:") {
+            playlist.push_str(&line.replace(&attribute, ""));
+            if !subtitles.is_empty() {
+                playlist.push_str(&attribute);
+            }
+        } else {
+            playlist.push_str(line);
+        }
+        playlist.push('\n');
+    }
+    playlist
+}
+
+/// `EXT-X-MEDIA` entry of a subtitle, whose URI is relative to `hls`
+fn media_tag(subtitle: &Subtitle) -> String {
+    // Quoted strings can't contain quotes or line breaks
+    let quoted = |value: &str| value.replace(['"', '\r', '\n'], "");
+    let mut tag = format!(
+        "#EXT-X-MEDIA:TYPE=SUBTITLES,GROUP-ID=\"{}\",NAME=\"{}\"",
+        GROUP_ID,
+        quoted(&subtitle.name)
+    );
+    if let Some(language) = &subtitle.language {
+        tag.push_str(&format!(",LANGUAGE=\"{}\"", quoted(language)));
+    }
+    tag.push_str(if subtitle.is_default {
+        ",DEFAULT=YES,AUTOSELECT=YES"
+    } else {
+        ",DEFAULT=NO,AUTOSELECT=YES"
+    });
+    if subtitle.forced {
+        tag.push_str(",FORCED=YES");
+    }
+    let uri = subtitle
+        .file_path
+        .strip_prefix("hls/")
+        .unwrap_or(&subtitle.file_path);
+    tag.push_str(&format!(",URI=\"{}\"\n", uri));
+    tag
+}
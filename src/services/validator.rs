@@ -0,0 +1,233 @@
+// src/services/validator.rs
+use crate::services::playlist;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::path::Path;
+use tokio::fs;
+
+/// How far the segments may add up from the probed duration: a second, or a
+/// hundredth of long videos
+const DURATION_TOLERANCE_SECS: f64 = 1.0;
+const DURATION_TOLERANCE_RATIO: f64 = 0.01;
+/// Factor by which a variant's peak segment bitrate may exceed its declared
+/// `BANDWIDTH`. Renditions are encoded to an average bitrate, so busy scenes
+/// go over it somewhat.
+const PEAK_BANDWIDTH_FACTOR: f64 = 2.0;
+/// Shorter segments, such as the last one, are left out of the peak bitrate
+const MIN_PEAK_SEGMENT_SECS: f64 = 1.0;
+
+#[derive(Debug, Serialize)]
+pub struct ValidationReport {
+    pub valid: bool,
+    pub playlists_checked: usize,
+    pub segments_checked: usize,
+    pub problems: Vec<Problem>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Problem {
+    /// Relative to the package, e.g. `720p/stream.m3u8`
+    pub playlist: String,
+    pub message: String,
+}
+
+impl ValidationReport {
+    fn problem(&mut self, playlist: &str, message: String) {
+        self.valid = false;
+        self.problems.push(Problem {
+            playlist: playlist.to_string(),
+            message,
+        });
+    }
+}
+
+/// Checks the HLS package in `hls_dir` the way a player would read it: the
+/// master playlist and every playlist it lists must parse, their segments
+/// must exist, add up to the video's `duration` if known, and variants must
+/// declare a bandwidth their segments don't far exceed.
+pub async fn validate(hls_dir: &Path, duration: Option<f64>) -> ValidationReport {
+    let mut report = ValidationReport {
+        valid: true,
+        playlists_checked: 0,
+        segments_checked: 0,
+        problems: Vec::new(),
+    };
+
+    let master = match fs::read_to_string(hls_dir.join("master.m3u8")).await {
+        Ok(master) => master,
+        Err(e) => {
+            report.problem("master.m3u8", format!("Can't be read: {}", e));
+            return report;
+        }
+    };
+    report.playlists_checked += 1;
+    if master.lines().next().map(str::trim) != Some("#EXTM3U") {
+        report.problem("master.m3u8", "Doesn't start with #EXTM3U".to_string());
+    }
+
+    let variants = playlist::parse_variants(&master);
+    if variants.is_empty() {
+        report.problem("master.m3u8", "Lists no variant streams".to_string());
+    }
+    for variant in &variants {
+        let bandwidth = variant
+            .attribute("BANDWIDTH")
+            .and_then(|bandwidth| bandwidth.parse::<u64>().ok())
+            .filter(|&bandwidth| bandwidth > 0);
+        if bandwidth.is_none() {
+            report.problem(
+                "master.m3u8",
+                format!("Variant {} has no valid BANDWIDTH", variant.uri),
+            );
+        }
+        let Some(sizes) = check_media(&mut report, hls_dir, &variant.uri, duration).await else {
+            continue;
+        };
+        let peak = sizes
+            .iter()
+            .filter(|(duration, _)| *duration >= MIN_PEAK_SEGMENT_SECS)
+            .map(|(duration, size)| *size as f64 * 8.0 / duration)
+            .fold(0.0, f64::max);
+        if let Some(bandwidth) = bandwidth {
+            if peak > bandwidth as f64 * PEAK_BANDWIDTH_FACTOR {
+                report.problem(
+                    &variant.uri,
+                    format!(
+                        "Peak segment bitrate of {:.0} bps is more than {}x the declared BANDWIDTH of {}",
+                        peak, PEAK_BANDWIDTH_FACTOR, bandwidth
+                    ),
+                );
+            }
+        }
+    }
+
+    for rendition in playlist::parse_renditions(&master) {
+        // Audio in the variants' own segments has no URI
+        if let Some(uri) = rendition.attribute("URI") {
+            check_media(&mut report, hls_dir, uri, duration).await;
+        }
+    }
+    report
+}
+
+/// Checks a rendition just packaged into `quality_dir`, before the master
+/// playlist lists it: its `stream.m3u8` must be complete and every segment
+/// present and non-empty. Output cut short by a storage error fails here.
+pub async fn check_rendition(quality_dir: &Path) -> Result<(), String> {
+    let mut report = ValidationReport {
+        valid: true,
+        playlists_checked: 0,
+        segments_checked: 0,
+        problems: Vec::new(),
+    };
+    check_media(&mut report, quality_dir, "stream.m3u8", None).await;
+    if report.valid {
+        return Ok(());
+    }
+    Err(report
+        .problems
+        .into_iter()
+        .map(|problem| problem.message)
+        .collect::<Vec<_>>()
+        .join("; "))
+}
+
+/// Checks a media playlist of the package and its segments. Returns the
+/// duration and size of each segment when it could be read.
+async fn check_media(
+    report: &mut ValidationReport,
+    hls_dir: &Path,
+    uri: &str,
+    duration: Option<f64>,
+) -> Option<Vec<(f64, u64)>> {
+    // Generated packages only refer to their own files
+    if uri.contains("://") || uri.starts_with('/') || uri.split('/').any(|part| part == "..") {
+        report.problem("master.m3u8", format!("{} is outside the package", uri));
+        return None;
+    }
+    let path = hls_dir.join(uri);
+    let text = match fs::read_to_string(&path).await {
+        Ok(text) => text,
+        Err(e) => {
+            report.problem(uri, format!("Can't be read: {}", e));
+            return None;
+        }
+    };
+    report.playlists_checked += 1;
+    let media = match playlist::parse_media(&text) {
+        Ok(media) => media,
+        Err(e) => {
+            report.problem(uri, format!("Invalid playlist: {}", e));
+            return None;
+        }
+    };
+
+    if !media.ended {
+        report.problem(
+            uri,
+            "Has no EXT-X-ENDLIST, so players treat it as live".to_string(),
+        );
+    }
+    if media.segments.is_empty() {
+        report.problem(uri, "Has no segments".to_string());
+    }
+
+    let dir = path.parent().unwrap_or(hls_dir);
+    let mut sizes = Vec::with_capacity(media.segments.len());
+    let mut maps = HashSet::new();
+    for (segment, _) in &media.segments {
+        report.segments_checked += 1;
+        // Rounded, as the spec compares them
+        if segment.duration.round() as u64 > media.target_duration {
+            report.problem(
+                uri,
+                format!(
+                    "Segment {} lasts {:.3}s, longer than the target duration of {}s",
+                    segment.uri, segment.duration, media.target_duration
+                ),
+            );
+        }
+        let size = check_file(report, uri, dir, &segment.uri).await;
+        sizes.push((segment.duration, size.unwrap_or_default()));
+        if let Some(map) = &segment.map {
+            if maps.insert(map.as_str()) {
+                check_file(report, uri, dir, map).await;
+            }
+        }
+    }
+
+    if let Some(expected) = duration.filter(|_| !media.segments.is_empty()) {
+        let total: f64 = media.segments.iter().map(|(s, _)| s.duration).sum();
+        let tolerance = DURATION_TOLERANCE_SECS.max(expected * DURATION_TOLERANCE_RATIO);
+        if (total - expected).abs() > tolerance {
+            report.problem(
+                uri,
+                format!(
+                    "Segments add up to {:.3}s, but the video lasts {:.3}s",
+                    total, expected
+                ),
+            );
+        }
+    }
+    Some(sizes)
+}
+
+/// Size of a file a playlist refers to, reporting it if missing or empty
+async fn check_file(
+    report: &mut ValidationReport,
+    playlist: &str,
+    dir: &Path,
+    uri: &str,
+) -> Option<u64> {
+    match fs::metadata(dir.join(uri)).await {
+        Ok(metadata) if metadata.len() > 0 => Some(metadata.len()),
+        Ok(_) => {
+            report.problem(playlist, format!("{} is empty", uri));
+            None
+        }
+        Err(e) => {
+            report.problem(playlist, format!("{} can't be read: {}", uri, e));
+            None
+        }
+    }
+}
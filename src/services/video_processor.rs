@@ -1,246 +1,2361 @@
 // src/services/video_processor.rs
-use crate::db::models::VideoQuality;
+use crate::api::shared::{validation_error, ErrorCode};
+use crate::config::app_config::{
+    FfmpegConfig, RenditionConfig, TranscodingConfig, ValidationConfig, VIDEO_CODECS, X264_PRESETS,
+};
+use crate::config::AppConfig;
+use crate::db::models::{AudioTrack, VideoQuality};
 use crate::db::DbPool;
+use crate::services::blocking::BlockingPool;
+use crate::services::chunks::{self, Chunk};
+use crate::services::events::{ProgressEvent, ProgressEvents, UPLOAD_PROGRESS_STEP};
+use crate::services::scan::{self, ScanVerdict};
+use crate::services::stalled::Heartbeat;
+use crate::services::watermark::Watermark;
+use crate::services::{
+    audio_tracks, codecs, dedup, ffmpeg, integrity, jobs, keyframes, loudness, per_title, playlist,
+    preview, probe, profiles, remux, storage, storyboard, subtitles, validator, webhooks,
+};
+use actix_web::web::Bytes;
 use actix_web::{web, Error};
 use anyhow::{Context, Result};
 use chrono::Utc;
-use diesel::ExpressionMethods;
-use diesel_async::{AsyncPgConnection, RunQueryDsl};
-use serde_json::Value;
+use diesel::{ExpressionMethods, QueryDsl};
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+use futures::{Future, Stream, TryStreamExt};
+use sha2::{Digest, Sha256};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::fs::{self, OpenOptions};
 use tokio::io::AsyncWriteExt;
-use tokio::process::Command;
 use uuid::Uuid;
 
 const CHUNK_DURATION: u32 = 6; // Duration of each HLS segment in seconds
-const QUALITIES: &[(&str, &str)] = &[
-    ("1080p", "5000k"),
-    ("720p", "2800k"),
-    ("480p", "1400k"),
-    ("360p", "800k"),
-];
-
-pub async fn handle_upload(
-    video_data: Vec<u8>,
+const KEYFRAME_INTERVAL: f64 = 2.0; // Seconds between forced keyframes
+const DEFAULT_FPS: f64 = 24.0; // Assumed when the source frame rate is unknown
+const COPY_TOLERANCE: f64 = 0.1; // How far a source may exceed a rung and still be stream-copied
+const PROGRESS_SAVE_INTERVAL: Duration = Duration::from_secs(2); // How often encoding progress is written to the DB
+
+pub const HDR_QUALITY: (&str, &str) = ("1080p-hdr", "6000k");
+/// Short edge the HDR rendition is scaled down to
+const HDR_HEIGHT: u32 = 1080;
+const HDR_CODEC: &str = "hvc1.2.4.L123.B0";
+/// Input of ffmpeg reading an upload piped in as it arrives
+const PIPE_INPUT: &str = "pipe:0";
+/// Bitrate of audio renditions when the ladder is empty
+const DEFAULT_AUDIO_BITRATE: &str = "128k";
+/// Review proxy rendition, packaged in the video's `review` directory rather
+/// than under `hls` so it never shows up in public playback
+pub const REVIEW_QUALITY: (&str, &str) = ("review", "800k");
+/// Short edge of the review proxy
+const REVIEW_HEIGHT: u32 = 360;
+/// Master playlist for data saver modes, next to `master.m3u8`
+pub const LOW_MASTER: &str = "master_low.m3u8";
+/// Variants above this are left out of `LOW_MASTER`. Fits the default 480p
+/// rung with its audio.
+const LOW_MASTER_MAX_BANDWIDTH: u64 = 2_000_000;
+// Linearize, map BT.2020 primaries to BT.709 and tone-map so HDR sources don't
+// come out washed-out in the SDR ladder. Requires ffmpeg built with libzimg.
+const TONEMAP_FILTER: &str = "zscale=t=linear:npl=100,format=gbrpf32le,zscale=p=bt709,\
+tonemap=tonemap=hable:desat=0,zscale=t=bt709:m=bt709:r=tv,format=yuv420p";
+// Only frames flagged as interlaced are touched, so mixed sources are safe
+const DEINTERLACE_FILTER: &str = "bwdif=mode=send_frame:parity=auto:deint=interlaced";
+
+/// The stage processing stopped at, attached as context to the errors of its
+/// steps so the video records an error code and a reason its uploader can
+/// act on rather than the underlying error
+#[derive(Debug, Clone, Copy)]
+pub struct ProcessingFailure {
+    pub code: ErrorCode,
+    pub reason: &'static str,
+}
+
+impl std::fmt::Display for ProcessingFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.reason)
+    }
+}
+
+impl ProcessingFailure {
+    /// Any stage without a more specific failure
+    pub const OTHER: ProcessingFailure = ProcessingFailure {
+        code: ErrorCode::ProcessingFailed,
+        reason: "Processing failed",
+    };
+    const PROBE: ProcessingFailure = ProcessingFailure {
+        code: ErrorCode::ProbeFailed,
+        reason: "Processing failed while reading the upload",
+    };
+    const TRANSCODE: ProcessingFailure = ProcessingFailure {
+        code: ErrorCode::TranscodeFailed,
+        reason: "Processing failed while transcoding: every rendition failed",
+    };
+    const THUMBNAILS: ProcessingFailure = ProcessingFailure {
+        code: ErrorCode::ThumbnailFailed,
+        reason: "Processing failed while generating thumbnails",
+    };
+    const PACKAGING: ProcessingFailure = ProcessingFailure {
+        code: ErrorCode::PackagingFailed,
+        reason: "Processing failed while writing the master playlist",
+    };
+
+    /// The failure recorded for an error returned by a processing step
+    pub fn of(error: &anyhow::Error) -> ProcessingFailure {
+        error
+            .downcast_ref::<ProcessingFailure>()
+            .copied()
+            .unwrap_or(ProcessingFailure::OTHER)
+    }
+}
+
+/// The original being packaged, with the watermark every rendition carries
+struct Source<'a> {
+    video_id: Uuid,
+    path: &'a Path,
+    info: &'a probe::MediaInfo,
+    watermark: Option<&'a Watermark>,
+    /// Audio filter normalizing the loudness of the default audio track
+    loudnorm: Option<String>,
+    /// Set when the source has several audio tracks, which are then packaged
+    /// as audio renditions and left out of the video renditions
+    separate_audio: bool,
+}
+
+/// A stored upload's SHA-256, and its media info if it could be probed while
+/// the content streamed in
+pub struct SavedUpload {
+    pub checksum: String,
+    pub info: Option<probe::MediaInfo>,
+}
+
+/// Streams an upload body (a multipart field or raw request payload) straight
+/// into the video's original file so large uploads never have to fit in
+/// memory, probing it on the way if `ffmpeg.probe_while_uploading` is set
+/// and remuxing it if `ffmpeg.remux_while_uploading` is.
+/// Partial files are removed on failure. `total_bytes` is the expected size,
+/// if known, for progress events; `filename` is the client's name for the
+/// file, used for its extension.
+pub async fn save_upload<S, E>(
+    body: &mut S,
     v_id: Uuid,
-    pool: web::Data<DbPool>,
-) -> Result<(), Error> {
+    max_file_size: usize,
+    total_bytes: Option<u64>,
+    filename: Option<&str>,
+    config: &AppConfig,
+    events: &ProgressEvents,
+) -> Result<SavedUpload, Error>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    E: Into<Error>,
+{
     let upload_dir = get_video_dir(v_id);
     fs::create_dir_all(&upload_dir).await.map_err(|e| {
         log::error!("Failed to create upload directory: {}", e);
         actix_web::error::ErrorInternalServerError("Storage error")
     })?;
 
-    let filepath = upload_dir.join("original.mp4");
-    // Write the video data to file
-    let mut f = OpenOptions::new()
-        .create(true)
-        .write(true)
-        .truncate(true)
-        .open(&filepath)
-        .await
-        .map_err(|e| {
-            log::error!("Failed to open file: {}", e);
-            actix_web::error::ErrorInternalServerError("Storage error")
-        })?;
+    let ffmpeg = &config.ffmpeg;
+    let mut stream_probe = None;
+    if ffmpeg.probe_while_uploading {
+        match probe::StreamProbe::start(&upload_dir, ffmpeg) {
+            Ok(started) => stream_probe = Some(started),
+            Err(e) => log::warn!("Failed to start probing upload {}: {}", v_id, e),
+        }
+    }
+    let mut stream_remux = None;
+    if let Some(args) = remux_args(&config.transcoding, ffmpeg) {
+        match remux::StreamRemux::start(&upload_dir, args, ffmpeg).await {
+            Ok(started) => stream_remux = Some(started),
+            Err(e) => log::warn!("Failed to start remuxing upload {}: {}", v_id, e),
+        }
+    }
+
+    let mut reported = 0;
+    let report = |received_bytes: u64| {
+        events.publish(
+            v_id,
+            ProgressEvent::Upload {
+                received_bytes,
+                total_bytes,
+            },
+        )
+    };
+    let result = write_body(
+        body,
+        &upload_dir.join(original_file_name(filename)),
+        max_file_size,
+        |chunk, written| {
+            if let Some(stream_probe) = &mut stream_probe {
+                stream_probe.feed(chunk);
+            }
+            if let Some(stream_remux) = &mut stream_remux {
+                stream_remux.feed(chunk);
+            }
+            if written - reported >= UPLOAD_PROGRESS_STEP {
+                reported = written;
+                report(written);
+            }
+        },
+    )
+    .await;
+    if let Ok((_, written)) = &result {
+        report(*written);
+    }
+    let checksum = match result {
+        Ok((checksum, _)) => checksum,
+        Err(e) => {
+            if let Err(e) = fs::remove_dir_all(&upload_dir).await {
+                log::error!("Failed to remove partial upload {}: {}", v_id, e);
+            }
+            return Err(e);
+        }
+    };
+
+    let info = match stream_probe {
+        Some(stream_probe) => stream_probe.finish().await,
+        None => None,
+    };
+    if let Some(stream_remux) = stream_remux {
+        stream_remux.finish().await;
+    }
+    Ok(SavedUpload { checksum, info })
+}
+
+/// Returns the checksum and size of the written file
+pub(crate) async fn write_body<S, E>(
+    body: &mut S,
+    filepath: &Path,
+    max_file_size: usize,
+    mut on_chunk: impl FnMut(&Bytes, u64),
+) -> Result<(String, u64), Error>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    E: Into<Error>,
+{
+    let mut f = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(filepath)
+        .await
+        .map_err(|e| {
+            log::error!("Failed to open file: {}", e);
+            actix_web::error::ErrorInternalServerError("Storage error")
+        })?;
+
+    let mut written = 0;
+    let mut hasher = Sha256::new();
+    while let Some(chunk) = body.try_next().await.map_err(Into::into)? {
+        written += chunk.len();
+        if written > max_file_size {
+            return Err(actix_web::error::ErrorPayloadTooLarge(
+                "Video file exceeds maximum upload size",
+            ));
+        }
+
+        f.write_all(&chunk).await.map_err(|e| {
+            log::error!("Error writing file: {}", e);
+            actix_web::error::ErrorInternalServerError("Storage error")
+        })?;
+        hasher.update(&chunk);
+        on_chunk(&chunk, written as u64);
+    }
+
+    f.sync_all().await.map_err(|e| {
+        log::error!("Error syncing file: {}", e);
+        actix_web::error::ErrorInternalServerError("Storage error")
+    })?;
+
+    Ok((hex::encode(hasher.finalize()), written as u64))
+}
+
+/// Fails the upload when the client supplied a checksum that doesn't match the
+/// stored file: the file is removed and the video marked failed
+pub async fn verify_checksum(
+    v_id: Uuid,
+    actual: &str,
+    expected: Option<&str>,
+    conn: &mut AsyncPgConnection,
+) -> Result<(), Error> {
+    use crate::db::schema::videos;
+
+    let Some(expected) = expected else {
+        return Ok(());
+    };
+    if expected.trim().eq_ignore_ascii_case(actual) {
+        return Ok(());
+    }
+
+    let reason = "Uploaded file does not match the provided SHA-256 checksum";
+    if let Err(e) = fs::remove_dir_all(get_video_dir(v_id)).await {
+        log::error!("Failed to remove upload {}: {}", v_id, e);
+    }
+
+    diesel::update(videos::table)
+        .filter(videos::id.eq(v_id))
+        .set((
+            videos::status.eq("failed"),
+            videos::status_reason.eq(reason),
+            videos::error_code.eq(ErrorCode::ChecksumMismatch.as_str()),
+        ))
+        .execute(conn)
+        .await
+        .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?;
+    webhooks::notify(conn, v_id).await;
+
+    Err(validation_error(
+        "checksum".to_string(),
+        reason.to_string(),
+        ErrorCode::ChecksumMismatch,
+    ))
+}
+
+/// Runs a stored upload through the content scan (if configured), validation
+/// and into processing. Without a scan the video is moved to "processing" or
+/// "failed" before this returns; with one it is left "scanning" and the rest
+/// happens in the background. `info` is the upload's media info if it was
+/// already probed, e.g. by `save_upload`. `conn` is only used until this
+/// returns; the background work takes its own from `pool`.
+pub async fn ingest(
+    v_id: Uuid,
+    info: Option<probe::MediaInfo>,
+    conn: &mut AsyncPgConnection,
+    pool: web::Data<DbPool>,
+    config: Arc<AppConfig>,
+    events: web::Data<ProgressEvents>,
+) -> Result<(), Error> {
+    use crate::db::schema::videos;
+
+    if !config.scan.enabled() {
+        return finish_ingest(v_id, info, conn, config, events).await;
+    }
+
+    diesel::update(videos::table)
+        .filter(videos::id.eq(v_id))
+        .filter(videos::status.eq("uploading"))
+        .set((
+            videos::status.eq("scanning"),
+            videos::updated_at.eq(Utc::now().naive_utc()),
+        ))
+        .execute(conn)
+        .await
+        .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?;
+
+    // Scans can take a while, so uploads don't wait for them. finish_ingest
+    // returns actix errors, which aren't Send, so stay on this worker.
+    actix_web::rt::spawn(async move {
+        // Holds the scan's lease, see `recovery::rescan_expired`
+        let heartbeat = Heartbeat::start(v_id, "scanning", pool.clone());
+        let clean = scan_upload(v_id, &pool, &config, &events).await;
+        drop(heartbeat);
+        if clean {
+            match pool.get().await {
+                Ok(mut conn) => {
+                    let _ = finish_ingest(v_id, info, &mut conn, config, events).await;
+                }
+                Err(e) => log::error!("Failed to ingest scanned upload {}: {}", v_id, e),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Scans a stored upload, rejecting or failing the video unless it passes
+async fn scan_upload(
+    v_id: Uuid,
+    pool: &DbPool,
+    config: &AppConfig,
+    events: &ProgressEvents,
+) -> bool {
+    use crate::db::schema::videos;
+
+    let upload_dir = get_video_dir(v_id);
+    let verdict = match find_original(&upload_dir).await {
+        Ok(path) => scan::scan(v_id, &path, &config.scan).await,
+        Err(e) => Err(e.into()),
+    };
+
+    let reason = match verdict {
+        Ok(ScanVerdict::Clean) => return true,
+        Ok(ScanVerdict::Rejected(report)) => {
+            log::warn!(
+                "Upload {} rejected by content scan: {}",
+                v_id,
+                report.as_deref().unwrap_or("no details")
+            );
+            let reason = "File was rejected by the content scan";
+            match pool.get().await {
+                Ok(mut conn) => {
+                    let code = ErrorCode::ScanRejected;
+                    reject_upload(v_id, &upload_dir, code, reason, &mut conn).await;
+                }
+                Err(e) => log::error!("Error recording rejection for {}: {}", v_id, e),
+            }
+            reason
+        }
+        Err(e) => {
+            log::error!("Failed to scan upload {}: {}", v_id, e);
+            let reason = "Video could not be scanned";
+            let conn = &mut pool.get().await.expect("Failed to get DB connection");
+            match diesel::update(videos::table)
+                .filter(videos::id.eq(v_id))
+                .filter(videos::status.eq("scanning"))
+                .set((
+                    videos::status.eq("failed"),
+                    videos::status_reason.eq(reason),
+                    videos::error_code.eq(ErrorCode::ScanFailed.as_str()),
+                ))
+                .execute(conn)
+                .await
+            {
+                Ok(0) => {}
+                Ok(_) => webhooks::notify(conn, v_id).await,
+                Err(e) => log::error!("Error updating video status: {}", e),
+            }
+            reason
+        }
+    };
+    events.publish(
+        v_id,
+        ProgressEvent::Failed {
+            reason: reason.to_string(),
+        },
+    );
+    false
+}
+
+/// Validates a stored (and scanned) upload and starts processing it, moving
+/// the video to "processing" or "failed" accordingly
+async fn finish_ingest(
+    v_id: Uuid,
+    info: Option<probe::MediaInfo>,
+    conn: &mut AsyncPgConnection,
+    config: Arc<AppConfig>,
+    events: web::Data<ProgressEvents>,
+) -> Result<(), Error> {
+    use crate::db::schema::videos;
+
+    match handle_upload(v_id, info, conn, config, &events).await {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            log::error!("Failed to handle upload: {}", e);
+            let reason = "Video could not be processed";
+            events.publish(
+                v_id,
+                ProgressEvent::Failed {
+                    reason: reason.to_string(),
+                },
+            );
+            // Leave rows the processor already marked as rejected alone
+            let updated = diesel::update(videos::table)
+                .filter(videos::id.eq(v_id))
+                .filter(videos::status.eq_any(["uploading", "scanning"]))
+                .set((
+                    videos::status.eq("failed"),
+                    videos::status_reason.eq(reason),
+                    videos::error_code.eq(ErrorCode::IngestFailed.as_str()),
+                ))
+                .execute(conn)
+                .await
+                .map_err(|_e| actix_web::error::ErrorInternalServerError("Database error"))?;
+            if updated > 0 {
+                webhooks::notify(conn, v_id).await;
+            }
+            Err(e)
+        }
+    }
+}
+
+async fn handle_upload(
+    v_id: Uuid,
+    info: Option<probe::MediaInfo>,
+    conn: &mut AsyncPgConnection,
+    config: Arc<AppConfig>,
+    events: &ProgressEvents,
+) -> Result<(), Error> {
+    use crate::db::schema::videos;
+
+    let upload_dir = fs::canonicalize(get_video_dir(v_id)).await.map_err(|e| {
+        log::error!("Failed to resolve upload directory: {}", e);
+        actix_web::error::ErrorInternalServerError("Storage error")
+    })?;
+    let filepath = find_original(&upload_dir).await.map_err(|e| {
+        log::error!("Failed to find upload for {}: {}", v_id, e);
+        actix_web::error::ErrorInternalServerError("Storage error")
+    })?;
+
+    // Identical content that was already packaged can reuse that package
+    match dedup::link_duplicate(v_id, conn).await {
+        Ok(true) => {
+            remux::remove(&upload_dir).await;
+            events.publish(v_id, ProgressEvent::Done);
+            webhooks::notify(conn, v_id).await;
+            return Ok(());
+        }
+        Ok(false) => {}
+        Err(e) => log::error!("Failed to check {} for duplicates: {}", v_id, e),
+    }
+
+    // Probe the upload and reject anything that isn't a usable video
+    let probed = match info {
+        Some(info) => Ok(info),
+        None => probe::probe(&filepath, &config.ffmpeg).await,
+    };
+    let validation = match probed {
+        Ok(info) => validate_upload(&info, &config.validation).map(|_| info),
+        Err(e) => {
+            log::warn!("Failed to probe upload {}: {}", v_id, e);
+            Err((
+                ErrorCode::UnreadableMedia,
+                "File is not a readable media file".to_string(),
+            ))
+        }
+    };
+
+    let info = match validation {
+        Ok(info) => info,
+        Err((code, reason)) => {
+            reject_upload(v_id, &upload_dir, code, &reason, conn).await;
+            events.publish(
+                v_id,
+                ProgressEvent::Failed {
+                    reason: reason.clone(),
+                },
+            );
+            return Err(validation_error(
+                "video".to_string(),
+                reason,
+                ErrorCode::InvalidMedia,
+            ));
+        }
+    };
+
+    // Store the original under the extension of its actual container
+    let current_ext = filepath.extension().and_then(|ext| ext.to_str());
+    if let Some(ext) = info.container_extension(current_ext) {
+        if current_ext != Some(ext.as_str()) {
+            fs::rename(&filepath, filepath.with_extension(&ext))
+                .await
+                .map_err(|e| {
+                    log::error!("Failed to rename upload for {}: {}", v_id, e);
+                    actix_web::error::ErrorInternalServerError("Storage error")
+                })?;
+        }
+    }
+
+    if let Some(duration) = info.duration() {
+        diesel::update(videos::table)
+            .filter(videos::id.eq(v_id))
+            .set(videos::duration.eq(duration))
+            .execute(conn)
+            .await
+            .map_err(|e| {
+                log::error!("Error updating video duration: {}", e);
+                actix_web::error::ErrorInternalServerError("Database error")
+            })?;
+    }
+
+    // Moved to "processing" along with queueing its job, so a worker never
+    // takes the job of a video that's still "uploading" or "scanning"
+    let queued = conn
+        .transaction::<_, diesel::result::Error, _>(|conn| {
+            async move {
+                let updated = diesel::update(videos::table)
+                    .filter(videos::id.eq(v_id))
+                    .filter(videos::status.eq_any(["uploading", "scanning"]))
+                    .set(videos::status.eq("processing"))
+                    .execute(conn)
+                    .await?;
+                if updated > 0 {
+                    jobs::enqueue(v_id, conn).await?;
+                }
+                Ok(updated > 0)
+            }
+            .scope_boxed()
+        })
+        .await
+        .map_err(|e| {
+            log::error!("Error queueing video {} for processing: {}", v_id, e);
+            actix_web::error::ErrorInternalServerError("Database error")
+        })?;
+    if queued {
+        webhooks::notify(conn, v_id).await;
+    }
+
+    Ok(())
+}
+
+/// What every step of a video's processing works from. The probe step plans
+/// it and records its decisions; the steps after it rebuild it from the
+/// original and those records.
+struct Pipeline {
+    video_id: Uuid,
+    profile: TranscodingConfig,
+    video_dir: PathBuf,
+    hls_dir: PathBuf,
+    input_path: PathBuf,
+    info: probe::MediaInfo,
+    watermark: Option<Watermark>,
+    tracks: Vec<AudioTrack>,
+    /// Audio filter normalizing the loudness of the default audio track
+    loudnorm: Option<String>,
+    /// The rungs of the ladder packaged, with any per-title bitrates
+    renditions: Vec<RenditionConfig>,
+}
+
+impl Pipeline {
+    /// The original and its probe, with the rungs of the ladder that fit it
+    async fn open(
+        conn: &mut AsyncPgConnection,
+        video_id: Uuid,
+        config: &AppConfig,
+    ) -> Result<Pipeline> {
+        use crate::db::schema::videos;
+
+        let profile = profiles::for_video(conn, video_id, config).await?;
+        let video_dir = fs::canonicalize(get_video_dir(video_id)).await?;
+        let input_path = find_original(&video_dir)
+            .await
+            .context(ProcessingFailure::PROBE)?;
+        let hls_dir = video_dir.join("hls");
+        fs::create_dir_all(&hls_dir).await?;
+
+        let info = probe::probe(&input_path, &config.ffmpeg)
+            .await
+            .context(ProcessingFailure::PROBE)?;
+        let watermark_position = videos::table
+            .find(video_id)
+            .select(videos::watermark_position)
+            .first::<Option<String>>(conn)
+            .await?;
+        let watermark =
+            Watermark::for_video(&video_dir, watermark_position.as_deref(), &config.watermark)
+                .await;
+        let renditions = renditions_for_source(&profile.renditions, &info)
+            .into_iter()
+            .cloned()
+            .collect();
+        Ok(Pipeline {
+            video_id,
+            profile,
+            video_dir,
+            hls_dir,
+            input_path,
+            info,
+            watermark,
+            tracks: Vec::new(),
+            loudnorm: None,
+            renditions,
+        })
+    }
+
+    /// The pipeline as the probe step planned it
+    async fn load(
+        conn: &mut AsyncPgConnection,
+        video_id: Uuid,
+        config: &AppConfig,
+    ) -> Result<Pipeline> {
+        use crate::db::schema::video_qualities;
+
+        let mut pipeline = Self::open(conn, video_id, config).await?;
+        pipeline.tracks = audio_tracks::load(conn, video_id).await?;
+        if config.loudness.normalize && pipeline.tracks.iter().any(|track| track.is_default) {
+            pipeline.loudnorm = loudness::load(conn, video_id)
+                .await?
+                .map(|measurement| loudness::filter(&measurement, &config.loudness));
+        }
+        // Per-title bitrates are only kept on the quality rows
+        let bitrates = video_qualities::table
+            .filter(video_qualities::video_id.eq(video_id))
+            .select((video_qualities::resolution, video_qualities::bitrate))
+            .load::<(String, String)>(conn)
+            .await?;
+        for rendition in &mut pipeline.renditions {
+            if let Some((_, bitrate)) = bitrates.iter().find(|(name, _)| *name == rendition.name) {
+                rendition.video_bitrate = bitrate.clone();
+            }
+        }
+        Ok(pipeline)
+    }
+
+    fn source(&self) -> Source<'_> {
+        Source {
+            video_id: self.video_id,
+            path: &self.input_path,
+            info: &self.info,
+            watermark: self.watermark.as_ref(),
+            loudnorm: self.loudnorm.clone(),
+            separate_audio: self.tracks.len() > 1,
+        }
+    }
+
+    /// Audio renditions are shared by every rung, so they get the best
+    /// rung's bitrate
+    fn audio_bitrate(&self) -> &str {
+        self.renditions
+            .iter()
+            .map(|r| r.audio_bitrate.as_str())
+            .max_by_key(|bitrate| parse_bitrate(bitrate).unwrap_or(0))
+            .unwrap_or(DEFAULT_AUDIO_BITRATE)
+    }
+
+    /// Rendition names of the audio tracks, when they are packaged apart
+    fn audio_qualities(&self) -> Vec<String> {
+        if self.tracks.len() > 1 {
+            self.tracks.iter().map(audio_tracks::quality).collect()
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn hdr(&self, ffmpeg: &FfmpegConfig) -> Option<probe::HdrFormat> {
+        self.info
+            .video_stream()
+            .and_then(probe::Stream::hdr_format)
+            .filter(|_| ffmpeg.hdr_rendition)
+    }
+
+    /// Rungs the ladder step encodes together from a single decode: all the
+    /// re-encoded ones, unless the source is long enough to be encoded in
+    /// chunks, which each rendition does on its own
+    fn shared_renditions(&self, ffmpeg: &FfmpegConfig) -> Vec<&RenditionConfig> {
+        let duration = self.info.duration().unwrap_or_default();
+        let chunked = ffmpeg.chunk_secs > 0
+            && !chunks::plan(duration, ffmpeg.chunk_secs, self.profile.segment_duration).is_empty();
+        if !ffmpeg.shared_decode || chunked {
+            return Vec::new();
+        }
+        let source = self.source();
+        let shared: Vec<_> = self
+            .renditions
+            .iter()
+            .filter(|r| copied_video(&source, r, ffmpeg).is_none())
+            .collect();
+        if shared.len() < 2 {
+            return Vec::new();
+        }
+        shared
+    }
+
+    fn source_fps(&self) -> f64 {
+        self.info
+            .video_stream()
+            .and_then(probe::Stream::frame_rate)
+            .unwrap_or(DEFAULT_FPS)
+    }
+}
+
+/// The renditions `plan_video` decided on, by the steps encoding them
+pub struct Plan {
+    /// Encoded by a rendition step each
+    pub qualities: Vec<String>,
+    /// Encoded together by the ladder step
+    pub ladder: Vec<String>,
+}
+
+/// The probe step of processing: probes the original, decides on its
+/// renditions and records each as pending
+pub async fn plan_video(
+    video_id: Uuid,
+    conn: &mut AsyncPgConnection,
+    config: &AppConfig,
+) -> Result<Plan> {
+    let (ffmpeg, review) = (&config.ffmpeg, &config.review);
+    let mut pipeline = Pipeline::open(conn, video_id, config).await?;
+    let ladder = &pipeline.profile.renditions;
+    let info = &pipeline.info;
+
+    let tracks = audio_tracks::detect(video_id, info);
+    audio_tracks::save(conn, video_id, &tracks).await?;
+    let default_track = tracks.iter().find(|track| track.is_default);
+    if let Some(track) = default_track.filter(|_| config.loudness.normalize) {
+        match loudness::measure(
+            video_id,
+            &pipeline.input_path,
+            track.position,
+            &config.loudness,
+            ffmpeg,
+        )
+        .await
+        {
+            Ok(measurement) => {
+                if let Err(e) = loudness::save(conn, &measurement).await {
+                    log::error!("Failed to store loudness of video {}: {}", video_id, e);
+                }
+            }
+            Err(e) => {
+                log::warn!(
+                    "Not normalizing loudness of video {}, measuring failed: {}",
+                    video_id,
+                    e
+                );
+                loudness::remove(conn, video_id).await?;
+            }
+        }
+    }
+    if pipeline.renditions.len() < ladder.len() {
+        log::info!(
+            "Skipping {} rendition(s) above the source resolution of video {}",
+            ladder.len() - pipeline.renditions.len(),
+            video_id
+        );
+    }
+    if pipeline.profile.per_title {
+        let renditions: Vec<_> = pipeline.renditions.iter().collect();
+        match per_title::scale_ladder(
+            &pipeline.input_path,
+            info,
+            &renditions,
+            ffmpeg,
+            &pipeline.video_dir,
+        )
+        .await
+        {
+            Ok(ladder) => {
+                log::info!(
+                    "Per-title bitrates for video {}: {}",
+                    video_id,
+                    ladder
+                        .iter()
+                        .map(|r| format!("{} {}", r.name, r.video_bitrate))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+                pipeline.renditions = ladder;
+            }
+            Err(e) => {
+                log::error!(
+                    "Per-title analysis of video {} failed, using the configured bitrates: {}",
+                    video_id,
+                    e
+                );
+            }
+        }
+    }
+    pipeline.tracks = tracks;
+    let info = &pipeline.info;
+
+    // Record every planned rendition up front so clients can follow progress
+    let mut planned: Vec<_> = pipeline
+        .renditions
+        .iter()
+        .map(|r| {
+            let fps = output_fps(r, info);
+            let dimensions = output_dimensions(r, info).unwrap_or_default();
+            let menu = (rendition_label(r, fps), fps, dimensions);
+            (r.name.as_str(), r.video_bitrate.as_str(), Some(menu))
+        })
+        .collect();
+    let audio_bitrate = pipeline.audio_bitrate();
+    let audio_qualities = pipeline.audio_qualities();
+    for quality in &audio_qualities {
+        planned.push((quality, audio_bitrate, None));
+    }
+    let source_fps = pipeline.source_fps();
+    if pipeline.hdr(ffmpeg).is_some() {
+        let (quality, bitrate) = HDR_QUALITY;
+        let menu = (
+            hdr_label(info, source_fps),
+            source_fps,
+            hdr_dimensions(info),
+        );
+        planned.push((quality, bitrate, Some(menu)));
+    }
+    if review.proxy {
+        let (quality, bitrate) = REVIEW_QUALITY;
+        let label = format!("{} review", quality_label(REVIEW_HEIGHT, source_fps));
+        let menu = (label, source_fps, review_dimensions(info));
+        planned.push((quality, bitrate, Some(menu)));
+    }
+    let pending = planned
+        .iter()
+        .map(|&(quality, bitrate, ref menu)| VideoQuality {
+            id: Uuid::new_v4(),
+            video_id,
+            resolution: quality.to_string(),
+            bitrate: bitrate.to_string(),
+            file_path: if quality == REVIEW_QUALITY.0 {
+                "review/stream.m3u8".to_string()
+            } else {
+                format!("hls/{}/stream.m3u8", quality)
+            },
+            created_at: Utc::now().naive_utc(),
+            state: "pending".to_string(),
+            progress: 0,
+            attempts: 0,
+            error: None,
+            label: menu.as_ref().map(|(label, _, _)| label.clone()),
+            frame_rate: menu.as_ref().map(|&(_, fps, _)| fps),
+            width: menu.as_ref().map(|&(_, _, (width, _))| width as i32),
+            height: menu.as_ref().map(|&(_, _, (_, height))| height as i32),
+        })
+        .collect::<Vec<_>>();
+    diesel::insert_into(crate::db::schema::video_qualities::table)
+        .values(&pending)
+        .execute(conn)
+        .await?;
+
+    let ladder: Vec<_> = pipeline
+        .shared_renditions(ffmpeg)
+        .iter()
+        .map(|r| r.name.clone())
+        .collect();
+    Ok(Plan {
+        qualities: pending
+            .into_iter()
+            .map(|quality| quality.resolution)
+            .filter(|quality| !ladder.contains(quality))
+            .collect(),
+        ladder,
+    })
+}
+
+/// A rendition step of processing: encodes one of the renditions
+/// `plan_video` planned, which may be an audio track, the HDR rendition or
+/// the review proxy, recording how it went on its quality row
+pub async fn encode_quality(
+    video_id: Uuid,
+    quality: &str,
+    conn: &mut AsyncPgConnection,
+    pool: &DbPool,
+    config: &AppConfig,
+    events: &ProgressEvents,
+    blocking: &BlockingPool,
+) -> Result<()> {
+    let ffmpeg = &config.ffmpeg;
+    let pipeline = Pipeline::load(conn, video_id, config).await?;
+    let source = pipeline.source();
+    let profile = &pipeline.profile;
+    let quality_dir = if quality == REVIEW_QUALITY.0 {
+        pipeline.video_dir.join("review")
+    } else {
+        pipeline.hls_dir.join(quality)
+    };
+    fs::create_dir_all(&quality_dir).await?;
+    let output_path = quality_dir.join("stream.m3u8");
+    set_quality_state(conn, video_id, quality, "encoding").await;
+
+    let progress = AtomicU8::new(0);
+    let on_progress = || transcode_progress(events, video_id, quality, &progress);
+    let track = pipeline
+        .tracks
+        .iter()
+        .find(|track| source.separate_audio && audio_tracks::quality(track) == quality);
+    let rendition = pipeline.renditions.iter().find(|r| r.name == quality);
+    let result = if let Some(track) = track {
+        let audio_bitrate = pipeline.audio_bitrate();
+        let transcode = || {
+            transcode_audio_to_hls(
+                &source,
+                track,
+                &output_path,
+                audio_bitrate,
+                profile,
+                ffmpeg,
+                on_progress(),
+            )
+        };
+        transcode_with_retries(
+            conn,
+            video_id,
+            &[(quality, &quality_dir)],
+            &progress,
+            ffmpeg,
+            transcode,
+        )
+        .await
+    } else if let Some(rendition) = rendition {
+        let transcode = || {
+            transcode_to_hls(
+                &source,
+                &output_path,
+                rendition,
+                profile,
+                ffmpeg,
+                pool,
+                on_progress(),
+            )
+        };
+        transcode_with_retries(
+            conn,
+            video_id,
+            &[(quality, &quality_dir)],
+            &progress,
+            ffmpeg,
+            transcode,
+        )
+        .await
+    } else if let Some(hdr) = pipeline.hdr(ffmpeg).filter(|_| quality == HDR_QUALITY.0) {
+        let transcode = || {
+            transcode_hdr_to_hls(
+                &source,
+                &output_path,
+                hdr,
+                profile.segment_duration,
+                ffmpeg,
+                on_progress(),
+            )
+        };
+        transcode_with_retries(
+            conn,
+            video_id,
+            &[(quality, &quality_dir)],
+            &progress,
+            ffmpeg,
+            transcode,
+        )
+        .await
+    } else if quality == REVIEW_QUALITY.0 {
+        let transcode = || {
+            transcode_review_proxy(
+                &pipeline.input_path,
+                &output_path,
+                &config.review.watermark,
+                &pipeline.info,
+                ffmpeg,
+                on_progress(),
+            )
+        };
+        transcode_with_retries(
+            conn,
+            video_id,
+            &[(quality, &quality_dir)],
+            &progress,
+            ffmpeg,
+            transcode,
+        )
+        .await
+    } else {
+        Err(anyhow::anyhow!("Quality {} isn't planned", quality))
+    };
+
+    finish_quality(
+        conn,
+        video_id,
+        quality,
+        &output_path,
+        events,
+        blocking,
+        result.as_ref().err(),
+    )
+    .await;
+    result
+}
+
+/// The ladder step of processing: encodes the renditions
+/// `Pipeline::shared_renditions` picks with one ffmpeg, which decodes the
+/// source once for all of them
+pub async fn encode_ladder(
+    video_id: Uuid,
+    conn: &mut AsyncPgConnection,
+    config: &AppConfig,
+    events: &ProgressEvents,
+    blocking: &BlockingPool,
+) -> Result<()> {
+    let ffmpeg = &config.ffmpeg;
+    let pipeline = Pipeline::load(conn, video_id, config).await?;
+    let source = pipeline.source();
+    let renditions = pipeline.shared_renditions(ffmpeg);
+    let quality_dirs: Vec<_> = renditions
+        .iter()
+        .map(|r| pipeline.hls_dir.join(&r.name))
+        .collect();
+    for (rendition, quality_dir) in renditions.iter().zip(&quality_dirs) {
+        fs::create_dir_all(quality_dir).await?;
+        set_quality_state(conn, video_id, &rendition.name, "encoding").await;
+    }
+    let outputs: Vec<_> = renditions
+        .iter()
+        .zip(&quality_dirs)
+        .map(|(r, quality_dir)| (r.name.as_str(), quality_dir.as_path()))
+        .collect();
+    let playlists: Vec<_> = renditions
+        .iter()
+        .zip(&quality_dirs)
+        .map(|(&r, quality_dir)| (r, quality_dir.join("stream.m3u8")))
+        .collect();
+
+    let progress = AtomicU8::new(0);
+    let transcode = || {
+        let mut each: Vec<_> = renditions
+            .iter()
+            .map(|r| transcode_progress(events, video_id, &r.name, &progress))
+            .collect();
+        transcode_ladder(
+            &source,
+            &playlists,
+            &pipeline.profile,
+            ffmpeg,
+            move |percent| each.iter_mut().for_each(|on_progress| on_progress(percent)),
+        )
+    };
+    let result =
+        transcode_with_retries(conn, video_id, &outputs, &progress, ffmpeg, transcode).await;
+    for (rendition, output_path) in &playlists {
+        finish_quality(
+            conn,
+            video_id,
+            &rendition.name,
+            output_path,
+            events,
+            blocking,
+            result.as_ref().err(),
+        )
+        .await;
+    }
+    result
+}
+
+/// Records how encoding a rendition into `output_path` went, once it's over
+async fn finish_quality(
+    conn: &mut AsyncPgConnection,
+    video_id: Uuid,
+    quality: &str,
+    output_path: &Path,
+    events: &ProgressEvents,
+    blocking: &BlockingPool,
+    error: Option<&anyhow::Error>,
+) {
+    match error {
+        None => {
+            // The review proxy isn't part of the package
+            if quality != REVIEW_QUALITY.0 {
+                if let Err(e) = embed_checksums(output_path, blocking).await {
+                    log::error!("Failed to write checksums for {}: {}", quality, e);
+                }
+            }
+
+            save_progress(conn, video_id, quality, 100).await;
+            set_quality_state(conn, video_id, quality, "ready").await;
+            events.publish(
+                video_id,
+                ProgressEvent::QualityReady {
+                    quality: quality.to_string(),
+                },
+            );
+        }
+        Some(e) => {
+            // The other renditions carry on, finalize decides if enough came out
+            log::error!("Giving up on quality {} of {}: {}", quality, video_id, e);
+            set_quality_state(conn, video_id, quality, "failed").await;
+            events.publish(
+                video_id,
+                ProgressEvent::QualityFailed {
+                    quality: quality.to_string(),
+                },
+            );
+        }
+    }
+}
+
+/// The thumbnails step of processing: thumbnails, which the video can't do
+/// without, then the keyframe index, storyboard and preview, which it can
+pub async fn generate_extras(
+    video_id: Uuid,
+    config: &AppConfig,
+    events: &ProgressEvents,
+) -> Result<()> {
+    let ffmpeg = &config.ffmpeg;
+    let video_dir = fs::canonicalize(get_video_dir(video_id)).await?;
+    let input_path = find_original(&video_dir).await?;
+    let info = probe::probe(&input_path, ffmpeg).await?;
+    let duration = info.duration().context("The source has no duration")?;
+
+    events.publish(video_id, ProgressEvent::Thumbnails);
+    generate_thumbnails(&input_path, &video_dir, ffmpeg).await?;
+
+    if let Err(e) = keyframes::write_index(&video_dir, &input_path, ffmpeg).await {
+        log::error!("Failed to index keyframes of video {}: {}", video_id, e);
+    }
+    if let Err(e) = storyboard::generate(&input_path, &video_dir, &info, duration, ffmpeg).await {
+        log::error!("Failed to generate storyboard of video {}: {}", video_id, e);
+    }
+    if let Err(e) = preview::generate(&input_path, &video_dir, duration, ffmpeg).await {
+        log::error!("Failed to generate preview of video {}: {}", video_id, e);
+    }
+    Ok(())
+}
+
+/// The last step of processing, once every other is over: writes the master
+/// playlist of the renditions that came out ready and marks the video
+/// processed. Fails the video if none did, or if its thumbnails failed.
+pub async fn finalize_video(
+    video_id: Uuid,
+    conn: &mut AsyncPgConnection,
+    config: &AppConfig,
+    thumbnails_error: Option<String>,
+) -> Result<()> {
+    use crate::db::schema::{video_qualities, videos};
+
+    let ffmpeg = &config.ffmpeg;
+    let pipeline = Pipeline::load(conn, video_id, config).await?;
+    let (source, info, hls_dir) = (pipeline.source(), &pipeline.info, &pipeline.hls_dir);
+    let ready = video_qualities::table
+        .filter(video_qualities::video_id.eq(video_id))
+        .filter(video_qualities::state.eq("ready"))
+        .select(video_qualities::resolution)
+        .load::<String>(conn)
+        .await?;
+    let is_ready = |quality: &str| ready.iter().any(|ready| ready == quality);
+
+    // fMP4 segments need a newer playlist version
+    let mut master_version = if ffmpeg.fmp4() || pipeline.renditions.iter().any(|r| r.needs_fmp4())
+    {
+        7
+    } else {
+        3
+    };
+    let mut master_playlist = String::new();
+
+    // Audio renditions first, as every variant refers to their group
+    let mut audio_media = String::new();
+    let audio_bitrate = pipeline.audio_bitrate();
+    if source.separate_audio {
+        let packaged: Vec<&AudioTrack> = pipeline
+            .tracks
+            .iter()
+            .filter(|track| is_ready(&audio_tracks::quality(track)))
+            .collect();
+        if packaged.is_empty() {
+            return Err(anyhow::anyhow!("Every audio track failed to transcode")
+                .context(ProcessingFailure::TRANSCODE));
+        }
+        // Should the default track fail, the first that didn't takes its place
+        let default = packaged
+            .iter()
+            .position(|track| track.is_default)
+            .unwrap_or(0);
+        for (i, track) in packaged.into_iter().enumerate() {
+            audio_media.push_str(&audio_tracks::media_tag(
+                track,
+                i == default,
+                &format!("{}/stream.m3u8", audio_tracks::quality(track)),
+            ));
+        }
+    }
+    // Variants are played with the group's audio, so their bandwidth
+    // includes it
+    let (audio_bandwidth, audio_group) = if source.separate_audio {
+        (
+            parse_bitrate(audio_bitrate)?,
+            format!(",AUDIO=\"{}\"", audio_tracks::GROUP_ID),
+        )
+    } else {
+        (0, String::new())
+    };
+
+    // Variants grouped by codec, in the order of `VIDEO_CODECS`
+    let mut variants: Vec<(usize, String)> = Vec::new();
+    for rendition in pipeline.renditions.iter().filter(|r| is_ready(&r.name)) {
+        let bandwidth = parse_bitrate(&rendition.video_bitrate)? + audio_bandwidth;
+        let fps = output_fps(rendition, info);
+        let (width, height) = output_dimensions(rendition, info).unwrap_or_default();
+        let group = VIDEO_CODECS
+            .iter()
+            .position(|&codec| codec == rendition.codec())
+            .unwrap_or(0);
+        variants.push((
+            group,
+            format!(
+                "#EXT-X-STREAM-INF:BANDWIDTH={},RESOLUTION={}x{},CODECS=\"{}\",FRAME-RATE={:.3},NAME=\"{}\"{}\n{}/stream.m3u8\n",
+                bandwidth,
+                width,
+                height,
+                rendition_codecs(&source, rendition, ffmpeg),
+                fps,
+                rendition_label(rendition, fps),
+                audio_group,
+                rendition.name
+            ),
+        ));
+    }
+    // Stable, so each group stays highest quality first
+    variants.sort_by_key(|&(group, _)| group);
+    for (_, variant) in variants {
+        master_playlist.push_str(&variant);
+    }
+
+    if let Some(hdr) = pipeline.hdr(ffmpeg).filter(|_| is_ready(HDR_QUALITY.0)) {
+        let (quality, bitrate) = HDR_QUALITY;
+        let source_fps = pipeline.source_fps();
+        // fMP4 segments and VIDEO-RANGE need a newer playlist version
+        master_version = 7;
+        let hdr_codecs = if source.separate_audio {
+            format!("{},{}", HDR_CODEC, codecs::AAC_LC)
+        } else {
+            HDR_CODEC.to_string()
+        };
+        let (width, height) = hdr_dimensions(info);
+        master_playlist.push_str(&format!(
+            "#EXT-X-STREAM-INF:BANDWIDTH={},RESOLUTION={}x{},CODECS=\"{}\",VIDEO-RANGE={},FRAME-RATE={:.3},NAME=\"{}\"{}\n{}/stream.m3u8\n",
+            parse_bitrate(bitrate)? + audio_bandwidth,
+            width,
+            height,
+            hdr_codecs,
+            hdr.video_range(),
+            source_fps,
+            hdr_label(info, source_fps),
+            audio_group,
+            quality
+        ));
+    }
+
+    if master_playlist.is_empty() {
+        return Err(anyhow::anyhow!("Every rendition failed to transcode")
+            .context(ProcessingFailure::TRANSCODE));
+    }
+    if let Some(error) = thumbnails_error {
+        return Err(anyhow::anyhow!(error).context(ProcessingFailure::THUMBNAILS));
+    }
+
+    let duration = info
+        .duration()
+        .context("The video has no duration")
+        .context(ProcessingFailure::PROBE)?;
+    let embedded = subtitles::extract(video_id, &pipeline.input_path, info, hls_dir, ffmpeg).await;
+    if let Err(e) = subtitles::save_embedded(conn, video_id, &embedded).await {
+        log::error!("Failed to store subtitles of video {}: {}", video_id, e);
+    }
+    // Uploaded ones too, which may predate this package
+    let published = match subtitles::load(conn, video_id).await {
+        Ok(all) => subtitles::publish_uploaded(&pipeline.video_dir, &all, duration)
+            .await
+            .map(|()| all),
+        Err(e) => Err(e),
+    };
+    let subtitles = published.unwrap_or_else(|e| {
+        log::error!(
+            "Failed to publish uploaded subtitles of video {}: {}",
+            video_id,
+            e
+        );
+        embedded
+    });
+
+    // Write master playlist
+    let master_playlist = subtitles::with_subtitles(
+        &format!(
+            "#EXTM3U\n#EXT-X-VERSION:{}\n{}{}",
+            master_version, audio_media, master_playlist
+        ),
+        &subtitles,
+    );
+    write_masters(hls_dir, &master_playlist)
+        .await
+        .context(ProcessingFailure::PACKAGING)?;
+    // Caught here rather than by viewers
+    let report = validator::validate(hls_dir, Some(duration)).await;
+    for problem in &report.problems {
+        log::warn!(
+            "Video {} package problem in {}: {}",
+            video_id,
+            problem.playlist,
+            problem.message
+        );
+    }
+
+    // Only once the master playlist is in place, so viewers never see a
+    // processed video they can't play, and unless it was canceled meanwhile
+    match diesel::update(videos::table)
+        .filter(videos::id.eq(video_id))
+        .filter(videos::status.eq("processing"))
+        .set((
+            videos::status.eq("processed"),
+            videos::duration.eq(Some(duration)),
+        ))
+        .execute(conn)
+        .await
+    {
+        Ok(_) => {}
+        Err(e) => {
+            log::error!("Failed to update video status: {e}");
+        }
+    };
+
+    // Sent only now so consumers find the master playlist and thumbnails
+    webhooks::notify(conn, video_id).await;
+    Ok(())
+}
+
+/// `CODECS` of a rendition of the ladder, with AAC for its audio
+fn rendition_codecs(
+    source: &Source<'_>,
+    rendition: &RenditionConfig,
+    ffmpeg: &FfmpegConfig,
+) -> String {
+    let info = source.info;
+    let mut codec_names = vec![match copied_video(source, rendition, ffmpeg) {
+        Some(copied) => copied,
+        None => codecs::video(
+            rendition.codec(),
+            output_dimensions(rendition, info).unwrap_or_default(),
+            output_fps(rendition, info),
+        ),
+    }];
+    if info.audio_stream().is_some() {
+        codec_names.push(codecs::AAC_LC.to_string());
+    }
+    codec_names.join(",")
+}
+
+/// Encodes a rendition of the ladder. Long sources are encoded in chunks,
+/// unless the video stream is copied, which are then joined into the
+/// rendition. With `ffmpeg.distribute_chunks` the chunks are queued for
+/// every worker to help with.
+async fn transcode_to_hls(
+    source: &Source<'_>,
+    output: &Path,
+    rendition: &RenditionConfig,
+    profile: &TranscodingConfig,
+    ffmpeg: &FfmpegConfig,
+    pool: &DbPool,
+    mut on_progress: impl FnMut(f64),
+) -> Result<()> {
+    let info = source.info;
+    let copied = copied_video(source, rendition, ffmpeg);
+    let duration = info.duration().unwrap_or_default();
+    let planned = if copied.is_none() && ffmpeg.chunk_secs > 0 {
+        chunks::plan(duration, ffmpeg.chunk_secs, profile.segment_duration)
+    } else {
+        Vec::new()
+    };
+    if copied.is_some() && take_remux(source, output, rendition, profile, ffmpeg).await {
+        on_progress(100.0);
+        return Ok(());
+    }
+    if planned.is_empty() {
+        encode_rendition(
+            source,
+            output,
+            rendition,
+            profile,
+            ffmpeg,
+            None,
+            on_progress,
+        )
+        .await?;
+        return Ok(());
+    }
+
+    // The original is kept in the video's directory
+    let video_dir = source.path.parent().context("Invalid source path")?;
+    let dir = chunks::rendition_dir(video_dir, &rendition.name);
+    let mut pending = Vec::new();
+    for chunk in &planned {
+        if chunks::is_done(&dir, chunk).await? {
+            log::info!(
+                "Reusing chunk {} of {}, encoded before",
+                chunk.name(),
+                rendition.name
+            );
+        } else {
+            pending.push(*chunk);
+        }
+    }
+
+    if ffmpeg.distribute_chunks {
+        // Chunk jobs run in the chunk's directory
+        let queued = pending
+            .into_iter()
+            .map(|chunk| {
+                let args = rendition_args(
+                    source,
+                    Path::new("stream.m3u8"),
+                    rendition,
+                    profile,
+                    ffmpeg,
+                    Some(&chunk),
+                );
+                (chunk, args)
+            })
+            .collect();
+        let conn = &mut pool.get().await?;
+        chunks::enqueue(conn, source.video_id, &rendition.name, queued).await?;
+        chunks::wait(conn, source.video_id, &rendition.name, ffmpeg, on_progress).await?;
+    } else {
+        for chunk in &pending {
+            let partial = chunks::start(&dir, chunk).await?;
+            let length = chunk.duration(duration);
+            let on_progress =
+                |percent| on_progress((chunk.start + length * percent / 100.0) / duration * 100.0);
+            encode_rendition(
+                source,
+                &partial.join("stream.m3u8"),
+                rendition,
+                profile,
+                ffmpeg,
+                Some(chunk),
+                on_progress,
+            )
+            .await?;
+            chunks::finish(&dir, chunk, &partial).await?;
+        }
+    }
+    chunks::join(&dir, &planned, output).await?;
+    Ok(())
+}
+
+/// Runs ffmpeg for several renditions of the ladder at once
+async fn transcode_ladder(
+    source: &Source<'_>,
+    outputs: &[(&RenditionConfig, PathBuf)],
+    profile: &TranscodingConfig,
+    ffmpeg: &FfmpegConfig,
+    on_progress: impl FnMut(f64),
+) -> Result<()> {
+    let mut cmd = ffmpeg::command("ffmpeg", ffmpeg, source.path.parent().unwrap());
+    cmd.args(ladder_args(source, outputs, profile, ffmpeg));
+    let status = ffmpeg::run_with_progress(&mut cmd, source.info.duration(), on_progress).await?;
+
+    if !status.success() {
+        return Err(anyhow::anyhow!("FFmpeg transcoding failed"));
+    }
+    Ok(())
+}
+
+/// Runs ffmpeg for a rendition, or for one chunk of it
+async fn encode_rendition(
+    source: &Source<'_>,
+    output: &Path,
+    rendition: &RenditionConfig,
+    profile: &TranscodingConfig,
+    ffmpeg: &FfmpegConfig,
+    chunk: Option<&Chunk>,
+    on_progress: impl FnMut(f64),
+) -> Result<()> {
+    let mut cmd = ffmpeg::command("ffmpeg", ffmpeg, output.parent().unwrap());
+    cmd.args(rendition_args(
+        source, output, rendition, profile, ffmpeg, chunk,
+    ));
+    let duration = match chunk {
+        Some(chunk) => source
+            .info
+            .duration()
+            .map(|duration| chunk.duration(duration)),
+        None => source.info.duration(),
+    };
+    let status = ffmpeg::run_with_progress(&mut cmd, duration, on_progress).await?;
+
+    if !status.success() {
+        return Err(anyhow::anyhow!("FFmpeg transcoding failed"));
+    }
+    Ok(())
+}
+
+/// ffmpeg arguments encoding a rendition, or one chunk of it, to the
+/// playlist `output`
+fn rendition_args(
+    source: &Source<'_>,
+    output: &Path,
+    rendition: &RenditionConfig,
+    profile: &TranscodingConfig,
+    ffmpeg: &FfmpegConfig,
+    chunk: Option<&Chunk>,
+) -> Vec<String> {
+    let (info, watermark) = (source.info, source.watermark);
+    let mut args = input_args(source, chunk);
+    if chunk.is_none() && copied_video(source, rendition, ffmpeg).is_some() {
+        log::info!("Source matches {}, copying video stream", rendition.name);
+        args.extend(["-c:v".to_string(), "copy".to_string()]);
+    } else {
+        // Scaled last, so a watermark keeps its size relative to the frame
+        let mut filters = video_filters(info, rendition);
+        let scale = scale_filter(output_dimensions(rendition, info).unwrap_or_default());
+        let filtergraph = match watermark {
+            Some(watermark) => format!("{},{}", watermark.filtergraph(&filters.join(",")), scale),
+            None => {
+                filters.push(scale);
+                filters.join(",")
+            }
+        };
+        args.extend(["-vf".to_string(), filtergraph]);
+        args.extend(encoder_args(info, rendition, profile, ffmpeg));
+    }
+    args.extend(audio_args(source, rendition, ffmpeg));
+    args.extend(["-loglevel", "quiet"].map(String::from));
+    args.extend(ffmpeg::progress_args().map(String::from));
+    args.extend(hls_args(output, rendition, profile, ffmpeg, chunk));
+    args
+}
+
+/// ffmpeg arguments segmenting an upload piped in as it arrives the way
+/// `rendition_args` segments a source whose video and audio it copies, or
+/// None when no rung of the ladder could be copied
+fn remux_args(profile: &TranscodingConfig, ffmpeg: &FfmpegConfig) -> Option<Vec<String>> {
+    if !ffmpeg.remux_while_uploading || !ffmpeg.stream_copy {
+        return None;
+    }
+    // Only H.264 rungs copy the source
+    let rendition = profile.renditions.iter().find(|r| r.codec() == "h264")?;
+    let mut args: Vec<String> = ffmpeg::input_protocol_args().map(String::from).into();
+    args.extend(["-i", PIPE_INPUT, "-c:v", "copy", "-c:a", "copy"].map(String::from));
+    args.extend(["-loglevel", "quiet"].map(String::from));
+    args.extend(ffmpeg::progress_args().map(String::from));
+    args.extend(hls_args(
+        Path::new("stream.m3u8"),
+        rendition,
+        profile,
+        ffmpeg,
+        None,
+    ));
+    Some(args)
+}
+
+/// Moves the upload's remux into place as `rendition`, if it was segmented
+/// exactly as `rendition_args` would have segmented the source from a pipe
+async fn take_remux(
+    source: &Source<'_>,
+    output: &Path,
+    rendition: &RenditionConfig,
+    profile: &TranscodingConfig,
+    ffmpeg: &FfmpegConfig,
+) -> bool {
+    let (Some(video_dir), Some(output_dir), Some(playlist)) =
+        (source.path.parent(), output.parent(), output.file_name())
+    else {
+        return false;
+    };
+    let piped = Source {
+        path: Path::new(PIPE_INPUT),
+        loudnorm: source.loudnorm.clone(),
+        ..*source
+    };
+    let args = rendition_args(
+        &piped,
+        Path::new(playlist),
+        rendition,
+        profile,
+        ffmpeg,
+        None,
+    );
+    match remux::take(video_dir, &args, &rendition.name, output_dir).await {
+        Ok(taken) => {
+            if taken {
+                log::info!("Using the upload's remux as {}", rendition.name);
+            }
+            taken
+        }
+        Err(e) => {
+            log::warn!(
+                "Failed to use the upload's remux as {}: {}",
+                rendition.name,
+                e
+            );
+            false
+        }
+    }
+}
+
+/// ffmpeg arguments encoding several renditions at once, one HLS output
+/// each. The source is decoded once and filtered once up to its watermark,
+/// then its frames are split between the renditions to be scaled.
+fn ladder_args(
+    source: &Source<'_>,
+    outputs: &[(&RenditionConfig, PathBuf)],
+    profile: &TranscodingConfig,
+    ffmpeg: &FfmpegConfig,
+) -> Vec<String> {
+    let (info, watermark) = (source.info, source.watermark);
+    let mut args = input_args(source, None);
+
+    let shared = shared_filters(info).join(",");
+    let head = match watermark {
+        Some(watermark) => watermark.filtergraph(&shared),
+        None if shared.is_empty() => "null".to_string(),
+        None => shared,
+    };
+    let mut filtergraph = format!("{},split={}", head, outputs.len());
+    for i in 0..outputs.len() {
+        filtergraph.push_str(&format!("[split{}]", i));
+    }
+    for (i, (rendition, _)) in outputs.iter().enumerate() {
+        let mut filters: Vec<_> = frame_rate_filter(info, rendition).into_iter().collect();
+        filters.push(scale_filter(
+            output_dimensions(rendition, info).unwrap_or_default(),
+        ));
+        filtergraph.push_str(&format!(";[split{}]{}[video{}]", i, filters.join(","), i));
+    }
+    args.extend(["-filter_complex".to_string(), filtergraph]);
+    args.extend(["-loglevel", "quiet"].map(String::from));
+    args.extend(ffmpeg::progress_args().map(String::from));
+
+    for (i, (rendition, output)) in outputs.iter().enumerate() {
+        args.extend(["-map".to_string(), format!("[video{}]", i)]);
+        if !source.separate_audio {
+            args.extend(["-map", "0:a:0?"].map(String::from));
+        }
+        args.extend(encoder_args(info, rendition, profile, ffmpeg));
+        args.extend(audio_args(source, rendition, ffmpeg));
+        args.extend(hls_args(output, rendition, profile, ffmpeg, None));
+    }
+    args
+}
+
+/// The input, or for a chunk the part of it the chunk covers. Chunks keep
+/// the source's timestamps, so their segments play back to back.
+fn input_args(source: &Source<'_>, chunk: Option<&Chunk>) -> Vec<String> {
+    let mut args: Vec<String> = Vec::new();
+    let mut push = |items: &[&str]| args.extend(items.iter().map(|item| item.to_string()));
+    push(&ffmpeg::input_protocol_args());
+    // Seeking before the input is fast, and exact when re-encoding
+    if let Some(chunk) = chunk {
+        push(&["-ss", &format!("{:.3}", chunk.start)]);
+    }
+    push(&["-i", &source.path.to_string_lossy()]);
+    if let Some(chunk) = chunk {
+        if let Some(length) = chunk.length {
+            push(&["-t", &format!("{:.3}", length)]);
+        }
+        push(&["-output_ts_offset", &format!("{:.3}", chunk.start)]);
+    }
+    args
+}
+
+/// The video encoder of a rendition and its settings
+fn encoder_args(
+    info: &probe::MediaInfo,
+    rendition: &RenditionConfig,
+    profile: &TranscodingConfig,
+    ffmpeg: &FfmpegConfig,
+) -> Vec<String> {
+    let mut args: Vec<String> = Vec::new();
+    let mut push = |items: &[&str]| args.extend(items.iter().map(|item| item.to_string()));
+    let output_fps = output_fps(rendition, info);
+    // Keep keyframes on a fixed time grid whatever the output frame rate
+    let gop = ((output_fps * KEYFRAME_INTERVAL).round() as u32).max(1);
+
+    if info
+        .video_stream()
+        .and_then(probe::Stream::hdr_format)
+        .is_some()
+    {
+        push(&["-color_primaries", "bt709", "-color_trc", "bt709"]);
+        push(&["-colorspace", "bt709"]);
+    }
+
+    let dimensions = output_dimensions(rendition, info).unwrap_or_default();
+    let codec = rendition.codec();
+    let level = codecs::encoder_level(codec, dimensions, output_fps).unwrap_or_default();
+    let preset = profile.preset.as_ref().unwrap_or(&ffmpeg.preset);
+    match codec {
+        "hevc" => {
+            // hvc1 sample entries, as Apple players don't accept hev1
+            push(&["-c:v", "libx265", "-tag:v", "hvc1", "-profile:v", "main"]);
+            push(&["-preset", preset]);
+            push(&["-x265-params", &format!("scenecut=0:level-idc={}", level)]);
+        }
+        "av1" => {
+            push(&["-c:v", "libsvtav1"]);
+            push(&["-preset", &svt_av1_preset(preset).to_string()]);
+        }
+        _ => {
+            push(&["-c:v", "libx264", "-profile:v", "high"]);
+            push(&["-level:v", &level.to_string()]);
+            push(&["-preset", preset, "-sc_threshold", "0"]);
+        }
+    }
+    push(&["-pix_fmt", "yuv420p", "-b:v", &rendition.video_bitrate]);
+    push(&["-threads", &ffmpeg.thread_count.to_string()]);
+    push(&["-g", &gop.to_string(), "-keyint_min", &gop.to_string()]);
+    args
+}
+
+/// The audio of a rendition: none when the tracks are packaged apart, else
+/// the source's AAC as-is when nothing needs filtering, or AAC at the
+/// rendition's bitrate
+fn audio_args(
+    source: &Source<'_>,
+    rendition: &RenditionConfig,
+    ffmpeg: &FfmpegConfig,
+) -> Vec<String> {
+    let info = source.info;
+    let mut args: Vec<String> = Vec::new();
+    let mut push = |items: &[&str]| args.extend(items.iter().map(|item| item.to_string()));
+    if source.separate_audio {
+        push(&["-an"]);
+        return args;
+    }
+
+    let vfr = info
+        .video_stream()
+        .is_some_and(probe::Stream::is_variable_frame_rate);
+    let mut audio_filters = Vec::new();
+    if vfr {
+        log::info!(
+            "Normalizing variable frame rate source to {:.3} fps",
+            output_fps(rendition, info)
+        );
+        audio_filters.push("aresample=async=1");
+    }
+    if let Some(loudnorm) = source.loudnorm.as_deref() {
+        audio_filters.push(loudnorm);
+    }
+    if !audio_filters.is_empty() {
+        push(&["-af", &audio_filters.join(",")]);
+    }
+
+    // Copied only when it fits the rung's audio bitrate, so low rungs don't
+    // carry a high bitrate source track
+    let max_audio_bitrate = parse_bitrate(&rendition.audio_bitrate).ok();
+    let copy_audio = ffmpeg.stream_copy
+        && audio_filters.is_empty()
+        && info.audio_stream().is_some_and(|s| {
+            s.codec_name.as_deref() == Some("aac")
+                && s.profile.as_deref() == Some("LC")
+                && s.bit_rate()
+                    .zip(max_audio_bitrate)
+                    .is_some_and(|(bitrate, max)| bitrate <= u64::from(max))
+        });
+    if copy_audio {
+        push(&["-c:a", "copy"]);
+    } else {
+        push(&["-c:a", "aac", "-b:a", &rendition.audio_bitrate]);
+    }
+    args
+}
 
-    f.write_all(&video_data).await.map_err(|e| {
-        log::error!("Error writing file: {}", e);
-        actix_web::error::ErrorInternalServerError("Storage error")
-    })?;
+/// HLS muxer settings writing a rendition, or a chunk of it, to the playlist
+/// `output`, which may be relative to the directory ffmpeg runs in
+fn hls_args(
+    output: &Path,
+    rendition: &RenditionConfig,
+    profile: &TranscodingConfig,
+    ffmpeg: &FfmpegConfig,
+    chunk: Option<&Chunk>,
+) -> Vec<String> {
+    let mut args: Vec<String> = Vec::new();
+    let mut push = |items: &[&str]| args.extend(items.iter().map(|item| item.to_string()));
+    // Chunks name their segments after themselves, so they don't clash once
+    // joined
+    let prefix = chunk.map_or("segment".to_string(), |chunk| {
+        format!("segment_{}", chunk.name())
+    });
+    // CMAF segments go with an init segment that ffmpeg names init.mp4
+    let segment_name = if ffmpeg.fmp4() || rendition.needs_fmp4() {
+        push(&["-hls_segment_type", "fmp4"]);
+        if let Some(chunk) = chunk {
+            push(&[
+                "-hls_fmp4_init_filename",
+                &format!("init_{}.mp4", chunk.name()),
+            ]);
+        }
+        format!("{}_%03d.m4s", prefix)
+    } else {
+        format!("{}_%03d.ts", prefix)
+    };
+    push(&["-hls_time", &profile.segment_duration.to_string()]);
+    push(&["-hls_playlist_type", "vod"]);
+    push(&[
+        "-hls_segment_filename",
+        &output.with_file_name(segment_name).to_string_lossy(),
+    ]);
+    push(&[&output.to_string_lossy()]);
+    args
+}
 
-    f.sync_all().await.map_err(|e| {
-        log::error!("Error syncing file: {}", e);
-        actix_web::error::ErrorInternalServerError("Storage error")
-    })?;
+/// Filters the video of a rendition needs before any watermark and scaling
+fn video_filters(info: &probe::MediaInfo, rendition: &RenditionConfig) -> Vec<String> {
+    let mut filters = Vec::new();
 
-    // Get video duration before processing
-    if let Ok(duration) = get_video_duration(&filepath.to_string_lossy()).await {
-        let conn = &mut pool.get().await.expect("Failed to get DB connection");
-        diesel::update(crate::db::schema::videos::table)
-            .filter(crate::db::schema::videos::id.eq(v_id))
-            .set(crate::db::schema::videos::duration.eq(duration))
-            .execute(conn)
-            .await
-            .map_err(|e| {
-                log::error!("Error updating video duration: {}", e);
-                actix_web::error::ErrorInternalServerError("Database error")
-            })?;
+    if info
+        .video_stream()
+        .is_some_and(probe::Stream::is_interlaced)
+    {
+        filters.push(DEINTERLACE_FILTER.to_string());
+    }
+    filters.extend(frame_rate_filter(info, rendition));
+    if info
+        .video_stream()
+        .and_then(probe::Stream::hdr_format)
+        .is_some()
+    {
+        filters.push(TONEMAP_FILTER.to_string());
+    }
+    filters
+}
+
+/// The filters of `video_filters` every rendition needs alike, so renditions
+/// encoded together run them once
+fn shared_filters(info: &probe::MediaInfo) -> Vec<String> {
+    let mut filters = Vec::new();
+    if info
+        .video_stream()
+        .is_some_and(probe::Stream::is_interlaced)
+    {
+        filters.push(DEINTERLACE_FILTER.to_string());
+    }
+    if info
+        .video_stream()
+        .and_then(probe::Stream::hdr_format)
+        .is_some()
+    {
+        filters.push(TONEMAP_FILTER.to_string());
     }
+    filters
+}
 
-    // Spawn video processing
-    let video_id_str = v_id.to_string();
+/// Variable frame rate sources break HLS seeking and drift out of audio sync,
+/// so they are resampled to a constant rate and their audio stretched to
+/// match. Sources above the rendition's frame rate cap are reduced the same
+/// way.
+fn frame_rate_filter(info: &probe::MediaInfo, rendition: &RenditionConfig) -> Option<String> {
+    let source_fps = info.video_stream().and_then(probe::Stream::frame_rate);
+    let vfr = info
+        .video_stream()
+        .is_some_and(probe::Stream::is_variable_frame_rate);
+    (vfr || source_fps.is_some_and(|fps| fps > rendition.max_fps))
+        .then(|| format!("fps={:.3}", output_fps(rendition, info)))
+}
 
-    tokio::spawn(async move {
-        let mut conn = pool.get().await.expect("Failed to get DB connection");
-        if let Err(e) = process_video(&video_id_str, &mut conn).await {
-            log::error!("Error processing video {}: {}", video_id_str, e);
+/// `CODECS` entry of the source's video stream if `rendition` segments it
+/// as-is rather than encoding it
+fn copied_video(
+    source: &Source<'_>,
+    rendition: &RenditionConfig,
+    ffmpeg: &FfmpegConfig,
+) -> Option<String> {
+    let info = source.info;
+    if ffmpeg.stream_copy
+        && source.watermark.is_none()
+        && video_filters(info, rendition).is_empty()
+        && can_copy_video(info, rendition)
+    {
+        info.video_stream().and_then(codecs::copied_h264)
+    } else {
+        None
+    }
+}
 
-            // Update status to failed if processing fails
-            if let Err(db_err) = diesel::update(crate::db::schema::videos::table)
-                .filter(crate::db::schema::videos::id.eq(Uuid::parse_str(&video_id_str).unwrap()))
-                .set(crate::db::schema::videos::status.eq("failed"))
-                .execute(&mut conn)
-                .await
-            {
-                log::error!("Error updating video status: {}", db_err);
-            }
-        }
-    });
+/// Packages one of the source's audio tracks as an audio-only rendition
+async fn transcode_audio_to_hls(
+    source: &Source<'_>,
+    track: &AudioTrack,
+    output: &Path,
+    bitrate: &str,
+    profile: &TranscodingConfig,
+    ffmpeg: &FfmpegConfig,
+    on_progress: impl FnMut(f64),
+) -> Result<()> {
+    let mut cmd = ffmpeg::command("ffmpeg", ffmpeg, output.parent().unwrap());
+    cmd.args(ffmpeg::input_protocol_args())
+        .arg("-i")
+        .arg(source.path)
+        .arg("-map")
+        .arg(format!("0:a:{}", track.position));
+
+    let mut audio_filters = Vec::new();
+    if source
+        .info
+        .video_stream()
+        .is_some_and(probe::Stream::is_variable_frame_rate)
+    {
+        audio_filters.push("aresample=async=1");
+    }
+    // Loudness was measured on the default track only
+    if let Some(loudnorm) = source.loudnorm.as_deref().filter(|_| track.is_default) {
+        audio_filters.push(loudnorm);
+    }
+    if !audio_filters.is_empty() {
+        cmd.arg("-af").arg(audio_filters.join(","));
+    }
+
+    let segment_name = if ffmpeg.fmp4() {
+        cmd.arg("-hls_segment_type").arg("fmp4");
+        "segment_%03d.m4s"
+    } else {
+        "segment_%03d.ts"
+    };
+    cmd.args(["-c:a", "aac", "-b:a"])
+        .arg(bitrate)
+        .arg("-hls_time")
+        .arg(profile.segment_duration.to_string())
+        .arg("-hls_playlist_type")
+        .arg("vod")
+        .arg("-loglevel")
+        .arg("quiet")
+        .args(ffmpeg::progress_args())
+        .arg("-hls_segment_filename")
+        .arg(output.parent().unwrap().join(segment_name))
+        .arg(output);
+    let status = ffmpeg::run_with_progress(&mut cmd, source.info.duration(), on_progress).await?;
+
+    if !status.success() {
+        return Err(anyhow::anyhow!("FFmpeg audio transcoding failed"));
+    }
 
     Ok(())
 }
 
-async fn process_video(v_id: &str, conn: &mut AsyncPgConnection) -> Result<()> {
-    use crate::db::schema::videos;
+/// SVT-AV1's numeric preset closest to an x264 preset name: 12 for
+/// ultrafast down to 3 for placebo
+fn svt_av1_preset(x264_preset: &str) -> usize {
+    let speed = X264_PRESETS
+        .iter()
+        .position(|&p| p == x264_preset)
+        .unwrap_or(5);
+    12 - speed.min(9)
+}
 
-    let video_dir = get_video_dir(Uuid::parse_str(v_id)?);
-    let input_path = video_dir.join("original.mp4");
-    let hls_dir = video_dir.join("hls");
-    fs::create_dir_all(&hls_dir).await?;
-
-    let mut master_playlist = String::from("#EXTM3U\n#EXT-X-VERSION:3\n");
-
-    // Process each quality
-    for &(quality, bitrate) in QUALITIES {
-        let quality_dir = hls_dir.join(quality);
-        fs::create_dir_all(&quality_dir).await?;
-        let output_path = quality_dir.join("stream.m3u8");
-
-        // Transcode to HLS
-        match transcode_to_hls(&input_path, &output_path, bitrate, quality, CHUNK_DURATION).await {
-            Ok(_) => {
-                // Store successful transcoding in database
-                let video_quality = VideoQuality {
-                    id: Uuid::new_v4(),
-                    video_id: Uuid::parse_str(v_id)?,
-                    resolution: quality.to_string(),
-                    bitrate: bitrate.to_string(),
-                    file_path: format!("hls/{}/stream.m3u8", quality),
-                    created_at: Utc::now().naive_utc(),
-                };
-
-                match diesel::insert_into(crate::db::schema::video_qualities::table)
-                    .values(&video_quality)
-                    .execute(conn)
-                    .await
-                {
-                    Ok(_) => {}
-                    Err(e) => {
-                        log::error!("Failed to update quality {e}")
-                    }
-                }
+/// Marks a video as rejected with the error code and reason and removes its
+/// uploaded files
+async fn reject_upload(
+    v_id: Uuid,
+    upload_dir: &Path,
+    code: ErrorCode,
+    reason: &str,
+    conn: &mut AsyncPgConnection,
+) {
+    use crate::db::schema::videos;
 
-                // Add to master playlist
-                let bandwidth = parse_bitrate(bitrate)?;
-                let resolution = get_resolution(quality);
-                master_playlist.push_str(&format!(
-                    "#EXT-X-STREAM-INF:BANDWIDTH={},RESOLUTION={}\n{}/stream.m3u8\n",
-                    bandwidth, resolution, quality
-                ));
-            }
-            Err(e) => {
-                log::error!("Failed to transcode quality {}: {}", quality, e);
-                // Continue with other qualities even if one fails
-                continue;
-            }
-        }
+    if let Err(e) = fs::remove_dir_all(upload_dir).await {
+        log::error!("Failed to remove rejected upload {}: {}", v_id, e);
     }
 
-    let uuid_vid_id = Uuid::parse_str(v_id).expect("Failed to parse video id into uuid");
-    let path_str = input_path
-        .as_os_str()
-        .to_str()
-        .expect("Failed to convert input path to string");
-    let duration = get_video_duration(path_str)
-        .await
-        .expect("failed to get video duration");
     match diesel::update(videos::table)
-        .filter(videos::id.eq(uuid_vid_id))
+        .filter(videos::id.eq(v_id))
         .set((
-            videos::status.eq("processed"),
-            videos::duration.eq(Some(duration)),
+            videos::status.eq("rejected"),
+            videos::status_reason.eq(reason),
+            videos::error_code.eq(code.as_str()),
         ))
         .execute(conn)
         .await
     {
-        Ok(_) => {}
-        Err(e) => {
-            log::error!("Failed to update video status: {e}");
-        }
+        Ok(_) => webhooks::notify(conn, v_id).await,
+        Err(e) => log::error!("Error recording rejection for {}: {}", v_id, e),
+    }
+}
+
+/// The renditions that don't upscale the source, comparing short edges so
+/// portrait videos are treated like landscape ones. A source smaller than
+/// every rendition still gets the smallest. Without known dimensions the
+/// whole ladder is kept.
+fn renditions_for_source<'a>(
+    ladder: &'a [RenditionConfig],
+    info: &probe::MediaInfo,
+) -> Vec<&'a RenditionConfig> {
+    let short_edge = |(width, height): (u32, u32)| width.min(height);
+    let Some(source) = info.dimensions().map(short_edge) else {
+        return ladder.iter().collect();
     };
 
-    // Write master playlist
-    fs::write(hls_dir.join("master.m3u8"), master_playlist).await?;
+    let fitting: Vec<_> = ladder
+        .iter()
+        .filter(|r| r.dimensions().is_some_and(|d| short_edge(d) <= source))
+        .collect();
+    if !fitting.is_empty() {
+        return fitting;
+    }
+    ladder
+        .iter()
+        .min_by_key(|r| r.dimensions().map_or(u32::MAX, short_edge))
+        .into_iter()
+        .collect()
+}
 
-    // Generate thumbnails
-    generate_thumbnails(&input_path, &video_dir).await?;
+/// Frame size of a rendition for this source: the ladder's short edge, with
+/// the long edge following the source's aspect ratio so nothing is
+/// stretched. Sources of unknown size get the ladder's size.
+pub fn output_dimensions(
+    rendition: &RenditionConfig,
+    info: &probe::MediaInfo,
+) -> Option<(u32, u32)> {
+    let (width, height) = rendition.dimensions()?;
+    Some(scaled_dimensions(info, width.min(height)).unwrap_or((width, height)))
+}
 
-    Ok(())
+/// Frame size of the HDR rendition, which is never upscaled
+fn hdr_dimensions(info: &probe::MediaInfo) -> (u32, u32) {
+    let lines = info
+        .display_dimensions()
+        .map_or(HDR_HEIGHT, |(width, height)| {
+            width.min(height).min(HDR_HEIGHT)
+        });
+    scaled_dimensions(info, lines).unwrap_or((1920, HDR_HEIGHT))
 }
 
-async fn transcode_to_hls(
-    input: &Path,
+fn review_dimensions(info: &probe::MediaInfo) -> (u32, u32) {
+    scaled_dimensions(info, REVIEW_HEIGHT).unwrap_or((640, REVIEW_HEIGHT))
+}
+
+/// Frame size with a short edge of `lines` and the source's aspect ratio as
+/// shown after any rotation. The long edge is rounded to an even size the
+/// way ffmpeg's `scale` filter does for `-2`.
+fn scaled_dimensions(info: &probe::MediaInfo, lines: u32) -> Option<(u32, u32)> {
+    let (width, height) = info
+        .display_dimensions()
+        .filter(|&(width, height)| width > 0 && height > 0)?;
+    let long_edge = |long: u32, short: u32| {
+        ((lines as f64 * long as f64 / short as f64 / 2.0).round() as u32 * 2).max(2)
+    };
+    if width >= height {
+        Some((long_edge(width, height), lines))
+    } else {
+        Some((lines, long_edge(height, width)))
+    }
+}
+
+/// `scale` filter for frames of `dimensions`, fixing the short edge and
+/// letting ffmpeg keep the aspect ratio for the long one
+pub fn scale_filter((width, height): (u32, u32)) -> String {
+    if width >= height {
+        format!("scale=-2:{}", height)
+    } else {
+        format!("scale={}:-2", width)
+    }
+}
+
+/// Frame rate a rendition is encoded at: the source's, capped at the
+/// rendition's maximum
+fn output_fps(rendition: &RenditionConfig, info: &probe::MediaInfo) -> f64 {
+    info.video_stream()
+        .and_then(probe::Stream::frame_rate)
+        .map_or(DEFAULT_FPS, |fps| fps.min(rendition.max_fps))
+}
+
+/// Quality menu label of video `lines` tall, with the frame rate when above
+/// the usual 30, e.g. `720p` or `1080p60`
+fn quality_label(lines: u32, fps: f64) -> String {
+    let fps = fps.round() as u32;
+    if fps > 30 {
+        format!("{}p{}", lines, fps)
+    } else {
+        format!("{}p", lines)
+    }
+}
+
+/// Label of a ladder rendition, by its short edge like `renditions_for_source`
+fn rendition_label(rendition: &RenditionConfig, fps: f64) -> String {
+    match rendition.dimensions() {
+        Some((width, height)) => quality_label(width.min(height), fps),
+        None => rendition.name.clone(),
+    }
+}
+
+/// Label of the HDR rendition, which keeps the source's frame rate and is
+/// scaled to at most `HDR_HEIGHT` lines
+fn hdr_label(info: &probe::MediaInfo, fps: f64) -> String {
+    let (width, height) = hdr_dimensions(info);
+    format!("{} HDR", quality_label(width.min(height), fps))
+}
+
+/// Whether the source video stream already matches a rendition closely enough
+/// (H.264 4:2:0, same frame size, bitrate not far above the target) that it
+/// can be segmented as-is. Rotated sources are turned upright by encoding,
+/// as segments don't keep the rotation.
+fn can_copy_video(info: &probe::MediaInfo, rendition: &RenditionConfig) -> bool {
+    let Some(stream) = info.video_stream() else {
+        return false;
+    };
+    let Some((width, height)) = output_dimensions(rendition, info) else {
+        return false;
+    };
+    let Ok(target_bitrate) = parse_bitrate(&rendition.video_bitrate) else {
+        return false;
+    };
+
+    rendition.codec() == "h264"
+        && stream.rotation() == 0
+        && stream.codec_name.as_deref() == Some("h264")
+        && stream.pix_fmt.as_deref() == Some("yuv420p")
+        && stream.width == Some(width)
+        && stream.height == Some(height)
+        && stream
+            .frame_rate()
+            .is_some_and(|fps| fps <= rendition.max_fps)
+        && stream
+            .bit_rate()
+            .is_some_and(|b| b as f64 <= target_bitrate as f64 * (1.0 + COPY_TOLERANCE))
+}
+
+/// Encodes an HEVC rendition that keeps the source's HDR transfer and BT.2020
+/// color metadata. HEVC in HLS needs fMP4 segments rather than MPEG-TS.
+async fn transcode_hdr_to_hls(
+    source: &Source<'_>,
     output: &Path,
-    bitrate: &str,
-    quality: &str,
+    hdr: probe::HdrFormat,
     segment_duration: u32,
+    ffmpeg: &FfmpegConfig,
+    on_progress: impl FnMut(f64),
 ) -> Result<()> {
-    let resolution = match quality {
-        "1080p" => "1920x1080",
-        "720p" => "1280x720",
-        "480p" => "854x480",
-        "360p" => "640x360",
-        _ => return Err(anyhow::anyhow!("Invalid quality")),
+    let (input, info, watermark) = (source.path, source.info, source.watermark);
+    let (_, bitrate) = HDR_QUALITY;
+    let mut filters = vec![scale_filter(hdr_dimensions(info))];
+    if info
+        .video_stream()
+        .is_some_and(probe::Stream::is_interlaced)
+    {
+        filters.insert(0, DEINTERLACE_FILTER.to_string());
+    }
+    let filtergraph = match watermark {
+        Some(watermark) => watermark.filtergraph(&filters.join(",")),
+        None => filters.join(","),
     };
 
-    let status = Command::new("ffmpeg")
-        .arg("-i")
-        .arg(input)
-        .arg("-c:v")
-        .arg("libx264")
-        .arg("-c:a")
-        .arg("aac")
+    let x265_params = format!(
+        "hdr-opt=1:repeat-headers=1:colorprim=bt2020:transfer={}:colormatrix=bt2020nc",
+        hdr.transfer()
+    );
+
+    let mut cmd = ffmpeg::command("ffmpeg", ffmpeg, output.parent().unwrap());
+    cmd.args(ffmpeg::input_protocol_args()).arg("-i").arg(input);
+    if source.separate_audio {
+        cmd.arg("-an");
+    } else {
+        if let Some(loudnorm) = &source.loudnorm {
+            cmd.arg("-af").arg(loudnorm);
+        }
+        cmd.args(["-c:a", "aac", "-b:a", "128k"]);
+    }
+    cmd.arg("-c:v")
+        .arg("libx265")
+        .arg("-tag:v")
+        .arg("hvc1")
+        .arg("-pix_fmt")
+        .arg("yuv420p10le")
+        .arg("-x265-params")
+        .arg(x265_params)
         .arg("-b:v")
         .arg(bitrate)
-        .arg("-b:a")
-        .arg("128k")
-        .arg("-s")
-        .arg(resolution)
+        .arg("-vf")
+        .arg(filtergraph)
         .arg("-preset")
-        .arg("fast")
+        .arg(&ffmpeg.preset)
+        .arg("-threads")
+        .arg(ffmpeg.thread_count.to_string())
         .arg("-g")
         .arg("48")
-        .arg("-sc_threshold")
-        .arg("0")
         .arg("-keyint_min")
         .arg("48")
         .arg("-hls_time")
         .arg(segment_duration.to_string())
         .arg("-hls_playlist_type")
         .arg("vod")
+        .arg("-hls_segment_type")
+        .arg("fmp4")
+        .arg("-loglevel")
+        .arg("quiet")
+        .args(ffmpeg::progress_args())
+        .arg("-hls_segment_filename")
+        .arg(output.parent().unwrap().join("segment_%03d.m4s"))
+        .arg(output);
+    let status = ffmpeg::run_with_progress(&mut cmd, info.duration(), on_progress).await?;
+
+    if !status.success() {
+        return Err(anyhow::anyhow!("FFmpeg HDR transcoding failed"));
+    }
+
+    Ok(())
+}
+
+/// Encodes a 360p review proxy with the source timecode and `watermark`
+/// burned in
+async fn transcode_review_proxy(
+    input: &Path,
+    output: &Path,
+    watermark: &str,
+    info: &probe::MediaInfo,
+    ffmpeg: &FfmpegConfig,
+    on_progress: impl FnMut(f64),
+) -> Result<()> {
+    let (_, bitrate) = REVIEW_QUALITY;
+    let fps = info
+        .video_stream()
+        .and_then(probe::Stream::frame_rate)
+        .unwrap_or(DEFAULT_FPS);
+    let mut filters = vec![scale_filter(review_dimensions(info))];
+    if info
+        .video_stream()
+        .is_some_and(probe::Stream::is_interlaced)
+    {
+        filters.insert(0, DEINTERLACE_FILTER.to_string());
+    }
+    filters.push(format!(
+        "drawtext=timecode={}:timecode_rate={:.3}:fontcolor=white:fontsize=h/14:\
+box=1:boxcolor=black@0.6:boxborderw=6:x=(w-text_w)/2:y=h-text_h-16",
+        filter_escape("00:00:00:00"),
+        fps
+    ));
+    filters.push(format!(
+        "drawtext=text={}:expansion=none:fontcolor=white@0.35:fontsize=h/10:\
+x=(w-text_w)/2:y=(h-text_h)/2",
+        filter_escape(watermark)
+    ));
+
+    let mut cmd = ffmpeg::command("ffmpeg", ffmpeg, output.parent().unwrap());
+    cmd.args(ffmpeg::input_protocol_args())
+        .arg("-i")
+        .arg(input)
+        .arg("-vf")
+        .arg(filters.join(","))
+        .arg("-c:v")
+        .arg("libx264")
+        .arg("-b:v")
+        .arg(bitrate)
+        .arg("-preset")
+        .arg(&ffmpeg.preset)
+        .arg("-threads")
+        .arg(ffmpeg.thread_count.to_string())
+        .arg("-c:a")
+        .arg("aac")
+        .arg("-b:a")
+        .arg("96k")
+        .arg("-hls_time")
+        .arg(CHUNK_DURATION.to_string())
+        .arg("-hls_playlist_type")
+        .arg("vod")
         .arg("-loglevel")
         .arg("quiet")
+        .args(ffmpeg::progress_args())
         .arg("-hls_segment_filename")
         .arg(output.parent().unwrap().join("segment_%03d.ts"))
-        .arg(output)
-        .status()
-        .await?;
+        .arg(output);
+    let status = ffmpeg::run_with_progress(&mut cmd, info.duration(), on_progress).await?;
 
     if !status.success() {
-        return Err(anyhow::anyhow!("FFmpeg transcoding failed"));
+        return Err(anyhow::anyhow!("FFmpeg review proxy encoding failed"));
     }
 
     Ok(())
 }
 
-async fn generate_thumbnails(input: &Path, output_dir: &Path) -> Result<()> {
+/// Escapes a filter option value for both the option parser and the
+/// filtergraph parser it is nested in
+pub(crate) fn filter_escape(value: &str) -> String {
+    let escape = |value: &str, special: &[char]| {
+        value.chars().fold(String::new(), |mut out, c| {
+            if special.contains(&c) {
+                out.push('\\');
+            }
+            out.push(c);
+            out
+        })
+    };
+    let option = escape(value, &['\\', '\'', ':']);
+    escape(&option, &['\\', '\'', '[', ']', ',', ';'])
+}
+
+/// Hashing runs on the blocking pool; the playlist is replaced through
+/// `storage` so a failed write can't leave it cut short
+async fn embed_checksums(playlist: &Path, blocking: &BlockingPool) -> Result<()> {
+    let path = playlist.to_path_buf();
+    let contents = blocking
+        .run(move || integrity::with_checksums(&path))
+        .await??;
+    storage::write(playlist, contents).await?;
+    Ok(())
+}
+
+async fn generate_thumbnails(input: &Path, output_dir: &Path, ffmpeg: &FfmpegConfig) -> Result<()> {
     let thumbnails_dir = output_dir.join("thumbnails");
     fs::create_dir_all(&thumbnails_dir).await?;
 
     // Generate thumbnail every 10 seconds
-    let status = Command::new("ffmpeg")
+    let status = ffmpeg::command("ffmpeg", ffmpeg, &thumbnails_dir)
+        .args(ffmpeg::input_protocol_args())
         .arg("-i")
         .arg(input)
         .arg("-vf")
@@ -262,47 +2377,303 @@ async fn generate_thumbnails(input: &Path, output_dir: &Path) -> Result<()> {
     Ok(())
 }
 
-fn get_video_dir(v_id: Uuid) -> PathBuf {
+/// Publishes progress events as a rendition encodes and keeps the latest
+/// percentage in `progress` for `saving_progress` to persist
+fn transcode_progress<'a>(
+    events: &'a ProgressEvents,
+    v_id: Uuid,
+    quality: &'a str,
+    progress: &'a AtomicU8,
+) -> impl FnMut(f64) + 'a {
+    let mut last = None;
+    move |percent| {
+        let percent = percent as u8;
+        if last != Some(percent) {
+            last = Some(percent);
+            progress.store(percent, Ordering::Relaxed);
+            events.publish(
+                v_id,
+                ProgressEvent::Transcode {
+                    quality: quality.to_string(),
+                    percent,
+                },
+            );
+        }
+    }
+}
+
+pub fn get_video_dir(v_id: Uuid) -> PathBuf {
     PathBuf::from("uploads").join(v_id.to_string())
 }
 
-async fn get_video_duration(file_path: &str) -> Result<f64, Box<dyn std::error::Error>> {
-    let output = Command::new("ffprobe")
-        .args([
-            "-v",
-            "quiet",
-            "-print_format",
-            "json",
-            "-show_format",
-            file_path,
-        ])
-        .output()
-        .await?;
+/// Name for a new original file, keeping the client's extension when it is a
+/// plausible one. Uploads without one get an extension once probed.
+pub fn original_file_name(filename: Option<&str>) -> String {
+    let ext = filename
+        .and_then(|name| Path::new(name).extension())
+        .and_then(|ext| ext.to_str())
+        .map(str::to_ascii_lowercase)
+        .filter(|ext| {
+            !ext.is_empty() && ext.len() <= 5 && ext.chars().all(|c| c.is_ascii_alphanumeric())
+        });
+    match ext {
+        Some(ext) => format!("original.{}", ext),
+        None => "original".to_string(),
+    }
+}
+
+/// The last component of a file name sent by a client, without control
+/// characters and at most 255 characters long. May be empty.
+pub fn client_file_name(filename: &str) -> String {
+    filename
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(255)
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+/// Writes a package's master playlist and its `LOW_MASTER` without the
+/// high-bitrate variants. Players may be fetching them meanwhile, so each is
+/// replaced whole.
+pub async fn write_masters(hls_dir: &Path, master: &str) -> std::io::Result<()> {
+    let low = playlist::limit_bandwidth(master, LOW_MASTER_MAX_BANDWIDTH);
+    for (name, text) in [(LOW_MASTER, low.as_str()), ("master.m3u8", master)] {
+        storage::write(&hls_dir.join(name), text).await?;
+    }
+    Ok(())
+}
+
+/// Path of the stored original upload, whatever its extension
+pub async fn find_original(video_dir: &Path) -> std::io::Result<PathBuf> {
+    let mut entries = fs::read_dir(video_dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path.file_stem().and_then(|stem| stem.to_str()) == Some("original") {
+            return Ok(path);
+        }
+    }
+    Err(std::io::Error::new(
+        std::io::ErrorKind::NotFound,
+        "Original upload not found",
+    ))
+}
+
+/// Runs `transcode` until it succeeds or `ffmpeg.transcode_attempts` are used
+/// up, backing off exponentially from `ffmpeg.retry_backoff_secs` between
+/// attempts. `outputs` are the renditions it writes, each with its
+/// directory; each attempt and its error are recorded on their quality rows.
+/// Output missing from a directory, as when storage failed while ffmpeg
+/// wrote it, fails the attempt.
+async fn transcode_with_retries<F, Fut, T>(
+    conn: &mut AsyncPgConnection,
+    v_id: Uuid,
+    outputs: &[(&str, &Path)],
+    progress: &AtomicU8,
+    ffmpeg: &FfmpegConfig,
+    mut transcode: F,
+) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let attempts = ffmpeg.transcode_attempts.max(1);
+    let mut backoff = Duration::from_secs(ffmpeg.retry_backoff_secs);
+    let mut attempt = 1;
+    let qualities: Vec<_> = outputs.iter().map(|&(quality, _)| quality).collect();
+    loop {
+        progress.store(0, Ordering::Relaxed);
+        for quality in &qualities {
+            record_attempt(conn, v_id, quality, attempt, None).await;
+        }
+
+        let error = match saving_progress(conn, v_id, &qualities, progress, transcode()).await {
+            Ok(output) => match check_outputs(outputs).await {
+                Ok(()) => return Ok(output),
+                Err(problems) => anyhow::anyhow!("Incomplete output: {}", problems),
+            },
+            Err(e) => e,
+        };
+        for quality in &qualities {
+            record_attempt(conn, v_id, quality, attempt, Some(&error.to_string())).await;
+        }
+        if attempt >= attempts {
+            return Err(error);
+        }
+
+        log::warn!(
+            "Attempt {} of {} at quality {} for {} failed, retrying in {}s: {}",
+            attempt,
+            attempts,
+            qualities.join(", "),
+            v_id,
+            backoff.as_secs(),
+            error
+        );
+        tokio::time::sleep(backoff).await;
+        backoff *= 2;
+        attempt += 1;
+
+        // ffmpeg won't overwrite the failed attempt's output
+        for &(_, quality_dir) in outputs {
+            fs::remove_dir_all(quality_dir).await?;
+            fs::create_dir_all(quality_dir).await?;
+        }
+    }
+}
+
+async fn check_outputs(outputs: &[(&str, &Path)]) -> Result<(), String> {
+    for &(quality, quality_dir) in outputs {
+        validator::check_rendition(quality_dir)
+            .await
+            .map_err(|problems| match outputs.len() {
+                1 => problems,
+                _ => format!("{}: {}", quality, problems),
+            })?;
+    }
+    Ok(())
+}
+
+async fn record_attempt(
+    conn: &mut AsyncPgConnection,
+    v_id: Uuid,
+    quality: &str,
+    attempt: u32,
+    error: Option<&str>,
+) {
+    use crate::db::schema::video_qualities;
+
+    if let Err(e) = diesel::update(video_qualities::table)
+        .filter(video_qualities::video_id.eq(v_id))
+        .filter(video_qualities::resolution.eq(quality))
+        .set((
+            video_qualities::attempts.eq(attempt as i32),
+            video_qualities::error.eq(error),
+            video_qualities::progress.eq(0),
+        ))
+        .execute(conn)
+        .await
+    {
+        log::error!(
+            "Failed to record attempt at {} for {}: {}",
+            quality,
+            v_id,
+            e
+        );
+    }
+}
+
+/// Awaits `transcode`, saving the percentage it reports through `progress` on
+/// the quality rows every `PROGRESS_SAVE_INTERVAL` so pollers can show it
+async fn saving_progress<T>(
+    conn: &mut AsyncPgConnection,
+    v_id: Uuid,
+    qualities: &[&str],
+    progress: &AtomicU8,
+    transcode: impl Future<Output = T>,
+) -> T {
+    tokio::pin!(transcode);
+    let mut interval = tokio::time::interval(PROGRESS_SAVE_INTERVAL);
+    let mut saved = 0;
+    loop {
+        tokio::select! {
+            result = &mut transcode => return result,
+            _ = interval.tick() => {
+                let percent = progress.load(Ordering::Relaxed);
+                if percent != saved {
+                    saved = percent;
+                    for quality in qualities {
+                        save_progress(conn, v_id, quality, percent).await;
+                    }
+                }
+            }
+        }
+    }
+}
+
+async fn save_progress(conn: &mut AsyncPgConnection, v_id: Uuid, quality: &str, percent: u8) {
+    use crate::db::schema::video_qualities;
+
+    if let Err(e) = diesel::update(video_qualities::table)
+        .filter(video_qualities::video_id.eq(v_id))
+        .filter(video_qualities::resolution.eq(quality))
+        .set(video_qualities::progress.eq(i32::from(percent)))
+        .execute(conn)
+        .await
+    {
+        log::error!("Failed to save progress of {} for {}: {}", quality, v_id, e);
+    }
+}
+
+async fn set_quality_state(conn: &mut AsyncPgConnection, v_id: Uuid, quality: &str, state: &str) {
+    use crate::db::schema::video_qualities;
+
+    if let Err(e) = diesel::update(video_qualities::table)
+        .filter(video_qualities::video_id.eq(v_id))
+        .filter(video_qualities::resolution.eq(quality))
+        .set(video_qualities::state.eq(state))
+        .execute(conn)
+        .await
+    {
+        log::error!("Failed to update quality {} of {}: {}", quality, v_id, e);
+    }
+}
+
+/// Checks that a probed upload is a decodable video within the configured input
+/// limits, returning the error code and reason for rejection otherwise
+pub(crate) fn validate_upload(
+    info: &probe::MediaInfo,
+    limits: &ValidationConfig,
+) -> Result<(), (ErrorCode, String)> {
+    let Some((width, height)) = info.dimensions() else {
+        return Err((
+            ErrorCode::NoVideoStream,
+            "File does not contain a video stream".to_string(),
+        ));
+    };
+
+    let duration = match info.duration() {
+        Some(duration) if duration > 0.0 => duration,
+        _ => {
+            return Err((
+                ErrorCode::UnreadableMedia,
+                "Video has no readable duration, the file may be corrupt".to_string(),
+            ))
+        }
+    };
+
+    if duration > limits.max_duration_secs {
+        return Err((
+            ErrorCode::DurationExceeded,
+            format!(
+                "Video is {:.0} seconds long, the maximum is {:.0} seconds",
+                duration, limits.max_duration_secs
+            ),
+        ));
+    }
 
-    let json: Value = serde_json::from_slice(&output.stdout)?;
-    let duration = json["format"]["duration"]
-        .as_str()
-        .ok_or("No duration found")?
-        .parse::<f64>()?;
+    let (long, short) = (width.max(height), width.min(height));
+    if long > limits.max_width || short > limits.max_height {
+        return Err((
+            ErrorCode::ResolutionExceeded,
+            format!(
+                "Video resolution {}x{} exceeds the maximum of {}x{}",
+                width, height, limits.max_width, limits.max_height
+            ),
+        ));
+    }
 
-    Ok(duration)
+    Ok(())
 }
 
-fn parse_bitrate(bitrate: &str) -> Result<u32> {
+pub(crate) fn parse_bitrate(bitrate: &str) -> Result<u32> {
     let num = bitrate
         .trim_end_matches('k')
         .parse::<u32>()
         .context("Invalid bitrate format")?;
     Ok(num * 1000) // Convert to bits per second
 }
-
-fn get_resolution(quality: &str) -> String {
-    match quality {
-        "1080p" => "1920x1080",
-        "720p" => "1280x720",
-        "480p" => "854x480",
-        "360p" => "640x360",
-        _ => "640x360", // default
-    }
-    .to_string()
-}
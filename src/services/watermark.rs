@@ -0,0 +1,142 @@
+// src/services/watermark.rs
+use crate::api::shared::{validation_error, ErrorCode};
+use crate::config::app_config::{WatermarkConfig, WATERMARK_POSITIONS};
+use crate::services::video_processor;
+use actix_web::web::Bytes;
+use actix_web::Error;
+use futures::Stream;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use tokio::io::AsyncReadExt;
+use uuid::Uuid;
+
+/// Largest watermark image an upload may bring
+const MAX_IMAGE_SIZE: usize = 5 * 1024 * 1024;
+/// Stem of an upload's own image in its video directory, next to the original
+const IMAGE_STEM: &str = "watermark";
+
+/// An image and where to overlay it on a rendition
+pub struct Watermark {
+    image: PathBuf,
+    position: String,
+    opacity: f64,
+    margin: u32,
+}
+
+impl Watermark {
+    /// The upload's own image, or else the server's, placed at the upload's
+    /// position or else the server's. None when there is no image.
+    pub async fn for_video(
+        video_dir: &Path,
+        position: Option<&str>,
+        config: &WatermarkConfig,
+    ) -> Option<Watermark> {
+        let image = match find_image(video_dir).await {
+            Some(image) => image,
+            None => fs::canonicalize(config.image.as_ref()?).await.ok()?,
+        };
+        Some(Watermark {
+            image,
+            position: position.unwrap_or(&config.position).to_string(),
+            opacity: config.opacity,
+            margin: config.margin,
+        })
+    }
+
+    /// A `-vf` filtergraph running `chain` (which may be empty) and then
+    /// overlaying the image. The overlay happens before the output is scaled
+    /// to the rendition, so the image keeps its size relative to the frame
+    /// across the ladder.
+    pub fn filtergraph(&self, chain: &str) -> String {
+        let margin = self.margin;
+        let (x, y) = match self.position.as_str() {
+            "top-left" => (margin.to_string(), margin.to_string()),
+            "top-right" => (format!("W-w-{}", margin), margin.to_string()),
+            "bottom-left" => (margin.to_string(), format!("H-h-{}", margin)),
+            "center" => ("(W-w)/2".to_string(), "(H-h)/2".to_string()),
+            _ => (format!("W-w-{}", margin), format!("H-h-{}", margin)),
+        };
+        format!(
+            "{}[video];movie={},format=rgba,colorchannelmixer=aa={:.2}[watermark];\
+[video][watermark]overlay={}:{}",
+            if chain.is_empty() { "null" } else { chain },
+            video_processor::filter_escape(&self.image.to_string_lossy()),
+            self.opacity,
+            x,
+            y
+        )
+    }
+}
+
+/// Rejects positions outside `WATERMARK_POSITIONS`
+pub fn validate_position(position: &str) -> Result<(), Error> {
+    if WATERMARK_POSITIONS.contains(&position) {
+        return Ok(());
+    }
+    Err(validation_error(
+        "watermark_position".to_string(),
+        format!(
+            "Watermark position must be one of {}",
+            WATERMARK_POSITIONS.join(", ")
+        ),
+        ErrorCode::ValidationFailed,
+    ))
+}
+
+/// Path of an upload's own watermark image, whatever its extension
+async fn find_image(video_dir: &Path) -> Option<PathBuf> {
+    for ext in ["png", "jpg"] {
+        let path = video_dir.join(format!("{}.{}", IMAGE_STEM, ext));
+        if fs::try_exists(&path).await.unwrap_or(false) {
+            return Some(path);
+        }
+    }
+    None
+}
+
+/// Whether the video brought its own watermark image
+pub async fn has_own_image(v_id: Uuid) -> bool {
+    find_image(&video_processor::get_video_dir(v_id))
+        .await
+        .is_some()
+}
+
+/// Stores a watermark image sent with an upload, rejecting anything but a
+/// PNG or JPEG
+pub async fn save_image<S, E>(body: &mut S, v_id: Uuid) -> Result<(), Error>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    E: Into<Error>,
+{
+    let video_dir = video_processor::get_video_dir(v_id);
+    fs::create_dir_all(&video_dir).await.map_err(|e| {
+        log::error!("Failed to create upload directory: {}", e);
+        actix_web::error::ErrorInternalServerError("Storage error")
+    })?;
+    let received = video_dir.join(IMAGE_STEM);
+    video_processor::write_body(body, &received, MAX_IMAGE_SIZE, |_, _| {}).await?;
+
+    let mut magic = [0; 8];
+    let read = match fs::File::open(&received).await {
+        Ok(mut file) => file.read(&mut magic).await.unwrap_or(0),
+        Err(_) => 0,
+    };
+    let ext = match &magic[..read] {
+        [0x89, b'P', b'N', b'G', ..] => "png",
+        [0xff, 0xd8, 0xff, ..] => "jpg",
+        _ => {
+            let _ = fs::remove_file(&received).await;
+            return Err(validation_error(
+                "watermark".to_string(),
+                "Watermark must be a PNG or JPEG image".to_string(),
+                ErrorCode::ValidationFailed,
+            ));
+        }
+    };
+    fs::rename(&received, video_dir.join(format!("{}.{}", IMAGE_STEM, ext)))
+        .await
+        .map_err(|e| {
+            log::error!("Failed to store watermark of {}: {}", v_id, e);
+            actix_web::error::ErrorInternalServerError("Storage error")
+        })
+}
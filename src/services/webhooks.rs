@@ -0,0 +1,281 @@
+// src/services/webhooks.rs
+use crate::db::models::{Video, Webhook};
+use crate::services::outbound;
+use anyhow::Result;
+use chrono::Utc;
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use hmac::{Hmac, Mac};
+use serde_json::Value;
+use sha2::Sha256;
+use std::time::Duration;
+use uuid::Uuid;
+use vid_storage_models::{CompactWebhookEvent, VideoResource, WebhookEvent, WebhookTestResult};
+
+/// Delays before each retry of a failed delivery
+const RETRY_DELAYS: &[Duration] = &[
+    Duration::from_secs(5),
+    Duration::from_secs(30),
+    Duration::from_secs(300),
+];
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+/// `sha256=` and the hex HMAC-SHA256 of `<timestamp>.<body>`, keyed with the
+/// webhook's secret
+pub const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+/// Unix time the delivery was signed at. Receivers should reject deliveries
+/// signed too long ago, so a captured one can't be replayed.
+pub const TIMESTAMP_HEADER: &str = "X-Webhook-Timestamp";
+/// Events webhooks can subscribe to, one per status a video is notified of
+pub const EVENTS: &[&str] = &[
+    "video.processing",
+    "video.processed",
+    "video.failed",
+    "video.rejected",
+    "video.removed",
+    "video.canceled",
+];
+pub const FORMATS: &[&str] = &["full", "compact"];
+/// Event of test deliveries, sent whatever the webhook subscribed to
+const TEST_EVENT: &str = "webhook.test";
+
+/// Tells every registered webhook about the video's current status. Call it
+/// right after changing the status; deliveries (and their retries) happen in
+/// the background, so a slow consumer never holds up processing.
+pub async fn notify(conn: &mut AsyncPgConnection, v_id: Uuid) {
+    if let Err(e) = try_notify(conn, v_id).await {
+        log::error!("Failed to send webhooks for video {}: {}", v_id, e);
+    }
+}
+
+async fn try_notify(conn: &mut AsyncPgConnection, v_id: Uuid) -> Result<()> {
+    use crate::db::schema::{videos, webhooks};
+
+    let hooks = webhooks::table.load::<Webhook>(conn).await?;
+    if hooks.is_empty() {
+        return Ok(());
+    }
+    let video = videos::table.find(v_id).first::<Video>(conn).await?;
+    let event = format!("video.{}", video.status);
+    let hooks: Vec<_> = hooks
+        .into_iter()
+        .filter(|hook| hook.events.is_empty() || hook.events.contains(&event))
+        .collect();
+
+    let full = WebhookEvent {
+        event,
+        video: VideoResource::from(video),
+    };
+    let client = client()?;
+    for hook in hooks {
+        let body = payload(&hook, &full)?;
+        tokio::spawn(deliver(client.clone(), hook, body));
+    }
+    Ok(())
+}
+
+/// Sends `hook` a `webhook.test` payload about `video` right away, once
+pub async fn test(hook: &Webhook, video: Video) -> Result<WebhookTestResult> {
+    let full = WebhookEvent {
+        event: TEST_EVENT.to_string(),
+        video: VideoResource::from(video),
+    };
+    let body = payload(hook, &full)?;
+    let result = send(&client()?, hook, body).await;
+    if let Err(e) = &result {
+        log::warn!("Test delivery of webhook {} failed: {}", hook.id, e);
+    }
+    Ok(WebhookTestResult {
+        delivered: result.is_ok(),
+    })
+}
+
+/// Webhook URLs are user-supplied, so they get the same policy as imports
+fn client() -> reqwest::Result<reqwest::Client> {
+    outbound::client(DELIVERY_TIMEOUT)
+}
+
+/// The body `hook` gets for `full`, in its format or rendered from its
+/// template
+fn payload(hook: &Webhook, full: &WebhookEvent) -> Result<Vec<u8>> {
+    if let Some(template) = &hook.template {
+        return Ok(serde_json::to_vec(&render(
+            template,
+            &serde_json::to_value(full)?,
+        ))?);
+    }
+    Ok(match hook.format.as_str() {
+        "compact" => serde_json::to_vec(&CompactWebhookEvent {
+            event: full.event.clone(),
+            video_id: full.video.id,
+            status: full.video.status.clone(),
+            error_code: full.video.error_code.clone(),
+        })?,
+        _ => serde_json::to_vec(full)?,
+    })
+}
+
+/// Fills the `{{path}}` placeholders in the strings of `template` with the
+/// fields of `payload` they name, e.g. `{{video.title}}`. A string that is
+/// just one placeholder is replaced by the field's value, keeping its type;
+/// others get its text, with nothing for missing and null fields.
+pub fn render(template: &Value, payload: &Value) -> Value {
+    let field = |path: &str| payload.pointer(&format!("/{}", path.trim().replace('.', "/")));
+    match template {
+        Value::String(text) => {
+            let whole = text
+                .strip_prefix("{{")
+                .and_then(|rest| rest.strip_suffix("}}"))
+                .filter(|path| !path.contains("{{") && !path.contains("}}"));
+            if let Some(path) = whole {
+                return field(path).cloned().unwrap_or(Value::Null);
+            }
+
+            let mut rendered = String::new();
+            let mut rest = text.as_str();
+            while let Some((before, after)) = rest.split_once("{{") {
+                let Some((path, after)) = after.split_once("}}") else {
+                    break;
+                };
+                rendered.push_str(before);
+                match field(path) {
+                    Some(Value::String(value)) => rendered.push_str(value),
+                    Some(Value::Null) | None => {}
+                    Some(value) => rendered.push_str(&value.to_string()),
+                }
+                rest = after;
+            }
+            rendered.push_str(rest);
+            Value::String(rendered)
+        }
+        Value::Array(items) => {
+            Value::Array(items.iter().map(|item| render(item, payload)).collect())
+        }
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(key, value)| (key.clone(), render(value, payload)))
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+async fn deliver(client: reqwest::Client, hook: Webhook, body: Vec<u8>) {
+    let mut retries = RETRY_DELAYS.iter();
+    loop {
+        let Err(e) = send(&client, &hook, body.clone()).await else {
+            return;
+        };
+
+        match retries.next() {
+            Some(delay) => {
+                log::warn!("Webhook {} delivery failed, retrying: {}", hook.id, e);
+                tokio::time::sleep(*delay).await;
+            }
+            None => {
+                log::error!("Giving up on webhook {} delivery: {}", hook.id, e);
+                return;
+            }
+        }
+    }
+}
+
+/// Posts `body` to the webhook; answers other than 2xx are errors. The URL
+/// is checked on every attempt, since the client's resolver only covers
+/// host names, not address literals.
+async fn send(client: &reqwest::Client, hook: &Webhook, body: Vec<u8>) -> Result<()> {
+    outbound::check_url(&hook.url.parse()?)?;
+    // Each attempt is signed anew, so retries carry a fresh timestamp
+    let timestamp = Utc::now().timestamp().to_string();
+    let mut signed = format!("{}.", timestamp).into_bytes();
+    signed.extend_from_slice(&body);
+    let signature = format!("sha256={}", sign(&hook.secret, &signed));
+    client
+        .post(&hook.url)
+        .header("Content-Type", "application/json")
+        .header(SIGNATURE_HEADER, signature)
+        .header(TIMESTAMP_HEADER, timestamp)
+        .body(body)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+/// Hex HMAC-SHA256 of `body` keyed with `secret`
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    hex::encode(mac(secret, body).finalize().into_bytes())
+}
+
+/// Whether `signature`, `sha256=` and a hex HMAC-SHA256 as `sign` makes it,
+/// is that of `body` keyed with `secret`. Compared in constant time.
+pub fn verify(secret: &str, body: &[u8], signature: &str) -> bool {
+    signature
+        .strip_prefix("sha256=")
+        .and_then(|signature| hex::decode(signature).ok())
+        .is_some_and(|signature| mac(secret, body).verify_slice(&signature).is_ok())
+}
+
+fn mac(secret: &str, body: &[u8]) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(body);
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn payload() -> Value {
+        json!({
+            "event": "video.processed",
+            "video": {
+                "id": "6f1c0e9a-2b3d-4c5e-8f70-1a2b3c4d5e6f",
+                "title": "Launch",
+                "duration": 12.5,
+                "error_code": null,
+                "tags": ["news", "live"]
+            }
+        })
+    }
+
+    #[test]
+    fn whole_placeholders_keep_the_field_type() {
+        let template = json!({
+            "seconds": "{{video.duration}}",
+            "tags": "{{ video.tags }}",
+            "code": "{{video.error_code}}",
+            "missing": "{{video.owner}}"
+        });
+        assert_eq!(
+            render(&template, &payload()),
+            json!({
+                "seconds": 12.5,
+                "tags": ["news", "live"],
+                "code": null,
+                "missing": null
+            })
+        );
+    }
+
+    #[test]
+    fn placeholders_in_text_are_filled_in() {
+        let template = json!({
+            "text": "{{video.title}} ({{video.duration}}s) is {{event}}",
+            "blank": "code: {{video.error_code}}{{video.owner}}",
+            "unclosed": "{{video.title}} {{video.id",
+            "nested": ["{{video.id}}", 3, true]
+        });
+        assert_eq!(
+            render(&template, &payload()),
+            json!({
+                "text": "Launch (12.5s) is video.processed",
+                "blank": "code: ",
+                "unclosed": "Launch {{video.id",
+                "nested": ["6f1c0e9a-2b3d-4c5e-8f70-1a2b3c4d5e6f", 3, true]
+            })
+        );
+    }
+}